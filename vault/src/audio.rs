@@ -0,0 +1,111 @@
+//! Audio files for the GUI viewer, played through rodio and decoded by symphonia
+//! (MP3, FLAC, Ogg Vorbis, WAV, AAC and M4A). The file is read from the vault through
+//! `VaultFileReader` as it plays, chunk by chunk, so neither the whole file nor a
+//! decrypted copy of it is kept anywhere.
+
+use rodio::{Decoder, OutputStream, Sink};
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::probe::Hint;
+use vault_core::container::{Session, VaultFileReader};
+
+/// A file in the vault as symphonia reads it, knowing its length.
+struct Stream(VaultFileReader);
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for Stream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl MediaSource for Stream {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.0.len())
+    }
+}
+
+/// Decodes file `id` from its start. The length is read from the file's headers by
+/// symphonia itself: rodio's `total_duration` is off by seconds when the length is a
+/// whole number of seconds.
+fn decode(sess: &Session, id: u64) -> anyhow::Result<(Decoder<Stream>, Option<Duration>)> {
+    let stream = MediaSourceStream::new(Box::new(Stream(VaultFileReader::open(sess, id)?)), Default::default());
+    let probed = symphonia::default::get_probe().format(&Hint::new(), stream, &Default::default(), &Default::default())?;
+    let duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        Some(Duration::from(params.time_base?.calc_time(params.n_frames?)))
+    });
+    Ok((Decoder::new(Stream(VaultFileReader::open(sess, id)?))?, duration))
+}
+
+/// One file played on the default output device; dropping it stops the sound.
+pub struct Player {
+    // the device plays only while the stream is kept
+    _stream: OutputStream,
+    sink: Sink,
+    duration: Option<Duration>,
+}
+
+impl Player {
+    /// Opens file `id` for playing, paused at its start.
+    pub fn open(sess: &Session, id: u64) -> anyhow::Result<Player> {
+        let (track, duration) = decode(sess, id)?;
+        let (stream, handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&handle)?;
+        sink.pause();
+        sink.append(track);
+        Ok(Player { _stream: stream, sink, duration })
+    }
+
+    /// Starts a file played to its end over again.
+    pub fn reload(&self, sess: &Session, id: u64) -> anyhow::Result<()> {
+        self.sink.append(decode(sess, id)?.0);
+        Ok(())
+    }
+
+    /// Length of the file, when its format tells it.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Whether the file has been played to its end.
+    pub fn finished(&self) -> bool {
+        self.sink.empty()
+    }
+
+    pub fn playing(&self) -> bool {
+        !self.sink.is_paused() && !self.finished()
+    }
+
+    pub fn play(&self) {
+        self.sink.play();
+    }
+
+    pub fn pause(&self) {
+        self.sink.pause();
+    }
+
+    pub fn position(&self) -> Duration {
+        match (self.finished(), self.duration) {
+            (true, Some(end)) => end,
+            _ => self.sink.get_pos(),
+        }
+    }
+
+    /// Goes to `pos` from the start. Past the end means just before it: decoders can't
+    /// seek to the very end.
+    pub fn seek(&self, pos: Duration) -> anyhow::Result<()> {
+        let pos = self.duration.map_or(pos, |end| pos.min(end.saturating_sub(Duration::from_millis(20))));
+        self.sink.try_seek(pos).map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
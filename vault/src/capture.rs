@@ -0,0 +1,35 @@
+//! Keeping the window out of screenshots and screen sharing. Only Windows lets an
+//! application ask for this (`SetWindowDisplayAffinity`); elsewhere it is a no-op and
+//! the GUI can only blank the file contents itself while the window is out of focus.
+
+/// The system can exclude a window from captures.
+pub const SUPPORTED: bool = cfg!(windows);
+
+/// Excludes the window from captures (`exclude`) or lets them see it again. From
+/// Windows 10 2004 the window is left out of the captured image; on older versions it
+/// shows up black.
+#[cfg(windows)]
+pub fn exclude_window(frame: &eframe::Frame, exclude: bool) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{SetWindowDisplayAffinity, WDA_EXCLUDEFROMCAPTURE, WDA_MONITOR, WDA_NONE};
+
+    let handle = frame.window_handle().map_err(|e| anyhow::anyhow!("window handle: {e}"))?;
+    let RawWindowHandle::Win32(win) = handle.as_raw() else {
+        anyhow::bail!("not a Win32 window");
+    };
+    let hwnd = win.hwnd.get() as windows_sys::Win32::Foundation::HWND;
+    let set = |affinity| {
+        // SAFETY: `hwnd` is the live top-level window of this process.
+        unsafe { SetWindowDisplayAffinity(hwnd, affinity) != 0 }
+    };
+    if set(if exclude { WDA_EXCLUDEFROMCAPTURE } else { WDA_NONE }) || (exclude && set(WDA_MONITOR)) {
+        return Ok(());
+    }
+    Err(std::io::Error::last_os_error()).context("SetWindowDisplayAffinity")
+}
+
+#[cfg(not(windows))]
+pub fn exclude_window(_frame: &eframe::Frame, _exclude: bool) -> anyhow::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! System clipboard for copying file contents out of the GUI. Copied data is cleared
+//! again after a timeout, unless something else has replaced it by then, so a secret
+//! doesn't stay on the clipboard longer than needed. Only a hash of it is kept to
+//! recognize it.
+
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+/// Largest binary file copied as base64.
+pub const MAX_BASE64_BYTES: u64 = 1024 * 1024;
+
+#[derive(Default)]
+pub struct Clipboard {
+    // opened on first use; on X11 the copied text lives as long as this does
+    board: Option<arboard::Clipboard>,
+    // hash of what was copied and when to clear it
+    pending: Option<([u8; 32], Instant)>,
+}
+
+impl Clipboard {
+    /// Puts `text` on the clipboard; with `clear_after`, it is cleared after that long
+    /// (see `poll`).
+    pub fn copy(&mut self, text: &str, clear_after: Option<Duration>) -> anyhow::Result<()> {
+        let board = match &mut self.board {
+            Some(b) => b,
+            None => self.board.insert(arboard::Clipboard::new()?),
+        };
+        let set = board.set();
+        // Windows would keep it in the clipboard history and sync it to the cloud.
+        #[cfg(windows)]
+        let set = {
+            use arboard::SetExtWindows;
+            set.exclude_from_history().exclude_from_cloud()
+        };
+        set.text(text)?;
+        self.pending = clear_after.map(|d| (Sha256::digest(text).into(), Instant::now() + d));
+        Ok(())
+    }
+
+    /// Clears the clipboard when the timeout is up. Returns the time left while a
+    /// clear is pending, None once nothing is.
+    pub fn poll(&mut self) -> Option<Duration> {
+        let (_, at) = self.pending?;
+        let left = at.saturating_duration_since(Instant::now());
+        if !left.is_zero() {
+            return Some(left);
+        }
+        self.clear();
+        None
+    }
+
+    /// Clears the clipboard now if it still holds what was copied.
+    pub fn clear(&mut self) {
+        let (Some((hash, _)), Some(board)) = (self.pending.take(), self.board.as_mut()) else {
+            return;
+        };
+        let current = board.get_text().ok().map(|t| <[u8; 32]>::from(Sha256::digest(t)));
+        if current == Some(hash) {
+            let _ = board.clear();
+        }
+    }
+}
+
+/// Standard base64 with padding.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            out.push(match i <= chunk.len() {
+                true => ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    out
+}
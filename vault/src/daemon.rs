@@ -0,0 +1,460 @@
+//! `vault daemon`: a long-lived process that holds unlocked sessions and is driven over
+//! a local socket (a Unix socket, a named pipe on Windows) with JSON-RPC 2.0, one
+//! request per line and one response per line. A tool or a thin GUI unlocks a vault
+//! once with `open` and pays for Argon2 once, instead of on every command.
+//!
+//! Methods (params by name; `session` is what `open` returned):
+//!
+//! - `open {path, password, read_only?, keyfile?}` → `{session, generation}`
+//! - `close {session}`, `sessions` → `[{session, path, read_only}]`
+//! - `ls {session, dir_id?}` → `[{id, name, type, size, modified, mime}]`
+//! - `import {session, os_path, parent_id?, name?, compress?}` → `{id}`
+//! - `export {session, file_id, os_path}`
+//! - `search {session, query, limit?}` → `[{id, path, score}]`
+//! - `shutdown`
+//!
+//! A failed call has error code -32000 and the kind of the error in `data.kind`
+//! (`wrong_password`, `not_found`, `name_exists`, ...).
+
+use crate::i18n;
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use vault_core::container::{self, Session};
+use vault_core::{crypto, fsmeta, VaultError};
+use zeroize::{Zeroize, Zeroizing};
+
+// longest request line read; a longer one closes the connection
+const MAX_LINE: u64 = 1024 * 1024;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const FAILED: i64 = -32000;
+
+struct Open {
+    sess: Session,
+    // needed again for every write
+    password: Zeroizing<String>,
+}
+
+#[derive(Default)]
+struct Daemon {
+    sessions: Mutex<HashMap<u64, Arc<Mutex<Open>>>>,
+    next_id: AtomicU64,
+}
+
+/// A failed call: a JSON-RPC error code, or an error of an operation (`FAILED`).
+struct CallError {
+    code: i64,
+    error: anyhow::Error,
+}
+
+impl<E: Into<anyhow::Error>> From<E> for CallError {
+    fn from(e: E) -> Self {
+        CallError { code: FAILED, error: e.into() }
+    }
+}
+
+fn invalid_params(msg: &str) -> CallError {
+    CallError {
+        code: INVALID_PARAMS,
+        error: anyhow::anyhow!("{msg}"),
+    }
+}
+
+/// Where the daemon listens if no `--socket` is given.
+pub fn default_socket() -> String {
+    local::default_address()
+}
+
+/// Listens on `socket` until a `shutdown` call; fails if another daemon has it.
+pub fn run(socket: &str) -> anyhow::Result<()> {
+    let listener = local::listen(socket)?;
+    println!("vault daemon listening on {socket}");
+    let daemon = Arc::new(Daemon::default());
+    loop {
+        let stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept: {e}");
+                continue;
+            }
+        };
+        let daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || {
+            if let Err(e) = daemon.connection(stream) {
+                eprintln!("connection: {e}");
+            }
+        });
+    }
+}
+
+impl Daemon {
+    fn connection(&self, stream: local::Stream) -> std::io::Result<()> {
+        let mut out = stream.try_clone()?;
+        let mut src = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            match (&mut src).take(MAX_LINE).read_line(&mut line)? {
+                0 => return Ok(()),
+                _ if !line.ends_with('\n') && line.len() as u64 == MAX_LINE => return Ok(()),
+                _ if line.trim().is_empty() => continue,
+                _ => {}
+            }
+            let (response, shutdown) = self.respond(line.trim());
+            // the line of an `open` has the password in it
+            line.zeroize();
+            writeln!(out, "{response}")?;
+            out.flush()?;
+            if shutdown {
+                local::cleanup();
+                std::process::exit(0);
+            }
+        }
+    }
+
+    /// The response to one request line, and whether it asked the daemon to stop.
+    fn respond(&self, line: &str) -> (Value, bool) {
+        let req = match serde_json::from_str::<Value>(line) {
+            Ok(req) => req,
+            Err(e) => return (error_response(Value::Null, PARSE_ERROR, &e.to_string(), None), false),
+        };
+        let id = req.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = req.get("method").and_then(Value::as_str) else {
+            return (error_response(id, INVALID_REQUEST, "no method", None), false);
+        };
+        let params = req.get("params").cloned().unwrap_or_else(|| json!({}));
+        let res = match method {
+            "open" => self.open(&params),
+            "close" => self.close(&params),
+            "sessions" => Ok(self.list_sessions()),
+            "ls" => self.ls(&params),
+            "import" => self.import(&params),
+            "export" => self.export(&params),
+            "search" => self.search(&params),
+            "shutdown" => Ok(Value::Null),
+            _ => Err(CallError {
+                code: METHOD_NOT_FOUND,
+                error: anyhow::anyhow!("no method {method:?}"),
+            }),
+        };
+        let shutdown = method == "shutdown";
+        let response = match res {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(CallError { code, error }) => {
+                let kind = error.downcast_ref::<VaultError>().map(|e| kind_of(e.root()));
+                error_response(id, code, &i18n::error(&error), kind)
+            }
+        };
+        (response, shutdown)
+    }
+
+    fn session(&self, params: &Value) -> Result<Arc<Mutex<Open>>, CallError> {
+        let id = params.get("session").and_then(Value::as_u64).ok_or_else(|| invalid_params("no session"))?;
+        let sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+        sessions.get(&id).cloned().ok_or_else(|| invalid_params("no such session"))
+    }
+
+    fn open(&self, params: &Value) -> Result<Value, CallError> {
+        let path = str_param(params, "path")?;
+        let password = str_param(params, "password")?;
+        let read_only = params.get("read_only").and_then(Value::as_bool).unwrap_or(false);
+        let keyfile = params.get("keyfile").and_then(Value::as_str).map(crypto::keyfile_digest).transpose()?;
+        let sess = match read_only {
+            true => container::open_vault_readonly(path, password, keyfile.as_deref())?,
+            false => container::open_vault(path, password, keyfile.as_deref())?,
+        };
+        let generation = sess.meta.generation;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let open = Open {
+            sess,
+            password: Zeroizing::new(password.to_string()),
+        };
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner).insert(id, Arc::new(Mutex::new(open)));
+        Ok(json!({"session": id, "generation": generation}))
+    }
+
+    fn close(&self, params: &Value) -> Result<Value, CallError> {
+        let id = params.get("session").and_then(Value::as_u64).ok_or_else(|| invalid_params("no session"))?;
+        // The session is dropped, and the vault unlocked, once a call still using it ends.
+        match self.sessions.lock().unwrap_or_else(PoisonError::into_inner).remove(&id) {
+            Some(_) => Ok(Value::Null),
+            None => Err(invalid_params("no such session")),
+        }
+    }
+
+    fn list_sessions(&self) -> Value {
+        let sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut ids: Vec<&u64> = sessions.keys().collect();
+        ids.sort();
+        let list = ids.into_iter().map(|id| {
+            let open = lock(&sessions[id]);
+            json!({"session": id, "path": open.sess.path, "read_only": open.sess.read_only})
+        });
+        Value::Array(list.collect())
+    }
+
+    fn ls(&self, params: &Value) -> Result<Value, CallError> {
+        let open = self.session(params)?;
+        let open = lock(&open);
+        let meta = &open.sess.meta;
+        let dir_id = params.get("dir_id").and_then(Value::as_u64).unwrap_or(meta.root_id);
+        match meta.get_node(dir_id) {
+            Some(n) if n.node_type == fsmeta::NodeType::Dir => {}
+            Some(_) => return Err(VaultError::NotADirectory.into()),
+            None => return Err(VaultError::NotFound.into()),
+        }
+        let nodes = meta.children_of(dir_id).into_iter().map(|n| {
+            let kind = match n.node_type {
+                fsmeta::NodeType::Dir => "dir",
+                fsmeta::NodeType::File => "file",
+            };
+            json!({
+                "id": n.id,
+                "name": n.name,
+                "type": kind,
+                "size": n.size,
+                "modified": n.modified_at,
+                "mime": n.mime,
+            })
+        });
+        Ok(Value::Array(nodes.collect()))
+    }
+
+    fn import(&self, params: &Value) -> Result<Value, CallError> {
+        let os_path = PathBuf::from(str_param(params, "os_path")?);
+        let name = params.get("name").and_then(Value::as_str).map(str::to_string);
+        let compress = params.get("compress").and_then(Value::as_bool).unwrap_or(false);
+        let open = self.session(params)?;
+        let mut open = lock(&open);
+        let Open { sess, password } = &mut *open;
+        let parent_id = params.get("parent_id").and_then(Value::as_u64).unwrap_or(sess.meta.root_id);
+        let id = container::import_file(sess, password, &os_path, parent_id, name, compress, &mut ())
+            .with_context(|| format!("import {}", os_path.display()))?;
+        Ok(json!({"id": id}))
+    }
+
+    fn export(&self, params: &Value) -> Result<Value, CallError> {
+        let file_id = params.get("file_id").and_then(Value::as_u64).ok_or_else(|| invalid_params("no file_id"))?;
+        let os_path = PathBuf::from(str_param(params, "os_path")?);
+        let open = self.session(params)?;
+        let open = lock(&open);
+        container::export_file(&open.sess, file_id, &os_path, &mut ())
+            .with_context(|| format!("export id={file_id} -> {}", os_path.display()))?;
+        Ok(Value::Null)
+    }
+
+    fn search(&self, params: &Value) -> Result<Value, CallError> {
+        let query = str_param(params, "query")?;
+        let limit = params.get("limit").and_then(Value::as_u64).unwrap_or(20) as usize;
+        let open = self.session(params)?;
+        let open = lock(&open);
+        let meta = &open.sess.meta;
+        let Some(fts) = &meta.fts else {
+            return Err(anyhow::anyhow!("no search index; enable it with `search-index --enabled true`").into());
+        };
+        let hits = fts.search(query).into_iter().filter(|(id, _)| !meta.is_in_trash(*id)).take(limit);
+        let hits = hits.map(|(id, score)| json!({"id": id, "path": meta.path_of(id), "score": score}));
+        Ok(Value::Array(hits.collect()))
+    }
+}
+
+fn lock(open: &Mutex<Open>) -> MutexGuard<'_, Open> {
+    // A call that panicked left its session as it was before the change.
+    open.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, CallError> {
+    params.get(name).and_then(Value::as_str).ok_or_else(|| invalid_params(&format!("no {name}")))
+}
+
+fn error_response(id: Value, code: i64, message: &str, kind: Option<&str>) -> Value {
+    let mut error = json!({"code": code, "message": message});
+    if let Some(kind) = kind {
+        error["data"] = json!({"kind": kind});
+    }
+    json!({"jsonrpc": "2.0", "id": id, "error": error})
+}
+
+/// The name of a kind of error for `data.kind`, stable for programs to match on.
+fn kind_of(e: &VaultError) -> &'static str {
+    match e {
+        VaultError::WrongPassword { .. } => "wrong_password",
+        VaultError::Corrupted(_) => "corrupted",
+        VaultError::Unsupported(_) => "unsupported",
+        VaultError::NotFound => "not_found",
+        VaultError::NameExists(_) => "name_exists",
+        VaultError::NotADirectory => "not_a_directory",
+        VaultError::NotAFile => "not_a_file",
+        VaultError::ReadOnly => "read_only",
+        VaultError::Locked => "locked",
+        VaultError::Cancelled => "cancelled",
+        VaultError::Invalid(_) => "invalid",
+        VaultError::Device(_) => "device",
+        VaultError::Io(_) => "io",
+        _ => "other",
+    }
+}
+
+#[cfg(unix)]
+mod local {
+    use anyhow::Context;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+
+    pub type Stream = UnixStream;
+
+    // the socket file, removed on shutdown
+    static SOCKET: OnceLock<String> = OnceLock::new();
+
+    pub struct Listener(UnixListener);
+
+    impl Listener {
+        pub fn accept(&self) -> std::io::Result<Stream> {
+            self.0.accept().map(|(stream, _)| stream)
+        }
+    }
+
+    /// `vault.sock` in `$XDG_RUNTIME_DIR` (private to the user), else in `vault-<uid>`
+    /// in the temp directory, which `listen` keeps private.
+    pub fn default_address() -> String {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(fallback_dir);
+        dir.join("vault.sock").to_string_lossy().to_string()
+    }
+
+    fn fallback_dir() -> PathBuf {
+        // SAFETY: geteuid can't fail and touches no memory.
+        std::env::temp_dir().join(format!("vault-{}", unsafe { libc::geteuid() }))
+    }
+
+    /// Creates `dir` with mode 0700, or checks that the one there is a directory of
+    /// this user that no one else can enter: the temp directory is shared, so someone
+    /// else may have made it first to swap the socket.
+    fn private_dir(dir: &Path) -> anyhow::Result<()> {
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e).with_context(|| format!("create {}", dir.display())),
+        }
+        let meta = std::fs::symlink_metadata(dir).with_context(|| dir.display().to_string())?;
+        // SAFETY: as in `fallback_dir`.
+        let private = meta.is_dir() && meta.uid() == unsafe { libc::geteuid() } && meta.mode() & 0o077 == 0;
+        if !private {
+            anyhow::bail!("{} isn't a private directory of this user (mode 0700)", dir.display());
+        }
+        Ok(())
+    }
+
+    pub fn listen(path: &str) -> anyhow::Result<Listener> {
+        let fallback = fallback_dir();
+        if Path::new(path).parent() == Some(fallback.as_path()) {
+            private_dir(&fallback)?;
+        }
+        if Path::new(path).exists() {
+            if UnixStream::connect(path).is_ok() {
+                anyhow::bail!("a daemon is already listening on {path}");
+            }
+            // left behind by a daemon that didn't stop with `shutdown`
+            std::fs::remove_file(path).with_context(|| format!("remove {path}"))?;
+        }
+        // Created without permissions for others, so no one else can connect between
+        // bind and chmod. Nothing else runs yet to be affected by the umask.
+        // SAFETY: umask only swaps the mask of the process.
+        let mask = unsafe { libc::umask(0o077) };
+        let bound = UnixListener::bind(path);
+        // SAFETY: as above.
+        unsafe { libc::umask(mask) };
+        let listener = bound.with_context(|| format!("listen on {path}"))?;
+        // only the user's own programs may connect
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).with_context(|| path.to_string())?;
+        let _ = SOCKET.set(path.to_string());
+        Ok(Listener(listener))
+    }
+
+    pub fn cleanup() {
+        if let Some(path) = SOCKET.get() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod local {
+    use std::fs::File;
+    use std::os::windows::io::{FromRawHandle, RawHandle};
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX};
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+        PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    // a connected pipe instance reads and writes like a file
+    pub type Stream = File;
+
+    pub struct Listener {
+        name: Vec<u16>,
+        // the instance the next client connects to
+        next: std::sync::Mutex<Option<File>>,
+    }
+
+    /// `\\.\pipe\vault-<user>`.
+    pub fn default_address() -> String {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!(r"\\.\pipe\vault-{user}")
+    }
+
+    fn create(name: &[u16], first: bool) -> std::io::Result<File> {
+        let flags = PIPE_ACCESS_DUPLEX | if first { FILE_FLAG_FIRST_PIPE_INSTANCE } else { 0 };
+        let mode = PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS;
+        // SAFETY: `name` is NUL-terminated; a null security descriptor gives the default
+        // one, which lets only the user (and administrators) write to the pipe.
+        let handle = unsafe {
+            CreateNamedPipeW(name.as_ptr(), flags, mode, PIPE_UNLIMITED_INSTANCES, 65536, 65536, 0, std::ptr::null())
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: the handle was just created and is owned by nothing else.
+        Ok(unsafe { File::from_raw_handle(handle as RawHandle) })
+    }
+
+    pub fn listen(path: &str) -> anyhow::Result<Listener> {
+        let name: Vec<u16> = path.encode_utf16().chain([0]).collect();
+        // Fails if the first instance already exists: another daemon has the name.
+        let first = create(&name, true).map_err(|e| anyhow::anyhow!("listen on {path}: {e}"))?;
+        Ok(Listener {
+            name,
+            next: std::sync::Mutex::new(Some(first)),
+        })
+    }
+
+    impl Listener {
+        pub fn accept(&self) -> std::io::Result<Stream> {
+            let pipe = match self.next.lock().unwrap_or_else(std::sync::PoisonError::into_inner).take() {
+                Some(pipe) => pipe,
+                None => create(&self.name, false)?,
+            };
+            use std::os::windows::io::AsRawHandle;
+            // SAFETY: `pipe` is a pipe instance this process created; no OVERLAPPED, so
+            // the call waits for a client.
+            let connected = unsafe { ConnectNamedPipe(pipe.as_raw_handle() as _, std::ptr::null_mut()) } != 0;
+            // a client that connected between creating and waiting is connected too
+            if !connected && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(pipe)
+        }
+    }
+
+    pub fn cleanup() {}
+}
@@ -0,0 +1,217 @@
+//! Camera data of photos (EXIF) for the GUI image viewer: the common fields from the
+//! APP1 segment of JPEG files and the `eXIf` chunk of PNG files. Read only; the data
+//! stays as imported, including a GPS position if the camera recorded one.
+
+#[derive(Debug, Default, Clone)]
+pub struct Exif {
+    /// Maker and model.
+    pub camera: Option<String>,
+    /// When the photo was taken, as the camera's clock showed it ("2024-05-01 13:45:10").
+    pub taken: Option<String>,
+    /// "1/125 s".
+    pub exposure: Option<String>,
+    /// "f/2.8".
+    pub aperture: Option<String>,
+    pub iso: Option<u32>,
+    /// "4.2 mm".
+    pub focal_length: Option<String>,
+    pub software: Option<String>,
+    /// Latitude and longitude in degrees, negative south and west.
+    pub gps: Option<(f64, f64)>,
+    /// Clockwise quarter turns that show the photo upright (from the orientation tag;
+    /// mirrored orientations are taken as not turned).
+    pub turns: u8,
+}
+
+/// EXIF data of a JPEG or PNG file, if it has any.
+pub fn read(file: &[u8]) -> Option<Exif> {
+    let tiff = match file {
+        [0xff, 0xd8, ..] => jpeg_app1(file)?,
+        [0x89, b'P', b'N', b'G', ..] => png_exif(file)?,
+        _ => return None,
+    };
+    parse(tiff)
+}
+
+fn jpeg_app1(file: &[u8]) -> Option<&[u8]> {
+    let mut i = 2;
+    while i + 4 <= file.len() && file[i] == 0xff {
+        let marker = file[i + 1];
+        // start of the image data: no more metadata segments
+        if marker == 0xda {
+            return None;
+        }
+        let len = u16::from_be_bytes([file[i + 2], file[i + 3]]) as usize;
+        let seg = file.get(i + 4..i + 2 + len)?;
+        if marker == 0xe1 {
+            if let Some(tiff) = seg.strip_prefix(b"Exif\0\0") {
+                return Some(tiff);
+            }
+        }
+        i += 2 + len;
+    }
+    None
+}
+
+fn png_exif(file: &[u8]) -> Option<&[u8]> {
+    let mut i = 8;
+    while i + 8 <= file.len() {
+        let len = u32::from_be_bytes(file[i..i + 4].try_into().ok()?) as usize;
+        let kind = &file[i + 4..i + 8];
+        let data = file.get(i + 8..(i + 8).checked_add(len)?)?;
+        match kind {
+            b"eXIf" => return Some(data),
+            b"IEND" => return None,
+            _ => i += 12 + len,
+        }
+    }
+    None
+}
+
+/// A TIFF structure: byte order, then directories of tagged values.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+struct Entry<'a> {
+    kind: u16,
+    count: usize,
+    value: &'a [u8],
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    /// Entries of the directory at `offset`, by tag.
+    fn directory(&self, offset: usize) -> Vec<(u16, Entry<'_>)> {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        (0..count.min(512))
+            .filter_map(|i| {
+                let at = offset + 2 + i * 12;
+                let (tag, kind, count) = (self.u16(at)?, self.u16(at + 2)?, self.u32(at + 4)? as usize);
+                let size = match kind {
+                    1 | 2 | 6 | 7 => 1,
+                    3 | 8 => 2,
+                    4 | 9 | 11 => 4,
+                    5 | 10 | 12 => 8,
+                    _ => return None,
+                };
+                let len = size * count;
+                let value = match len <= 4 {
+                    true => self.data.get(at + 8..at + 8 + len)?,
+                    false => {
+                        let start = self.u32(at + 8)? as usize;
+                        self.data.get(start..start.checked_add(len)?)?
+                    }
+                };
+                Some((tag, Entry { kind, count, value }))
+            })
+            .collect()
+    }
+
+    fn text(&self, e: &Entry) -> Option<String> {
+        let s = String::from_utf8_lossy(e.value);
+        let s = s.trim_end_matches('\0').trim();
+        (e.kind == 2 && !s.is_empty()).then(|| s.to_string())
+    }
+
+    fn int(&self, e: &Entry, i: usize) -> Option<u32> {
+        let t = Tiff { data: e.value, big_endian: self.big_endian };
+        match e.kind {
+            3 if i < e.count => t.u16(i * 2).map(u32::from),
+            4 if i < e.count => t.u32(i * 4),
+            _ => None,
+        }
+    }
+
+    fn rational(&self, e: &Entry, i: usize) -> Option<(f64, f64)> {
+        let t = Tiff { data: e.value, big_endian: self.big_endian };
+        if !matches!(e.kind, 5 | 10) || i >= e.count {
+            return None;
+        }
+        let (n, d) = (t.u32(i * 8)?, t.u32(i * 8 + 4)?);
+        let (n, d) = match e.kind {
+            10 => (n as i32 as f64, d as i32 as f64),
+            _ => (n as f64, d as f64),
+        };
+        (d != 0.0).then_some((n, d))
+    }
+}
+
+fn parse(data: &[u8]) -> Option<Exif> {
+    let big_endian = match data.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let t = Tiff { data, big_endian };
+    if t.u16(2)? != 42 {
+        return None;
+    }
+    let ifd0 = t.directory(t.u32(4)? as usize);
+    let mut x = Exif::default();
+
+    let text0 = |tag| find(&ifd0, tag).and_then(|e| t.text(e));
+    x.camera = match (text0(0x010f), text0(0x0110)) {
+        (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{make} {model}")),
+        (make, model) => make.or(model),
+    };
+    x.software = text0(0x0131);
+    x.turns = match find(&ifd0, 0x0112).and_then(|e| t.int(e, 0)) {
+        Some(6) => 1,
+        Some(3) => 2,
+        Some(8) => 3,
+        _ => 0,
+    };
+    let mut taken = text0(0x0132);
+
+    if let Some(exif) = find(&ifd0, 0x8769).and_then(|e| t.int(e, 0)) {
+        let dir = t.directory(exif as usize);
+        let rational = |tag| find(&dir, tag).and_then(|e| t.rational(e, 0));
+        taken = find(&dir, 0x9003).and_then(|e| t.text(e)).or(taken);
+        x.exposure = rational(0x829a).map(|(n, d)| match n > 0.0 && d / n >= 2.0 {
+            true => format!("1/{:.0} s", d / n),
+            false => format!("{} s", trim(n / d)),
+        });
+        x.aperture = rational(0x829d).map(|(n, d)| format!("f/{}", trim(n / d)));
+        x.focal_length = rational(0x920a).map(|(n, d)| format!("{} mm", trim(n / d)));
+        x.iso = find(&dir, 0x8827).and_then(|e| t.int(e, 0));
+    }
+    x.taken = taken.map(|s| s.replacen(':', "-", 2));
+
+    if let Some(gps) = find(&ifd0, 0x8825).and_then(|e| t.int(e, 0)) {
+        let dir = t.directory(gps as usize);
+        let degrees = |reference: u16, tag: u16| {
+            let e = find(&dir, tag)?;
+            let part = |i| t.rational(e, i).map(|(n, d)| n / d);
+            let value = part(0)? + part(1).unwrap_or(0.0) / 60.0 + part(2).unwrap_or(0.0) / 3600.0;
+            let sign = match find(&dir, reference).and_then(|e| t.text(e)).as_deref() {
+                Some("S" | "W") => -1.0,
+                _ => 1.0,
+            };
+            Some(sign * value)
+        };
+        x.gps = degrees(1, 2).zip(degrees(3, 4));
+    }
+    Some(x)
+}
+
+fn find<'d, 'a>(dir: &'d [(u16, Entry<'a>)], tag: u16) -> Option<&'d Entry<'a>> {
+    dir.iter().find(|(t, _)| *t == tag).map(|(_, e)| e)
+}
+
+/// A number with at most one decimal, without a trailing ".0".
+fn trim(v: f64) -> String {
+    let s = format!("{v:.1}");
+    s.strip_suffix(".0").map(str::to_string).unwrap_or(s)
+}
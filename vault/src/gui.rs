@@ -1,20 +1,47 @@
-use crate::container;
-use crate::fsmeta::NodeType;
+use crate::audio;
+use crate::capture;
+use crate::clipboard;
+use crate::exif;
+use crate::i18n::{self, t};
+use crate::keychain;
+use crate::notify::{self, Notifier};
+use crate::pdf;
+use crate::settings::{self, Settings, Theme};
+use crate::strength;
+use crate::video;
 use eframe::egui;
 use rfd::FileDialog;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use vault_core::crypto::secmem::SecretString;
+use vault_core::fsmeta::{self, NodeType};
+use vault_core::{container, crypto, mime, recovery, VaultError};
 use zeroize::Zeroize;
 
 pub fn run() -> anyhow::Result<()> {
+    let settings = Settings::load();
+    i18n::set(settings.lang.or_else(i18n::from_env).unwrap_or(i18n::Lang::Ru));
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([1024.0, 700.0]),
+        // Выбранную тему eframe не должен менять вслед за системной.
+        follow_system_theme: settings.theme == Theme::System,
         ..Default::default()
     };
 
     eframe::run_native(
         "Vault",
         native_options,
-        Box::new(|_cc| Box::new(VaultApp::default())),
+        Box::new(|_cc| {
+            Box::new(VaultApp {
+                create_unlock_ms: 750,
+                settings,
+                ..Default::default()
+            })
+        }),
     )
     .map_err(|e| anyhow::anyhow!("gui: {e}"))?;
 
@@ -23,30 +50,411 @@ pub fn run() -> anyhow::Result<()> {
 
 #[derive(Default)]
 struct VaultApp {
+    settings: Settings,
+    show_settings: bool,
+    // время последнего ввода, для автоматического закрытия (см. `auto_lock`)
+    last_input: Option<Instant>,
+
     // locked screen
     vault_path: String,
+    // необязательный второй фактор (и при открытии, и при создании)
+    keyfile_path: String,
     password: String,
+    open_read_only: bool,
+    // открыть аппаратным токеном; поле пароля тогда — PIN токена
+    open_with_token: bool,
+    // после открытия по паролю сохранить ключ в системной связке ключей
+    remember: bool,
     create_password: String,
-    status: String,
+    create_recovery_code: bool,
+    // создать и со слабым паролем (как `init --force`)
+    create_allow_weak: bool,
+    // время разблокировки, под которое подбираются параметры Argon2id
+    create_unlock_ms: u32,
+    // код восстановления только что созданного контейнера: показывается один раз
+    shown_recovery_code: Option<String>,
+    // сообщения об операциях: всплывают на время, ошибки ещё и копятся в списке
+    notify: Notifier,
+    show_errors: bool,
+    slideshow: Option<Slideshow>,
 
     // session
+    // вкладки открытых контейнеров и показанная; её место в `tabs` пустое, а сам
+    // контейнер — в полях ниже (см. `VaultTab`)
+    tabs: Vec<VaultTab>,
+    tab: usize,
     sess: Option<container::Session>,
-    unlocked_password: String,
+    unlocked_password: SecretString,
+    // операция в рабочем потоке; сессия на это время у него
+    job: Option<Job>,
+    // перетащенные в окно файлы и папки, ждут подтверждения импорта
+    dropped: Vec<DropItem>,
 
     // navigation
     current_dir_id: u64,
     selected_id: Option<u64>,
+    // узлы, отмеченные в списке с Ctrl/Shift, для пакетных операций; `selected_id` —
+    // последний щелчок (от него отсчитывается диапазон Shift)
+    selection: BTreeSet<u64>,
+    // что показывается в списке: папка или узлы из любых папок
+    listing: Listing,
+    // выбранное в контекстном меню узла или клавишей; выполняется как кнопка панели
+    menu_action: Option<(u64, MenuAction)>,
+    // окно подтверждения удаления по клавише Delete и его итог
+    confirm_delete: bool,
+    delete_confirmed: bool,
+    confirm_export: Option<ExportMany>,
+    // клавиши: прокрутить список к выбранному узлу, перейти в поле поиска
+    scroll_to_selected: bool,
+    focus_search: bool,
+    // раскрыть «Сведения» (пункт «Свойства» меню)
+    show_props: bool,
+    search_box: String,
+    // сетка миниатюр вместо списка; текстуры по (id файла, смещение миниатюры),
+    // None — миниатюра не загрузилась
+    grid: bool,
+    thumbs: HashMap<(u64, u64), Option<egui::TextureHandle>>,
+    // строка состояния: (папка, поколение метаданных) и текст, пересчитывается при изменениях
+    stats_line: Option<((u64, u64), String)>,
+    // узлы списка по (поколению метаданных, порядку, папке, виду списка), см. `rows`
+    rows: Option<(RowsKey, Rc<Vec<u64>>)>,
+    // окно дубликатов: открыто ли и группы (id, путь) по поколению метаданных
+    show_dupes: bool,
+    dupes: Option<(u64, Vec<DupeGroup>)>,
+    // окно «Место» и его цифры по поколению метаданных
+    show_usage: bool,
+    usage: Option<(u64, Result<container::Usage, String>)>,
 
     // actions
     new_folder_name: String,
-    rename_to: String,
+    // узел, имя которого правится прямо в списке, и новое имя
+    renaming: Option<(u64, String)>,
+    // прошлый щелчок по имени: второй неспешный щелчок по выбранному файлу
+    // начинает переименование
+    last_click: Option<(u64, Instant)>,
+    new_tag: String,
+    batch_tag: String,
+    move_to: Option<u64>,
+    // перетаскивание: узлы и папка, куда их бросили
+    drop_move: Option<(Vec<u64>, u64)>,
+    // узлы для вставки в другой контейнер: путь их контейнера и id
+    copied: Option<(String, Vec<u64>)>,
+    clipboard: clipboard::Clipboard,
+    // применённые тема, масштаб и тема системы
+    applied_style: Option<(Theme, u32, Option<eframe::Theme>)>,
+    // окно исключено из захвата экрана (по `settings.hide_content`)
+    applied_capture: Option<bool>,
+    compress_imports: bool,
+    shred_on_delete: bool,
+    token_pin: String,
+    // окно смены пароля: текущий, новый и его повтор
+    show_change_password: bool,
+    cp_old: String,
+    cp_new: String,
+    cp_repeat: String,
+    cp_allow_weak: bool,
 
     // viewer
+    viewer_id: Option<u64>,
+    viewer_bytes: Option<Vec<u8>>,
+    viewer_mode: ViewerMode,
+    viewer_text: String,
+    // текст можно править; `viewer_bytes` — сохранённое содержимое
+    viewer_editing: bool,
+    confirm_discard: Option<Discard>,
+    // Markdown показывается оформленным, пока не выбран исходный текст
+    viewer_markdown: bool,
+    viewer_source: bool,
+    markdown: egui_commonmark::CommonMarkCache,
+    viewer_error: String,
+    viewer_texture: Option<egui::TextureHandle>,
+    // PDF: номер показанной страницы с 0; масштаб None — по размеру окна
+    viewer_pdf: Option<pdf::Document>,
+    viewer_page: usize,
+    // звук и видео играют, пока открыты; на другой вкладке встают на паузу
+    viewer_audio: Option<audio::Player>,
+    viewer_video: Option<video::Player>,
+    viewer_zoom: Option<f32>,
+    // изображение: поворот четвертями по часовой и сдвиг от центра; EXIF
+    // показывается поверх, пока включён
+    viewer_turns: u8,
+    viewer_pan: egui::Vec2,
+    viewer_exif: Option<exif::Exif>,
+    show_exif: bool,
+    viewer_hex: HexView,
+}
+
+/// Контейнер в фоновой вкладке: его сессия и всё, что окно помнит о нём (папка,
+/// отметки, просмотр с несохранёнными правками). Показанный контейнер живёт в полях
+/// `VaultApp`; при переключении вкладок они меняются местами (`swap_tab`).
+#[derive(Default)]
+struct VaultTab {
+    // название, пока вкладка в фоне (см. `tab_title`)
+    title: String,
+    sess: Option<container::Session>,
+    unlocked_password: SecretString,
+    current_dir_id: u64,
+    selected_id: Option<u64>,
+    selection: BTreeSet<u64>,
+    listing: Listing,
+    search_box: String,
+    thumbs: HashMap<(u64, u64), Option<egui::TextureHandle>>,
+    stats_line: Option<((u64, u64), String)>,
+    rows: Option<(RowsKey, Rc<Vec<u64>>)>,
+    show_dupes: bool,
+    dupes: Option<(u64, Vec<DupeGroup>)>,
+    show_usage: bool,
+    usage: Option<(u64, Result<container::Usage, String>)>,
+    renaming: Option<(u64, String)>,
+    new_tag: String,
+    move_to: Option<u64>,
+    viewer_id: Option<u64>,
     viewer_bytes: Option<Vec<u8>>,
     viewer_mode: ViewerMode,
     viewer_text: String,
+    viewer_editing: bool,
+    viewer_markdown: bool,
+    viewer_source: bool,
     viewer_error: String,
     viewer_texture: Option<egui::TextureHandle>,
+    viewer_pdf: Option<pdf::Document>,
+    viewer_page: usize,
+    viewer_audio: Option<audio::Player>,
+    viewer_video: Option<video::Player>,
+    viewer_zoom: Option<f32>,
+    viewer_turns: u8,
+    viewer_pan: egui::Vec2,
+    viewer_exif: Option<exif::Exif>,
+    viewer_hex: HexView,
+}
+
+/// Шестнадцатеричный просмотр: в памяти только показанная страница, она читается
+/// из контейнера по смещению.
+#[derive(Default)]
+struct HexView {
+    size: u64,
+    // смещение начала страницы и её байты
+    page: u64,
+    data: Vec<u8>,
+    goto: String,
+    // байт, найденный «Перейти», и прокрутка к его строке
+    mark: Option<u64>,
+    scroll: bool,
+}
+
+/// Слайд-шоу: изображения списка во всё окно. Они расшифровываются и раскодируются
+/// в своём потоке — текущее и соседние заранее, так что листание не ждёт.
+struct Slideshow {
+    images: Vec<u64>,
+    index: usize,
+    // показанное изображение или ошибка его загрузки
+    shown: Option<(u64, Result<egui::TextureHandle, String>)>,
+    shown_at: Instant,
+    autoplay: bool,
+    interval_sec: u32,
+    // готовые, но ещё не показанные, и отданные потоку
+    ready: HashMap<u64, anyhow::Result<egui::ColorImage>>,
+    requested: HashSet<u64>,
+    tx: mpsc::Sender<(u64, container::VaultFileReader)>,
+    rx: mpsc::Receiver<(u64, anyhow::Result<egui::ColorImage>)>,
+}
+
+impl Slideshow {
+    /// Поток загрузки живёт, пока живёт слайд-шоу: с ним закрывается и канал запросов.
+    fn new(ctx: &egui::Context, images: Vec<u64>, index: usize) -> Self {
+        let (tx, requests) = mpsc::channel::<(u64, container::VaultFileReader)>();
+        let (done, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            for (id, reader) in requests {
+                if done.send((id, decode_slide(reader))).is_err() {
+                    break;
+                }
+                ctx.request_repaint();
+            }
+        });
+        Slideshow {
+            images,
+            index,
+            shown: None,
+            shown_at: Instant::now(),
+            autoplay: false,
+            interval_sec: 5,
+            ready: HashMap::new(),
+            requested: HashSet::new(),
+            tx,
+            rx,
+        }
+    }
+
+    /// Показываемое изображение и соседние с ним: они и загружаются.
+    fn window(&self) -> [u64; 3] {
+        let n = self.images.len();
+        [self.index, (self.index + 1) % n, (self.index + n - 1) % n].map(|i| self.images[i])
+    }
+
+    /// Отдаёт потоку изображение, если оно ещё не загружено и не загружается.
+    fn request(&mut self, sess: &container::Session, id: u64) {
+        if self.ready.contains_key(&id) || self.requested.contains(&id) || self.shown.as_ref().is_some_and(|(s, _)| *s == id) {
+            return;
+        }
+        let size = sess.meta.get_node(id).map_or(0, |n| n.size);
+        let reader = match size > MAX_VIEW_BYTES {
+            true => Err(anyhow::anyhow!(t!("Файл больше {}", size_text(MAX_VIEW_BYTES)))),
+            false => container::VaultFileReader::open(sess, id).map_err(anyhow::Error::from),
+        };
+        match reader {
+            Ok(reader) => {
+                if self.tx.send((id, reader)).is_ok() {
+                    self.requested.insert(id);
+                }
+            }
+            Err(e) => {
+                self.ready.insert(id, Err(e));
+            }
+        }
+    }
+
+    fn step(&mut self, forward: bool) {
+        let n = self.images.len();
+        self.index = match forward {
+            true => (self.index + 1) % n,
+            false => (self.index + n - 1) % n,
+        };
+    }
+}
+
+/// Операция над контейнером в рабочем потоке, чтобы окно не замирало на больших
+/// файлах. Поток получает сессию и возвращает её вместе с результатом.
+struct Job {
+    label: String,
+    // ход, если операция его сообщает; тогда её можно и отменить
+    progress: Option<JobProgress>,
+    cancel: Arc<AtomicBool>,
+    rx: mpsc::Receiver<JobEvent>,
+}
+
+/// Ход импорта или экспорта: байты всех файлов и файл, который копируется сейчас.
+#[derive(Clone, Default)]
+struct JobProgress {
+    file: String,
+    done: u64,
+    total: u64,
+}
+
+enum JobEvent {
+    Progress(JobProgress),
+    Done(Box<container::Session>, anyhow::Result<JobDone>),
+}
+
+/// Итог операции: сообщение о нём (если есть) и что поменять в окне.
+struct JobDone {
+    note: Option<(notify::Level, String)>,
+    apply: Box<dyn FnOnce(&mut VaultApp) + Send>,
+}
+
+impl JobDone {
+    fn new(note: Option<(notify::Level, String)>) -> Self {
+        JobDone {
+            note,
+            apply: Box::new(|_| {}),
+        }
+    }
+
+    fn info(text: impl Into<String>) -> Self {
+        Self::new(Some((notify::Level::Info, text.into())))
+    }
+
+    fn error(text: impl Into<String>) -> Self {
+        Self::new(Some((notify::Level::Error, text.into())))
+    }
+
+    fn then(mut self, apply: impl FnOnce(&mut VaultApp) + Send + 'static) -> Self {
+        self.apply = Box::new(apply);
+        self
+    }
+}
+
+/// Передаёт окну ход операции из рабочего потока и говорит ей, что её отменили.
+struct Reporter {
+    progress: JobProgress,
+    sent: Option<Instant>,
+    cancel: Arc<AtomicBool>,
+    tx: mpsc::Sender<JobEvent>,
+    ctx: egui::Context,
+}
+
+impl Reporter {
+    /// Сколько байт скопирует вся операция.
+    fn total(&mut self, bytes: u64) {
+        self.progress.total = bytes;
+    }
+
+    fn send(&mut self) {
+        let _ = self.tx.send(JobEvent::Progress(self.progress.clone()));
+        self.ctx.request_repaint();
+        self.sent = Some(Instant::now());
+    }
+}
+
+impl container::Progress for Reporter {
+    fn file(&mut self, path: &std::path::Path, _: u64) {
+        self.progress.file = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        self.send();
+    }
+
+    fn advance(&mut self, bytes: u64) -> bool {
+        self.progress.done += bytes;
+        // Окну хватит нескольких обновлений в секунду.
+        if self.sent.is_none_or(|at| at.elapsed() >= Duration::from_millis(50)) {
+            self.send();
+        }
+        !self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+/// Перетащенный файл или папка: сколько в нём файлов и байт.
+struct DropItem {
+    path: PathBuf,
+    is_dir: bool,
+    files: usize,
+    bytes: u64,
+}
+
+/// Группа одинаковых файлов для окна «Дубликаты».
+struct DupeGroup {
+    size: u64,
+    extra: u64,
+    files: Vec<(u64, String)>,
+}
+
+/// Содержимое списка узлов. Кроме папки — узлы из любых папок, с путями от корня.
+#[derive(Default, Clone, PartialEq, Eq)]
+enum Listing {
+    #[default]
+    Dir,
+    Tag(String),
+    // результаты поиска по тексту, самые подходящие первыми
+    Search(String),
+    Favorites,
+}
+
+type RowsKey = (u64, fsmeta::SortOrder, u64, Listing);
+
+/// Перетаскиваемые узлы списка.
+struct DraggedNodes(Vec<u64>);
+
+/// Пункты контекстного меню узла списка.
+#[derive(Clone, Copy)]
+enum MenuAction {
+    Open,
+    View,
+    Export,
+    Rename,
+    Move(u64),
+    Copy,
+    Delete,
+    Properties,
 }
 
 #[derive(Default, Clone, Copy, PartialEq, Eq)]
@@ -56,456 +464,3731 @@ enum ViewerMode {
     Text,
     Hex,
     Image,
+    Pdf,
+    Audio,
+    Video,
+}
+
+/// Экспорт отмеченных файлов в папку, где часть их имён уже занята: ждёт ответа,
+/// заменять ли.
+struct ExportMany {
+    files: Vec<(u64, String)>,
+    dir: PathBuf,
+    existing: Vec<String>,
+}
+
+/// Что сделать после подтверждения отказа от несохранённых правок текста.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Discard {
+    // показать выбранный файл вместо редактируемого
+    View,
+    // вернуть сохранённый текст
+    Revert,
+    // закрыть контейнер
+    Lock,
 }
 
 impl VaultApp {
     fn lock(&mut self) {
         self.sess = None;
         self.selected_id = None;
+        self.selection.clear();
         self.current_dir_id = 1;
+        self.listing = Listing::Dir;
+        self.menu_action = None;
+        self.confirm_delete = false;
+        self.delete_confirmed = false;
+        self.confirm_export = None;
+        self.thumbs.clear();
+        self.stats_line = None;
+        self.rows = None;
+        self.show_dupes = false;
+        self.dupes = None;
+        self.show_usage = false;
+        self.usage = None;
+        self.dropped.clear();
+        self.drop_move = None;
+        self.clipboard.clear();
+        self.clear_viewer();
+        self.slideshow = None;
+        self.notify.clear_toasts();
 
-        self.viewer_bytes = None;
-        self.viewer_text.clear();
-        self.viewer_error.clear();
-        self.viewer_texture = None;
-        self.viewer_mode = ViewerMode::None;
+        self.unlocked_password = SecretString::default();
+        self.token_pin.zeroize();
+        self.close_change_password();
 
-        self.unlocked_password.zeroize();
+        // Вкладка закрывается вместе с контейнером, если есть другие; в ошибках бывают
+        // имена файлов, так что с последним контейнером забываются и они.
+        if self.tabs.len() > 1 {
+            self.tabs.remove(self.tab);
+            self.tab = self.tab.min(self.tabs.len() - 1);
+            let mut tab = std::mem::take(&mut self.tabs[self.tab]);
+            self.swap_tab(&mut tab);
+        } else {
+            self.notify.clear_errors();
+            self.show_errors = false;
+        }
     }
 
-    fn selected_node_name(&self) -> String {
-        let Some(sess) = &self.sess else {
-            return String::new();
-        };
-        let Some(id) = self.selected_id else {
-            return String::new();
-        };
-        sess.meta
-            .get_node(id)
-            .map(|n| n.name.clone())
-            .unwrap_or_default()
+    /// Закрывает контейнеры во всех вкладках.
+    fn lock_all(&mut self) {
+        while self.sess.is_some() || self.tabs.len() > 1 {
+            self.lock();
+        }
     }
 
-    fn open_vault_action(&mut self) {
-        self.status.clear();
-        match container::open_vault(&self.vault_path, &self.password) {
-            Ok(sess) => {
-                self.current_dir_id = sess.meta.root_id;
-                self.selected_id = Some(sess.meta.root_id);
-                self.sess = Some(sess);
-
-                self.unlocked_password = self.password.clone();
-                self.password.zeroize();
-            }
-            Err(e) => self.status = format!("Не удалось открыть: {e}"),
-        }
+    /// Меняет местами показанный контейнер и `tab`.
+    fn swap_tab(&mut self, tab: &mut VaultTab) {
+        use std::mem::swap;
+        swap(&mut self.sess, &mut tab.sess);
+        swap(&mut self.unlocked_password, &mut tab.unlocked_password);
+        swap(&mut self.current_dir_id, &mut tab.current_dir_id);
+        swap(&mut self.selected_id, &mut tab.selected_id);
+        swap(&mut self.selection, &mut tab.selection);
+        swap(&mut self.listing, &mut tab.listing);
+        swap(&mut self.search_box, &mut tab.search_box);
+        swap(&mut self.thumbs, &mut tab.thumbs);
+        swap(&mut self.stats_line, &mut tab.stats_line);
+        swap(&mut self.rows, &mut tab.rows);
+        swap(&mut self.show_dupes, &mut tab.show_dupes);
+        swap(&mut self.dupes, &mut tab.dupes);
+        swap(&mut self.show_usage, &mut tab.show_usage);
+        swap(&mut self.usage, &mut tab.usage);
+        swap(&mut self.renaming, &mut tab.renaming);
+        swap(&mut self.new_tag, &mut tab.new_tag);
+        swap(&mut self.move_to, &mut tab.move_to);
+        swap(&mut self.viewer_id, &mut tab.viewer_id);
+        swap(&mut self.viewer_bytes, &mut tab.viewer_bytes);
+        swap(&mut self.viewer_mode, &mut tab.viewer_mode);
+        swap(&mut self.viewer_text, &mut tab.viewer_text);
+        swap(&mut self.viewer_editing, &mut tab.viewer_editing);
+        swap(&mut self.viewer_markdown, &mut tab.viewer_markdown);
+        swap(&mut self.viewer_source, &mut tab.viewer_source);
+        swap(&mut self.viewer_error, &mut tab.viewer_error);
+        swap(&mut self.viewer_texture, &mut tab.viewer_texture);
+        swap(&mut self.viewer_pdf, &mut tab.viewer_pdf);
+        swap(&mut self.viewer_page, &mut tab.viewer_page);
+        swap(&mut self.viewer_audio, &mut tab.viewer_audio);
+        swap(&mut self.viewer_video, &mut tab.viewer_video);
+        swap(&mut self.viewer_zoom, &mut tab.viewer_zoom);
+        swap(&mut self.viewer_turns, &mut tab.viewer_turns);
+        swap(&mut self.viewer_pan, &mut tab.viewer_pan);
+        swap(&mut self.viewer_exif, &mut tab.viewer_exif);
+        swap(&mut self.viewer_hex, &mut tab.viewer_hex);
     }
 
-    fn create_vault_action(&mut self) {
-        self.status.clear();
-        if self.vault_path.trim().is_empty() {
-            self.status = "Укажите путь к vault.dat".to_string();
+    /// Показывает вкладку `i`. Открытые окна вопросов относятся к прежней и
+    /// закрываются; пока идёт операция, вкладка не меняется.
+    fn switch_tab(&mut self, i: usize) {
+        if i == self.tab || i >= self.tabs.len() || self.job.is_some() {
             return;
         }
-        if self.create_password.is_empty() {
-            self.status = "Задайте пароль".to_string();
-            return;
+        let mut tabs = std::mem::take(&mut self.tabs);
+        self.swap_tab(&mut tabs[self.tab]);
+        if let Some(player) = &tabs[self.tab].viewer_audio {
+            player.pause();
         }
-
-        match container::create_vault(&self.vault_path, &self.create_password, 131072, 3) {
-            Ok(()) => self.status = "Создано. Теперь нажмите Открыть".to_string(),
-            Err(e) => self.status = format!("Не удалось создать: {e}"),
+        if let Some(player) = &mut tabs[self.tab].viewer_video {
+            let _ = player.pause();
         }
+        tabs[self.tab].title = vault_title(tabs[self.tab].sess.as_ref());
+        self.swap_tab(&mut tabs[i]);
+        self.tabs = tabs;
+        self.tab = i;
+        self.menu_action = None;
+        self.confirm_delete = false;
+        self.delete_confirmed = false;
+        self.confirm_export = None;
+        self.confirm_discard = None;
+        self.drop_move = None;
+        self.dropped.clear();
+        self.close_change_password();
+        self.notify.clear_toasts();
     }
 
-    fn render_dir_tree(&mut self, ui: &mut egui::Ui, parent_id: u64) {
-        // Важно: не держим borrow на self.sess во время рекурсивного вызова.
-        let dirs: Vec<(u64, String)> = match self.sess.as_ref() {
-            Some(sess) => sess
-                .meta
-                .children_of(parent_id)
-                .into_iter()
-                .filter(|n| n.node_type == NodeType::Dir)
-                .map(|n| (n.id, n.name.clone()))
-                .collect(),
-            None => return,
-        };
-
-        for (dir_id, dir_name) in dirs {
-            let label = if self.current_dir_id == dir_id {
-                format!("📁 {}", dir_name)
-            } else {
-                dir_name
-            };
+    /// Новая вкладка с окном открытия контейнера; открытый остаётся в своей.
+    fn new_tab(&mut self) {
+        if self.tabs.is_empty() {
+            self.tabs.push(VaultTab::default());
+        }
+        self.tabs.push(VaultTab::default());
+        self.switch_tab(self.tabs.len() - 1);
+    }
 
-            egui::CollapsingHeader::new(label)
-                .default_open(false)
-                .show(ui, |ui| {
-                    ui.horizontal(|ui| {
-                        if ui.button("Открыть").clicked() {
-                            self.current_dir_id = dir_id;
-                            self.selected_id = Some(dir_id);
-                        }
-                    });
-                    self.render_dir_tree(ui, dir_id);
-                });
+    /// Название вкладки `i`. У фоновой оно запомнено: её сессия может быть на время
+    /// у операции (см. `paste_nodes`).
+    fn tab_title(&self, i: usize) -> String {
+        match i == self.tab {
+            true => vault_title(self.sess.as_ref()),
+            false => self.tabs[i].title.clone(),
         }
     }
 
-    fn load_viewer(&mut self, ctx: &egui::Context) {
-        self.viewer_bytes = None;
-        self.viewer_text.clear();
-        self.viewer_error.clear();
-        self.viewer_texture = None;
-        self.viewer_mode = ViewerMode::None;
+    /// Узлы, скопированные кнопкой «Копировать», если их можно вставить сюда: они из
+    /// другого контейнера, и он ещё открыт.
+    fn can_paste(&self) -> bool {
+        let (Some(sess), Some((path, _))) = (&self.sess, &self.copied) else {
+            return false;
+        };
+        &sess.path != path && self.tabs.iter().any(|t| t.sess.as_ref().is_some_and(|s| &s.path == path))
+    }
 
-        let Some(sess) = &self.sess else {
+    /// Вставляет в текущую папку узлы, скопированные в другой вкладке, папки — с
+    /// содержимым. Файлы читаются из того контейнера, расшифровываются и шифруются
+    /// заново ключами этого, не попадая на диск; всё одной транзакцией, в рабочем
+    /// потоке, которому на это время отдаётся и сессия-источник.
+    fn paste_nodes(&mut self, ctx: &egui::Context) {
+        if !self.can_paste() || self.job.is_some() {
+            return;
+        }
+        let Some((path, ids)) = self.copied.clone() else {
             return;
         };
-        let Some(id) = self.selected_id else {
+        let Some(i) = self.tabs.iter().position(|t| t.sess.as_ref().is_some_and(|s| s.path == path)) else {
             return;
         };
-        let Some(node) = sess.meta.get_node(id) else {
+        let Some(src) = self.tabs[i].sess.take() else {
             return;
         };
-        if node.node_type != NodeType::File {
+        let total = ids
+            .iter()
+            .filter_map(|&id| src.meta.get_node(id))
+            .map(|n| match n.node_type {
+                NodeType::File => n.size,
+                NodeType::Dir => src.meta.dir_stats(n.id).map_or(0, |s| s.size),
+            })
+            .sum();
+        let dir_id = self.current_dir_id;
+        self.spawn_job(ctx, t!("Вставка"), move |dst, password, progress| {
+            progress.total(total);
+            // Ошибка тоже возвращает сессию-источник её вкладке.
+            let done = match container::copy_nodes_from(&src, dst, password, &ids, dir_id, progress) {
+                Ok(n) => JobDone::info(t!("Скопировано файлов: {}", n)),
+                Err(e) => JobDone::error(t!("вставка: {}", i18n::error(&e))),
+            };
+            Ok(done.then(move |app| {
+                if let Some(tab) = app.tabs.get_mut(i) {
+                    tab.sess = Some(src);
+                }
+            }))
+        });
+    }
+
+    /// Запускает `op` над сессией в рабочем потоке; `op` получает пароль и
+    /// `Reporter` для импорта и экспорта. Пока операция идёт, окно показывает только
+    /// её ход, и, если она его сообщает, её можно отменить.
+    fn spawn_job<F>(&mut self, ctx: &egui::Context, label: &str, op: F)
+    where
+        F: FnOnce(&mut container::Session, &str, &mut Reporter) -> anyhow::Result<JobDone> + Send + 'static,
+    {
+        if self.job.is_some() {
             return;
         }
+        let Some(mut sess) = self.sess.take() else {
+            return;
+        };
+        let password = SecretString::new(&self.unlocked_password);
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut reporter = Reporter {
+            progress: JobProgress::default(),
+            sent: None,
+            cancel: cancel.clone(),
+            tx,
+            ctx: ctx.clone(),
+        };
+        std::thread::spawn(move || {
+            let res = op(&mut sess, &password, &mut reporter);
+            let _ = reporter.tx.send(JobEvent::Done(Box::new(sess), res));
+            reporter.ctx.request_repaint();
+        });
+        self.job = Some(Job {
+            label: label.to_string(),
+            progress: None,
+            cancel,
+            rx,
+        });
+    }
 
-        match container::read_file_bytes(sess, id) {
-            Ok(bytes) => {
-                // Text
-                if let Ok(s) = std::str::from_utf8(&bytes) {
-                    self.viewer_mode = ViewerMode::Text;
-                    self.viewer_text = s.to_string();
-                    self.viewer_bytes = Some(bytes);
+    /// Забирает сообщения рабочего потока; по завершении возвращает сессию.
+    fn poll_job(&mut self) {
+        let Some(job) = &mut self.job else {
+            return;
+        };
+        loop {
+            match job.rx.try_recv() {
+                Ok(JobEvent::Progress(progress)) => job.progress = Some(progress),
+                Ok(JobEvent::Done(sess, res)) => {
+                    let label = job.label.clone();
+                    self.job = None;
+                    self.sess = Some(*sess);
+                    match res {
+                        Ok(done) => {
+                            if let Some((level, text)) = done.note {
+                                self.notify.push(level, text);
+                            }
+                            (done.apply)(self);
+                        }
+                        // Отмена — не ошибка, в список ошибок она не попадает.
+                        Err(e) if is_cancelled(&e) => self.notify.info(t!("{}: отменено", label)),
+                        Err(e) => self.notify.error(format!("{label}: {}", i18n::error(&e))),
+                    }
                     return;
                 }
-
-                // Image
-                if let Ok(img) = image::load_from_memory(&bytes) {
-                    let rgba = img.to_rgba8();
-                    let size = [rgba.width() as usize, rgba.height() as usize];
-                    let pixels = rgba.into_raw();
-                    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, &pixels);
-                    self.viewer_texture = Some(ctx.load_texture(
-                        "vault_image",
-                        color_image,
-                        egui::TextureOptions::default(),
-                    ));
-                    self.viewer_mode = ViewerMode::Image;
-                    self.viewer_bytes = Some(bytes);
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    // Поток упал вместе с сессией; журнал откатит незаконченную запись
+                    // при следующем открытии.
+                    let label = job.label.clone();
+                    self.job = None;
+                    self.lock();
+                    self.notify.error(t!("{}: операция прервалась, контейнер закрыт", label));
                     return;
                 }
-
-                // Hex fallback
-                self.viewer_mode = ViewerMode::Hex;
-                self.viewer_bytes = Some(bytes);
-                self.viewer_error = "Бинарный файл: показан hex-превью (MVP)".to_string();
             }
-            Err(e) => self.viewer_error = format!("Ошибка чтения: {e}"),
         }
     }
-}
 
-impl eframe::App for VaultApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::TopBottomPanel::top("top").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                ui.heading("Vault");
-                if self.sess.is_some() {
-                    if ui.button("Lock").clicked() {
-                        self.lock();
-                    }
-                }
-                ui.separator();
-                ui.label(&self.status);
+    /// Импортирует файлы и папки (со всем содержимым) в текущую папку одной
+    /// транзакцией, в рабочем потоке.
+    fn start_import(&mut self, ctx: &egui::Context, paths: Vec<PathBuf>) {
+        let (dir_id, compress) = (self.current_dir_id, self.compress_imports);
+        self.spawn_job(ctx, t!("Импорт"), move |sess, password, progress| {
+            progress.total(paths.iter().map(|p| count_files(p).1).sum());
+            sess.begin()?;
+            let res = paths.iter().try_fold(None, |_, p| {
+                let id = if p.is_dir() {
+                    container::import_dir(sess, password, p, dir_id, compress, progress)
+                } else {
+                    container::import_file(sess, password, p, dir_id, None, compress, progress)
+                };
+                id.map(Some).map_err(|e| anyhow::Error::from(e).context(p.display().to_string()))
             });
+            match res {
+                Ok(last) => {
+                    sess.commit(password)?;
+                    Ok(JobDone::new(None).then(move |app| app.selected_id = last.or(app.selected_id)))
+                }
+                Err(e) => {
+                    sess.rollback()?;
+                    Err(e)
+                }
+            }
         });
+    }
 
-        if self.sess.is_none() {
-            egui::CentralPanel::default().show(ctx, |ui| {
-                ui.heading("Открыть / создать контейнер");
-                ui.add_space(10.0);
-
-                ui.horizontal(|ui| {
-                    ui.label("vault.dat:");
-                    ui.text_edit_singleline(&mut self.vault_path);
-                    if ui.button("Выбрать").clicked() {
-                        if let Some(p) = FileDialog::new().add_filter("vault", &["dat"]).pick_file() {
-                            self.vault_path = p.display().to_string();
-                        }
-                    }
-                });
-
-                ui.horizontal(|ui| {
-                    ui.label("Пароль:");
-                    ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
-                    if ui.button("Открыть").clicked() {
-                        self.open_vault_action();
-                    }
-                });
-
-                ui.separator();
-
-                ui.horizontal(|ui| {
-                    ui.label("Новый пароль:");
-                    ui.add(
-                        egui::TextEdit::singleline(&mut self.create_password)
-                            .password(true)
-                            .hint_text("придумайте пароль"),
-                    );
-                    if ui.button("Создать новый контейнер").clicked() {
-                        self.create_vault_action();
+    /// Окно подтверждения импорта перетащенных файлов.
+    fn render_drop(&mut self, ctx: &egui::Context) {
+        let writable = self.sess.as_ref().is_some_and(|s| !s.read_only);
+        let dropped: Vec<PathBuf> = ctx.input(|i| i.raw.dropped_files.iter().filter_map(|f| f.path.clone()).collect());
+        if writable && !dropped.is_empty() {
+            self.dropped = dropped
+                .into_iter()
+                .map(|path| {
+                    let (files, bytes) = count_files(&path);
+                    DropItem {
+                        is_dir: path.is_dir(),
+                        path,
+                        files,
+                        bytes,
                     }
+                })
+                .collect();
+        }
+        if writable && ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            egui::Area::new(egui::Id::new("drop_hint"))
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.heading(t!("Отпустите, чтобы импортировать в текущую папку"));
+                    });
                 });
-
-                ui.add_space(12.0);
-                ui.label("Примечание: 100% 'без следов' на ПК гарантировать нельзя. В этом GUI нет 'Открыть во внешней программе' — чтобы уменьшить утечки/следы.");
-            });
+        }
+        if self.dropped.is_empty() {
             return;
         }
 
-        egui::SidePanel::left("left").resizable(true).show(ctx, |ui| {
-            ui.heading("Папки");
-            ui.separator();
-
-            if ui.button("Корень").clicked() {
-                if let Some(sess) = &self.sess {
-                    self.current_dir_id = sess.meta.root_id;
-                    self.selected_id = Some(sess.meta.root_id);
+        let target = self.sess.as_ref().map(|s| s.meta.path_of(self.current_dir_id)).unwrap_or_default();
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new(t!("Импорт перетащенных файлов")).collapsible(false).show(ctx, |ui| {
+            ui.label(t!("В папку {}:", target));
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for item in &self.dropped {
+                    let name = item.path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+                    match item.is_dir {
+                        true => ui.label(t!("📁 {} — файлов: {}, {}", name, item.files, size_text(item.bytes))),
+                        false => ui.label(format!("📄 {name} — {}", size_text(item.bytes))),
+                    };
                 }
-            }
-
-            self.render_dir_tree(ui, 1);
-        });
-
-        egui::CentralPanel::default().show(ctx, |ui| {
-            // Сначала собираем действия (клики) в переменные, а изменения контейнера делаем ПОСЛЕ ui.horizontal.
-            let mut do_mkdir: Option<String> = None;
-            let mut do_import: Option<PathBuf> = None;
-            let mut do_export: bool = false;
-            let mut do_delete: bool = false;
-            let mut do_view: bool = false;
-            let mut do_start_rename: bool = false;
-            let mut do_apply_rename: bool = false;
-
+            });
+            let files: usize = self.dropped.iter().map(|d| d.files).sum();
+            let bytes: u64 = self.dropped.iter().map(|d| d.bytes).sum();
+            ui.label(t!("Всего файлов: {}, {}", files, size_text(bytes)));
+            ui.checkbox(&mut self.compress_imports, t!("Сжимать (zstd)"));
             ui.horizontal(|ui| {
-                ui.label(format!("Текущая папка: id={}", self.current_dir_id));
-
-                ui.separator();
-                ui.label("Новая папка:");
-                ui.text_edit_singleline(&mut self.new_folder_name);
-                if ui.button("Создать").clicked() {
-                    do_mkdir = Some(self.new_folder_name.trim().to_string());
-                }
-
-                ui.separator();
+                confirmed = ui.button(t!("Импортировать")).clicked();
+                cancelled = ui.button(t!("Отмена")).clicked();
+            });
+        });
+        if confirmed {
+            let paths = self.dropped.drain(..).map(|d| d.path).collect();
+            self.start_import(ctx, paths);
+        } else if cancelled {
+            self.dropped.clear();
+        }
+    }
 
-                if ui.button("Импорт файла").clicked() {
-                    if let Some(p) = FileDialog::new().pick_file() {
-                        do_import = Some(p);
-                    }
+    /// Клавиши окна открытого контейнера. Стрелки, Enter, Delete и F2 не действуют,
+    /// пока вводится текст.
+    fn handle_keys(&mut self, ctx: &egui::Context) {
+        if self.viewer_mode == ViewerMode::Hex && !ctx.wants_keyboard_input() {
+            let hex = &self.viewer_hex;
+            let page = ctx.input(|i| match (i.key_pressed(egui::Key::PageUp), i.key_pressed(egui::Key::PageDown)) {
+                (true, _) => Some(hex.page.saturating_sub(HEX_PAGE)),
+                (_, true) => Some(hex.page + HEX_PAGE).filter(|&p| p < hex.size),
+                _ => None,
+            });
+            if let Some(page) = page.filter(|&p| p != hex.page) {
+                self.load_hex_page(page);
+            }
+        }
+        if self.viewer_mode == ViewerMode::Pdf && !ctx.wants_keyboard_input() {
+            let pages = self.viewer_pdf.as_ref().map_or(0, pdf::Document::page_count);
+            let page = ctx.input(|i| match (i.key_pressed(egui::Key::PageUp), i.key_pressed(egui::Key::PageDown)) {
+                (true, _) => self.viewer_page.saturating_sub(1),
+                (_, true) => (self.viewer_page + 1).min(pages.saturating_sub(1)),
+                _ => self.viewer_page,
+            });
+            if page != self.viewer_page {
+                // рисуется при показе
+                self.viewer_page = page;
+                self.viewer_texture = None;
+                self.viewer_error.clear();
+            }
+        }
+        if self.viewer_mode == ViewerMode::Image && !ctx.wants_keyboard_input() {
+            let (prev, next) = self.image_neighbours();
+            let step = ctx.input(|i| match (i.key_pressed(egui::Key::PageUp), i.key_pressed(egui::Key::PageDown)) {
+                (true, _) => prev,
+                (_, true) => next,
+                _ => None,
+            });
+            if let Some(id) = step {
+                self.step_image(ctx, id);
+            }
+        }
+        // Ctrl+S работает и при вводе в редакторе.
+        if self.viewer_editing && ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::S)) && self.viewer_dirty() {
+            self.save_viewer();
+        }
+        // При вводе текста Ctrl+Z и Ctrl+Shift+Z отменяют и повторяют правку в поле.
+        if !ctx.wants_keyboard_input() {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)) {
+                self.undo_redo(false);
+            } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z)) {
+                self.undo_redo(true);
+            }
+        }
+        if self.tabs.len() > 1 {
+            let len = self.tabs.len();
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Tab)) {
+                self.switch_tab((self.tab + len - 1) % len);
+            } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Tab)) {
+                self.switch_tab((self.tab + 1) % len);
+            }
+            if self.sess.is_none() && ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::L)) {
+                self.request_lock();
+            }
+        }
+        let Some(sess) = &self.sess else {
+            return;
+        };
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::L)) {
+            self.request_lock();
+            return;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F)) {
+            match sess.meta.fts.is_some() {
+                true => self.focus_search = true,
+                false => self.notify.warn(t!("Поиск не включён (флажок «Поиск по тексту»)")),
+            }
+        }
+        if ctx.wants_keyboard_input()
+            || self.confirm_delete
+            || self.confirm_discard.is_some()
+            || self.confirm_export.is_some()
+            || !self.dropped.is_empty()
+        {
+            return;
+        }
+
+        let listed = self.rows();
+        let Some(sess) = &self.sess else {
+            return;
+        };
+        let pos = self.selected_id.and_then(|id| listed.iter().position(|&i| i == id));
+        let (prev, next) = match self.grid {
+            true => ctx.input(|i| {
+                let prev = i.key_pressed(egui::Key::ArrowUp) || i.key_pressed(egui::Key::ArrowLeft);
+                (prev, i.key_pressed(egui::Key::ArrowDown) || i.key_pressed(egui::Key::ArrowRight))
+            }),
+            false => ctx.input(|i| (i.key_pressed(egui::Key::ArrowUp), i.key_pressed(egui::Key::ArrowDown))),
+        };
+        let to = match (pos, prev, next) {
+            (Some(p), true, _) => Some(p.saturating_sub(1)),
+            (Some(p), _, true) => Some((p + 1).min(listed.len() - 1)),
+            (None, true, _) | (None, _, true) => (!listed.is_empty()).then_some(0),
+            _ => None,
+        };
+        if let Some(to) = to {
+            // Со Shift отмечается и пройденное.
+            let id = listed[to];
+            match ctx.input(|i| i.modifiers.shift) {
+                true => self.selection.extend(self.selected_id.into_iter().chain([id])),
+                false => self.selection.clear(),
+            }
+            self.selected_id = Some(id);
+            self.scroll_to_selected = true;
+        }
+
+        let Some(n) = pos.and_then(|p| sess.meta.get_node(listed[p])) else {
+            return;
+        };
+        let (id, node_type) = (n.id, n.node_type);
+        let writable = !sess.read_only;
+        ctx.input(|i| {
+            if i.key_pressed(egui::Key::Enter) {
+                let action = match node_type {
+                    NodeType::Dir => MenuAction::Open,
+                    NodeType::File => MenuAction::View,
+                };
+                self.menu_action = Some((id, action));
+            } else if i.key_pressed(egui::Key::F2) && writable {
+                self.menu_action = Some((id, MenuAction::Rename));
+            } else if i.key_pressed(egui::Key::Delete) && writable {
+                self.confirm_delete = true;
+            }
+        });
+    }
+
+    /// Экспортирует файлы в папку `dir` под их именами; уже лежащие там файлы
+    /// заменяются с `replace`, иначе пропускаются.
+    fn export_many(&mut self, ctx: &egui::Context, files: Vec<(u64, String)>, dir: PathBuf, replace: bool) {
+        self.spawn_job(ctx, t!("Экспорт"), move |sess, _, progress| {
+            progress.total(files.iter().filter_map(|(id, _)| sess.meta.get_node(*id)).map(|n| n.size).sum());
+            let mut written = HashSet::new();
+            let mut skipped = 0;
+            for (id, name) in &files {
+                // Одноимённые файлы из разных папок не перезаписывают друг друга.
+                let out = dir.join(name);
+                let fresh = written.insert(name.as_str());
+                if out.exists() && !(replace && fresh) {
+                    skipped += 1;
+                    continue;
+                }
+                container::export_file(sess, *id, &out, progress)?;
+            }
+            Ok(JobDone::info(match skipped {
+                0 => t!("Экспортировано файлов: {}", files.len()),
+                _ => t!("Экспортировано файлов: {}, пропущено: {}", files.len() - skipped, skipped),
+            }))
+        });
+    }
+
+    /// Окно вопроса, заменять ли файлы при экспорте в папку.
+    fn render_confirm_export(&mut self, ctx: &egui::Context) {
+        let Some(plan) = &self.confirm_export else {
+            return;
+        };
+        let mut choice = None;
+        egui::Window::new(t!("Файлы уже есть")).collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label(t!("В папке {} уже есть файлы с такими именами: {}", plan.dir.display(), plan.existing.len()));
+            for name in plan.existing.iter().take(10) {
+                ui.label(format!("• {name}"));
+            }
+            if plan.existing.len() > 10 {
+                ui.label(t!("… и ещё {}", plan.existing.len() - 10));
+            }
+            ui.horizontal(|ui| {
+                if ui.button(t!("Заменить")).clicked() {
+                    choice = Some(Some(true));
+                }
+                if ui.button(t!("Пропустить")).clicked() {
+                    choice = Some(Some(false));
+                }
+                if ui.button(t!("Отмена")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    choice = Some(None);
+                }
+            });
+        });
+        let Some(choice) = choice else {
+            return;
+        };
+        let Some(plan) = self.confirm_export.take() else {
+            return;
+        };
+        if let Some(replace) = choice {
+            self.export_many(ctx, plan.files, plan.dir, replace);
+        }
+    }
+
+    /// Закрывает контейнер; несохранённые правки текста — только после подтверждения.
+    fn request_lock(&mut self) {
+        match self.viewer_dirty() {
+            true => self.confirm_discard = Some(Discard::Lock),
+            // пустая вкладка просто закрывается
+            false if self.sess.is_none() => self.lock(),
+            false => {
+                self.lock();
+                self.notify.info(t!("Контейнер закрыт"));
+            }
+        }
+    }
+
+    /// Окно подтверждения удаления: сколько файлов и байт удаляется вместе с папками.
+    fn render_confirm_delete(&mut self, ctx: &egui::Context) {
+        let Some(sess) = self.sess.as_ref().filter(|_| self.confirm_delete) else {
+            return;
+        };
+        let ids = self.selected_ids();
+        if ids.is_empty() {
+            self.confirm_delete = false;
+            self.notify.warn(t!("Ничего не выбрано"));
+            return;
+        }
+        let in_trash = sess.meta.trash_id != 0 && sess.meta.trash_id == self.current_dir_id;
+        let question = match (ids.as_slice(), in_trash) {
+            ([id], false) => t!("Переместить «{}» в корзину?", sess.meta.get_node(*id).map_or("", |n| &n.name)),
+            ([id], true) => t!("Удалить «{}» навсегда?", sess.meta.get_node(*id).map_or("", |n| &n.name)),
+            (_, false) => t!("Переместить в корзину узлов: {}?", ids.len()),
+            (_, true) => t!("Удалить навсегда узлов: {}?", ids.len()),
+        };
+        let (mut files, mut bytes) = (0, 0);
+        for n in ids.iter().filter_map(|&id| sess.meta.get_node(id)) {
+            match n.node_type {
+                NodeType::File => (files, bytes) = (files + 1, bytes + n.size),
+                NodeType::Dir => {
+                    let stats = sess.meta.dir_stats(n.id).unwrap_or_default();
+                    (files, bytes) = (files + stats.files, bytes + stats.size);
+                }
+            }
+        }
+        let mut close = false;
+        egui::Window::new(t!("Удаление")).collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label(question);
+            ui.label(t!("Файлов: {}, {}", files, size_text(bytes)));
+            if in_trash && self.shred_on_delete {
+                ui.label(t!("Шифротекст будет затёрт."));
+            }
+            ui.horizontal(|ui| {
+                let ok = ui.button(t!("Удалить"));
+                // Enter подтверждает, Escape отменяет.
+                if ok.clicked() || ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    self.delete_confirmed = true;
+                    close = true;
+                }
+                if ui.button(t!("Отмена")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    close = true;
+                }
+            });
+        });
+        if close {
+            self.confirm_delete = false;
+        }
+    }
+
+    /// Закрывает контейнер после `lock_after_min` минут без ввода, а с `lock_on_blur`
+    /// и когда окно свёрнуто или неактивно. Пока идёт операция, контейнер не
+    /// закрывается: её время считается активностью.
+    fn auto_lock(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        let active = self.job.is_some() || ctx.input(|i| !i.events.is_empty() || i.pointer.is_moving());
+        let last = *self.last_input.get_or_insert(now);
+        if active {
+            self.last_input = Some(now);
+        }
+        let open = self.sess.is_some() || self.tabs.iter().any(|t| t.sess.is_some());
+        if !open || self.job.is_some() {
+            return;
+        }
+        let blurred = ctx.input(|i| !i.focused || i.viewport().minimized == Some(true));
+        let minutes = self.settings.lock_after_min;
+        let limit = Duration::from_secs(u64::from(minutes) * 60);
+        let idle = now.duration_since(last);
+        if self.settings.lock_on_blur && blurred {
+            self.lock_all();
+            self.notify.info(t!("Контейнер закрыт: окно свёрнуто или неактивно"));
+        } else if minutes > 0 && !active && idle >= limit {
+            self.lock_all();
+            self.notify.info(t!("Контейнер закрыт после {} мин бездействия", minutes));
+        } else if minutes > 0 {
+            // Без ввода кадры не рисуются: разбудить окно к сроку.
+            ctx.request_repaint_after(limit.saturating_sub(idle));
+        }
+    }
+
+    fn close_change_password(&mut self) {
+        self.show_change_password = false;
+        self.cp_old.zeroize();
+        self.cp_new.zeroize();
+        self.cp_repeat.zeroize();
+        self.cp_allow_weak = false;
+    }
+
+    /// Окно смены пароля. Ключ контейнера не меняется: он только заново шифруется
+    /// новым паролем, так что данные не перешифровываются, а коды восстановления,
+    /// токен и запомненный вход продолжают работать.
+    fn render_change_password(&mut self, ctx: &egui::Context) {
+        if !self.show_change_password || self.sess.is_none() {
+            return;
+        }
+        let mut open = true;
+        let mut submit = false;
+        let mut cancel = false;
+        let est = strength::estimate(&self.cp_new, &strength::path_words(&self.vault_path));
+        egui::Window::new(t!("Смена пароля"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("change_password").num_columns(2).show(ui, |ui| {
+                    ui.label(t!("Текущий пароль:"));
+                    ui.add(egui::TextEdit::singleline(&mut self.cp_old).password(true));
+                    ui.end_row();
+                    ui.label(t!("Новый пароль:"));
+                    ui.add(egui::TextEdit::singleline(&mut self.cp_new).password(true));
+                    ui.end_row();
+                    ui.label(t!("Ещё раз:"));
+                    ui.add(egui::TextEdit::singleline(&mut self.cp_repeat).password(true));
+                    ui.end_row();
+                });
+                if !self.cp_new.is_empty() {
+                    ui.horizontal(|ui| {
+                        strength_bar(ui, &est);
+                        if est.is_weak() {
+                            ui.checkbox(&mut self.cp_allow_weak, t!("Всё равно сменить"));
+                        }
+                    });
+                }
+                if !self.cp_repeat.is_empty() && self.cp_repeat != self.cp_new {
+                    ui.colored_label(egui::Color32::from_rgb(200, 60, 60), t!("Пароли не совпадают"));
+                }
+                ui.horizontal(|ui| {
+                    submit = ui.button(t!("Сменить пароль")).clicked();
+                    cancel = ui.button(t!("Отмена")).clicked();
+                });
+            });
+        if !open || cancel {
+            self.close_change_password();
+            return;
+        }
+        if !submit {
+            return;
+        }
+        if self.cp_old.is_empty() {
+            self.notify.warn(t!("Введите пароль"));
+        } else if self.cp_new.is_empty() {
+            self.notify.warn(t!("Задайте пароль"));
+        } else if self.cp_new != self.cp_repeat {
+            self.notify.warn(t!("Пароли не совпадают"));
+        } else if est.is_weak() && !self.cp_allow_weak {
+            self.notify.warn(t!("Пароль слабый: придумайте надёжнее или отметьте «Всё равно сменить»"));
+        } else {
+            let old = SecretString::new(&self.cp_old);
+            let new = SecretString::new(&self.cp_new);
+            self.close_change_password();
+            // Два вывода ключа Argon2id (проверка старого пароля и новый) — в рабочем потоке.
+            self.spawn_job(ctx, t!("Смена пароля"), move |sess, _, _| {
+                container::change_password(sess, &old, &new)?;
+                Ok(JobDone::info(t!("Пароль изменён")).then(move |app| app.unlocked_password = new))
+            });
+        }
+    }
+
+    /// Тема и масштаб из настроек. Масштаб, изменённый клавишами Ctrl+плюс и
+    /// Ctrl+минус, тоже запоминается в настройках.
+    fn apply_style(&mut self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let system = frame.info().system_theme;
+        let zoom = (ctx.zoom_factor() * 100.0).round() as u32;
+        if self.applied_style.is_some_and(|(_, pct, _)| pct != zoom) {
+            self.settings.ui_scale_pct = zoom.clamp(*settings::UI_SCALE_PCT.start(), *settings::UI_SCALE_PCT.end());
+            if let Err(e) = self.settings.save() {
+                self.notify.error(t!("Настройки не сохранены: {}", i18n::error(&e)));
+            }
+        }
+        let style = (self.settings.theme, self.settings.ui_scale_pct, system);
+        if self.applied_style == Some(style) {
+            return;
+        }
+        let dark = match self.settings.theme {
+            Theme::System => system != Some(eframe::Theme::Light),
+            Theme::Dark => true,
+            Theme::Light => false,
+        };
+        ctx.set_visuals(if dark { egui::Visuals::dark() } else { egui::Visuals::light() });
+        ctx.set_zoom_factor(self.settings.ui_scale_pct as f32 / 100.0);
+        self.applied_style = Some(style);
+    }
+
+    /// Исключает окно из снимков и демонстрации экрана, пока включено «Скрывать
+    /// содержимое файлов», и возвращает, когда выключено.
+    fn apply_capture(&mut self, frame: &eframe::Frame) {
+        let exclude = self.settings.hide_content;
+        if self.applied_capture == Some(exclude) {
+            return;
+        }
+        self.applied_capture = Some(exclude);
+        if let Err(e) = capture::exclude_window(frame, exclude) {
+            self.notify.warn(t!("Окно не скрыто от записи экрана: {}", i18n::error(&e)));
+        }
+    }
+
+    /// Содержимое файлов не показывается: окно неактивно или свёрнуто, а в настройках
+    /// включено «Скрывать содержимое файлов».
+    fn content_hidden(&self, ctx: &egui::Context) -> bool {
+        self.settings.hide_content && ctx.input(|i| !i.focused || i.viewport().minimized == Some(true))
+    }
+
+    /// Окно настроек; изменения сразу применяются и сохраняются (см. `settings`).
+    fn render_settings(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_settings;
+        let mut changed = false;
+        egui::Window::new(t!("Настройки")).open(&mut open).collapsible(false).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(t!("Язык:"));
+                let mut lang = i18n::current();
+                egui::ComboBox::from_id_source("lang").selected_text(lang.name()).show_ui(ui, |ui| {
+                    for l in i18n::Lang::ALL {
+                        ui.selectable_value(&mut lang, l, l.name());
+                    }
+                });
+                if lang != i18n::current() {
+                    i18n::set(lang);
+                    self.settings.lang = Some(lang);
+                    changed = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(t!("Закрывать контейнер после"));
+                let minutes = egui::DragValue::new(&mut self.settings.lock_after_min).clamp_range(0..=24 * 60);
+                changed |= ui.add(minutes).changed();
+                ui.label(t!("мин бездействия (0 — никогда)"));
+            });
+            let blur = egui::Checkbox::new(&mut self.settings.lock_on_blur, t!("Закрывать при сворачивании и потере фокуса"));
+            changed |= ui.add(blur).changed();
+            let hide = egui::Checkbox::new(&mut self.settings.hide_content, t!("Скрывать содержимое файлов, когда окно неактивно"));
+            let hint = match capture::SUPPORTED {
+                true => t!("Просмотр, миниатюры и слайд-шоу пустеют без фокуса; окно не попадает в снимки и демонстрацию экрана"),
+                false => t!("Просмотр, миниатюры и слайд-шоу пустеют без фокуса; скрыть окно от записи экрана здесь система не даёт"),
+            };
+            changed |= ui.add(hide).on_hover_text(hint).changed();
+            ui.horizontal(|ui| {
+                ui.label(t!("Тема:"));
+                let mut theme = self.settings.theme;
+                egui::ComboBox::from_id_source("theme").selected_text(theme_name(theme)).show_ui(ui, |ui| {
+                    for th in Theme::ALL {
+                        ui.selectable_value(&mut theme, th, theme_name(th));
+                    }
+                });
+                if theme != self.settings.theme {
+                    self.settings.theme = theme;
+                    changed = true;
+                }
+            });
+            // Кнопками, а не ползунком: ползунок уезжал бы из-под указателя вместе с
+            // масштабом.
+            ui.horizontal(|ui| {
+                ui.label(t!("Масштаб интерфейса:"));
+                let (pct, range, step) = (self.settings.ui_scale_pct, settings::UI_SCALE_PCT, settings::UI_SCALE_STEP_PCT);
+                let mut set = None;
+                if ui.add_enabled(pct > *range.start(), egui::Button::new("−")).on_hover_text("Ctrl −").clicked() {
+                    set = Some(pct.saturating_sub(step).max(*range.start()));
+                }
+                ui.label(format!("{pct}%"));
+                if ui.add_enabled(pct < *range.end(), egui::Button::new("+")).on_hover_text("Ctrl +").clicked() {
+                    set = Some((pct + step).min(*range.end()));
+                }
+                if ui.add_enabled(pct != 100, egui::Button::new("100%")).clicked() {
+                    set = Some(100);
+                }
+                if let Some(pct) = set {
+                    self.settings.ui_scale_pct = pct;
+                    changed = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(t!("Очищать буфер обмена через"));
+                let secs = egui::DragValue::new(&mut self.settings.clipboard_clear_sec).clamp_range(0..=3600);
+                changed |= ui.add(secs).changed();
+                ui.label(t!("с после копирования (0 — не очищать)"));
+            });
+        });
+        self.show_settings = open;
+        if changed {
+            if let Err(e) = self.settings.save() {
+                self.notify.error(t!("Настройки не сохранены: {}", i18n::error(&e)));
+            }
+        }
+    }
+
+    fn render_job(&mut self, ctx: &egui::Context) {
+        let Some(job) = &self.job else {
+            return;
+        };
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.add_space(40.0);
+            ui.vertical_centered(|ui| {
+                ui.heading(&job.label);
+                ui.add_space(10.0);
+                let Some(p) = &job.progress else {
+                    ui.spinner();
+                    return;
+                };
+                let share = match p.total {
+                    0 => 0.0,
+                    total => (p.done as f64 / total as f64).min(1.0) as f32,
+                };
+                let bar = egui::ProgressBar::new(share)
+                    .text(t!("{} из {}", size_text(p.done), size_text(p.total)))
+                    .desired_width(300.0);
+                ui.add(bar);
+                ui.label(&p.file);
+                ui.add_space(10.0);
+                // Отменённый импорт откатывается целиком; экспорт удаляет недописанный файл.
+                let cancelled = job.cancel.load(Ordering::Relaxed);
+                let label = if cancelled { t!("Отмена…") } else { t!("Отмена") };
+                if ui.add_enabled(!cancelled, egui::Button::new(label)).clicked() {
+                    job.cancel.store(true, Ordering::Relaxed);
+                }
+            });
+        });
+    }
+
+    /// Удаляет всё из корзины, с «Затирать» — затирая освобождённое.
+    fn empty_trash(&mut self, ctx: &egui::Context) {
+        let shred = self.shred_on_delete;
+        self.spawn_job(ctx, t!("Очистка корзины"), move |sess, password, _| {
+            container::empty_trash(sess, password, shred)?;
+            Ok(JobDone::info(t!("Корзина очищена")).then(|app| app.selected_id = None))
+        });
+    }
+
+    fn compact(&mut self, ctx: &egui::Context) {
+        self.spawn_job(ctx, t!("Сжатие контейнера"), |sess, password, _| {
+            let freed = container::compact(sess, password)?;
+            Ok(JobDone::info(t!("Сжато, освобождено {} байт", freed)))
+        });
+    }
+
+    /// Окно «Место»: сколько занимает контейнер и сколько из этого можно вернуть
+    /// очисткой корзины и сжатием. Пересчитывается при изменении метаданных.
+    fn render_usage(&mut self, ctx: &egui::Context) {
+        if !self.show_usage {
+            return;
+        }
+        let Some(sess) = self.sess.as_ref() else {
+            return;
+        };
+        let generation = sess.meta.generation;
+        if self.usage.as_ref().map(|(g, _)| *g) != Some(generation) {
+            let usage = container::usage(sess).map_err(|e| i18n::error(&e));
+            self.usage = Some((generation, usage));
+        }
+        let Some((_, usage)) = &self.usage else {
+            return;
+        };
+
+        let writable = !sess.read_only;
+        let trash_empty = sess.meta.trash_id == 0 || sess.meta.children_of(sess.meta.trash_id).is_empty();
+        let (mut do_empty_trash, mut do_compact) = (false, false);
+        let mut open = true;
+        egui::Window::new(t!("Место")).open(&mut open).resizable(false).show(ctx, |ui| match usage {
+            Ok(u) => {
+                egui::Grid::new("usage").num_columns(2).spacing([24.0, 4.0]).show(ui, |ui| {
+                    ui.label(t!("Файл контейнера"));
+                    ui.label(size_text(u.vault));
+                    ui.end_row();
+                    ui.label(t!("Данные файлов"));
+                    ui.label(size_text(u.logical)).on_hover_text(t!("До шифрования и сжатия, вместе с корзиной"));
+                    ui.end_row();
+                    ui.label(t!("Свободно внутри"));
+                    ui.label(size_text(u.free)).on_hover_text(t!("Освободится после «Сжать контейнер»"));
+                    ui.end_row();
+                    ui.label(t!("Только в корзине"));
+                    ui.label(size_text(u.trash)).on_hover_text(t!("Освободится после очистки корзины и сжатия"));
+                    ui.end_row();
+                    ui.label(t!("Можно вернуть"));
+                    ui.strong(size_text(u.free + u.trash));
+                    ui.end_row();
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.shred_on_delete, t!("Затирать"));
+                    do_empty_trash = ui.add_enabled(writable && !trash_empty, egui::Button::new(t!("Очистить корзину"))).clicked();
+                    do_compact = ui.add_enabled(writable, egui::Button::new(t!("Сжать контейнер"))).clicked();
+                });
+            }
+            Err(e) => {
+                ui.colored_label(ui.visuals().error_fg_color, e.as_str());
+            }
+        });
+        self.show_usage = open;
+        if do_empty_trash {
+            self.empty_trash(ctx);
+        } else if do_compact {
+            self.compact(ctx);
+        }
+    }
+
+    /// Всплывающие сообщения в правом нижнем углу; щелчок убирает сообщение раньше
+    /// времени.
+    fn render_toasts(&mut self, ctx: &egui::Context) {
+        let (toasts, next) = self.notify.toasts();
+        let mut dismiss = None;
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-12.0, -12.0])
+            .order(egui::Order::Foreground)
+            .interactable(true)
+            .show(ctx, |ui| {
+                for (i, toast) in toasts.iter().enumerate() {
+                    let color = match toast.level {
+                        notify::Level::Info => ui.visuals().text_color(),
+                        notify::Level::Warning => ui.visuals().warn_fg_color,
+                        notify::Level::Error => ui.visuals().error_fg_color,
+                    };
+                    let frame = egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(360.0);
+                        ui.add(egui::Label::new(egui::RichText::new(&toast.text).color(color)).wrap(true));
+                    });
+                    if frame.response.interact(egui::Sense::click()).clicked() {
+                        dismiss = Some(i);
+                    }
+                }
+            });
+        if let Some(i) = dismiss {
+            self.notify.dismiss(i);
+        }
+        if let Some(next) = next {
+            ctx.request_repaint_after(next);
+        }
+    }
+
+    /// Окно последних ошибок: они остаются здесь и после того, как сообщение исчезло.
+    fn render_errors(&mut self, ctx: &egui::Context) {
+        if !self.show_errors {
+            return;
+        }
+        let mut open = true;
+        let mut clear = false;
+        egui::Window::new(t!("Последние ошибки")).open(&mut open).vscroll(true).show(ctx, |ui| {
+            for e in self.notify.errors().iter().rev() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.weak(fsmeta::format_time(e.time));
+                    ui.label(&e.text);
+                });
+            }
+            ui.separator();
+            clear = ui.button(t!("Очистить")).clicked();
+        });
+        if clear {
+            self.notify.clear_errors();
+        }
+        self.show_errors = open && !self.notify.errors().is_empty();
+    }
+
+    /// Узлы, к которым относятся «Экспорт» и «Удалить»: отмеченные или выбранный.
+    fn selected_ids(&self) -> Vec<u64> {
+        match self.selection.is_empty() {
+            true => self.selected_id.into_iter().collect(),
+            false => self.selection.iter().copied().collect(),
+        }
+    }
+
+    /// Узлы показанного списка по порядку. Он собирается заново, только когда меняются
+    /// метаданные, папка, вид списка или порядок; сами узлы берутся из метаданных лишь
+    /// для видимых строк.
+    fn rows(&mut self) -> Rc<Vec<u64>> {
+        let Some(sess) = &self.sess else {
+            return Rc::default();
+        };
+        let key = (sess.meta.generation, sess.meta.sort, self.current_dir_id, self.listing.clone());
+        if let Some((_, rows)) = self.rows.as_ref().filter(|(k, _)| *k == key) {
+            return rows.clone();
+        }
+        let rows: Rc<Vec<u64>> = Rc::new(list_nodes(&sess.meta, &self.listing, self.current_dir_id).into_iter().map(|(_, n)| n.id).collect());
+        self.rows = Some((key, rows.clone()));
+        rows
+    }
+
+    /// Перемещает узлы в папку `dir_id` одной транзакцией; отменяется это тоже разом.
+    fn move_nodes(&mut self, ids: &[u64], dir_id: u64) {
+        let Some(sess) = self.sess.as_mut() else {
+            return;
+        };
+        let moved: Vec<u64> = ids
+            .iter()
+            .filter_map(|&id| sess.meta.get_node(id))
+            .filter(|n| n.parent_id != dir_id)
+            .map(|n| n.id)
+            .collect();
+        let res = in_transaction(sess, &self.unlocked_password, |sess, _| {
+            moved.iter().try_for_each(|&id| sess.move_node(id, dir_id))?;
+            Ok(())
+        });
+        match res {
+            Ok(()) => self.notify.info(t!("Перемещено: {}", moved.len())),
+            Err(e) => self.notify.error(t!("перемещение: {}", i18n::error(&e))),
+        }
+    }
+
+    /// Заканчивает правку имени в списке: с `commit` узел переименовывается, иначе
+    /// правка отбрасывается.
+    fn finish_rename(&mut self, commit: bool) {
+        let (Some((id, name)), Some(sess)) = (self.renaming.take(), self.sess.as_mut()) else {
+            return;
+        };
+        let name = name.trim();
+        if !commit || sess.meta.get_node(id).is_none_or(|n| n.name == name) {
+            return;
+        }
+        match sess.rename(id, name.to_string()) {
+            Ok(()) => {
+                if let Err(e) = container::save_metadata(sess, &self.unlocked_password) {
+                    self.notify.error(t!("сохранение: {}", i18n::error(&e)));
+                }
+            }
+            Err(e) => self.notify.error(t!("переименование: {}", i18n::error(&e))),
+        }
+    }
+
+    /// Копирует содержимое файла в буфер обмена: текст как есть, небольшой двоичный
+    /// файл — в base64.
+    fn copy_contents(&mut self, id: u64) {
+        let Some(n) = self.sess.as_ref().and_then(|s| s.meta.get_node(id)) else {
+            return;
+        };
+        let as_text = n.mime.as_deref().is_none_or(mime::is_text);
+        let limit = if as_text { MAX_VIEW_BYTES } else { clipboard::MAX_BASE64_BYTES };
+        if n.size > limit {
+            self.notify.warn(t!("Файл больше {}: не скопирован", size_text(limit)));
+            return;
+        }
+        let mut bytes = match self.sess.as_ref().map(|s| container::read_file_bytes(s, id)) {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(e)) => {
+                self.notify.error(t!("Ошибка чтения: {}", i18n::error(&e)));
+                return;
+            }
+            None => return,
+        };
+        // Файл без типа, который не оказался текстом UTF-8, копируется как двоичный.
+        match std::str::from_utf8(&bytes).ok().filter(|_| as_text) {
+            Some(text) => self.copy_to_clipboard(text, t!("Содержимое файла")),
+            None if bytes.len() as u64 > clipboard::MAX_BASE64_BYTES => {
+                self.notify.warn(t!("Файл больше {}: не скопирован", size_text(clipboard::MAX_BASE64_BYTES)));
+            }
+            None => {
+                let mut text = clipboard::base64(&bytes);
+                self.copy_to_clipboard(&text, t!("Файл в base64"));
+                text.zeroize();
+            }
+        }
+        bytes.zeroize();
+    }
+
+    /// Кладёт `text` в буфер обмена; через `clipboard_clear_sec` секунд он очищается,
+    /// если там всё ещё этот текст.
+    fn copy_to_clipboard(&mut self, text: &str, what: &str) {
+        let secs = self.settings.clipboard_clear_sec;
+        let clear_after = (secs > 0).then(|| Duration::from_secs(secs.into()));
+        match self.clipboard.copy(text, clear_after) {
+            Ok(()) if secs > 0 => self.notify.info(t!("{} в буфере обмена; он очистится через {} с", what, secs)),
+            Ok(()) => self.notify.info(t!("{} в буфере обмена", what)),
+            Err(e) => self.notify.error(t!("буфер обмена: {}", i18n::error(&e))),
+        }
+    }
+
+    /// Создаёт пустую текстовую заметку в текущей папке и открывает её на правку.
+    fn new_note(&mut self, ctx: &egui::Context) {
+        let Some(sess) = self.sess.as_mut() else {
+            return;
+        };
+        let dir_id = self.current_dir_id;
+        let base = t!("Заметка");
+        let name = (1..)
+            .map(|i| match i {
+                1 => format!("{base}.txt"),
+                _ => format!("{base} ({i}).txt"),
+            })
+            .find(|name| sess.meta.child_named(dir_id, name).is_none())
+            .unwrap_or_default();
+        match container::create_file(sess, &self.unlocked_password, dir_id, &name, Some("text/plain".into())) {
+            Ok(id) => {
+                self.notify.info(t!("Создана заметка «{}»", name));
+                self.selection.clear();
+                self.selected_id = Some(id);
+                self.scroll_to_selected = true;
+                self.view_selected(ctx);
+                // С несохранёнными правками другого файла сначала спрашивается, что с
+                // ними делать.
+                if self.viewer_id == Some(id) {
+                    self.viewer_editing = true;
+                }
+            }
+            Err(e) => self.notify.error(t!("заметка: {}", i18n::error(&e))),
+        }
+    }
+
+    /// Отменяет (`undo`) или повторяет последнее переименование, перемещение, удаление
+    /// в корзину или создание папки за этот сеанс.
+    fn undo_redo(&mut self, undo: bool) {
+        let Some(sess) = self.sess.as_mut() else {
+            return;
+        };
+        let res = match undo {
+            true => sess.undo(&self.unlocked_password),
+            false => sess.redo(&self.unlocked_password),
+        };
+        match (res, undo) {
+            (Ok(false), _) => return,
+            (Ok(true), true) => self.notify.info(t!("Отменено")),
+            (Ok(true), false) => self.notify.info(t!("Повторено")),
+            (Err(e), true) => self.notify.error(t!("отмена: {}", i18n::error(&e))),
+            (Err(e), false) => self.notify.error(t!("повтор: {}", i18n::error(&e))),
+        }
+        // Узел мог уйти из текущей папки, а папка — исчезнуть.
+        if sess.meta.get_node(self.current_dir_id).is_none() {
+            self.current_dir_id = sess.meta.root_id;
+        }
+        self.selection.retain(|&id| sess.meta.get_node(id).is_some());
+        if self.selected_id.is_some_and(|id| sess.meta.get_node(id).is_none()) {
+            self.selected_id = None;
+        }
+    }
+
+    /// Хэш ключевого файла, если он указан.
+    fn keyfile(&self) -> anyhow::Result<Option<crypto::SecretKey>> {
+        match self.keyfile_path.trim() {
+            "" => Ok(None),
+            p => Ok(Some(crypto::keyfile_digest(p)?)),
+        }
+    }
+
+    fn open_vault_action(&mut self) {
+        let keyfile = match self.keyfile() {
+            Ok(k) => k,
+            Err(e) => {
+                self.notify.error(t!("Не удалось открыть: {}", i18n::error(&e)));
+                return;
+            }
+        };
+        let res = if self.open_with_token {
+            let pin = Some(self.password.as_str()).filter(|p| !p.is_empty());
+            container::open_vault_token(&self.vault_path, None, pin, self.open_read_only)
+        } else if self.password.is_empty() {
+            // Без пароля — сохранённым на этом компьютере ключом, если он есть.
+            match keychain::load(&self.vault_path) {
+                Ok(Some((id, key))) => {
+                    let res = container::open_vault_slot(
+                        &self.vault_path,
+                        container::SlotKind::Remembered,
+                        Some(&id),
+                        &key,
+                        self.open_read_only,
+                    );
+                    if res.is_err() {
+                        // Слот удалён или заменён: запись больше не откроет контейнер.
+                        let _ = keychain::delete(&self.vault_path);
+                        self.notify.warn(t!("Сохранённый вход отозван, введите пароль"));
+                        return;
+                    }
+                    res
+                }
+                // Иначе — ключом, запечатанным в TPM (`vault tpm enroll`), если загрузка та же.
+                Ok(None) => match container::open_vault_tpm(&self.vault_path, self.open_read_only) {
+                    Ok(sess) => Ok(sess),
+                    Err(_) => {
+                        self.notify.warn(t!("Введите пароль"));
+                        return;
+                    }
+                },
+                Err(e) => {
+                    self.notify.error(t!("Не удалось открыть: {}", i18n::error(&e)));
+                    return;
+                }
+            }
+        } else if self.open_read_only {
+            container::open_vault_readonly(&self.vault_path, &self.password, keyfile.as_deref())
+        } else {
+            container::open_vault(&self.vault_path, &self.password, keyfile.as_deref())
+        };
+        match res {
+            Ok(mut sess) => {
+                if self.remember && !self.password.is_empty() && !self.open_with_token {
+                    match self.remember_action(&mut sess) {
+                        Ok(()) => self.notify.info(t!("Вход запомнен на этом компьютере")),
+                        Err(e) => self.notify.error(t!("Не удалось запомнить вход: {}", i18n::error(&e))),
+                    }
+                }
+                self.current_dir_id = sess.meta.root_id;
+                self.selected_id = Some(sess.meta.root_id);
+                self.sess = Some(sess);
+
+                self.unlocked_password = SecretString::new(&self.password);
+                self.password.zeroize();
+            }
+            Err(e) if matches!(e.root(), VaultError::Locked) => {
+                self.notify.error(t!("Контейнер уже открыт на запись другой программой; его можно открыть «Только чтение»"))
+            }
+            Err(e) => self.notify.error(t!("Не удалось открыть: {}", i18n::error(&e))),
+        }
+    }
+
+    /// Новый слот для входа без пароля; его ключ — в системной связке ключей.
+    fn remember_action(&self, sess: &mut container::Session) -> anyhow::Result<()> {
+        let id = crypto::random_bytes::<8>();
+        let key = crypto::SecretKey::new(crypto::random_bytes());
+        // Сначала связка ключей: слот без сохранённого ключа бесполезен.
+        keychain::store(&self.vault_path, &id, &key)?;
+        container::add_key_slot(sess, &self.password, container::SlotKind::Remembered, id, &key).inspect_err(|_| {
+            let _ = keychain::delete(&self.vault_path);
+        })?;
+        Ok(())
+    }
+
+    fn create_vault_action(&mut self) {
+        if self.vault_path.trim().is_empty() {
+            self.notify.warn(t!("Укажите путь к vault.dat"));
+            return;
+        }
+        if self.create_password.is_empty() {
+            self.notify.warn(t!("Задайте пароль"));
+            return;
+        }
+        if !self.create_allow_weak && strength::estimate(&self.create_password, &strength::path_words(&self.vault_path)).is_weak() {
+            self.notify.warn(t!("Пароль слабый: придумайте надёжнее или отметьте «Всё равно создать»"));
+            return;
+        }
+
+        let keyfile = match self.keyfile() {
+            Ok(k) => k,
+            Err(e) => {
+                self.notify.error(t!("Не удалось создать: {}", i18n::error(&e)));
+                return;
+            }
+        };
+        let (m_cost_kib, t_cost) = match crypto::calibrate_kdf(Duration::from_millis(self.create_unlock_ms as u64), 1) {
+            Ok((m, t, _)) => (m, t),
+            Err(e) => {
+                self.notify.error(t!("Не удалось создать: {}", i18n::error(&e)));
+                return;
+            }
+        };
+        let (code, code_key) = match self.create_recovery_code {
+            true => {
+                let (code, key) = recovery::new_code();
+                (Some(code), Some(key))
+            }
+            false => (None, None),
+        };
+        let params = container::NewVault {
+            m_cost_kib,
+            t_cost,
+            keyfile,
+            recovery_code: code_key,
+            ..Default::default()
+        };
+        match container::create_vault(&self.vault_path, &self.create_password, &params) {
+            Ok(()) => {
+                self.notify.info(t!("Создано (Argon2id: {} МиБ, проходов: {}). Теперь нажмите Открыть", m_cost_kib / 1024, t_cost));
+                self.shown_recovery_code = code;
+            }
+            Err(e) => self.notify.error(t!("Не удалось создать: {}", i18n::error(&e))),
+        }
+    }
+
+    /// Окно с кодом восстановления после создания контейнера.
+    fn render_recovery_code(&mut self, ctx: &egui::Context) {
+        let Some(code) = &self.shown_recovery_code else {
+            return;
+        };
+        let mut close = false;
+        egui::Window::new(t!("Код восстановления"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(t!("Если пароль будет забыт, этот код откроет контейнер (vault.exe recovery restore --code ...)."));
+                ui.label(t!("Запишите или распечатайте его и храните отдельно от компьютера. Больше он показан не будет."));
+                ui.add_space(8.0);
+                ui.label(egui::RichText::new(code.as_str()).monospace().size(18.0));
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.button(t!("Копировать")).clicked() {
+                        ui.output_mut(|o| o.copied_text = code.clone());
+                    }
+                    if ui.button(t!("Сохранить для печати…")).clicked() {
+                        if let Some(p) = FileDialog::new().set_file_name("recovery-code.txt").save_file() {
+                            let text = t!("Код восстановления для {}\n\n{}\n", self.vault_path, code);
+                            if let Err(e) = std::fs::write(&p, text) {
+                                self.notify.error(t!("Не удалось сохранить: {}", e));
+                            }
+                        }
+                    }
+                    if ui.button(t!("Я записал код")).clicked() {
+                        close = true;
+                    }
+                });
+            });
+        if close {
+            if let Some(mut code) = self.shown_recovery_code.take() {
+                code.zeroize();
+            }
+        }
+    }
+
+    /// Окно «Дубликаты»: группы файлов с одинаковым содержимым (по SHA-256 из
+    /// метаданных) и кнопки, чтобы убрать лишние копии в корзину.
+    fn render_dupes(&mut self, ctx: &egui::Context) {
+        if !self.show_dupes {
+            return;
+        }
+        let Some(sess) = self.sess.as_mut() else {
+            return;
+        };
+        let generation = sess.meta.generation;
+        if self.dupes.as_ref().map(|(g, _)| *g) != Some(generation) {
+            let groups = sess
+                .meta
+                .duplicates(&Default::default())
+                .into_iter()
+                .map(|g| DupeGroup {
+                    size: g[0].size,
+                    extra: fsmeta::duplicate_bytes(&g),
+                    files: g.iter().map(|n| (n.id, sess.meta.path_of(n.id))).collect(),
+                })
+                .collect();
+            self.dupes = Some((generation, groups));
+        }
+        let Some((_, groups)) = &self.dupes else {
+            return;
+        };
+
+        let writable = !sess.read_only;
+        let mut do_trash: Option<u64> = None;
+        let mut open = true;
+        egui::Window::new(t!("Дубликаты")).open(&mut open).vscroll(true).show(ctx, |ui| {
+            if groups.is_empty() {
+                ui.label(t!("Одинаковых файлов нет."));
+            }
+            let total: u64 = groups.iter().map(|g| g.extra).sum();
+            ui.label(t!("Групп: {}, лишние копии занимают {}", groups.len(), size_text(total)));
+            ui.label(t!("Файлы, изменённые на месте (write/append), не сравниваются: для них нет SHA-256 (vault.exe dupes --hash-missing)."));
+            for g in groups {
+                ui.separator();
+                ui.label(t!("Файлов: {}, по {}", g.files.len(), size_text(g.size)));
+                for (id, path) in &g.files {
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(writable, egui::Button::new(t!("В корзину"))).clicked() {
+                            do_trash = Some(*id);
+                        }
+                        if ui.link(path).clicked() {
+                            self.selected_id = Some(*id);
+                        }
+                    });
+                }
+            }
+        });
+        self.show_dupes = open;
+
+        if let Some(id) = do_trash {
+            match sess.trash(id).and_then(|()| container::save_metadata(sess, &self.unlocked_password)) {
+                Ok(()) => {
+                    if self.selected_id == Some(id) {
+                        self.selected_id = None;
+                    }
+                    self.notify.info(t!("Перемещено в корзину"));
+                }
+                Err(e) => self.notify.error(t!("удаление: {}", i18n::error(&e))),
+            }
+        }
+    }
+
+    fn render_dir_tree(&mut self, ui: &mut egui::Ui, parent_id: u64) {
+        // Важно: не держим borrow на self.sess во время рекурсивного вызова.
+        let dirs: Vec<(u64, String)> = match self.sess.as_ref() {
+            Some(sess) => sess
+                .meta
+                .children_of(parent_id)
+                .into_iter()
+                .filter(|n| n.node_type == NodeType::Dir)
+                .map(|n| (n.id, n.name.clone()))
+                .collect(),
+            None => return,
+        };
+
+        for (dir_id, dir_name) in dirs {
+            let label = if self.current_dir_id == dir_id {
+                format!("📁 {}", dir_name)
+            } else {
+                dir_name
+            };
+
+            let header = egui::CollapsingHeader::new(label)
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button(t!("Открыть")).clicked() {
+                            self.current_dir_id = dir_id;
+                            self.selected_id = Some(dir_id);
+                            self.listing = Listing::Dir;
+                        }
+                    });
+                    self.render_dir_tree(ui, dir_id);
+                })
+                .header_response;
+            if let Some(ids) = drop_target(ui, &header, dir_id) {
+                self.drop_move = Some((ids, dir_id));
+            }
+        }
+    }
+
+    fn clear_viewer(&mut self) {
+        self.viewer_id = None;
+        self.viewer_bytes = None;
+        self.viewer_text.clear();
+        self.viewer_error.clear();
+        self.viewer_texture = None;
+        self.viewer_mode = ViewerMode::None;
+        self.viewer_editing = false;
+        self.confirm_discard = None;
+        self.viewer_markdown = false;
+        self.viewer_source = false;
+        self.viewer_pdf = None;
+        self.viewer_page = 0;
+        self.viewer_audio = None;
+        self.viewer_video = None;
+        self.viewer_zoom = None;
+        self.viewer_turns = 0;
+        self.viewer_pan = egui::Vec2::ZERO;
+        self.viewer_exif = None;
+        self.viewer_hex = HexView::default();
+    }
+
+    /// Пускает (`Some(true)`) или останавливает звук и перематывает его на `seek`.
+    /// Доигранный до конца файл открывается заново.
+    fn control_audio(&mut self, play: Option<bool>, seek: Option<Duration>) {
+        let (Some(player), Some(sess), Some(id)) = (&self.viewer_audio, &self.sess, self.viewer_id) else {
+            return;
+        };
+        let result = (|| {
+            if player.finished() && (play == Some(true) || seek.is_some()) {
+                player.reload(sess, id)?;
+            }
+            if let Some(pos) = seek {
+                player.seek(pos)?;
+            }
+            match play {
+                Some(true) => player.play(),
+                Some(false) => player.pause(),
+                None => {}
+            }
+            anyhow::Ok(())
+        })();
+        if let Err(e) = result {
+            self.notify.error(t!("Звук не воспроизводится: {}", i18n::error(&e)));
+        }
+    }
+
+    /// То же для видео.
+    fn control_video(&mut self, play: Option<bool>, seek: Option<Duration>) {
+        let Some(player) = &mut self.viewer_video else {
+            return;
+        };
+        let result = (|| {
+            if let Some(pos) = seek {
+                player.seek(pos)?;
+            }
+            match play {
+                Some(true) => player.play(),
+                Some(false) => player.pause(),
+                None => Ok(()),
+            }
+        })();
+        if let Err(e) = result {
+            self.notify.error(t!("Видео не воспроизводится: {}", i18n::error(&e)));
+        }
+    }
+
+    /// Показывает изображение, повёрнутое на `viewer_turns` четвертей по часовой.
+    fn show_image(&mut self, ctx: &egui::Context, img: image::DynamicImage) {
+        let img = rotated(img, self.viewer_turns);
+        self.viewer_texture = Some(texture(ctx, "vault_image", &img));
+        self.viewer_zoom = None;
+        self.viewer_pan = egui::Vec2::ZERO;
+    }
+
+    /// Поворачивает показанное изображение на четверть: по часовой или против.
+    fn rotate_image(&mut self, ctx: &egui::Context, clockwise: bool) {
+        let Some(img) = self.viewer_bytes.as_deref().and_then(|b| image::load_from_memory(b).ok()) else {
+            return;
+        };
+        self.viewer_turns = (self.viewer_turns + if clockwise { 1 } else { 3 }) % 4;
+        self.show_image(ctx, img);
+    }
+
+    /// Соседние изображения показанного в текущем списке: предыдущее и следующее.
+    fn image_neighbours(&mut self) -> (Option<u64>, Option<u64>) {
+        let images = self.listed_images();
+        let Some(id) = self.viewer_id else {
+            return (None, None);
+        };
+        let Some(i) = images.iter().position(|&x| x == id) else {
+            return (None, None);
+        };
+        (i.checked_sub(1).map(|i| images[i]), images.get(i + 1).copied())
+    }
+
+    /// Изображения показанного списка по порядку.
+    fn listed_images(&mut self) -> Vec<u64> {
+        let rows = self.rows();
+        let Some(sess) = self.sess.as_ref() else {
+            return Vec::new();
+        };
+        rows.iter()
+            .filter_map(|&id| sess.meta.get_node(id))
+            .filter(|n| n.node_type == NodeType::File && n.mime.as_deref().is_some_and(mime::is_viewable_image))
+            .map(|n| n.id)
+            .collect()
+    }
+
+    /// Слайд-шоу из изображений показанного списка, с выбранного или с первого.
+    fn start_slideshow(&mut self, ctx: &egui::Context) {
+        let images = self.listed_images();
+        if images.is_empty() {
+            self.notify.warn(t!("В списке нет изображений"));
+            return;
+        }
+        let index = self.selected_id.and_then(|id| images.iter().position(|&i| i == id)).unwrap_or(0);
+        self.slideshow = Some(Slideshow::new(ctx, images, index));
+    }
+
+    /// Слайд-шоу во всё окно: стрелки и PageUp/PageDown листают, пробел включает и
+    /// выключает автопоказ, Escape закрывает, и показанное изображение остаётся
+    /// выбранным в списке.
+    fn render_slideshow(&mut self, ctx: &egui::Context) {
+        let hidden = self.content_hidden(ctx);
+        let (Some(show), Some(sess)) = (self.slideshow.as_mut(), self.sess.as_ref()) else {
+            return;
+        };
+        while let Ok((id, res)) = show.rx.try_recv() {
+            show.requested.remove(&id);
+            if show.window().contains(&id) {
+                show.ready.insert(id, res);
+            }
+        }
+
+        let mut close = false;
+        ctx.input(|i| {
+            if i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::PageDown) {
+                show.step(true);
+            } else if i.key_pressed(egui::Key::ArrowLeft) || i.key_pressed(egui::Key::PageUp) {
+                show.step(false);
+            } else if i.key_pressed(egui::Key::Space) {
+                show.autoplay = !show.autoplay;
+                show.shown_at = Instant::now();
+            }
+            close = i.key_pressed(egui::Key::Escape);
+        });
+        let current = show.images[show.index];
+        let showing = show.shown.as_ref().is_some_and(|(id, _)| *id == current);
+        if show.autoplay && showing {
+            let left = Duration::from_secs(show.interval_sec.into()).saturating_sub(show.shown_at.elapsed());
+            match left.is_zero() {
+                true => show.step(true),
+                false => ctx.request_repaint_after(left),
+            }
+        }
+
+        let current = show.images[show.index];
+        if show.shown.as_ref().is_none_or(|(id, _)| *id != current) {
+            if let Some(res) = show.ready.remove(&current) {
+                let tex = res.map(|img| ctx.load_texture("slideshow", img, egui::TextureOptions::default()));
+                show.shown = Some((current, tex.map_err(|e| i18n::error(&e))));
+                show.shown_at = Instant::now();
+            }
+        }
+        let window = show.window();
+        for id in window {
+            show.request(sess, id);
+        }
+        show.ready.retain(|id, _| window.contains(id));
+
+        egui::TopBottomPanel::bottom("slideshow_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("◀").on_hover_text("←").clicked() {
+                    show.step(false);
+                }
+                if ui.button("▶").on_hover_text("→").clicked() {
+                    show.step(true);
+                }
+                if ui.checkbox(&mut show.autoplay, t!("Автопоказ")).on_hover_text(t!("Пробел")).changed() {
+                    show.shown_at = Instant::now();
+                }
+                ui.add(egui::DragValue::new(&mut show.interval_sec).clamp_range(1..=600).suffix(t!(" с")));
+                ui.separator();
+                let name = sess.meta.get_node(current).map_or(String::new(), |n| n.name.clone());
+                ui.label(format!("{} / {} · {name}", show.index + 1, show.images.len()));
+                ui.separator();
+                if ui.button(t!("Выход")).on_hover_text("Esc").clicked() {
+                    close = true;
+                }
+            });
+        });
+        egui::CentralPanel::default().frame(egui::Frame::none().fill(egui::Color32::BLACK)).show(ctx, |ui| {
+            match show.shown.as_ref().filter(|(id, _)| *id == current) {
+                _ if hidden => {
+                    ui.centered_and_justified(|ui| ui.weak(t!("Содержимое скрыто: окно неактивно")));
+                }
+                Some((_, Ok(tex))) => {
+                    let size = tex.size_vec2();
+                    let avail = ui.available_size();
+                    let scale = (avail.x / size.x).min(avail.y / size.y).min(1.0);
+                    ui.centered_and_justified(|ui| ui.add(egui::Image::new(tex).fit_to_exact_size(size * scale)));
+                }
+                Some((_, Err(e))) => {
+                    ui.centered_and_justified(|ui| ui.colored_label(ui.visuals().error_fg_color, e.as_str()));
+                }
+                None => {
+                    ui.centered_and_justified(|ui| ui.spinner());
+                }
+            }
+        });
+
+        if close {
+            let current = show.images[show.index];
+            self.slideshow = None;
+            self.selection.clear();
+            self.selected_id = Some(current);
+            self.scroll_to_selected = true;
+        }
+    }
+
+    /// Показывает соседнее изображение и выделяет его в списке.
+    fn step_image(&mut self, ctx: &egui::Context, id: u64) {
+        self.selection.clear();
+        self.selected_id = Some(id);
+        self.scroll_to_selected = true;
+        self.load_viewer(ctx);
+    }
+
+    /// Показывает файл в шестнадцатеричном виде с начала.
+    fn open_hex(&mut self, size: u64) {
+        self.viewer_mode = ViewerMode::Hex;
+        self.viewer_hex = HexView { size, ..HexView::default() };
+        self.load_hex_page(0);
+    }
+
+    /// Читает страницу шестнадцатеричного просмотра, в которой лежит `offset`.
+    fn load_hex_page(&mut self, offset: u64) {
+        let (Some(sess), Some(id)) = (self.sess.as_ref(), self.viewer_id) else {
+            return;
+        };
+        let page = offset.min(self.viewer_hex.size.saturating_sub(1)) / HEX_PAGE * HEX_PAGE;
+        match container::read_file_range(sess, id, page, HEX_PAGE as usize) {
+            Ok(data) => {
+                self.viewer_hex.page = page;
+                self.viewer_hex.data = data;
+            }
+            Err(e) => self.viewer_error = t!("Ошибка чтения: {}", i18n::error(&e)),
+        }
+    }
+
+    /// Рисует страницу `viewer_page` PDF шириной `width` пикселей.
+    fn load_pdf_page(&mut self, ctx: &egui::Context, width: u32) {
+        self.viewer_texture = None;
+        let Some(doc) = &self.viewer_pdf else {
+            return;
+        };
+        match doc.render(self.viewer_page, width) {
+            Ok(img) => {
+                self.viewer_texture = Some(texture(ctx, "vault_pdf_page", &img));
+                self.viewer_error.clear();
+            }
+            Err(e) => self.viewer_error = t!("Страница не показана: {}", i18n::error(&e)),
+        }
+    }
+
+    /// В тексте есть несохранённые правки.
+    fn viewer_dirty(&self) -> bool {
+        self.viewer_mode == ViewerMode::Text && self.viewer_bytes.as_deref() != Some(self.viewer_text.as_bytes())
+    }
+
+    /// Показывает выбранный файл; несохранённые правки текста — только после
+    /// подтверждения.
+    fn view_selected(&mut self, ctx: &egui::Context) {
+        match self.viewer_dirty() {
+            true => self.confirm_discard = Some(Discard::View),
+            false => self.load_viewer(ctx),
+        }
+    }
+
+    /// Записывает текст редактора в файл; true, если записан.
+    fn save_viewer(&mut self) -> bool {
+        let (Some(sess), Some(id)) = (self.sess.as_mut(), self.viewer_id) else {
+            return false;
+        };
+        match container::replace_file(sess, &self.unlocked_password, id, self.viewer_text.as_bytes()) {
+            Ok(()) => {
+                self.viewer_bytes = Some(self.viewer_text.as_bytes().to_vec());
+                self.notify.info(t!("Сохранено"));
+                true
+            }
+            Err(e) => {
+                self.notify.error(t!("сохранение: {}", i18n::error(&e)));
+                false
+            }
+        }
+    }
+
+    fn render_confirm_discard(&mut self, ctx: &egui::Context) {
+        let Some(then) = self.confirm_discard else {
+            return;
+        };
+        let name = self.sess.as_ref().zip(self.viewer_id).and_then(|(s, id)| s.meta.get_node(id)).map_or("", |n| &n.name);
+        let mut choice = None;
+        egui::Window::new(t!("Несохранённые изменения")).collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.label(t!("Правки в «{}» не сохранены.", name));
+            if then == Discard::Lock {
+                ui.label(t!("Без сохранения они пропадут при закрытии контейнера."));
+            }
+            ui.horizontal(|ui| {
+                if then != Discard::Revert && ui.button(t!("Сохранить")).clicked() {
+                    choice = Some(true);
+                }
+                if ui.button(t!("Не сохранять")).clicked() {
+                    choice = Some(false);
+                }
+                if ui.button(t!("Отмена")).clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    self.confirm_discard = None;
+                }
+            });
+        });
+        let Some(save) = choice else {
+            return;
+        };
+        self.confirm_discard = None;
+        if save && !self.save_viewer() {
+            return;
+        }
+        match then {
+            Discard::View => self.load_viewer(ctx),
+            Discard::Revert => {
+                let saved = self.viewer_bytes.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+                self.viewer_text = saved.into_owned();
+                self.viewer_editing = false;
+            }
+            Discard::Lock => {
+                self.lock();
+                self.notify.info(t!("Контейнер закрыт"));
+            }
+        }
+    }
+
+    fn load_viewer(&mut self, ctx: &egui::Context) {
+        self.clear_viewer();
+
+        let Some(sess) = &self.sess else {
+            return;
+        };
+        let Some(id) = self.selected_id else {
+            return;
+        };
+        let Some(node) = sess.meta.get_node(id) else {
+            return;
+        };
+        if node.node_type != NodeType::File {
+            return;
+        }
+        self.viewer_id = Some(id);
+        // Просмотрщик выбирается по типу, определённому при импорте; без типа (старые
+        // метаданные) — пробуем текст, затем изображение.
+        let mime = node.mime.clone();
+        let (try_text, try_image, try_pdf) = match mime.as_deref() {
+            Some(m) => (mime::is_text(m), mime::is_viewable_image(m), m == "application/pdf"),
+            None => (true, true, true),
+        };
+        let size = node.size;
+        // Звук читается по мере проигрывания, какого бы размера ни был файл.
+        if mime.as_deref().is_some_and(mime::is_audio) {
+            match audio::Player::open(sess, id) {
+                Ok(player) => {
+                    self.viewer_audio = Some(player);
+                    self.viewer_mode = ViewerMode::Audio;
+                }
+                Err(e) => {
+                    self.viewer_error = t!("Звук не воспроизводится: {}; показан шестнадцатеричный вид", i18n::error(&e));
+                    self.open_hex(size);
+                }
+            }
+            return;
+        }
+        // Видео тоже; его проигрывает GStreamer, если программа собрана с ним.
+        if mime.as_deref().is_some_and(mime::is_video) {
+            match video::Player::open(ctx, sess, id) {
+                Ok(player) => {
+                    self.viewer_video = Some(player);
+                    self.viewer_mode = ViewerMode::Video;
+                }
+                Err(e) => {
+                    self.viewer_error = t!("Видео не воспроизводится: {}; показан шестнадцатеричный вид", i18n::error(&e));
+                    self.open_hex(size);
+                }
+            }
+            return;
+        }
+        // Остальное и слишком большие файлы — постранично, без чтения всего файла.
+        if !(try_text || try_image || try_pdf) || size > MAX_VIEW_BYTES {
+            self.viewer_error = match (size > MAX_VIEW_BYTES, mime) {
+                (true, _) => t!("Файл больше {}: показан шестнадцатеричный вид", size_text(MAX_VIEW_BYTES)),
+                (false, Some(m)) => t!("{}: показан шестнадцатеричный вид", m),
+                (false, None) => t!("Двоичный файл: показан шестнадцатеричный вид").to_string(),
+            };
+            self.open_hex(size);
+            return;
+        }
+
+        match container::read_file_bytes(sess, id) {
+            Ok(bytes) => {
+                // Text
+                if let Some(s) = try_text.then(|| std::str::from_utf8(&bytes).ok()).flatten() {
+                    self.viewer_mode = ViewerMode::Text;
+                    self.viewer_text = s.to_string();
+                    self.viewer_bytes = Some(bytes);
+                    self.viewer_markdown = match mime.as_deref() {
+                        Some(m) => m == "text/markdown",
+                        None => {
+                            let name = node.name.to_lowercase();
+                            name.ends_with(".md") || name.ends_with(".markdown")
+                        }
+                    };
+                    return;
+                }
+
+                // Image
+                if let Some(img) = try_image.then(|| image::load_from_memory(&bytes).ok()).flatten() {
+                    // Снимок с камеры показывается так, как его держали.
+                    self.viewer_exif = exif::read(&bytes);
+                    self.viewer_turns = self.viewer_exif.as_ref().map_or(0, |x| x.turns);
+                    self.show_image(ctx, img);
+                    self.viewer_mode = ViewerMode::Image;
+                    self.viewer_bytes = Some(bytes);
+                    return;
+                }
+
+                // PDF
+                if try_pdf {
+                    match pdf::Document::open(bytes.clone()) {
+                        Ok(doc) => {
+                            self.viewer_pdf = Some(doc);
+                            self.viewer_mode = ViewerMode::Pdf;
+                            self.viewer_bytes = Some(bytes);
+                            return;
+                        }
+                        // Без типа это мог быть и не PDF.
+                        Err(e) if mime.is_some() => {
+                            self.viewer_error = t!("PDF не открыт: {}; показан шестнадцатеричный вид", i18n::error(&e));
+                            self.open_hex(size);
+                            return;
+                        }
+                        Err(_) => {}
+                    }
+                }
+
+                self.viewer_error = match mime {
+                    Some(m) => t!("{}: показан шестнадцатеричный вид", m),
+                    None => t!("Двоичный файл: показан шестнадцатеричный вид").to_string(),
+                };
+                self.open_hex(size);
+            }
+            Err(e) => self.viewer_error = t!("Ошибка чтения: {}", i18n::error(&e)),
+        }
+    }
+}
+
+impl eframe::App for VaultApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.apply_style(ctx, frame);
+        self.apply_capture(frame);
+        // Слайд-шоу занимает всё окно; автопоказ не откладывает закрытие по бездействию.
+        if self.slideshow.is_some() {
+            self.auto_lock(ctx);
+            self.render_slideshow(ctx);
+            self.render_toasts(ctx);
+            return;
+        }
+        egui::TopBottomPanel::top("top").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Vault");
+                // Пока идёт операция, у неё сессия этой вкладки (или другой, при вставке).
+                let can_close = self.job.is_none() && (self.sess.is_some() || self.tabs.len() > 1);
+                if can_close && ui.button(t!("Закрыть")).on_hover_text("Ctrl+L").clicked() {
+                    self.request_lock();
+                }
+                if self.sess.is_some()
+                    && self.job.is_none()
+                    && ui.button(t!("Ещё контейнер")).on_hover_text(t!("Открыть другой контейнер в новой вкладке")).clicked()
+                {
+                    self.new_tab();
+                }
+                if ui.button(t!("Настройки")).clicked() {
+                    self.show_settings = !self.show_settings;
+                }
+                ui.separator();
+                let errors = self.notify.errors().len();
+                if errors > 0 && ui.button(t!("Ошибки: {}", errors)).on_hover_text(t!("Последние ошибки")).clicked() {
+                    self.show_errors = !self.show_errors;
+                }
+                let (can_undo, can_redo) = self.sess.as_ref().map_or((false, false), |s| (s.can_undo(), s.can_redo()));
+                if can_undo && ui.button(t!("Отменить")).on_hover_text("Ctrl+Z").clicked() {
+                    self.undo_redo(true);
+                }
+                if can_redo && ui.button(t!("Повторить")).on_hover_text("Ctrl+Shift+Z").clicked() {
+                    self.undo_redo(false);
+                }
+            });
+        });
+        if self.tabs.len() > 1 {
+            let mut switch = None;
+            egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+                ui.add_enabled_ui(self.job.is_none(), |ui| {
+                    ui.horizontal(|ui| {
+                        for i in 0..self.tabs.len() {
+                            if ui.selectable_label(i == self.tab, self.tab_title(i)).on_hover_text("Ctrl+Tab").clicked() {
+                                switch = Some(i);
+                            }
+                        }
+                    });
+                });
+            });
+            if let Some(i) = switch {
+                self.switch_tab(i);
+            }
+        }
+
+        self.auto_lock(ctx);
+        if let Some(left) = self.clipboard.poll() {
+            ctx.request_repaint_after(left);
+        }
+        self.poll_job();
+        self.render_toasts(ctx);
+        self.render_errors(ctx);
+        if self.job.is_some() {
+            self.render_job(ctx);
+            return;
+        }
+
+        self.render_recovery_code(ctx);
+        self.render_dupes(ctx);
+        self.render_usage(ctx);
+        self.render_drop(ctx);
+        self.render_confirm_delete(ctx);
+        self.render_confirm_export(ctx);
+        self.render_confirm_discard(ctx);
+        self.render_settings(ctx);
+        self.render_change_password(ctx);
+        self.handle_keys(ctx);
+
+        if self.sess.is_none() {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.heading(t!("Открыть / создать контейнер"));
+                ui.add_space(10.0);
+
+                ui.horizontal(|ui| {
+                    ui.label("vault.dat:");
+                    ui.text_edit_singleline(&mut self.vault_path);
+                    if ui.button(t!("Выбрать")).clicked() {
+                        if let Some(p) = FileDialog::new().add_filter("vault", &["dat"]).pick_file() {
+                            self.vault_path = p.display().to_string();
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(t!("Ключевой файл:"));
+                    ui.add(egui::TextEdit::singleline(&mut self.keyfile_path).hint_text(t!("необязательно")));
+                    if ui.button(t!("Выбрать")).clicked() {
+                        if let Some(p) = FileDialog::new().pick_file() {
+                            self.keyfile_path = p.display().to_string();
+                        }
+                    }
+                    if !self.keyfile_path.is_empty() && ui.button(t!("Убрать")).clicked() {
+                        self.keyfile_path.clear();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(t!("Пароль:"));
+                    ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+                    if ui.button(t!("Открыть")).clicked() {
+                        self.open_vault_action();
+                    }
+                    ui.checkbox(&mut self.open_read_only, t!("Только чтение"));
+                    ui.checkbox(&mut self.open_with_token, t!("Токеном (пароль = PIN)"));
+                    ui.checkbox(&mut self.remember, t!("Запомнить на этом компьютере"));
+                });
+                ui.label(t!("Запомненный контейнер открывается кнопкой «Открыть» с пустым паролем."));
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label(t!("Новый пароль:"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.create_password)
+                            .password(true)
+                            .hint_text(t!("придумайте пароль")),
+                    );
+                    if ui.button(t!("Создать новый контейнер")).clicked() {
+                        self.create_vault_action();
+                    }
+                    ui.checkbox(&mut self.create_recovery_code, t!("С кодом восстановления"));
+                });
+                if !self.create_password.is_empty() {
+                    let est = strength::estimate(&self.create_password, &strength::path_words(&self.vault_path));
+                    ui.horizontal(|ui| {
+                        strength_bar(ui, &est);
+                        if est.is_weak() {
+                            ui.checkbox(&mut self.create_allow_weak, t!("Всё равно создать"));
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label(t!("Время разблокировки:"));
+                    ui.add(egui::Slider::new(&mut self.create_unlock_ms, 250..=5000).suffix(t!(" мс")));
+                    ui.label(t!("дольше — надёжнее против перебора пароля"));
+                });
+
+                ui.add_space(12.0);
+                ui.label(t!("Примечание: 100% 'без следов' на ПК гарантировать нельзя. В этом GUI нет 'Открыть во внешней программе' — чтобы уменьшить утечки/следы."));
+            });
+            return;
+        }
+
+        egui::TopBottomPanel::bottom("stats").show(ctx, |ui| {
+            if let Some(sess) = &self.sess {
+                let key = (self.current_dir_id, sess.meta.generation);
+                if self.stats_line.as_ref().map(|(k, _)| *k) != Some(key) {
+                    let dir = sess.meta.dir_stats(self.current_dir_id).unwrap_or_default();
+                    let all = sess.meta.stats();
+                    let text = t!(
+                        "Папка: {} файлов, {} папок, {} (в контейнере {}), вложенность {}  |  Всего: {} файлов, {} (в контейнере {})",
+                        dir.files,
+                        dir.dirs,
+                        size_text(dir.size),
+                        size_text(dir.stored),
+                        dir.depth,
+                        all.files,
+                        size_text(all.size),
+                        size_text(all.stored),
+                    );
+                    self.stats_line = Some((key, text));
+                }
+                if let Some((_, text)) = &self.stats_line {
+                    ui.label(text);
+                }
+            }
+        });
+
+        egui::SidePanel::left("left").resizable(true).show(ctx, |ui| {
+            ui.heading(t!("Папки"));
+            ui.separator();
+
+            let root = ui.button(t!("Корень"));
+            if let Some(sess) = &self.sess {
+                let root_id = sess.meta.root_id;
+                if root.clicked() {
+                    self.current_dir_id = root_id;
+                    self.selected_id = Some(root_id);
+                    self.listing = Listing::Dir;
+                }
+                if let Some(ids) = drop_target(ui, &root, root_id) {
+                    self.drop_move = Some((ids, root_id));
+                }
+            }
+
+            self.render_dir_tree(ui, 1);
+
+            ui.separator();
+            let trash_id = self.sess.as_ref().map(|s| s.meta.trash_id).unwrap_or(0);
+            if trash_id != 0 && ui.button(t!("Корзина")).clicked() {
+                self.current_dir_id = trash_id;
+                self.selected_id = None;
+                self.listing = Listing::Dir;
+            }
+
+            let has_favorites = self.sess.as_ref().is_some_and(|s| s.meta.nodes.iter().any(|n| n.favorite));
+            if has_favorites {
+                ui.separator();
+                let selected = self.listing == Listing::Favorites;
+                if ui.selectable_label(selected, t!("★ Избранное")).clicked() {
+                    self.listing = if selected { Listing::Dir } else { Listing::Favorites };
+                }
+            }
+
+            // Метки: щелчок показывает все помеченные узлы из любых папок.
+            let tags = self.sess.as_ref().map(|s| s.meta.all_tags()).unwrap_or_default();
+            if !tags.is_empty() {
+                ui.separator();
+                ui.heading(t!("Метки"));
+                for (tag, count) in tags {
+                    let selected = self.listing == Listing::Tag(tag.clone());
+                    if ui.selectable_label(selected, format!("{tag} ({count})")).clicked() {
+                        self.listing = if selected { Listing::Dir } else { Listing::Tag(tag) };
+                    }
+                }
+            }
+
+            // Поиск по тексту файлов, если индекс включён.
+            if self.sess.as_ref().is_some_and(|s| s.meta.fts.is_some()) {
+                ui.separator();
+                ui.heading(t!("Поиск"));
+                let edit = ui.add(egui::TextEdit::singleline(&mut self.search_box).hint_text(t!("слова из текста")));
+                if std::mem::take(&mut self.focus_search) {
+                    edit.request_focus();
+                }
+                let enter = edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if (ui.button(t!("Найти")).clicked() || enter) && !self.search_box.trim().is_empty() {
+                    self.listing = Listing::Search(self.search_box.trim().to_string());
+                }
+            }
+        });
+
+        let hidden = self.content_hidden(ctx);
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // Сначала собираем действия (клики) в переменные, а изменения контейнера делаем ПОСЛЕ ui.horizontal.
+            let mut do_mkdir: Option<String> = None;
+            let mut do_new_note: bool = false;
+            let mut do_import: Option<Vec<PathBuf>> = None;
+            let mut do_export: bool = false;
+            let mut export_to: Option<(u64, PathBuf)> = None;
+            let mut export_many: Option<(Vec<(u64, String)>, PathBuf)> = None;
+            let mut do_move: Option<u64> = None;
+            let mut do_batch_tag: Option<bool> = None;
+            let mut do_delete: bool = false;
+            let mut do_copy: bool = false;
+            let mut do_paste: bool = false;
+            let mut do_view: bool = false;
+            let mut do_slideshow: bool = false;
+            let mut do_verify: bool = false;
+            let mut do_start_rename: bool = false;
+            let mut do_compact: bool = false;
+            let mut do_set_versioning: Option<bool> = None;
+            let mut versioning = self.sess.as_ref().is_some_and(|s| s.meta.versioning);
+            let mut do_set_search_index: Option<bool> = None;
+            let mut search_index = self.sess.as_ref().is_some_and(|s| s.meta.fts.is_some());
+            let mut do_untrash: bool = false;
+            let mut do_empty_trash: bool = false;
+            let mut do_enroll_token: bool = false;
+            let mut do_remove_token: bool = false;
+            let mut do_forget: bool = false;
+            // В режиме только чтения изменяющие кнопки неактивны.
+            let writable = self.sess.as_ref().is_some_and(|s| !s.read_only);
+            let in_trash = self
+                .sess
+                .as_ref()
+                .is_some_and(|s| s.meta.trash_id != 0 && s.meta.trash_id == self.current_dir_id);
+
+            if std::mem::take(&mut self.delete_confirmed) {
+                do_delete = true;
+            }
+
+            // Меню относится к отмеченным узлам, если узел среди них, иначе только к нему.
+            if let Some((id, action)) = self.menu_action.take() {
+                if !self.selection.contains(&id) {
+                    self.selection.clear();
+                }
+                self.selected_id = Some(id);
+                match action {
+                    MenuAction::Open => {
+                        self.current_dir_id = id;
+                        self.listing = Listing::Dir;
+                    }
+                    MenuAction::View => do_view = true,
+                    MenuAction::Export => do_export = true,
+                    MenuAction::Rename => do_start_rename = true,
+                    MenuAction::Move(dir_id) => do_move = Some(dir_id),
+                    MenuAction::Copy => self.copy_contents(id),
+                    MenuAction::Delete => self.confirm_delete = true,
+                    MenuAction::Properties => self.show_props = true,
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.label(t!("Текущая папка: id={}", self.current_dir_id));
+
+                ui.separator();
+                ui.label(t!("Новая папка:"));
+                ui.text_edit_singleline(&mut self.new_folder_name);
+                if ui.add_enabled(writable, egui::Button::new(t!("Создать"))).clicked() {
+                    do_mkdir = Some(self.new_folder_name.trim().to_string());
+                }
+                // Заметка создаётся в показанной папке, поэтому не в корзине и не в
+                // списке меток или поиска.
+                let in_dir = self.listing == Listing::Dir && !in_trash;
+                if ui.add_enabled(writable && in_dir, egui::Button::new(t!("Новая заметка"))).clicked() {
+                    do_new_note = true;
                 }
 
-                if ui.button("Экспорт").clicked() {
+                ui.separator();
+
+                if ui.add_enabled(writable, egui::Button::new(t!("Импорт файла"))).clicked() {
+                    if let Some(p) = FileDialog::new().pick_files() {
+                        do_import = Some(p);
+                    }
+                }
+                ui.checkbox(&mut self.compress_imports, t!("Сжимать (zstd)"));
+
+                if ui.button(t!("Экспорт")).clicked() {
                     do_export = true;
                 }
 
-                if ui.button("Переименовать").clicked() {
+                if ui.add_enabled(writable, egui::Button::new(t!("Переименовать"))).clicked() {
                     do_start_rename = true;
                 }
-
-                if ui.button("Удалить").clicked() {
-                    do_delete = true;
+
+                if ui.add_enabled(writable, egui::Button::new(t!("Удалить"))).clicked() {
+                    self.confirm_delete = true;
+                }
+                // Копирование между контейнерами, открытыми во вкладках.
+                if self.tabs.len() > 1 {
+                    let copy = ui.button(t!("Копировать")).on_hover_text(t!("Отмеченное — для вставки в другой контейнер"));
+                    do_copy = copy.clicked();
+                    let paste = egui::Button::new(t!("Вставить"));
+                    do_paste = ui.add_enabled(writable && in_dir && self.can_paste(), paste).clicked();
+                }
+                if in_trash {
+                    ui.checkbox(&mut self.shred_on_delete, t!("Затирать"));
+                    if ui.add_enabled(writable, egui::Button::new(t!("Восстановить"))).clicked() {
+                        do_untrash = true;
+                    }
+                    if ui.add_enabled(writable, egui::Button::new(t!("Очистить корзину"))).clicked() {
+                        do_empty_trash = true;
+                    }
+                }
+
+                if ui.button(t!("Просмотр")).clicked() {
+                    do_view = true;
+                }
+                if ui.button(t!("Слайд-шоу")).on_hover_text(t!("Изображения списка во всё окно")).clicked() {
+                    do_slideshow = true;
+                }
+                ui.checkbox(&mut self.grid, t!("Миниатюры"));
+                if ui.button(t!("Проверить целостность")).clicked() {
+                    do_verify = true;
+                }
+                if ui.button(t!("Дубликаты")).clicked() {
+                    self.show_dupes = true;
+                }
+                if ui.button(t!("Место")).on_hover_text(t!("Сколько занимает контейнер и сколько можно освободить")).clicked() {
+                    self.show_usage = true;
+                }
+
+                ui.separator();
+
+                if ui.add_enabled(writable, egui::Button::new(t!("Сжать контейнер"))).clicked() {
+                    do_compact = true;
+                }
+
+                if ui.add_enabled(writable, egui::Checkbox::new(&mut versioning, t!("Хранить версии"))).changed() {
+                    do_set_versioning = Some(versioning);
+                }
+                let search_check = egui::Checkbox::new(&mut search_index, t!("Поиск по тексту"));
+                if ui.add_enabled(writable, search_check).on_hover_text(t!("Индекс слов текстовых файлов")).changed() {
+                    do_set_search_index = Some(search_index);
+                }
+
+                ui.separator();
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.token_pin)
+                        .password(true)
+                        .desired_width(60.0)
+                        .hint_text("PIN"),
+                );
+                if ui.add_enabled(writable, egui::Button::new(t!("Привязать токен"))).clicked() {
+                    do_enroll_token = true;
+                }
+                if ui.add_enabled(writable, egui::Button::new(t!("Отвязать токен"))).clicked() {
+                    do_remove_token = true;
+                }
+                if ui.add_enabled(writable, egui::Button::new(t!("Забыть вход без пароля"))).clicked() {
+                    do_forget = true;
+                }
+                if ui.add_enabled(writable, egui::Button::new(t!("Сменить пароль…"))).clicked() {
+                    self.show_change_password = true;
+                }
+            });
+
+            // Имя правится прямо в строке списка (см. rename_field).
+            if do_start_rename {
+                let node = self.sess.as_ref().zip(self.selected_id).and_then(|(s, id)| s.meta.get_node(id));
+                self.renaming = node.map(|n| (n.id, n.name.clone()));
+            }
+
+            // Пакетные операции над отмеченными узлами.
+            if self.selection.len() > 1 {
+                let dirs = self.sess.as_ref().map(|s| move_targets(&s.meta)).unwrap_or_default();
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(t!("Отмечено: {}", self.selection.len()));
+                    if ui.button(t!("Снять отметки")).clicked() {
+                        self.selection.clear();
+                    }
+
+                    ui.separator();
+                    let target = dirs.iter().find(|(_, id)| Some(*id) == self.move_to).map(|(path, _)| path.as_str());
+                    egui::ComboBox::from_id_source("move_to")
+                        .selected_text(target.unwrap_or(t!("папка…")))
+                        .show_ui(ui, |ui| {
+                            for (path, id) in &dirs {
+                                ui.selectable_value(&mut self.move_to, Some(*id), path);
+                            }
+                        });
+                    if ui.add_enabled(writable && target.is_some(), egui::Button::new(t!("Переместить"))).clicked() {
+                        do_move = self.move_to;
+                    }
+
+                    ui.separator();
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.batch_tag)
+                            .desired_width(100.0)
+                            .hint_text(t!("метка")),
+                    );
+                    if ui.add_enabled(writable, egui::Button::new(t!("Добавить метку"))).clicked() {
+                        do_batch_tag = Some(true);
+                    }
+                    if ui.add_enabled(writable, egui::Button::new(t!("Снять метку"))).clicked() {
+                        do_batch_tag = Some(false);
+                    }
+                });
+            }
+
+            // Выполняем операции над контейнером здесь (нет borrow-конфликтов с egui).
+            let ids = self.selected_ids();
+            if let Some(dir_id) = do_move {
+                self.move_nodes(&ids, dir_id);
+            }
+            if let (true, Some(sess)) = (do_copy, &self.sess) {
+                match ids.is_empty() {
+                    true => self.notify.warn(t!("Ничего не выбрано")),
+                    false => self.notify.info(t!("Скопировано узлов: {}; вставьте их в другой вкладке", ids.len())),
+                }
+                self.copied = Some((sess.path.clone(), ids.clone())).filter(|_| !ids.is_empty());
+            }
+            if do_paste {
+                self.paste_nodes(ctx);
+            }
+            if do_new_note {
+                self.new_note(ctx);
+            }
+            if let Some(sess) = self.sess.as_mut() {
+                if let Some(name) = do_mkdir {
+                    if name.trim().is_empty() {
+                        self.notify.warn(t!("Введите имя папки"));
+                    } else {
+                        match sess.mkdir(self.current_dir_id, name) {
+                            Ok(new_id) => {
+                                if let Err(e) = container::save_metadata(sess, &self.unlocked_password) {
+                                    self.notify.error(t!("сохранение: {}", i18n::error(&e)));
+                                } else {
+                                    self.new_folder_name.clear();
+                                    self.selected_id = Some(new_id);
+                                }
+                            }
+                            Err(e) => self.notify.error(t!("создание папки: {}", i18n::error(&e))),
+                        }
+                    }
+                }
+
+                if do_export {
+                    // Несколько файлов — в выбранную папку под своими именами, папки пропускаются.
+                    let files: Vec<(u64, String)> = ids
+                        .iter()
+                        .filter_map(|&id| sess.meta.get_node(id))
+                        .filter(|n| n.node_type == NodeType::File)
+                        .map(|n| (n.id, n.name.clone()))
+                        .collect();
+                    match (ids.len(), files.as_slice()) {
+                        (0, _) => self.notify.warn(t!("Выберите файл")),
+                        (_, []) => self.notify.warn(t!("Экспорт только для файлов")),
+                        (1, [(id, name)]) => {
+                            export_to = FileDialog::new().set_file_name(name).save_file().map(|p| (*id, p));
+                        }
+                        _ => export_many = FileDialog::new().pick_folder().map(|dir| (files, dir)),
+                    }
+                }
+
+                if do_verify && self.selected_id.is_none() {
+                    self.notify.warn(t!("Выберите файл"));
+                }
+
+                if do_delete {
+                    if !ids.is_empty() {
+                        // Вне корзины «Удалить» только перемещает в корзину. Отмеченные узлы
+                        // удаляются вместе или ни один.
+                        let shred = self.shred_on_delete;
+                        let res = in_transaction(sess, &self.unlocked_password, |sess, password| {
+                            for &id in &ids {
+                                if in_trash {
+                                    container::remove_node(sess, password, id, shred)?;
+                                } else if !sess.meta.is_in_trash(id) {
+                                    // Узел мог уйти в корзину вместе с отмеченной папкой.
+                                    sess.trash(id)?;
+                                }
+                            }
+                            Ok(())
+                        });
+                        match res {
+                            Ok(()) => {
+                                self.selected_id = None;
+                                self.selection.clear();
+                                self.viewer_mode = ViewerMode::None;
+                                self.viewer_bytes = None;
+                                self.viewer_editing = false;
+                                self.notify.info(if !in_trash {
+                                    t!("Перемещено в корзину")
+                                } else if self.shred_on_delete {
+                                    t!("Удалено и затёрто")
+                                } else {
+                                    t!("Удалено (место освободится после «Сжать контейнер»)")
+                                });
+                            }
+                            Err(e) => self.notify.error(t!("удаление: {}", i18n::error(&e))),
+                        }
+                    } else {
+                        self.notify.warn(t!("Ничего не выбрано"));
+                    }
+                }
+
+                if let Some(add) = do_batch_tag {
+                    let tag = self.batch_tag.trim().to_string();
+                    let res = in_transaction(sess, &self.unlocked_password, |sess, _| {
+                        for &id in &ids {
+                            match add {
+                                true => sess.meta.add_tag(id, &tag)?,
+                                false => sess.meta.remove_tag(id, &tag)?,
+                            };
+                        }
+                        Ok(())
+                    });
+                    match res {
+                        Ok(()) => self.batch_tag.clear(),
+                        Err(e) => self.notify.error(t!("метка: {}", i18n::error(&e))),
+                    }
+                }
+
+                if do_untrash {
+                    if let Some(id) = self.selected_id {
+                        match sess.meta.restore_from_trash(id) {
+                            Ok(parent_id) => match container::save_metadata(sess, &self.unlocked_password) {
+                                Ok(()) => self.notify.info(t!("Восстановлено в папку id={}", parent_id)),
+                                Err(e) => self.notify.error(t!("сохранение: {}", i18n::error(&e))),
+                            },
+                            Err(e) => self.notify.error(t!("восстановление: {}", i18n::error(&e))),
+                        }
+                    } else {
+                        self.notify.warn(t!("Ничего не выбрано"));
+                    }
+                }
+
+
+
+                if let Some(on) = do_set_versioning {
+                    sess.meta.versioning = on;
+                    if let Err(e) = container::save_metadata(sess, &self.unlocked_password) {
+                        sess.meta.versioning = !on;
+                        self.notify.error(t!("сохранение: {}", i18n::error(&e)));
+                    }
+                }
+
+
+                if do_enroll_token {
+                    // Токен должен быть вставлен: программа ответа (ykchalresp) вызывается сразу.
+                    let pin = Some(self.token_pin.as_str()).filter(|p| !p.is_empty());
+                    match container::add_token_slot(sess, &self.unlocked_password, None, pin) {
+                        Ok(()) => self.notify.info(t!("Токен привязан")),
+                        Err(e) => self.notify.error(t!("Не удалось привязать токен: {}", i18n::error(&e))),
+                    }
+                    self.token_pin.zeroize();
+                }
+
+                if do_forget {
+                    // Слот удаляется из заголовка, так что вход отзывается на всех компьютерах.
+                    let removed = container::remove_key_slots(sess, &self.unlocked_password, &[container::SlotKind::Remembered])
+                        .map_err(anyhow::Error::from);
+                    let deleted = keychain::delete(&sess.path);
+                    match (removed, deleted) {
+                        (Ok(_), Ok(_)) => self.notify.info(t!("Вход без пароля отозван")),
+                        (Err(e), _) | (_, Err(e)) => self.notify.error(t!("Не удалось отозвать вход: {}", i18n::error(&e))),
+                    }
+                }
+
+                if do_remove_token {
+                    match container::remove_key_slots(sess, &self.unlocked_password, container::TOKEN_SLOTS) {
+                        Ok(true) => self.notify.info(t!("Токен отвязан")),
+                        Ok(false) => self.notify.warn(t!("Токен не был привязан")),
+                        Err(e) => self.notify.error(t!("Не удалось отвязать токен: {}", i18n::error(&e))),
+                    }
+                }
+            }
+
+            // Долгие операции — в рабочем потоке (см. `Job`).
+            if let Some(paths) = do_import {
+                self.start_import(ctx, paths);
+            }
+
+            if let Some((id, out)) = export_to {
+                self.spawn_job(ctx, t!("Экспорт"), move |sess, _, progress| {
+                    progress.total(sess.meta.get_node(id).map_or(0, |n| n.size));
+                    container::export_file(sess, id, &out, progress)?;
+                    Ok(JobDone::info(t!("Экспортировано")))
+                });
+            }
+
+            // Один файл: о замене спрашивает сам диалог сохранения.
+            if let Some((files, dir)) = export_many {
+                let existing: Vec<String> = files.iter().map(|(_, name)| name).filter(|name| dir.join(name).exists()).cloned().collect();
+                match existing.is_empty() {
+                    true => self.export_many(ctx, files, dir, false),
+                    false => self.confirm_export = Some(ExportMany { files, dir, existing }),
+                }
+            }
+
+            if let (true, Some(id)) = (do_verify, self.selected_id) {
+                // Расшифровывает все чанки файла, ничего не записывая на диск.
+                self.spawn_job(ctx, t!("Проверка"), move |sess, _, _| {
+                    Ok(match container::verify_file(sess, id) {
+                        Ok(()) => JobDone::info(t!("Файл цел")),
+                        Err(e) => JobDone::error(t!("Файл повреждён: {}", i18n::error(&e))),
+                    })
+                });
+            }
+
+            if do_empty_trash {
+                self.empty_trash(ctx);
+            }
+
+            if do_compact {
+                self.compact(ctx);
+            }
+
+            if let Some(on) = do_set_search_index {
+                self.spawn_job(ctx, t!("Индекс поиска"), move |sess, password, _| {
+                    let count = container::set_search_index(sess, password, on)?;
+                    Ok(match on {
+                        true => JobDone::info(t!("Проиндексировано текстовых файлов: {}", count)),
+                        false => JobDone::info(t!("Индекс поиска удалён")).then(|app| app.listing = Listing::Dir),
+                    })
+                });
+            }
+
+            if do_view {
+                self.view_selected(ctx);
+            }
+            if do_slideshow {
+                self.start_slideshow(ctx);
+            }
+
+            ui.separator();
+            let heading = match &self.listing {
+                Listing::Dir => None,
+                Listing::Tag(tag) => Some(t!("Метка «{}»", tag)),
+                Listing::Search(query) => Some(t!("Поиск «{}»", query)),
+                Listing::Favorites => Some(t!("Избранное").to_string()),
+            };
+            match heading {
+                Some(heading) => {
+                    ui.horizontal(|ui| {
+                        ui.heading(heading);
+                        if ui.button(t!("Показать папку")).clicked() {
+                            self.listing = Listing::Dir;
+                        }
+                    });
+                }
+                None => {
+                    ui.heading(t!("Содержимое"));
+                }
+            }
+
+            let rows = self.rows();
+            // Отметки не переживают переход в другую папку.
+            if !self.selection.is_empty() {
+                let listed: HashSet<u64> = rows.iter().copied().collect();
+                self.selection.retain(|id| listed.contains(id));
+            }
+            if self.renaming.as_ref().is_some_and(|(id, _)| !rows.contains(id)) {
+                self.renaming = None;
+            }
+
+            // Размечаются только видимые строки: в сетке строка — ряд ячеек, в таблице
+            // первая строка — заголовок.
+            let spacing = ui.spacing().item_spacing;
+            let padding = ui.spacing().button_padding;
+            let (row_height, per_row, lines) = match self.grid {
+                true => {
+                    let step = THUMB_CELL + 2.0 * padding.x + spacing.x;
+                    let per_row = (((ui.available_width() + spacing.x) / step).floor() as usize).max(1);
+                    (THUMB_CELL + 2.0 * padding.y, per_row, rows.len().div_ceil(per_row))
+                }
+                false => {
+                    let text = ui.text_style_height(&egui::TextStyle::Button) + 2.0 * padding.y;
+                    (text.max(ui.spacing().interact_size.y), 1, rows.len() + 1)
+                }
+            };
+            let selected_line = self
+                .selected_id
+                .and_then(|id| rows.iter().position(|&r| r == id))
+                .map(|p| if self.grid { p / per_row } else { p + 1 });
+
+            let mut sort = None;
+            let mut drop_move = None;
+            let mut rename_done = None;
+            egui::ScrollArea::vertical().show_rows(ui, row_height, lines, |ui, range| {
+                let mut clicked = None;
+                let mut name_clicked = None;
+                let mut menu = None;
+                // Выбранная строка может быть ещё не размечена: прокручивается её место.
+                if let (true, Some(line)) = (std::mem::take(&mut self.scroll_to_selected), selected_line) {
+                    let top = ui.max_rect().top() + (line as f32 - range.start as f32) * (row_height + spacing.y);
+                    ui.scroll_to_rect(egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), top..=top + row_height), None);
+                }
+                let marked = |id: u64| match self.selection.is_empty() {
+                    true => self.selected_id == Some(id),
+                    false => self.selection.contains(&id),
+                };
+                let Some(meta) = self.sess.as_ref().map(|s| &s.meta) else {
+                    return;
+                };
+                // Вне папки вместо имени показывается путь от корня.
+                let shown = |from: usize, to: usize| {
+                    let ids = &rows[from.min(rows.len())..to.min(rows.len())];
+                    ids.iter().filter_map(|&id| meta.get_node(id)).map(|n| match self.listing {
+                        Listing::Dir => (n.name.clone(), n),
+                        _ => (meta.path_of(n.id), n),
+                    })
+                };
+                if self.grid {
+                    // Изображения — миниатюрами, остальное — значком с именем; миниатюры
+                    // расшифровываются, когда ряд впервые показан.
+                    let cell = egui::vec2(THUMB_CELL, THUMB_CELL);
+                    for line in range {
+                        ui.horizontal(|ui| {
+                            for (name, n) in shown(line * per_row, (line + 1) * per_row) {
+                                if let Some((_, text)) = self.renaming.as_mut().filter(|(id, _)| *id == n.id) {
+                                    ui.allocate_ui(cell, |ui| {
+                                        rename_done = rename_field(ui, text, do_start_rename, THUMB_CELL);
+                                    });
+                                    continue;
+                                }
+                                let selected = marked(n.id);
+                                let tex = match (&n.thumbnail, self.sess.as_ref()) {
+                                    _ if hidden => None,
+                                    (Some(ch), Some(sess)) => self.thumbs.entry((n.id, ch.offset)).or_insert_with(|| load_thumb(ctx, sess, n.id)).clone(),
+                                    _ => None,
+                                };
+                                let resp = match tex {
+                                    Some(tex) => {
+                                        let image = egui::Image::new(&tex).fit_to_exact_size(cell);
+                                        ui.add(egui::ImageButton::new(image).selected(selected))
+                                    }
+                                    None => {
+                                        let icon = match n.node_type {
+                                            NodeType::Dir => "📁",
+                                            NodeType::File => mime::icon(n.mime.as_deref()),
+                                        };
+                                        ui.add_sized(cell, egui::SelectableLabel::new(selected, format!("{icon}\n{name}")))
+                                    }
+                                };
+                                let resp = resp.on_hover_text(name.as_str()).interact(egui::Sense::drag());
+                                if let Some(dropped) = drag_row(ui, &resp, n, &self.selection, writable) {
+                                    drop_move = Some(dropped);
+                                }
+                                if resp.clicked() {
+                                    clicked = Some(n);
+                                    name_clicked = Some(n.id);
+                                }
+                                resp.context_menu(|ui| menu = node_menu(ui, meta, n, writable).or(menu));
+                            }
+                        });
+                    }
+                } else {
+                    // Таблица; щелчок по любой ячейке строки выбирает узел.
+                    let grid = egui::Grid::new("listing").num_columns(4).striped(true).spacing([16.0, spacing.y]);
+                    grid.start_row(range.start).show(ui, |ui| {
+                        if range.start == 0 {
+                            use fsmeta::SortKey;
+                            for (key, text) in [
+                                (SortKey::Name, t!("Имя")),
+                                (SortKey::Type, t!("Тип")),
+                                (SortKey::Size, t!("Размер")),
+                                (SortKey::Modified, t!("Изменён (UTC)")),
+                            ] {
+                                sort = sort_header(ui, meta.sort, key, text).or(sort);
+                            }
+                            ui.end_row();
+                        }
+                        for (name, n) in shown(range.start.saturating_sub(1), range.end.saturating_sub(1)) {
+                            let selected = marked(n.id);
+                            let star = if n.favorite { " ★" } else { "" };
+                            let (icon, kind, size) = match n.node_type {
+                                NodeType::Dir => ("📁", t!("папка").to_string(), String::new()),
+                                NodeType::File => (
+                                    mime::icon(n.mime.as_deref()),
+                                    n.mime.clone().unwrap_or_else(|| "—".into()),
+                                    size_text(n.size),
+                                ),
+                            };
+                            let modified = match n.modified_at {
+                                0 => String::new(),
+                                at => fsmeta::format_time(at),
+                            };
+                            // Правящееся имя не выбирает строку и не тянется.
+                            let name_cell = match self.renaming.as_mut().filter(|(id, _)| *id == n.id) {
+                                Some((_, text)) => {
+                                    rename_done = rename_field(ui, text, do_start_rename, 200.0);
+                                    None
+                                }
+                                None => Some(ui.selectable_label(selected, format!("{icon} {name}{star}")).on_hover_text(format!("id={}", n.id))),
+                            };
+                            let resp = ui.selectable_label(selected, kind)
+                                | ui.selectable_label(selected, size)
+                                | ui.selectable_label(selected, modified);
+                            ui.end_row();
+                            if name_cell.as_ref().is_some_and(|r| r.clicked()) {
+                                name_clicked = Some(n.id);
+                            }
+                            let resp = match name_cell {
+                                Some(name_cell) => name_cell | resp,
+                                None => resp,
+                            };
+                            let resp = resp.interact(egui::Sense::drag());
+                            if let Some(dropped) = drag_row(ui, &resp, n, &self.selection, writable) {
+                                drop_move = Some(dropped);
+                            }
+                            if resp.clicked() {
+                                clicked = Some(n);
+                            }
+                            resp.context_menu(|ui| menu = node_menu(ui, meta, n, writable).or(menu));
+                        }
+                    });
+                }
+                if menu.is_some() {
+                    self.menu_action = menu;
+                    ctx.request_repaint();
                 }
-
-                if ui.button("Просмотр").clicked() {
-                    do_view = true;
+                let modifiers = ui.input(|i| i.modifiers);
+                match clicked {
+                    // Ctrl отмечает узел или снимает отметку, Shift отмечает диапазон от
+                    // прошлого щелчка; папки при этом не открываются.
+                    Some(n) if modifiers.command || modifiers.shift => {
+                        let pos = |id: u64| rows.iter().position(|&r| r == id);
+                        match (modifiers.shift, self.selected_id.and_then(pos), pos(n.id)) {
+                            (true, Some(a), Some(b)) => self.selection.extend(&rows[a.min(b)..=a.max(b)]),
+                            _ => {
+                                if self.selection.is_empty() {
+                                    self.selection.extend(self.selected_id.filter(|&id| pos(id).is_some()));
+                                }
+                                if !self.selection.remove(&n.id) {
+                                    self.selection.insert(n.id);
+                                }
+                                self.selected_id = Some(n.id);
+                            }
+                        }
+                    }
+                    Some(n) => {
+                        // Второй неспешный щелчок по имени выбранного файла — не двойной —
+                        // начинает переименование, как в проводнике.
+                        let now = Instant::now();
+                        let again = self.selection.is_empty() && self.selected_id == Some(n.id) && name_clicked == Some(n.id);
+                        let slow = self.last_click.is_some_and(|(id, at)| id == n.id && (SLOW_CLICK_MIN..=SLOW_CLICK_MAX).contains(&(now - at)));
+                        if again && slow && writable && n.node_type == NodeType::File {
+                            self.menu_action = Some((n.id, MenuAction::Rename));
+                            ctx.request_repaint();
+                        }
+                        self.last_click = name_clicked.map(|id| (id, now));
+                        self.selection.clear();
+                        self.selected_id = Some(n.id);
+                        if n.node_type == NodeType::Dir {
+                            self.current_dir_id = n.id;
+                            self.listing = Listing::Dir;
+                        }
+                    }
+                    None => {}
                 }
             });
 
-            // start rename
-            if do_start_rename {
-                self.rename_to = self.selected_node_name();
+            if let Some(commit) = rename_done {
+                self.finish_rename(commit);
             }
 
-            // rename editor
-            if !self.rename_to.is_empty() {
-                ui.separator();
-                ui.horizontal(|ui| {
-                    ui.label("Новое имя:");
-                    ui.text_edit_singleline(&mut self.rename_to);
-                    if ui.button("OK").clicked() {
-                        do_apply_rename = true;
-                    }
-                    if ui.button("Отмена").clicked() {
-                        self.rename_to.clear();
-                    }
+            // Брошенное на папку в дереве или в списке.
+            if let Some((ids, dir_id)) = drop_move.or(self.drop_move.take()) {
+                self.move_nodes(&ids, dir_id);
+            }
+            if let Some(dragged) = egui::DragAndDrop::payload::<DraggedNodes>(ctx) {
+                egui::show_tooltip_at_pointer(ctx, egui::Id::new("dragged"), |ui| {
+                    ui.label(t!("Перемещение: {}", dragged.0.len()));
                 });
             }
 
-            // Выполняем операции над контейнером здесь (нет borrow-конфликтов с egui).
-            if let Some(sess) = self.sess.as_mut() {
-                if let Some(name) = do_mkdir {
-                    if name.trim().is_empty() {
-                        self.status = "Введите имя папки".to_string();
-                    } else {
-                        match sess.meta.mkdir(self.current_dir_id, name) {
-                            Ok(new_id) => {
-                                if let Err(e) = container::save_metadata(sess, &self.unlocked_password) {
-                                    self.status = format!("save: {e}");
-                                } else {
-                                    self.new_folder_name.clear();
-                                    self.selected_id = Some(new_id);
-                                    self.status.clear();
-                                }
-                            }
-                            Err(e) => self.status = format!("mkdir: {e}"),
-                        }
+            // Порядок хранится в метаданных контейнера; только для чтения — до закрытия.
+            if let (Some(order), Some(sess)) = (sort, self.sess.as_mut()) {
+                sess.meta.sort = order;
+                if writable {
+                    if let Err(e) = container::save_metadata(sess, &self.unlocked_password) {
+                        self.notify.error(t!("сортировка: {}", i18n::error(&e)));
                     }
                 }
+            }
 
-                if let Some(p) = do_import {
-                    match container::import_file(sess, &self.unlocked_password, &p, self.current_dir_id, None) {
-                        Ok(id) => {
-                            self.selected_id = Some(id);
-                            self.status.clear();
-                        }
-                        Err(e) => self.status = format!("import: {e}"),
+            // Сведения о выбранном узле (время — UTC).
+            let mut do_tag: Option<String> = None;
+            let mut do_untag: Option<String> = None;
+            let mut do_favorite: Option<bool> = None;
+            let single = self.selection.len() <= 1;
+            if let Some(n) = self.sess.as_ref().zip(self.selected_id.filter(|_| single)).and_then(|(s, id)| s.meta.get_node(id)) {
+                ui.separator();
+                let open = std::mem::take(&mut self.show_props).then_some(true);
+                egui::CollapsingHeader::new(t!("Сведения")).open(open).show(ui, |ui| {
+                    let mut favorite = n.favorite;
+                    if ui.add_enabled(writable, egui::Checkbox::new(&mut favorite, t!("★ В избранном"))).changed() {
+                        do_favorite = Some(favorite);
                     }
-                }
-
-                if do_export {
-                    if let Some(id) = self.selected_id {
-                        if let Some(node) = sess.meta.get_node(id) {
-                            if node.node_type != NodeType::File {
-                                self.status = "Экспорт только для файлов".to_string();
-                            } else if let Some(out) =
-                                FileDialog::new().set_file_name(&node.name).save_file()
-                            {
-                                if let Err(e) = container::export_file(sess, id, &out) {
-                                    self.status = format!("export: {e}");
-                                } else {
-                                    self.status = "Экспортировано".to_string();
-                                }
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(t!("Метки:"));
+                        for t in &n.tags {
+                            let remove = ui.add_enabled(writable, egui::Button::new(format!("{t} ✕")));
+                            if remove.on_hover_text(t!("Снять метку")).clicked() {
+                                do_untag = Some(t.clone());
                             }
-                        } else {
-                            self.status = "Не найдено".to_string();
                         }
-                    } else {
-                        self.status = "Выберите файл".to_string();
-                    }
-                }
-
-                if do_delete {
-                    if let Some(id) = self.selected_id {
-                        match sess.meta.remove_subtree(id) {
-                            Ok(()) => match container::save_metadata(sess, &self.unlocked_password) {
-                                Ok(()) => {
-                                    self.selected_id = None;
-                                    self.viewer_mode = ViewerMode::None;
-                                    self.viewer_bytes = None;
-                                    self.status = "Удалено (MVP: место в контейнере не очищается)".to_string();
-                                }
-                                Err(e) => self.status = format!("save: {e}"),
-                            },
-                            Err(e) => self.status = format!("delete: {e}"),
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.new_tag)
+                                .desired_width(100.0)
+                                .hint_text(t!("новая метка")),
+                        );
+                        if ui.add_enabled(writable, egui::Button::new(t!("Добавить"))).clicked() {
+                            do_tag = Some(self.new_tag.trim().to_string());
                         }
-                    } else {
-                        self.status = "Ничего не выбрано".to_string();
+                    });
+                    if n.node_type == NodeType::File {
+                        ui.label(t!("Размер: {} байт", n.size));
+                        ui.label(t!("Тип: {}", n.mime.as_deref().unwrap_or("неизвестен")));
                     }
-                }
-
-                if do_apply_rename {
-                    if let Some(id) = self.selected_id {
-                        match sess.meta.rename(id, self.rename_to.trim().to_string()) {
-                            Ok(()) => match container::save_metadata(sess, &self.unlocked_password) {
-                                Ok(()) => {
-                                    self.rename_to.clear();
-                                    self.status.clear();
-                                }
-                                Err(e) => self.status = format!("save: {e}"),
-                            },
-                            Err(e) => self.status = format!("rename: {e}"),
-                        }
-                    } else {
-                        self.status = "Ничего не выбрано".to_string();
+                    if let Some(uuid) = n.uuid {
+                        ui.label(format!("UUID: {uuid}"));
                     }
-                }
+                    ui.label(t!("Добавлен: {} UTC", fsmeta::format_time(n.created_at)));
+                    ui.label(t!("Изменён: {} UTC", fsmeta::format_time(n.modified_at)));
+                    if let Some(t) = n.source_mtime {
+                        ui.label(t!("Изменён до импорта: {} UTC", fsmeta::format_time(t)));
+                    }
+                });
             }
 
-            if do_view {
-                self.load_viewer(ctx);
+            if let (Some(sess), Some(id), Some(on)) = (self.sess.as_mut(), self.selected_id, do_favorite) {
+                if let Err(e) = sess.meta.set_favorite(id, on).and_then(|()| container::save_metadata(sess, &self.unlocked_password)) {
+                    self.notify.error(t!("избранное: {}", i18n::error(&e)));
+                }
             }
 
-            ui.separator();
-            ui.heading("Содержимое");
+            if let (Some(sess), Some(id)) = (self.sess.as_mut(), self.selected_id) {
+                let res = match (&do_tag, &do_untag) {
+                    (Some(tag), _) => Some(sess.meta.add_tag(id, tag)),
+                    (None, Some(tag)) => Some(sess.meta.remove_tag(id, tag)),
+                    (None, None) => None,
+                };
+                match res.map(|r| r.and_then(|_| container::save_metadata(sess, &self.unlocked_password))) {
+                    Some(Ok(())) if do_tag.is_some() => self.new_tag.clear(),
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => self.notify.error(t!("метка: {}", i18n::error(&e))),
+                    None => {}
+                }
+            }
 
-            let children = self
+            // История версий выбранного файла.
+            let versions: Vec<u64> = self
                 .sess
                 .as_ref()
-                .map(|s| s.meta.children_of(self.current_dir_id))
+                .zip(self.selected_id)
+                .and_then(|(s, id)| s.meta.get_node(id))
+                .map(|n| n.versions.iter().map(|v| v.size).collect())
                 .unwrap_or_default();
+            if !versions.is_empty() {
+                let mut do_restore: Option<usize> = None;
+                // Восстановление перечитало бы файл поверх несохранённых правок.
+                let can_restore = writable && !self.viewer_dirty();
+                ui.separator();
+                ui.collapsing(t!("История версий ({})", versions.len()), |ui| {
+                    for (i, size) in versions.iter().enumerate().rev() {
+                        ui.horizontal(|ui| {
+                            ui.label(t!("Версия {}: {} байт", i + 1, size));
+                            if ui.add_enabled(can_restore, egui::Button::new(t!("Восстановить"))).clicked() {
+                                do_restore = Some(i + 1);
+                            }
+                        });
+                    }
+                });
 
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                for n in children {
-                    let label = match n.node_type {
-                        NodeType::Dir => format!("[DIR]  {} (id={})", n.name, n.id),
-                        NodeType::File => format!("[FILE] {} (id={}, {} bytes)", n.name, n.id, n.size),
-                    };
-                    let selected = self.selected_id == Some(n.id);
-                    if ui.selectable_label(selected, label).clicked() {
-                        self.selected_id = Some(n.id);
-                        if n.node_type == NodeType::Dir {
-                            self.current_dir_id = n.id;
+                if let (Some(v), Some(sess), Some(id)) = (do_restore, self.sess.as_mut(), self.selected_id) {
+                    match container::restore_version(sess, &self.unlocked_password, id, v) {
+                        Ok(()) => {
+                            self.notify.info(t!("Восстановлена версия {}", v));
+                            self.load_viewer(ctx);
                         }
+                        Err(e) => self.notify.error(t!("восстановление: {}", i18n::error(&e))),
                     }
                 }
-            });
+            }
 
             ui.separator();
-            ui.heading("Просмотр (внутри приложения)");
+            ui.heading(t!("Просмотр (внутри приложения)"));
             if !self.viewer_error.is_empty() {
                 ui.label(&self.viewer_error);
             }
 
             match self.viewer_mode {
+                _ if hidden => {
+                    ui.weak(t!("Содержимое скрыто: окно неактивно"));
+                }
                 ViewerMode::None => {
-                    ui.label("Выберите файл и нажмите 'Просмотр'.");
+                    ui.label(t!("Выберите файл и нажмите 'Просмотр'."));
                 }
                 ViewerMode::Text => {
-                    ui.add(
-                        egui::TextEdit::multiline(&mut self.viewer_text)
-                            .desired_rows(14)
-                            .code_editor(),
-                    );
+                    let dirty = self.viewer_dirty();
+                    let mut copy_text = false;
+                    ui.horizontal(|ui| {
+                        // Выйти из правки можно, только сохранив или отменив изменения.
+                        let toggle = egui::Checkbox::new(&mut self.viewer_editing, t!("Правка"));
+                        ui.add_enabled(writable && !dirty, toggle);
+                        if self.viewer_editing {
+                            let save = ui.add_enabled(dirty, egui::Button::new(t!("Сохранить")));
+                            if save.on_hover_text("Ctrl+S").clicked() {
+                                self.save_viewer();
+                            }
+                            if ui.add_enabled(dirty, egui::Button::new(t!("Отменить изменения"))).clicked() {
+                                self.confirm_discard = Some(Discard::Revert);
+                            }
+                        }
+                        if dirty {
+                            ui.colored_label(egui::Color32::from_rgb(220, 160, 0), t!("● не сохранено"));
+                        }
+                        copy_text = ui.button(t!("Копировать")).clicked();
+                        // Правится исходный текст.
+                        if self.viewer_markdown && !self.viewer_editing {
+                            ui.separator();
+                            ui.selectable_value(&mut self.viewer_source, false, t!("Оформление"));
+                            ui.selectable_value(&mut self.viewer_source, true, t!("Исходный текст"));
+                        }
+                    });
+                    if copy_text {
+                        let text = std::mem::take(&mut self.viewer_text);
+                        self.copy_to_clipboard(&text, t!("Текст"));
+                        self.viewer_text = text;
+                    }
+                    if self.viewer_markdown && !self.viewer_source && !self.viewer_editing {
+                        egui::ScrollArea::vertical().id_source("markdown").show(ui, |ui| {
+                            markdown_view(ui, &mut self.markdown, &self.viewer_text);
+                        });
+                    } else {
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.viewer_text)
+                                .desired_rows(14)
+                                .code_editor()
+                                .interactive(self.viewer_editing),
+                        );
+                    }
                 }
                 ViewerMode::Image => {
+                    let (prev, next) = self.image_neighbours();
+                    let mut step = None;
+                    let mut turn = None;
+                    let bar = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+                    let avail = ui.available_size() - egui::vec2(0.0, bar);
+                    let fit = self.viewer_texture.as_ref().map_or(1.0, |tex| {
+                        let size = tex.size_vec2();
+                        (avail.x / size.x).min(avail.y / size.y).min(1.0)
+                    });
+                    let scale = self.viewer_zoom.unwrap_or(fit);
+                    let mut zoom = None;
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(prev.is_some(), egui::Button::new("◀")).on_hover_text("PageUp").clicked() {
+                            step = prev;
+                        }
+                        if ui.add_enabled(next.is_some(), egui::Button::new("▶")).on_hover_text("PageDown").clicked() {
+                            step = next;
+                        }
+                        ui.separator();
+                        if ui.button("⟲").on_hover_text(t!("Повернуть против часовой")).clicked() {
+                            turn = Some(false);
+                        }
+                        if ui.button("⟳").on_hover_text(t!("Повернуть по часовой")).clicked() {
+                            turn = Some(true);
+                        }
+                        ui.separator();
+                        if ui.button("−").clicked() {
+                            zoom = Some((scale / ZOOM_STEP).max(MIN_ZOOM));
+                        }
+                        ui.label(format!("{:.0}%", scale * 100.0));
+                        if ui.button("+").clicked() {
+                            zoom = Some((scale * ZOOM_STEP).min(MAX_ZOOM));
+                        }
+                        if ui.selectable_label(self.viewer_zoom.is_none(), t!("По размеру")).clicked() {
+                            self.viewer_zoom = None;
+                            self.viewer_pan = egui::Vec2::ZERO;
+                        }
+                        ui.separator();
+                        ui.toggle_value(&mut self.show_exif, "EXIF");
+                    });
                     if let Some(tex) = &self.viewer_texture {
-                        let avail = ui.available_size();
-                        let mut size = tex.size_vec2();
-                        let scale = (avail.x / size.x).min(avail.y / size.y).min(1.0);
-                        size *= scale;
-                        ui.add(egui::Image::new(tex).fit_to_exact_size(size));
+                        // Колесо мыши приближает к указателю, перетаскивание сдвигает,
+                        // двойной щелчок возвращает «по размеру».
+                        let (rect, resp) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+                        let size = tex.size_vec2();
+                        let wheel = match resp.hovered() {
+                            true => ui.input(|i| i.smooth_scroll_delta.y),
+                            false => 0.0,
+                        };
+                        if wheel != 0.0 {
+                            zoom = Some((scale * (wheel / 200.0).exp()).clamp(MIN_ZOOM, MAX_ZOOM));
+                        }
+                        if let Some(new) = zoom {
+                            let anchor = resp.hover_pos().filter(|_| wheel != 0.0).map_or(egui::Vec2::ZERO, |p| p - rect.center());
+                            self.viewer_pan = anchor - (anchor - self.viewer_pan) * (new / scale);
+                            self.viewer_zoom = Some(new);
+                        }
+                        if resp.dragged() {
+                            self.viewer_pan += resp.drag_delta();
+                        }
+                        if resp.double_clicked() {
+                            self.viewer_zoom = None;
+                            self.viewer_pan = egui::Vec2::ZERO;
+                        }
+                        let shown = size * self.viewer_zoom.unwrap_or(fit);
+                        // Хотя бы край изображения остаётся в окне.
+                        let limit = ((shown + rect.size()) / 2.0 - egui::vec2(32.0, 32.0)).max(egui::Vec2::ZERO);
+                        self.viewer_pan = self.viewer_pan.clamp(-limit, limit);
+                        let image = egui::Rect::from_center_size(rect.center() + self.viewer_pan, shown);
+                        let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                        ui.painter_at(rect).image(tex.id(), image, uv, egui::Color32::WHITE);
+                        if resp.dragged() {
+                            ui.ctx().set_cursor_icon(egui::CursorIcon::Grabbing);
+                        } else if resp.hovered() && (shown.x > rect.width() || shown.y > rect.height()) {
+                            ui.ctx().set_cursor_icon(egui::CursorIcon::Grab);
+                        }
+                        if self.show_exif {
+                            exif_overlay(ui, rect, self.viewer_exif.as_ref());
+                        }
                     } else {
-                        ui.label("(не удалось загрузить изображение)");
+                        ui.label(t!("(не удалось загрузить изображение)"));
+                    }
+                    if let Some(clockwise) = turn {
+                        self.rotate_image(ctx, clockwise);
+                    }
+                    if let Some(id) = step {
+                        self.step_image(ctx, id);
+                    }
+                }
+                ViewerMode::Pdf => {
+                    let pages = self.viewer_pdf.as_ref().map_or(0, pdf::Document::page_count);
+                    let mut page = self.viewer_page;
+                    let bar = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+                    let avail = ui.available_size() - egui::vec2(0.0, bar);
+                    // размер страницы при 100%
+                    let size = self
+                        .viewer_pdf
+                        .as_ref()
+                        .and_then(|doc| doc.page_size(self.viewer_page).ok())
+                        .map_or(egui::vec2(1.0, 1.0), |(w, h)| egui::vec2(w.max(1.0), h.max(1.0)));
+                    let fit = (avail.x / size.x).min(avail.y / size.y).max(MIN_ZOOM);
+                    let scale = self.viewer_zoom.unwrap_or(fit);
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(page > 0, egui::Button::new("◀")).on_hover_text("PageUp").clicked() {
+                            page -= 1;
+                        }
+                        ui.label(t!("Страница {} из {}", page + 1, pages));
+                        if ui.add_enabled(page + 1 < pages, egui::Button::new("▶")).on_hover_text("PageDown").clicked() {
+                            page += 1;
+                        }
+                        ui.separator();
+                        if ui.button("−").clicked() {
+                            self.viewer_zoom = Some((scale / ZOOM_STEP).max(MIN_ZOOM));
+                        }
+                        ui.label(format!("{:.0}%", scale * 100.0));
+                        if ui.button("+").clicked() {
+                            self.viewer_zoom = Some((scale * ZOOM_STEP).min(MAX_ZOOM));
+                        }
+                        if ui.selectable_label(self.viewer_zoom.is_none(), t!("По размеру")).clicked() {
+                            self.viewer_zoom = None;
+                        }
+                    });
+                    if page != self.viewer_page {
+                        self.viewer_page = page;
+                        self.viewer_texture = None;
+                        self.viewer_error.clear();
+                    }
+                    // Страница рисуется заново в пикселях экрана, когда её размер на
+                    // экране заметно разойдётся с нарисованным, — так текст остаётся
+                    // чётким при любом масштабе.
+                    let shown = size * self.viewer_zoom.unwrap_or(fit);
+                    let width = ((shown.x * ctx.pixels_per_point()).round() as u32).clamp(1, pdf::MAX_RENDER_SIDE);
+                    let drawn = self.viewer_texture.as_ref().map(|tex| tex.size()[0] as u32);
+                    // (не нарисованная с ошибкой — до смены страницы)
+                    if drawn.map_or(self.viewer_error.is_empty(), |w| w.abs_diff(width) * 8 > w) {
+                        self.load_pdf_page(ctx, width);
+                    }
+                    if let Some(tex) = &self.viewer_texture {
+                        egui::ScrollArea::both().id_source("pdf_page").show(ui, |ui| {
+                            ui.add(egui::Image::new(tex).fit_to_exact_size(shown));
+                        });
+                    }
+                }
+                ViewerMode::Audio => {
+                    let (mut play, mut seek) = (None, None);
+                    if let Some(player) = &self.viewer_audio {
+                        (play, seek) = media_bar(ui, player.playing(), player.position(), player.duration());
+                        if player.playing() {
+                            ctx.request_repaint_after(Duration::from_millis(250));
+                        }
+                    }
+                    self.control_audio(play, seek);
+                }
+                ViewerMode::Video => {
+                    let (mut play, mut seek) = (None, None);
+                    if let Some(player) = &mut self.viewer_video {
+                        if let Some(error) = player.poll() {
+                            self.viewer_error = t!("Видео не воспроизводится: {}", error);
+                        }
+                        if let Some(frame) = player.take_frame() {
+                            match &mut self.viewer_texture {
+                                Some(tex) => tex.set(frame, egui::TextureOptions::LINEAR),
+                                None => self.viewer_texture = Some(ctx.load_texture("vault_video", frame, egui::TextureOptions::LINEAR)),
+                            }
+                        }
+                        (play, seek) = media_bar(ui, player.playing(), player.position(), player.duration());
+                        if player.playing() {
+                            ctx.request_repaint_after(Duration::from_millis(250));
+                        }
+                    }
+                    if let Some(tex) = &self.viewer_texture {
+                        let size = tex.size_vec2();
+                        let avail = ui.available_size();
+                        let fit = (avail.x / size.x).min(avail.y / size.y);
+                        ui.centered_and_justified(|ui| ui.add(egui::Image::new(tex).fit_to_exact_size(size * fit)));
                     }
+                    self.control_video(play, seek);
                 }
                 ViewerMode::Hex => {
-                    if let Some(bytes) = &self.viewer_bytes {
-                        let preview_len = bytes.len().min(4096);
-                        let mut s = String::new();
-                        for (i, b) in bytes[..preview_len].iter().enumerate() {
-                            if i % 16 == 0 {
-                                s.push_str(&format!("\n{:08x}: ", i));
+                    let hex = &mut self.viewer_hex;
+                    let last = hex.size.saturating_sub(1) / HEX_PAGE * HEX_PAGE;
+                    let mut go = None;
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(hex.page > 0, egui::Button::new("⏮")).clicked() {
+                            go = Some(0);
+                        }
+                        if ui.add_enabled(hex.page > 0, egui::Button::new("◀")).on_hover_text("PageUp").clicked() {
+                            go = Some(hex.page - HEX_PAGE);
+                        }
+                        let end = hex.page + hex.data.len() as u64;
+                        ui.label(t!("{} из {}", format!("{:x}–{:x}", hex.page, end), size_text(hex.size)));
+                        if ui.add_enabled(hex.page < last, egui::Button::new("▶")).on_hover_text("PageDown").clicked() {
+                            go = Some(hex.page + HEX_PAGE);
+                        }
+                        if ui.add_enabled(hex.page < last, egui::Button::new("⏭")).clicked() {
+                            go = Some(last);
+                        }
+                        ui.separator();
+                        let field = ui.add(egui::TextEdit::singleline(&mut hex.goto).desired_width(110.0).hint_text(t!("смещение (hex)")));
+                        let enter = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if ui.button(t!("Перейти")).clicked() || enter {
+                            let text = hex.goto.trim();
+                            match u64::from_str_radix(text.trim_start_matches("0x"), 16) {
+                                Ok(offset) if offset < hex.size => {
+                                    hex.mark = Some(offset);
+                                    hex.scroll = true;
+                                    go = Some(offset);
+                                }
+                                _ => self.notify.error(t!("Нет такого смещения: {}", text)),
+                            }
+                        }
+                    });
+                    let digits = if hex.size > u32::MAX as u64 { 12 } else { 8 };
+                    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                    let rows = hex.data.len().div_ceil(HEX_ROW);
+                    let mut area = egui::ScrollArea::vertical().id_source("hex").auto_shrink([false, true]);
+                    match hex.mark.filter(|_| std::mem::take(&mut hex.scroll)) {
+                        Some(mark) if mark >= hex.page => {
+                            let row = (mark - hex.page) as usize / HEX_ROW;
+                            area = area.vertical_scroll_offset(row as f32 * (row_height + ui.spacing().item_spacing.y));
+                        }
+                        _ if go.is_some() => area = area.vertical_scroll_offset(0.0),
+                        _ => {}
+                    }
+                    area.show_rows(ui, row_height, rows, |ui, range| {
+                        for row in range {
+                            let offset = hex.page + (row * HEX_ROW) as u64;
+                            let bytes = &hex.data[row * HEX_ROW..((row + 1) * HEX_ROW).min(hex.data.len())];
+                            let mut text = egui::RichText::new(hex_row(offset, bytes, digits)).monospace();
+                            if hex.mark.is_some_and(|m| m / HEX_ROW as u64 == offset / HEX_ROW as u64) {
+                                text = text.background_color(ui.visuals().selection.bg_fill);
                             }
-                            s.push_str(&format!("{:02x} ", b));
+                            ui.label(text);
+                        }
+                    });
+                    if let Some(offset) = go {
+                        if hex.mark.is_some_and(|m| m / HEX_PAGE != offset / HEX_PAGE) {
+                            hex.mark = None;
                         }
-                        ui.add(egui::TextEdit::multiline(&mut s).desired_rows(14).code_editor());
+                        self.load_hex_page(offset);
                     }
                 }
             }
         });
     }
-}
\ No newline at end of file
+
+    /// Скопированное не остаётся в буфере обмена после выхода.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.clipboard.clear();
+    }
+}
+
+/// Узлы списка: папки `dir_id` или `listing` из любых папок (тогда вместо имени путь
+/// от корня).
+fn list_nodes<'a>(meta: &'a fsmeta::Metadata, listing: &Listing, dir_id: u64) -> Vec<(String, &'a fsmeta::Node)> {
+    let mut nodes = match listing {
+        Listing::Dir => meta.children_of(dir_id).into_iter().map(|n| (n.name.clone(), n)).collect(),
+        Listing::Tag(tag) => meta.find(meta.root_id, None, std::slice::from_ref(tag)),
+        Listing::Search(query) => {
+            let hits = meta.fts.as_ref().map(|f| f.search(query)).unwrap_or_default();
+            // Порядок по релевантности не пересортировывается.
+            return hits
+                .into_iter()
+                .filter(|(id, _)| !meta.is_in_trash(*id))
+                .filter_map(|(id, _)| meta.get_node(id).map(|n| (meta.path_of(id), n)))
+                .collect();
+        }
+        Listing::Favorites => meta.favorites(),
+    };
+    meta.sort.sort(&mut nodes);
+    nodes
+}
+
+/// Заголовок столбца таблицы: щелчок сортирует по нему, повторный — в обратном порядке.
+fn sort_header(ui: &mut egui::Ui, order: fsmeta::SortOrder, key: fsmeta::SortKey, text: &str) -> Option<fsmeta::SortOrder> {
+    let active = order.key == key;
+    let text = match (active, order.descending) {
+        (true, false) => format!("{text} ▲"),
+        (true, true) => format!("{text} ▼"),
+        (false, _) => text.to_string(),
+    };
+    ui.selectable_label(active, egui::RichText::new(text).strong()).clicked().then_some(fsmeta::SortOrder {
+        key,
+        descending: active && !order.descending,
+    })
+}
+
+/// Папки вне корзины с путями, по пути: куда можно переместить узлы.
+fn move_targets(meta: &fsmeta::Metadata) -> Vec<(String, u64)> {
+    let dirs = meta.nodes.iter().filter(|n| n.node_type == NodeType::Dir && !meta.is_in_trash(n.id));
+    let mut dirs: Vec<(String, u64)> = dirs.map(|n| (meta.path_of(n.id), n.id)).collect();
+    dirs.sort();
+    dirs
+}
+
+/// Контекстное меню узла `n` списка; возвращает выбранный пункт.
+fn node_menu(ui: &mut egui::Ui, meta: &fsmeta::Metadata, n: &fsmeta::Node, writable: bool) -> Option<(u64, MenuAction)> {
+    let mut action = None;
+    let mut item = |ui: &mut egui::Ui, enabled: bool, text: &str, a: MenuAction| {
+        if ui.add_enabled(enabled, egui::Button::new(text)).clicked() {
+            action = Some((n.id, a));
+            ui.close_menu();
+        }
+    };
+    match n.node_type {
+        NodeType::Dir => item(ui, true, t!("Открыть"), MenuAction::Open),
+        NodeType::File => {
+            item(ui, true, t!("Просмотр"), MenuAction::View);
+            item(ui, true, t!("Экспорт…"), MenuAction::Export);
+            // Текст копируется как есть, небольшие двоичные файлы — в base64.
+            match n.mime.as_deref().is_none_or(mime::is_text) {
+                true => item(ui, n.size <= MAX_VIEW_BYTES, t!("Копировать содержимое"), MenuAction::Copy),
+                false => item(ui, n.size <= clipboard::MAX_BASE64_BYTES, t!("Копировать как base64"), MenuAction::Copy),
+            }
+        }
+    }
+    item(ui, writable, t!("Переименовать"), MenuAction::Rename);
+    ui.add_enabled_ui(writable, |ui| {
+        ui.menu_button(t!("Переместить в…"), |ui| {
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for (path, id) in move_targets(meta) {
+                    item(ui, id != n.parent_id, &path, MenuAction::Move(id));
+                }
+            });
+        });
+    });
+    item(ui, writable, t!("Удалить"), MenuAction::Delete);
+    ui.separator();
+    item(ui, true, t!("Свойства"), MenuAction::Properties);
+    action
+}
+
+/// Поле нового имени на месте ячейки списка; с `focus` получает фокус, и имя до
+/// расширения выделяется. Some(true), когда правку подтвердили Enter или щелчком
+/// мимо, Some(false) — когда отменили Escape.
+fn rename_field(ui: &mut egui::Ui, text: &mut String, focus: bool, width: f32) -> Option<bool> {
+    let mut out = egui::TextEdit::singleline(text).desired_width(width).show(ui);
+    if focus {
+        out.response.request_focus();
+        let stem = match text.rfind('.') {
+            Some(dot) if dot > 0 => text[..dot].chars().count(),
+            _ => text.chars().count(),
+        };
+        let range = egui::text::CCursorRange::two(egui::text::CCursor::new(0), egui::text::CCursor::new(stem));
+        out.state.cursor.set_char_range(Some(range));
+        out.state.store(ui.ctx(), out.response.id);
+    }
+    match out.response.lost_focus() {
+        true => Some(!ui.input(|i| i.key_pressed(egui::Key::Escape))),
+        false => None,
+    }
+}
+
+/// Узел списка как источник перетаскивания: тянутся отмеченные узлы, если он среди
+/// них, иначе только он. Папка к тому же принимает брошенные на неё узлы.
+fn drag_row(
+    ui: &egui::Ui,
+    resp: &egui::Response,
+    n: &fsmeta::Node,
+    selection: &BTreeSet<u64>,
+    writable: bool,
+) -> Option<(Vec<u64>, u64)> {
+    if writable && resp.drag_started() {
+        let ids = match selection.contains(&n.id) {
+            true => selection.iter().copied().collect(),
+            false => vec![n.id],
+        };
+        resp.dnd_set_drag_payload(DraggedNodes(ids));
+    }
+    match n.node_type {
+        NodeType::Dir => drop_target(ui, resp, n.id).map(|ids| (ids, n.id)),
+        NodeType::File => None,
+    }
+}
+
+/// Папка под перетаскиваемыми узлами подсвечивается; отпущенные над ней узлы
+/// возвращаются, если среди них нет её самой.
+fn drop_target(ui: &egui::Ui, resp: &egui::Response, dir_id: u64) -> Option<Vec<u64>> {
+    let dragged = resp.dnd_hover_payload::<DraggedNodes>()?;
+    if dragged.0.contains(&dir_id) {
+        return None;
+    }
+    ui.painter().rect_stroke(resp.rect.expand(2.0), 2.0, ui.visuals().selection.stroke);
+    resp.dnd_release_payload::<DraggedNodes>().map(|p| p.0.clone())
+}
+
+/// Название вкладки контейнера: имя его файла.
+fn vault_title(sess: Option<&container::Session>) -> String {
+    match sess {
+        Some(sess) => std::path::Path::new(&sess.path).file_name().map_or(sess.path.clone(), |n| n.to_string_lossy().to_string()),
+        None => t!("Новая вкладка").to_string(),
+    }
+}
+
+/// Операция остановлена кнопкой «Отмена», а не сломалась.
+fn is_cancelled(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<VaultError>().map(VaultError::root), Some(VaultError::Cancelled))
+}
+
+/// Выполняет `op` одной транзакцией: метаданные записываются один раз в конце, а при
+/// ошибке не меняется ничего.
+fn in_transaction(
+    sess: &mut container::Session,
+    password: &str,
+    op: impl FnOnce(&mut container::Session, &str) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    sess.begin()?;
+    match op(sess, password) {
+        Ok(()) => Ok(sess.commit(password)?),
+        Err(e) => {
+            sess.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// Полоса надёжности пароля и время его подбора.
+fn strength_bar(ui: &mut egui::Ui, est: &strength::Strength) {
+    let (label, color) = match est.score {
+        0 | 1 => (t!("очень слабый"), egui::Color32::from_rgb(200, 60, 60)),
+        2 => (t!("слабый"), egui::Color32::from_rgb(220, 140, 40)),
+        3 => (t!("хороший"), egui::Color32::from_rgb(120, 170, 60)),
+        _ => (t!("сильный"), egui::Color32::from_rgb(60, 160, 80)),
+    };
+    ui.add(
+        egui::ProgressBar::new((est.score as f32 + 1.0) / 5.0)
+            .desired_width(160.0)
+            .fill(color)
+            .text(label),
+    );
+    let secs = Duration::from(est.crack_time).as_secs();
+    ui.label(t!("подбор при 10⁴ попыток/с: {}", crack_time_text(secs)));
+}
+
+/// Время подбора пароля словами: «3 ч», «2 мес», «века».
+fn crack_time_text(secs: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const MONTH: u64 = 31 * DAY;
+    const YEAR: u64 = 12 * MONTH;
+    match secs {
+        0 => t!("меньше секунды").to_string(),
+        s if s < MINUTE => t!("{} с", s),
+        s if s < HOUR => t!("{} мин", s / MINUTE),
+        s if s < DAY => t!("{} ч", s / HOUR),
+        s if s < MONTH => t!("{} дн", s / DAY),
+        s if s < YEAR => t!("{} мес", s / MONTH),
+        s if s < 100 * YEAR => t!("{} г.", s / YEAR),
+        _ => t!("века").to_string(),
+    }
+}
+
+/// Число файлов и их байт в `path` (файле или папке со всем содержимым), без
+/// символических ссылок — так же, как их импортирует `container::import_dir`.
+fn count_files(path: &std::path::Path) -> (usize, u64) {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return (0, 0);
+    };
+    if meta.is_file() {
+        return (1, meta.len());
+    }
+    let mut total = (0, 0);
+    for e in std::fs::read_dir(path).into_iter().flatten().flatten() {
+        match e.file_type() {
+            Ok(t) if t.is_dir() => {
+                let (files, bytes) = count_files(&e.path());
+                total = (total.0 + files, total.1 + bytes);
+            }
+            Ok(t) if t.is_file() => {
+                total = (total.0 + 1, total.1 + e.metadata().map_or(0, |m| m.len()));
+            }
+            _ => {}
+        }
+    }
+    total
+}
+
+/// Сторона ячейки в сетке миниатюр, в точках.
+const THUMB_CELL: f32 = 128.0;
+/// Промежуток между щелчками по выбранному файлу, после которого начинается
+/// переименование: короче — двойной щелчок, дольше — просто новый щелчок.
+const SLOW_CLICK_MIN: Duration = Duration::from_millis(500);
+const SLOW_CLICK_MAX: Duration = Duration::from_secs(2);
+/// Файлы больше открываются в шестнадцатеричном виде: весь файл не читается.
+const MAX_VIEW_BYTES: u64 = 64 * 1024 * 1024;
+/// Байт на странице и в строке шестнадцатеричного просмотра.
+const HEX_PAGE: u64 = 64 * 1024;
+const HEX_ROW: usize = 16;
+/// Наибольшая сторона текстуры, в пикселях.
+const MAX_TEXTURE_SIDE: u32 = 8192;
+/// Шаг и пределы масштаба страницы PDF.
+const ZOOM_STEP: f32 = 1.25;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 8.0;
+/// Шаг перемотки звука и видео кнопками.
+const MEDIA_STEP: Duration = Duration::from_secs(10);
+
+/// Текстура миниатюры файла `id` (см. `container::read_thumbnail`).
+fn load_thumb(ctx: &egui::Context, sess: &container::Session, id: u64) -> Option<egui::TextureHandle> {
+    let jpeg = container::read_thumbnail(sess, id).ok()??;
+    Some(texture(ctx, &format!("thumb_{id}"), &image::load_from_memory(&jpeg).ok()?))
+}
+
+/// Строка шестнадцатеричного просмотра: смещение, байты и они же как ASCII.
+fn hex_row(offset: u64, bytes: &[u8], digits: usize) -> String {
+    let mut s = format!("{offset:0digits$x}  ");
+    for i in 0..HEX_ROW {
+        match bytes.get(i) {
+            Some(b) => s.push_str(&format!("{b:02x} ")),
+            None => s.push_str("   "),
+        }
+        if i == HEX_ROW / 2 - 1 {
+            s.push(' ');
+        }
+    }
+    s.push_str(" |");
+    s.extend(bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+    s.push('|');
+    s
+}
+
+/// Оформленный Markdown. Ссылки только показываются (адрес во всплывающей
+/// подсказке): приложение ничего не открывает во внешних программах. Картинки не
+/// загружаются, вместо них виден их текст.
+fn markdown_view(ui: &mut egui::Ui, cache: &mut egui_commonmark::CommonMarkCache, text: &str) {
+    ui.style_mut().url_in_tooltip = true;
+    egui_commonmark::CommonMarkViewer::new("markdown").show(ui, cache, text);
+    ui.ctx().output_mut(|o| o.open_url = None);
+}
+
+fn theme_name(theme: Theme) -> &'static str {
+    match theme {
+        Theme::System => t!("Как в системе"),
+        Theme::Dark => t!("Тёмная"),
+        Theme::Light => t!("Светлая"),
+    }
+}
+
+/// Данные камеры поверх изображения, в его левом верхнем углу.
+fn exif_overlay(ui: &mut egui::Ui, rect: egui::Rect, exif: Option<&exif::Exif>) {
+    let frame = egui::Frame::popup(ui.style()).multiply_with_opacity(0.85);
+    let area = egui::Rect::from_min_size(rect.min + egui::vec2(8.0, 8.0), rect.size() - egui::vec2(16.0, 16.0));
+    ui.allocate_ui_at_rect(area, |ui| {
+        frame.show(ui, |ui| {
+            let rows: Vec<(&str, String)> = exif
+                .map(|x| {
+                    vec![
+                        (t!("Камера"), x.camera.clone()),
+                        (t!("Снято"), x.taken.clone()),
+                        (t!("Выдержка"), x.exposure.clone()),
+                        (t!("Диафрагма"), x.aperture.clone()),
+                        ("ISO", x.iso.map(|v| v.to_string())),
+                        (t!("Фокусное расстояние"), x.focal_length.clone()),
+                        (t!("Программа"), x.software.clone()),
+                        ("GPS", x.gps.map(|(lat, lon)| format!("{lat:.6}, {lon:.6}"))),
+                    ]
+                })
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|(name, value)| Some((name, value?)))
+                .collect();
+            if rows.is_empty() {
+                ui.label(t!("Нет данных EXIF"));
+                return;
+            }
+            egui::Grid::new("exif").num_columns(2).show(ui, |ui| {
+                for (name, value) in rows {
+                    ui.label(name);
+                    ui.label(value);
+                    ui.end_row();
+                }
+            });
+        });
+    });
+}
+
+/// Текстура из изображения; больше `MAX_TEXTURE_SIDE` по стороне — уменьшенная
+/// (видеокарта может не принять).
+fn texture(ctx: &egui::Context, name: &str, img: &image::DynamicImage) -> egui::TextureHandle {
+    ctx.load_texture(name, color_image(img), egui::TextureOptions::default())
+}
+
+fn color_image(img: &image::DynamicImage) -> egui::ColorImage {
+    let rgba = match img.width().max(img.height()) > MAX_TEXTURE_SIDE {
+        true => img.thumbnail(MAX_TEXTURE_SIDE, MAX_TEXTURE_SIDE).to_rgba8(),
+        false => img.to_rgba8(),
+    };
+    let size = [rgba.width() as usize, rgba.height() as usize];
+    egui::ColorImage::from_rgba_unmultiplied(size, &rgba.into_raw())
+}
+
+/// Изображение, повёрнутое на `turns` четвертей по часовой стрелке.
+fn rotated(img: image::DynamicImage, turns: u8) -> image::DynamicImage {
+    match turns % 4 {
+        1 => img.rotate90(),
+        2 => img.rotate180(),
+        3 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Расшифровывает и раскодирует изображение для слайд-шоу, повёрнутое, как его
+/// держали при съёмке. Расшифрованные байты затираются.
+fn decode_slide(mut reader: container::VaultFileReader) -> anyhow::Result<egui::ColorImage> {
+    use std::io::Read;
+    let mut bytes = Vec::with_capacity(reader.len() as usize);
+    let res = match reader.read_to_end(&mut bytes) {
+        Ok(_) => image::load_from_memory(&bytes).map_err(anyhow::Error::from),
+        Err(e) => Err(e.into()),
+    };
+    let turns = exif::read(&bytes).map_or(0, |x| x.turns);
+    bytes.zeroize();
+    Ok(color_image(&rotated(res?, turns)))
+}
+
+/// Кнопки и ползунок проигрывания звука и видео: что нажато — пустить (`Some(true)`)
+/// или остановить, и куда перемотать.
+fn media_bar(ui: &mut egui::Ui, playing: bool, position: Duration, end: Option<Duration>) -> (Option<bool>, Option<Duration>) {
+    let (mut play, mut seek) = (None, None);
+    let mut secs = position.as_secs_f32();
+    // пока ползунок тянут, файл не перематывается
+    let dragged = ui.id().with("media_seek");
+    if let Some(at) = ui.data(|d| d.get_temp::<f32>(dragged)) {
+        secs = at;
+    }
+    ui.horizontal(|ui| {
+        if playing {
+            if ui.button("⏸").on_hover_text(t!("Пауза")).clicked() {
+                play = Some(false);
+            }
+        } else if ui.button("▶").on_hover_text(t!("Играть")).clicked() {
+            play = Some(true);
+        }
+        if ui.button("⏪").on_hover_text(t!("Назад на {} с", MEDIA_STEP.as_secs())).clicked() {
+            seek = Some(position.saturating_sub(MEDIA_STEP));
+        }
+        if ui.button("⏩").on_hover_text(t!("Вперёд на {} с", MEDIA_STEP.as_secs())).clicked() {
+            seek = Some(position + MEDIA_STEP);
+        }
+        let shown = Duration::from_secs_f32(secs);
+        ui.label(match end {
+            Some(end) => format!("{} / {}", play_time(shown), play_time(end)),
+            None => play_time(shown),
+        });
+        ui.spacing_mut().slider_width = ui.available_width();
+        let range = 0.0..=end.map_or(1.0, |end| end.as_secs_f32());
+        let slider = ui.add_enabled(end.is_some(), egui::Slider::new(&mut secs, range).show_value(false));
+        if slider.dragged() {
+            ui.data_mut(|d| d.insert_temp(dragged, secs));
+        } else {
+            ui.data_mut(|d| d.remove::<f32>(dragged));
+            if slider.changed() || slider.drag_stopped() {
+                seek = Some(Duration::from_secs_f32(secs));
+            }
+        }
+    });
+    (play, seek)
+}
+
+/// Время в проигрываемом файле: «1:05», «1:02:05».
+fn play_time(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        h => format!("{h}:{:02}:{:02}", secs / 60 % 60, secs % 60),
+    }
+}
+
+fn size_text(bytes: u64) -> String {
+    let units = [t!("Б"), t!("КиБ"), t!("МиБ"), t!("ГиБ"), t!("ТиБ")];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => t!("{} Б", bytes),
+        _ => format!("{value:.1} {}", units[unit]),
+    }
+}
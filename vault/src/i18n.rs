@@ -0,0 +1,626 @@
+//! Interface language. Messages are written in the source in one language (Russian
+//! in the GUI, English in the CLI and the errors of the core) and looked up in
+//! `TABLE`, a list of (Russian, English) pairs, so either side of a pair finds the
+//! other. A message without a pair is shown as written.
+//!
+//! `{}` in a message stands for a value: `t!("Файлов: {}", n)`. Messages built at
+//! run time (errors with names and paths in them) are matched against the pairs
+//! with `{}` by `translate`.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use vault_core::VaultError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Ru,
+    En,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::Ru, Lang::En];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Ru => "ru",
+            Lang::En => "en",
+        }
+    }
+
+    /// Language of a code like `ru`, `en_US.UTF-8` or `en-GB`.
+    pub fn from_code(code: &str) -> Option<Lang> {
+        let code = code.get(..2)?.to_ascii_lowercase();
+        Lang::ALL.into_iter().find(|l| l.code() == code)
+    }
+
+    /// Name of the language in itself, for the language selector.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lang::Ru => "Русский",
+            Lang::En => "English",
+        }
+    }
+}
+
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+pub fn set(lang: Lang) {
+    CURRENT.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn current() -> Lang {
+    match CURRENT.load(Ordering::Relaxed) {
+        0 => Lang::Ru,
+        _ => Lang::En,
+    }
+}
+
+/// Language asked for with the `VAULT_LANG` environment variable.
+pub fn from_env() -> Option<Lang> {
+    std::env::var("VAULT_LANG").ok().and_then(|v| Lang::from_code(&v))
+}
+
+/// Position in `TABLE` of each message.
+fn index() -> &'static HashMap<&'static str, usize> {
+    static INDEX: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        let mut index = HashMap::new();
+        for (i, &(ru, en)) in TABLE.iter().enumerate() {
+            index.insert(ru, i);
+            index.insert(en, i);
+        }
+        index
+    })
+}
+
+fn pick(pair: (&'static str, &'static str)) -> &'static str {
+    match current() {
+        Lang::Ru => pair.0,
+        Lang::En => pair.1,
+    }
+}
+
+/// `msg` in the current language.
+pub fn tr(msg: &'static str) -> &'static str {
+    match index().get(msg) {
+        Some(&i) => pick(TABLE[i]),
+        None => msg,
+    }
+}
+
+/// `template` with each `{}` replaced by the next of `args`.
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    out.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// Values that `template` (with `{}` for each) matched in `msg`, or None if it
+/// doesn't match.
+fn captures<'m>(template: &str, msg: &'m str) -> Option<Vec<&'m str>> {
+    let parts: Vec<&str> = template.split("{}").collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() < 2 || msg.len() < first.len() + last.len() {
+        return None;
+    }
+    let mut rest = msg.strip_prefix(first)?.strip_suffix(last)?;
+    let mut values = vec![];
+    for part in &parts[1..parts.len() - 1] {
+        let at = rest.find(part).filter(|_| !part.is_empty())?;
+        values.push(&rest[..at]);
+        rest = &rest[at + part.len()..];
+    }
+    values.push(rest);
+    Some(values)
+}
+
+/// `msg` in the current language, also if it was built from a message with `{}`.
+pub fn translate(msg: &str) -> String {
+    if let Some(&i) = index().get(msg) {
+        return pick(TABLE[i]).to_string();
+    }
+    // Templates with little text of their own ("{} с", "{} of {}") would match
+    // nearly anything.
+    let distinctive = |t: &str| t.contains("{}") && t.replace("{}", "").chars().filter(|c| !c.is_whitespace()).count() >= 4;
+    for &(ru, en) in TABLE {
+        for from in [ru, en].into_iter().filter(|t| distinctive(t)) {
+            if let Some(values) = captures(from, msg) {
+                let values: Vec<String> = values.into_iter().map(translate).collect();
+                let args: Vec<&dyn Display> = values.iter().map(|v| v as &dyn Display).collect();
+                return fill(pick((ru, en)), &args);
+            }
+        }
+    }
+    msg.to_string()
+}
+
+/// Errors `error` and `report` can list the causes of: `anyhow::Error` and those of
+/// `vault_core`.
+pub trait Causes {
+    /// The message of the error, then those of its causes.
+    fn messages(&self) -> Vec<String>;
+}
+
+impl Causes for anyhow::Error {
+    fn messages(&self) -> Vec<String> {
+        self.chain().map(|c| c.to_string()).collect()
+    }
+}
+
+impl Causes for VaultError {
+    fn messages(&self) -> Vec<String> {
+        std::iter::successors(Some(self as &dyn std::error::Error), |e| e.source()).map(|e| e.to_string()).collect()
+    }
+}
+
+/// An error with its causes, each in the current language: "message: cause: ...".
+pub fn error(e: &impl Causes) -> String {
+    e.messages().iter().map(|m| translate(m)).collect::<Vec<_>>().join(": ")
+}
+
+/// An error as the CLI reports it, like anyhow does but in the current language.
+pub fn report(e: &impl Causes) -> String {
+    let mut chain = e.messages().into_iter().map(|m| translate(&m));
+    let mut out = fill(tr("Error: {}"), &[&chain.next().unwrap_or_default()]);
+    let causes: Vec<String> = chain.collect();
+    if !causes.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(tr("Caused by:"));
+        for c in causes {
+            out.push_str("\n    ");
+            out.push_str(&c);
+        }
+    }
+    out
+}
+
+/// `t!("текст")` is the message in the current language; `t!("текст {}", x)` fills
+/// in the values.
+macro_rules! t {
+    ($msg:literal) => {
+        $crate::i18n::tr($msg)
+    };
+    ($msg:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill($crate::i18n::tr($msg), &[$(&$arg),+])
+    };
+}
+pub(crate) use t;
+
+/// (Russian, English).
+static TABLE: &[(&str, &str)] = &[
+    // Окно
+    ("Закрыть", "Lock"),
+    ("Контейнер закрыт", "Vault locked"),
+    ("Ещё контейнер", "Another vault"),
+    ("Открыть другой контейнер в новой вкладке", "Open another vault in a new tab"),
+    ("Новая вкладка", "New tab"),
+    ("Отмеченное — для вставки в другой контейнер", "The marked nodes, to paste into another vault"),
+    ("Вставить", "Paste"),
+    ("Вставка", "Paste"),
+    ("Скопировано узлов: {}; вставьте их в другой вкладке", "Copied nodes: {}; paste them in another tab"),
+    ("Скопировано файлов: {}", "Files copied: {}"),
+    ("вставка: {}", "paste: {}"),
+    ("Настройки", "Settings"),
+    ("Язык:", "Language:"),
+    ("Настройки не сохранены: {}", "Settings not saved: {}"),
+    ("Закрывать контейнер после", "Lock the vault after"),
+    ("мин бездействия (0 — никогда)", "idle minutes (0 for never)"),
+    ("Закрывать при сворачивании и потере фокуса", "Lock when minimized or unfocused"),
+    ("Контейнер закрыт: окно свёрнуто или неактивно", "Vault locked: the window was minimized or unfocused"),
+    ("Контейнер закрыт после {} мин бездействия", "Vault locked after {} idle minutes"),
+    ("нет папки настроек (не задан HOME или APPDATA)", "no config directory (HOME or APPDATA isn't set)"),
+    // Открытие и создание
+    ("Открыть / создать контейнер", "Open / create a vault"),
+    ("Выбрать", "Browse"),
+    ("Ключевой файл:", "Keyfile:"),
+    ("необязательно", "optional"),
+    ("Убрать", "Clear"),
+    ("Пароль:", "Password:"),
+    ("Открыть", "Open"),
+    ("Только чтение", "Read-only"),
+    ("Токеном (пароль = PIN)", "With the token (password = PIN)"),
+    ("Запомнить на этом компьютере", "Remember on this computer"),
+    ("Запомненный контейнер открывается кнопкой «Открыть» с пустым паролем.", "A remembered vault opens with «Open» and an empty password."),
+    ("Новый пароль:", "New password:"),
+    ("придумайте пароль", "choose a password"),
+    ("Создать новый контейнер", "Create a new vault"),
+    ("С кодом восстановления", "With a recovery code"),
+    ("очень слабый", "very weak"),
+    ("слабый", "weak"),
+    ("хороший", "good"),
+    ("сильный", "strong"),
+    ("подбор при 10⁴ попыток/с: {}", "guessing at 10⁴ tries/s: {}"),
+    ("Всё равно создать", "Create anyway"),
+    ("Время разблокировки:", "Unlock time:"),
+    (" мс", " ms"),
+    ("дольше — надёжнее против перебора пароля", "longer is safer against password guessing"),
+    ("Укажите путь к vault.dat", "Enter the path to vault.dat"),
+    ("Введите пароль", "Enter the password"),
+    ("Задайте пароль", "Choose a password"),
+    ("Пароль слабый: придумайте надёжнее или отметьте «Всё равно создать»", "The password is weak: choose a stronger one or check «Create anyway»"),
+    ("Не удалось открыть: {}", "Could not open: {}"),
+    ("Не удалось создать: {}", "Could not create: {}"),
+    ("Не удалось запомнить вход: {}", "Could not remember the login: {}"),
+    ("Вход запомнен на этом компьютере", "Login remembered on this computer"),
+    ("Сохранённый вход отозван, введите пароль", "The remembered login was revoked, enter the password"),
+    ("Создано (Argon2id: {} МиБ, проходов: {}). Теперь нажмите Открыть", "Created (Argon2id: {} MiB, {} passes). Now press Open"),
+    ("Код восстановления", "Recovery code"),
+    ("Если пароль будет забыт, этот код откроет контейнер (vault.exe recovery restore --code ...).", "If the password is forgotten, this code opens the vault (vault.exe recovery restore --code ...)."),
+    ("Запишите или распечатайте его и храните отдельно от компьютера. Больше он показан не будет.", "Write it down or print it and keep it away from the computer. It won't be shown again."),
+    ("Копировать", "Copy"),
+    ("Сохранить для печати…", "Save for printing…"),
+    ("Код восстановления для {}\n\n{}\n", "Recovery code for {}\n\n{}\n"),
+    ("Не удалось сохранить: {}", "Could not save: {}"),
+    ("Я записал код", "I wrote the code down"),
+    ("Сменить пароль…", "Change password…"),
+    ("Смена пароля", "Password change"),
+    ("Текущий пароль:", "Current password:"),
+    ("Ещё раз:", "Repeat:"),
+    ("Всё равно сменить", "Change anyway"),
+    ("Пароли не совпадают", "The passwords don't match"),
+    ("Сменить пароль", "Change password"),
+    ("Пароль слабый: придумайте надёжнее или отметьте «Всё равно сменить»", "The password is weak: choose a stronger one or check «Change anyway»"),
+    ("Пароль изменён", "Password changed"),
+    // Панели
+    ("Папки", "Folders"),
+    ("Корень", "Root"),
+    ("Корзина", "Trash"),
+    ("★ Избранное", "★ Favorites"),
+    ("Метки", "Tags"),
+    ("Поиск", "Search"),
+    ("слова из текста", "words of the text"),
+    ("Найти", "Find"),
+    ("Папка: {} файлов, {} папок, {} (в контейнере {}), вложенность {}  |  Всего: {} файлов, {} (в контейнере {})", "Folder: {} files, {} folders, {} ({} in the vault), depth {}  |  Total: {} files, {} ({} in the vault)"),
+    ("Текущая папка: id={}", "Current folder: id={}"),
+    ("Новая папка:", "New folder:"),
+    ("Создать", "Create"),
+    ("Импорт файла", "Import file"),
+    ("Сжимать (zstd)", "Compress (zstd)"),
+    ("Экспорт", "Export"),
+    ("Переименовать", "Rename"),
+    ("Удалить", "Delete"),
+    ("Затирать", "Shred"),
+    ("Восстановить", "Restore"),
+    ("Очистить корзину", "Empty trash"),
+    ("Просмотр", "View"),
+    ("Слайд-шоу", "Slideshow"),
+    ("Изображения списка во всё окно", "Images of the list in the whole window"),
+    ("В списке нет изображений", "No images in the list"),
+    ("Автопоказ", "Autoplay"),
+    ("Пробел", "Space"),
+    (" с", " s"),
+    ("Выход", "Exit"),
+    ("Файл больше {}", "File larger than {}"),
+    ("Миниатюры", "Thumbnails"),
+    ("Проверить целостность", "Verify"),
+    ("Дубликаты", "Duplicates"),
+    ("Сжать контейнер", "Compact vault"),
+    ("Хранить версии", "Keep versions"),
+    ("Поиск по тексту", "Text search"),
+    ("Индекс слов текстовых файлов", "Index of the words of text files"),
+    ("Привязать токен", "Enroll token"),
+    ("Отвязать токен", "Remove token"),
+    ("Забыть вход без пароля", "Forget passwordless login"),
+    ("Отмена", "Cancel"),
+    ("Правка", "Edit"),
+    ("Сохранить", "Save"),
+    ("Сохранено", "Saved"),
+    ("Отменить изменения", "Discard changes"),
+    ("● не сохранено", "● unsaved"),
+    ("Несохранённые изменения", "Unsaved changes"),
+    ("Правки в «{}» не сохранены.", "Changes to “{}” are not saved."),
+    ("Не сохранять", "Don't save"),
+    ("Оформление", "Rendered"),
+    ("Исходный текст", "Source"),
+    ("Страница {} из {}", "Page {} of {}"),
+    ("По размеру", "Fit"),
+    ("Страница не показана: {}", "Page not shown: {}"),
+    ("Повернуть против часовой", "Rotate counterclockwise"),
+    ("Повернуть по часовой", "Rotate clockwise"),
+    ("Нет данных EXIF", "No EXIF data"),
+    ("Камера", "Camera"),
+    ("Снято", "Taken"),
+    ("Выдержка", "Exposure"),
+    ("Диафрагма", "Aperture"),
+    ("Фокусное расстояние", "Focal length"),
+    ("Программа", "Software"),
+    ("Отмечено: {}", "Selected: {}"),
+    ("Снять отметки", "Clear selection"),
+    ("папка…", "folder…"),
+    ("Переместить", "Move"),
+    ("Копировать содержимое", "Copy contents"),
+    ("Копировать как base64", "Copy as base64"),
+    ("Текст", "Text"),
+    ("Содержимое файла", "File contents"),
+    ("Файл в base64", "File as base64"),
+    ("Файл больше {}: не скопирован", "File larger than {}: not copied"),
+    ("{} в буфере обмена; он очистится через {} с", "{} on the clipboard; it will be cleared in {} s"),
+    ("{} в буфере обмена", "{} on the clipboard"),
+    ("буфер обмена: {}", "clipboard: {}"),
+    ("Тема:", "Theme:"),
+    ("Как в системе", "System"),
+    ("Тёмная", "Dark"),
+    ("Светлая", "Light"),
+    ("Масштаб интерфейса:", "Interface scale:"),
+    ("Очищать буфер обмена через", "Clear the clipboard"),
+    ("с после копирования (0 — не очищать)", "s after copying (0: never)"),
+    ("Новая заметка", "New note"),
+    ("Заметка", "Note"),
+    ("Создана заметка «{}»", "Created note «{}»"),
+    ("заметка: {}", "note: {}"),
+    ("Перемещение: {}", "Moving: {}"),
+    ("Отменить", "Undo"),
+    ("Повторить", "Redo"),
+    ("Отменено", "Undone"),
+    ("Повторено", "Redone"),
+    ("отмена: {}", "undo: {}"),
+    ("повтор: {}", "redo: {}"),
+    ("Ошибки: {}", "Errors: {}"),
+    ("Последние ошибки", "Recent errors"),
+    ("Очистить", "Clear"),
+    ("метка", "tag"),
+    ("Добавить метку", "Add tag"),
+    ("Снять метку", "Remove tag"),
+    ("Метка «{}»", "Tag «{}»"),
+    ("Поиск «{}»", "Search «{}»"),
+    ("Избранное", "Favorites"),
+    ("Показать папку", "Show folder"),
+    ("Содержимое", "Contents"),
+    ("Имя", "Name"),
+    ("Тип", "Type"),
+    ("Размер", "Size"),
+    ("Изменён (UTC)", "Modified (UTC)"),
+    ("папка", "folder"),
+    // Меню и клавиши
+    ("Экспорт…", "Export…"),
+    ("Переместить в…", "Move to…"),
+    ("Свойства", "Properties"),
+    ("Удаление", "Confirm deletion"),
+    ("Переместить «{}» в корзину?", "Move «{}» to the trash?"),
+    ("Удалить «{}» навсегда?", "Delete «{}» permanently?"),
+    ("Переместить в корзину узлов: {}?", "Move {} items to the trash?"),
+    ("Удалить навсегда узлов: {}?", "Delete {} items permanently?"),
+    ("Поиск не включён (флажок «Поиск по тексту»)", "Search is off (the «Text search» checkbox)"),
+    // Сведения
+    ("Сведения", "Details"),
+    ("★ В избранном", "★ Favorite"),
+    ("Метки:", "Tags:"),
+    ("новая метка", "new tag"),
+    ("Добавить", "Add"),
+    ("Размер: {} байт", "Size: {} bytes"),
+    ("Тип: {}", "Type: {}"),
+    ("неизвестен", "unknown"),
+    ("Добавлен: {} UTC", "Added: {} UTC"),
+    ("Изменён: {} UTC", "Modified: {} UTC"),
+    ("Изменён до импорта: {} UTC", "Modified before import: {} UTC"),
+    ("История версий ({})", "Version history ({})"),
+    ("Версия {}: {} байт", "Version {}: {} bytes"),
+    ("Восстановлена версия {}", "Version {} restored"),
+    // Просмотр
+    ("Просмотр (внутри приложения)", "Viewer (inside the app)"),
+    ("Выберите файл и нажмите 'Просмотр'.", "Select a file and press 'View'."),
+    ("Примечание: 100% 'без следов' на ПК гарантировать нельзя. В этом GUI нет 'Открыть во внешней программе' — чтобы уменьшить утечки/следы.", "Note: leaving no traces on a PC can't be guaranteed. This GUI has no 'Open in an external program' to leave fewer of them."),
+    ("(не удалось загрузить изображение)", "(could not load the image)"),
+    ("Двоичный файл: показан шестнадцатеричный вид", "Binary file: shown as hex"),
+    ("{}: показан шестнадцатеричный вид", "{}: shown as hex"),
+    ("PDF не открыт: {}; показан шестнадцатеричный вид", "PDF not opened: {}; shown as hex"),
+    ("Файл больше {}: показан шестнадцатеричный вид", "File larger than {}: shown as hex"),
+    ("смещение (hex)", "offset (hex)"),
+    ("Перейти", "Go"),
+    ("Нет такого смещения: {}", "No such offset: {}"),
+    ("Ошибка чтения: {}", "Read error: {}"),
+    ("Звук не воспроизводится: {}; показан шестнадцатеричный вид", "Can't play the sound: {}; shown as hex"),
+    ("Звук не воспроизводится: {}", "Can't play the sound: {}"),
+    ("Видео не воспроизводится: {}; показан шестнадцатеричный вид", "Can't play the video: {}; shown as hex"),
+    ("Видео не воспроизводится: {}", "Can't play the video: {}"),
+    ("эта сборка не проигрывает видео (она собрана без `video`)", "this build plays no video (it is built without the `video` feature)"),
+    ("в GStreamer нет элемента appsink (gst-plugins-base)", "GStreamer has no appsink element (gst-plugins-base)"),
+    ("в GStreamer нет элемента playbin (gst-plugins-base)", "GStreamer has no playbin element (gst-plugins-base)"),
+    ("Играть", "Play"),
+    ("Пауза", "Pause"),
+    ("Назад на {} с", "Back {} s"),
+    ("Вперёд на {} с", "Forward {} s"),
+    // Дубликаты
+    ("Одинаковых файлов нет.", "No identical files."),
+    ("Групп: {}, лишние копии занимают {}", "Groups: {}, extra copies take {}"),
+    ("Файлов: {}, по {}", "Files: {}, {} each"),
+    ("В корзину", "To the trash"),
+    ("Файлы, изменённые на месте (write/append), не сравниваются: для них нет SHA-256 (vault.exe dupes --hash-missing).", "Files changed in place (write/append) aren't compared: they have no SHA-256 (vault.exe dupes --hash-missing)."),
+    // Перетаскивание и операции
+    ("Отпустите, чтобы импортировать в текущую папку", "Drop to import into the current folder"),
+    ("Импорт перетащенных файлов", "Import dropped files"),
+    ("В папку {}:", "Into folder {}:"),
+    ("📁 {} — файлов: {}, {}", "📁 {} — {} files, {}"),
+    ("Всего файлов: {}, {}", "Total: {} files, {}"),
+    ("Импортировать", "Import"),
+    ("Импорт", "Importing"),
+    ("Проверка", "Verifying"),
+    ("Очистка корзины", "Emptying trash"),
+    ("Сжатие контейнера", "Compacting"),
+    ("Индекс поиска", "Search index"),
+    ("{} из {}", "{} of {}"),
+    ("Отмена…", "Cancelling…"),
+    ("{}: операция прервалась, контейнер закрыт", "{}: the operation was interrupted, the vault is locked"),
+    // Состояние
+    ("Введите имя папки", "Enter a folder name"),
+    ("Выберите файл", "Select a file"),
+    ("Экспорт только для файлов", "Only files can be exported"),
+    ("Ничего не выбрано", "Nothing selected"),
+    ("Перемещено в корзину", "Moved to the trash"),
+    ("Удалено и затёрто", "Deleted and shredded"),
+    ("Удалено (место освободится после «Сжать контейнер»)", "Deleted (the space is freed by «Compact vault»)"),
+    ("Перемещено: {}", "Moved: {}"),
+    ("Восстановлено в папку id={}", "Restored to folder id={}"),
+    ("Токен привязан", "Token enrolled"),
+    ("Не удалось привязать токен: {}", "Could not enroll the token: {}"),
+    ("Вход без пароля отозван", "Passwordless login revoked"),
+    ("Не удалось отозвать вход: {}", "Could not revoke the login: {}"),
+    ("Токен отвязан", "Token removed"),
+    ("Токен не был привязан", "No token was enrolled"),
+    ("Не удалось отвязать токен: {}", "Could not remove the token: {}"),
+    ("Экспортировано", "Exported"),
+    ("Экспортировано файлов: {}", "Files exported: {}"),
+    ("Экспортировано файлов: {}, пропущено: {}", "Files exported: {}, skipped: {}"),
+    ("Файлы уже есть", "Files already exist"),
+    ("В папке {} уже есть файлы с такими именами: {}", "The folder {} already has files with these names: {}"),
+    ("… и ещё {}", "… and {} more"),
+    ("Заменить", "Replace"),
+    ("Пропустить", "Skip"),
+    ("Файлов: {}, {}", "Files: {}, {}"),
+    ("Шифротекст будет затёрт.", "The ciphertext will be overwritten."),
+    ("Без сохранения они пропадут при закрытии контейнера.", "Unless saved, they are lost when the vault is closed."),
+    ("Файл цел", "The file is intact"),
+    ("Файл повреждён: {}", "The file is damaged: {}"),
+    ("Корзина очищена", "Trash emptied"),
+    ("Сжато, освобождено {} байт", "Compacted, {} bytes freed"),
+    ("Проиндексировано текстовых файлов: {}", "Text files indexed: {}"),
+    ("Индекс поиска удалён", "Search index removed"),
+    ("сохранение: {}", "save: {}"),
+    ("создание папки: {}", "mkdir: {}"),
+    ("удаление: {}", "delete: {}"),
+    ("перемещение: {}", "move: {}"),
+    ("метка: {}", "tag: {}"),
+    ("восстановление: {}", "restore: {}"),
+    ("переименование: {}", "rename: {}"),
+    ("избранное: {}", "favorite: {}"),
+    ("сортировка: {}", "sort: {}"),
+    // Единицы
+    ("меньше секунды", "less than a second"),
+    ("{} с", "{} s"),
+    ("{} мин", "{} min"),
+    ("{} ч", "{} h"),
+    ("{} дн", "{} days"),
+    ("{} мес", "{} months"),
+    ("{} г.", "{} years"),
+    ("века", "centuries"),
+    ("{} Б", "{} B"),
+    ("Б", "B"),
+    ("КиБ", "KiB"),
+    ("МиБ", "MiB"),
+    ("ГиБ", "GiB"),
+    ("ТиБ", "TiB"),
+    // Ошибки CLI и ядра
+    ("Ошибка: {}", "Error: {}"),
+    ("Причина:", "Caused by:"),
+    ("неверно: {}, или контейнер повреждён", "wrong {}, or the vault is damaged"),
+    ("пароль или ключевой файл", "password or keyfile"),
+    ("части восстановления", "recovery shares"),
+    ("код восстановления", "recovery code"),
+    ("токен или PIN", "token or PIN"),
+    ("запомненный ключ", "remembered key"),
+    ("файл постквантового ключа", "post-quantum key file"),
+    ("TPM или состояние загрузки", "TPM or boot state"),
+    ("не найдено", "not found"),
+    ("отменено", "cancelled"),
+    ("не файл", "not a file"),
+    ("не папка", "not a directory"),
+    ("родитель в корзине", "parent is in the trash"),
+    ("имя уже занято", "name already exists"),
+    ("имя уже занято как {} (регистр в именах не различается)", "name already exists as {} (names ignore case)"),
+    ("пустое имя", "empty name"),
+    ("пустая метка", "empty tag"),
+    ("корень удалить нельзя", "cannot remove root"),
+    ("эту папку переместить нельзя", "cannot move this directory"),
+    ("папку нельзя переместить в неё саму", "cannot move a directory into itself"),
+    ("уже в корзине", "already in the trash"),
+    ("не в корзине", "not in the trash"),
+    ("контейнер открыт только для чтения", "vault is open read-only"),
+    ("контейнер уже открыт для записи другим процессом", "vault is already open for writing by another process"),
+    ("этому контейнеру нужен ключевой файл", "this vault needs a keyfile"),
+    ("этот контейнер не использует ключевой файл", "this vault doesn't use a keyfile"),
+    ("для этого контейнер нужно открыть его паролем", "this needs the vault opened with its password"),
+    ("формат контейнера нужно обновить: откройте его один раз паролем", "the vault format has to be upgraded: open it with the password once"),
+    ("пароль под принуждением должен отличаться от пароля", "the duress password must differ from the password"),
+    ("нельзя перевести этот контейнер на {}", "can't switch this vault to {}"),
+    ("токен привязан с PIN", "the token was enrolled with a PIN"),
+    ("к контейнеру не привязан токен", "no token is enrolled for this vault"),
+    ("нет индекса поиска; включите его командой `search-index --enabled true`", "no search index; enable it with `search-index --enabled true`"),
+    ("нет миниатюры", "no thumbnail"),
+    ("данные не совпадают с SHA-256, записанным при импорте", "the exported data doesn't match the SHA-256 recorded at import"),
+    ("SHA-256 отличается от записанного при импорте", "SHA-256 differs from the one recorded at import"),
+    ("метаданные слишком велики", "metadata too large"),
+    ("метаданные слишком велики: {} байт в сжатом виде, можно не больше {}", "metadata too large: {} bytes compressed, at most {}"),
+    ("файл слишком велик", "file too large"),
+    ("файл слишком велик: не больше {} байт при чанках по {} байт", "file too large: at most {} bytes with {}-byte chunks"),
+    ("чанк слишком велик", "chunk too large"),
+    ("неверный ключ слота или повреждённый заголовок", "wrong key for this key slot or corrupted header"),
+    ("проверка метаданных не прошла (неверный пароль или повреждённый контейнер)", "metadata auth failed (wrong password or corrupted vault)"),
+    ("не удалось определить имя файла", "cannot determine filename"),
+    ("не удалось определить имя папки", "cannot determine directory name"),
+    ("--name нужен один --os-path", "--name needs a single --os-path"),
+    ("{} доступен с других компьютеров, а трафик не шифруется: чтобы всё же открыть его там, добавьте --allow-remote", "{} can be reached from other machines and the traffic is not encrypted: use --allow-remote to serve there anyway"),
+    ("прослушивание {}", "listen on {}"),
+    ("{} — не личная папка этого пользователя (права 0700)", "{} isn't a private directory of this user (mode 0700)"),
+    ("демон уже слушает {}", "a daemon is already listening on {}"),
+    ("для контейнера в хранилище это невозможно: сначала скопируйте его в файл", "not possible for a vault in a storage backend: copy it to a file first"),
+    ("контейнер в хранилище нельзя разбить на части", "a vault in a storage backend can't be split into parts"),
+    ("контейнер в S3 открывается только паролем", "a vault in S3 opens with its password only"),
+    ("{}: ожидается s3://корзина/ключ", "{}: expected s3://bucket/key"),
+    ("подключение к S3 для {}", "connect to S3 for {}"),
+    ("неверный объект размера {}", "bad size object {}"),
+    ("не задан AWS_ACCESS_KEY_ID", "AWS_ACCESS_KEY_ID isn't set"),
+    ("не задан AWS_SECRET_ACCESS_KEY", "AWS_SECRET_ACCESS_KEY isn't set"),
+    ("адреса https не поддерживаются: укажите http:// (через TLS-прокси, если сервис его требует)", "https endpoints are not supported: use http:// (through a TLS proxy if the service requires it)"),
+    ("парольная фраза или ключ не открывают эту резервную копию", "the passphrase or identity doesn't open this backup"),
+    ("резервная копия повреждена или обрезана", "the backup is damaged or truncated"),
+    ("это не файл age, или его заголовок повреждён", "not an age file, or its header is damaged"),
+    ("{} — не открытый ключ age (age1...)", "{} isn't an age public key (age1...)"),
+    ("неверная сложность scrypt 2^{}", "bad scrypt work factor 2^{}"),
+    ("это не файл ключей age (строки AGE-SECRET-KEY-1...)", "not an age identity file (AGE-SECRET-KEY-1... lines)"),
+    ("в файле ключей нет ключей", "no keys in the identity file"),
+    ("не задано, кому шифровать", "no recipients to encrypt to"),
+    ("парольную фразу нельзя сочетать с другими получателями", "a passphrase can't be combined with other recipients"),
+    ("у резервной копии слишком высокая сложность scrypt (2^{})", "the scrypt work factor of the backup (2^{}) is too high"),
+    ("резервная копия — не архив tar: {}", "the backup isn't a valid tar archive: {}"),
+    ("неверная контрольная сумма заголовка", "bad header checksum"),
+    ("неверный размер", "bad size"),
+    ("путь не в UTF-8", "a path isn't UTF-8"),
+    ("неверный заголовок pax", "bad pax header"),
+    ("слишком большой заголовок pax", "pax header too large"),
+    ("резервная копия обрезана: {}", "the backup is truncated: {}"),
+    ("нет конца архива", "the end of the archive is missing"),
+    ("небезопасный путь в резервной копии: {}", "unsafe path in the backup: {}"),
+    ("восстановление {}", "restore {}"),
+    ("удаление {}", "remove {}"),
+    ("нет такого сеанса", "no such session"),
+    ("чтение {}", "read {}"),
+    ("запись {}", "write {}"),
+    ("создание {}", "create {}"),
+    ("открытие {}", "open {}"),
+    ("импорт {}", "import {}"),
+    ("экспорт id={} -> {}", "export id={} -> {}"),
+    ("перемещение {}", "move {}"),
+    ("{} уже существует", "{} already exists"),
+    ("Место", "Space"),
+    ("Сколько занимает контейнер и сколько можно освободить", "How much the vault takes up and how much can be freed"),
+    ("Файл контейнера", "Vault file"),
+    ("Данные файлов", "File data"),
+    ("До шифрования и сжатия, вместе с корзиной", "Before encryption and compression, trash included"),
+    ("Свободно внутри", "Free inside"),
+    ("Освободится после «Сжать контейнер»", "Freed by «Compact vault»"),
+    ("Только в корзине", "Only in the trash"),
+    ("Освободится после очистки корзины и сжатия", "Freed by emptying the trash and compacting"),
+    ("Можно вернуть", "Reclaimable"),
+    ("Окно не скрыто от записи экрана: {}", "The window isn't hidden from screen capture: {}"),
+    ("Скрывать содержимое файлов, когда окно неактивно", "Hide file contents while the window is inactive"),
+    ("Просмотр, миниатюры и слайд-шоу пустеют без фокуса; окно не попадает в снимки и демонстрацию экрана", "The viewer, thumbnails and slideshow go blank without focus; the window stays out of screenshots and screen sharing"),
+    ("Просмотр, миниатюры и слайд-шоу пустеют без фокуса; скрыть окно от записи экрана здесь система не даёт", "The viewer, thumbnails and slideshow go blank without focus; this system doesn't let the window hide from screen capture"),
+    ("Содержимое скрыто: окно неактивно", "Contents hidden: the window is inactive"),
+    ("{}: отменено", "{}: cancelled"),
+    ("Контейнер уже открыт на запись другой программой; его можно открыть «Только чтение»", "The vault is already open for writing by another program; it can be opened «Read-only»"),
+    ("библиотека pdfium недоступна: {}", "the pdfium library is not available: {}"),
+    ("в PDF-файле нет страниц", "no pages found in the PDF file"),
+    ("нет такой страницы", "no such page"),
+    ("фильтр изображений PDF {} не поддерживается", "PDF image filter {} is not supported"),
+    ("цветовое пространство PDF {} не поддерживается", "PDF color space {} is not supported"),
+];
@@ -0,0 +1,54 @@
+//! "Remember on this machine": the key of a `SlotKind::Remembered` key slot kept in
+//! the OS keychain (macOS Keychain, Windows Credential Manager, Secret Service on
+//! Linux), so the vault opens without the password for this OS user.
+//!
+//! The entry is named after the absolute vault path and holds the slot id and key.
+//! Removing the slot from the header revokes it everywhere, deleting the entry only
+//! on this machine.
+
+use vault_core::crypto::{SecretKey, KEY_LEN};
+use zeroize::Zeroize;
+
+const SERVICE: &str = "vault";
+
+fn entry(vault_path: &str) -> anyhow::Result<keyring::Entry> {
+    let name = std::path::absolute(vault_path)?.to_string_lossy().to_string();
+    keyring::Entry::new(SERVICE, &name).map_err(|e| anyhow::anyhow!("keychain: {e}"))
+}
+
+pub fn store(vault_path: &str, id: &[u8; 8], key: &[u8; KEY_LEN]) -> anyhow::Result<()> {
+    let mut secret = [id.as_slice(), key.as_slice()].concat();
+    let res = entry(vault_path)?.set_secret(&secret);
+    secret.zeroize();
+    res.map_err(|e| anyhow::anyhow!("keychain: {e}"))
+}
+
+/// Slot id and key remembered for `vault_path`, if any.
+pub fn load(vault_path: &str) -> anyhow::Result<Option<([u8; 8], SecretKey)>> {
+    let mut secret = match entry(vault_path)?.get_secret() {
+        Ok(secret) => secret,
+        Err(keyring::Error::NoEntry) => return Ok(None),
+        Err(e) => anyhow::bail!("keychain: {e}"),
+    };
+    let parsed = match secret.len() == 8 + KEY_LEN {
+        true => {
+            let mut id = [0u8; 8];
+            let mut key = SecretKey::new([0u8; KEY_LEN]);
+            id.copy_from_slice(&secret[..8]);
+            key.copy_from_slice(&secret[8..]);
+            Some((id, key))
+        }
+        false => None,
+    };
+    secret.zeroize();
+    parsed.map(Some).ok_or_else(|| anyhow::anyhow!("keychain entry for {vault_path} is malformed"))
+}
+
+/// Deletes the entry for `vault_path`; returns false if there was none.
+pub fn delete(vault_path: &str) -> anyhow::Result<bool> {
+    match entry(vault_path)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => anyhow::bail!("keychain: {e}"),
+    }
+}
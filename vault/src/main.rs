@@ -1,15 +1,71 @@
-mod container;
-mod crypto;
-mod fsmeta;
+mod audio;
+mod capture;
+mod clipboard;
+mod daemon;
+mod exif;
 mod gui;
+mod i18n;
+mod keychain;
+mod notify;
+mod pdf;
+mod s3;
+mod settings;
+mod shell;
+mod strength;
+mod video;
+mod webdav;
 
 use anyhow::Context;
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use vault_core::crypto::age;
+use vault_core::{backup, container, crypto, fsmeta, recovery, sync, VaultError};
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Parser)]
 #[command(name = "vault", version, about = "Encrypted container vault (MVP)")]
 struct Cli {
+    /// Open read-only, without taking the write lock (write-protected media, a vault in use)
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Record the vault generation in this file (kept outside the vault) and warn when
+    /// the vault is older than the last known state, i.e. was rolled back
+    #[arg(long, global = true)]
+    counter_file: Option<String>,
+
+    /// Keyfile required besides the password (set at init; any non-empty file)
+    #[arg(long, global = true)]
+    keyfile: Option<String>,
+
+    /// Unlock with the enrolled hardware token instead of the password; `--password`
+    /// is then the token PIN (empty if it was enrolled without one)
+    #[arg(long, global = true)]
+    token: bool,
+
+    /// Challenge-response program for the token (default: ykchalresp -2); gets the hex
+    /// challenge as its last argument and prints the hex response
+    #[arg(long, global = true)]
+    token_cmd: Option<String>,
+
+    /// Unlock with the post-quantum key file from `pq create` instead of the password;
+    /// `--password` is then ignored
+    #[arg(long, global = true, conflicts_with = "token")]
+    pq_key: Option<PathBuf>,
+
+    /// Unlock with the key sealed to this machine's TPM (`tpm enroll`) instead of the
+    /// password; `--password` is then ignored
+    #[arg(long, global = true, conflicts_with_all = ["token", "pq_key"])]
+    tpm: bool,
+
+    /// Say why a vault didn't open (wrong key, damaged header or metadata) instead of
+    /// one message for all; don't use it where someone else sees the output
+    #[arg(long, global = true)]
+    verbose_errors: bool,
+
     #[command(subcommand)]
     cmd: Cmd,
 }
@@ -23,11 +79,37 @@ enum Cmd {
         #[arg(long)]
         password: String,
         /// Argon2 memory cost in KiB (e.g. 262144 = 256 MiB)
-        #[arg(long, default_value_t = 131072)]
+        #[arg(long, default_value_t = crypto::DEFAULT_M_COST_KIB)]
         m_cost_kib: u32,
         /// Argon2 time cost (iterations)
-        #[arg(long, default_value_t = 3)]
+        #[arg(long, default_value_t = crypto::DEFAULT_T_COST)]
         t_cost: u32,
+        /// Argon2 parallelism (lanes); Argon2id needs at least 8 KiB of memory per lane
+        #[arg(long, default_value_t = 1)]
+        p_cost: u32,
+        /// Benchmark this machine and pick the Argon2 costs for an unlock time of
+        /// about this many milliseconds (instead of --m-cost-kib / --t-cost)
+        #[arg(long, conflicts_with_all = ["m_cost_kib", "t_cost"])]
+        target_ms: Option<u64>,
+        /// Chunk padding: none, pow2 or a bucket size in bytes
+        #[arg(long, default_value = "none")]
+        padding: container::Padding,
+        /// Split the vault into parts of this many MiB (PATH.001, PATH.002, ...)
+        #[arg(long, default_value_t = 0)]
+        part_size_mib: u64,
+        /// Plaintext chunk size, e.g. 256K or 8M (default 1M)
+        #[arg(long, default_value = "1M", value_parser = parse_size)]
+        chunk_size: usize,
+        /// AEAD: auto (AES-256-GCM with hardware AES, else XChaCha20-Poly1305),
+        /// xchacha20-poly1305, aes-256-gcm or chacha20-poly1305
+        #[arg(long, default_value = "auto")]
+        cipher: crypto::CipherSuite,
+        /// Also print a recovery code that opens the vault if the password is lost
+        #[arg(long)]
+        recovery_code: bool,
+        /// Create the vault even if the password is weak (only warn)
+        #[arg(long)]
+        force: bool,
     },
 
     /// List children of a directory id (default: root)
@@ -38,6 +120,101 @@ enum Cmd {
         password: String,
         #[arg(long, default_value_t = 1)]
         dir_id: u64,
+        /// Also show sizes and times (UTC): created in the vault, last modified, and
+        /// modified before import
+        #[arg(short, long)]
+        long: bool,
+        /// List the favorites of the whole vault instead, with their paths
+        #[arg(long)]
+        favorites: bool,
+    },
+
+    /// List the nodes below a directory (default: the whole vault, without the trash)
+    /// matching a name and tags
+    Find {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long, default_value_t = 1)]
+        dir_id: u64,
+        /// Part of the name (case-insensitive)
+        #[arg(long)]
+        name: Option<String>,
+        /// Only nodes with this tag (repeat to require several)
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+
+    /// Text files containing all words of a query, best match first (needs the
+    /// search index, see `search-index`)
+    Search {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        query: String,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Tags on files and directories, to group them across folders
+    Tag {
+        #[command(subcommand)]
+        cmd: TagCmd,
+    },
+
+    /// Mark a file or directory as a favorite, for quick access (`ls --favorites`), or
+    /// unmark it
+    Favorite {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        #[arg(long, action = clap::ArgAction::Set, default_value_t = true)]
+        enabled: bool,
+    },
+
+    /// Extended attributes: named values other tools keep on files and directories
+    Xattr {
+        #[command(subcommand)]
+        cmd: XattrCmd,
+    },
+
+    /// Count the files and directories below a directory, with their size and the
+    /// space they take in the vault (without `--dir-id`: the whole vault)
+    Stat {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        dir_id: Option<u64>,
+    },
+
+    /// Report groups of identical files (by the SHA-256 recorded at import) and the
+    /// bytes the extra copies take
+    Dupes {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// Also decrypt and hash the files without a recorded SHA-256 (changed with
+        /// `write`/`append`), which are skipped otherwise
+        #[arg(long)]
+        hash_missing: bool,
+    },
+
+    /// Print the id and path of the node with a UUID (see `ls --long`)
+    Resolve {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        uuid: uuid::Uuid,
     },
 
     /// Create directory
@@ -52,18 +229,22 @@ enum Cmd {
         name: String,
     },
 
-    /// Import a file from OS into vault
+    /// Import files from OS into vault (several files are imported all or nothing)
     Import {
         #[arg(long)]
         path: String,
         #[arg(long)]
         password: String,
-        #[arg(long)]
-        os_path: PathBuf,
+        #[arg(long, required = true, num_args = 1..)]
+        os_path: Vec<PathBuf>,
         #[arg(long, default_value_t = 1)]
         parent_id: u64,
+        /// Name in the vault (single file only)
         #[arg(long)]
         name: Option<String>,
+        /// Compress chunks with zstd before encryption
+        #[arg(long)]
+        compress: bool,
     },
 
     /// Export a file from vault to OS
@@ -78,6 +259,76 @@ enum Cmd {
         out_path: PathBuf,
     },
 
+    /// Write the JPEG thumbnail of an image, made at import
+    Thumbnail {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+        #[arg(long)]
+        out_path: PathBuf,
+    },
+
+    /// Make thumbnails for the images that have none (imported before thumbnails were
+    /// made, or written in place)
+    Thumbnails {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+
+    /// Print the SHA-256 of a file recorded at import (as `sha256sum` prints it), to
+    /// compare with an exported copy or the original
+    Hash {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+    },
+
+    /// Overwrite part of a file in the vault with the contents of an OS file
+    Write {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+        #[arg(long)]
+        os_path: PathBuf,
+    },
+
+    /// Append the contents of an OS file to a file in the vault
+    Append {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+        #[arg(long)]
+        os_path: PathBuf,
+    },
+
+    /// Replace the whole contents of a file in the vault with an OS file (it may shrink)
+    Replace {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+        #[arg(long)]
+        os_path: PathBuf,
+    },
+
     /// Rename node by id
     Rename {
         #[arg(long)]
@@ -89,89 +340,1866 @@ enum Cmd {
         #[arg(long)]
         new_name: String,
     },
-}
 
-fn main() -> anyhow::Result<()> {
-    // Если запуск без аргументов (двойной клик по .exe) — открываем GUI.
-    if std::env::args().len() == 1 {
-        return gui::run();
-    }
+    /// Move nodes into another directory (several nodes are moved all or nothing)
+    Mv {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long, required = true, num_args = 1..)]
+        id: Vec<u64>,
+        #[arg(long)]
+        parent_id: u64,
+    },
 
-    let cli = Cli::parse();
+    /// Copy a file without duplicating its data (copy-on-write)
+    Cp {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        /// Target directory (default: same as the source)
+        #[arg(long)]
+        parent_id: Option<u64>,
+        #[arg(long)]
+        name: String,
+    },
 
-    match cli.cmd {
-        Cmd::Init {
-            path,
-            password,
-            m_cost_kib,
-            t_cost,
-        } => {
-            container::create_vault(&path, &password, m_cost_kib, t_cost)?;
-            println!("Created vault: {path}");
-        }
+    /// Move node by id to the trash (directories recursively)
+    Rm {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        /// Delete immediately instead of moving to the trash
+        #[arg(long)]
+        permanent: bool,
+        /// Overwrite the freed ciphertext with random data
+        #[arg(long, requires = "permanent")]
+        shred: bool,
+    },
 
-        Cmd::Ls {
-            path,
-            password,
-            dir_id,
-        } => {
-            let sess = container::open_vault(&path, &password)?;
-            let children = sess.meta.children_of(dir_id);
-            for n in children {
-                let t = match n.node_type {
-                    fsmeta::NodeType::Dir => "DIR ",
-                    fsmeta::NodeType::File => "FILE",
-                };
-                println!("{t}  id={}  parent={}  name={}", n.id, n.parent_id, n.name);
-            }
-        }
+    /// List nodes in the trash
+    Trash {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
 
-        Cmd::Mkdir {
-            path,
-            password,
-            parent_id,
-            name,
-        } => {
-            let mut sess = container::open_vault(&path, &password)?;
-            let id = sess.meta.mkdir(parent_id, name)?;
-            container::save_metadata(&sess, &password)?;
-            println!("mkdir id={id}");
-        }
+    /// Move node by id from the trash back to where it was deleted from
+    TrashRestore {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+    },
 
-        Cmd::Import {
-            path,
-            password,
-            os_path,
-            parent_id,
-            name,
-        } => {
-            let mut sess = container::open_vault(&path, &password)?;
-            let id = container::import_file(&mut sess, &password, &os_path, parent_id, name)?;
-            println!("imported file id={id}");
-        }
+    /// Permanently delete everything in the trash
+    EmptyTrash {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// Overwrite the freed ciphertext with random data
+        #[arg(long)]
+        shred: bool,
+    },
 
-        Cmd::Export {
-            path,
-            password,
-            file_id,
-            out_path,
-        } => {
-            let sess = container::open_vault(&path, &password)?;
-            container::export_file(&sess, file_id, &out_path)
-                .with_context(|| format!("export id={file_id} -> {}", out_path.display()))?;
-            println!("exported");
-        }
+    /// Turn keeping of previous file versions on or off
+    Versioning {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long, action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
 
-        Cmd::Rename {
-            path,
-            password,
-            id,
-            new_name,
-        } => {
-            let mut sess = container::open_vault(&path, &password)?;
-            sess.meta.rename(id, new_name)?;
-            container::save_metadata(&sess, &password)?;
-            println!("renamed");
+    /// Set the longest name, in bytes, of files and directories created or renamed
+    /// from now on (default 255)
+    NameLimit {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        max_len: usize,
+    },
+
+    /// Make names that differ only in case collide (as on Windows and macOS) or not,
+    /// for files and directories created or renamed from now on
+    CaseInsensitive {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long, action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+
+    /// Keep a full-text index of the text files (in the encrypted metadata, updated on
+    /// every write), or drop it. Enabling reads all text files once.
+    SearchIndex {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long, action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+
+    /// Set chunk padding for data written from now on
+    Padding {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// none, pow2 or a bucket size in bytes
+        #[arg(long)]
+        policy: container::Padding,
+    },
+
+    /// List kept versions of a file
+    Versions {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+    },
+
+    /// Make a kept version current again (the current contents become a version)
+    Restore {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        file_id: u64,
+        #[arg(long)]
+        version: usize,
+    },
+
+    /// Reclaim space left by deleted files
+    Compact {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+
+    /// Check the vault for corruption
+    Verify {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// Only compare ciphertext against the stored Merkle hashes, without decrypting
+        #[arg(long)]
+        fast: bool,
+        /// Check only this file
+        #[arg(long, conflicts_with = "fast")]
+        file_id: Option<u64>,
+    },
+
+    /// Generate a new master key and re-encrypt all data under it
+    Rekey {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// Also switch to this AEAD (see `init --cipher`)
+        #[arg(long)]
+        cipher: Option<crypto::CipherSuite>,
+    },
+
+    /// Change the password (the keyfile, if any, stays; other key slots keep working)
+    Passwd {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        new_password: String,
+        /// Accept a weak new password (only warn)
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Recovery of a vault whose password is lost
+    Recovery {
+        #[command(subcommand)]
+        cmd: RecoveryCmd,
+    },
+
+    /// Hardware token (YubiKey challenge-response, FIDO2 hmac-secret through a helper)
+    Token {
+        #[command(subcommand)]
+        cmd: TokenCmd,
+    },
+
+    /// Post-quantum key slot (X25519 + ML-KEM-768) opened by a key file, for long-term
+    /// archives
+    Pq {
+        #[command(subcommand)]
+        cmd: PqCmd,
+    },
+
+    /// TPM 2.0 auto-unlock: a key sealed to this machine and its boot state
+    Tpm {
+        #[command(subcommand)]
+        cmd: TpmCmd,
+    },
+
+    /// Duress password: it opens a decoy tree instead of the real one
+    Duress {
+        #[command(subcommand)]
+        cmd: DuressCmd,
+    },
+
+    /// Revoke "remember on this machine" (GUI) on every machine and delete this
+    /// machine's keychain entry
+    Forget {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+
+    /// Copy a file or the contents of a directory from another vault, re-encrypting it
+    Copy {
+        #[arg(long)]
+        src: String,
+        #[arg(long)]
+        src_password: String,
+        /// Keyfile of the source vault (`--keyfile` applies to the destination)
+        #[arg(long)]
+        src_keyfile: Option<String>,
+        #[arg(long, default_value_t = 1)]
+        src_id: u64,
+        #[arg(long)]
+        dst: String,
+        #[arg(long)]
+        dst_password: String,
+        #[arg(long, default_value_t = 1)]
+        parent_id: u64,
+        /// Merge into existing directories of the same name instead of copying next to them
+        #[arg(long)]
+        merge: bool,
+    },
+
+    /// Sync a vault with a replica of it both ways (e.g. on a USB stick or a cloud
+    /// drive): what was added, changed or deleted on either side since the last sync
+    /// goes to the other; a file changed on both sides keeps the remote version next to
+    /// it as a conflict copy
+    Sync {
+        /// The vault, opened with `--password` and the global key options
+        #[arg(long)]
+        local: String,
+        #[arg(long)]
+        password: String,
+        /// The replica: a path, or s3://bucket/key
+        #[arg(long)]
+        remote: String,
+        /// Password of the replica (default: `--password`)
+        #[arg(long)]
+        remote_password: Option<String>,
+        #[arg(long)]
+        remote_keyfile: Option<String>,
+        /// Only print what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Write the whole tree (without versions and the trash) to a tar archive encrypted
+    /// with age to a passphrase or to public keys; `age -d backup.age | tar x` also
+    /// restores it
+    Backup {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        out: PathBuf,
+        /// Encrypt to this passphrase
+        #[arg(long, required_unless_present = "recipient", conflicts_with = "recipient")]
+        passphrase: Option<String>,
+        /// Encrypt to this age public key (age1..., from `age-keygen`); can be repeated
+        #[arg(long)]
+        recipient: Vec<String>,
+    },
+
+    /// Restore a backup made with `backup` (or any age-encrypted tar) into a directory
+    /// of the vault; names that are taken get a free one
+    BackupRestore {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long = "in")]
+        input: PathBuf,
+        /// Passphrase the backup was encrypted to
+        #[arg(long, required_unless_present = "identity", conflicts_with = "identity")]
+        passphrase: Option<String>,
+        /// age identity file with the secret key (AGE-SECRET-KEY-1...)
+        #[arg(long)]
+        identity: Option<PathBuf>,
+        #[arg(long, default_value_t = 1)]
+        parent_id: u64,
+    },
+
+    /// Keep vaults unlocked in a background process driven with JSON-RPC over a local
+    /// socket, so that other programs don't unlock them on every call
+    Daemon {
+        /// Unix socket path, or named pipe name on Windows (default: vault.sock in
+        /// $XDG_RUNTIME_DIR or the temp directory; \\.\pipe\vault-<user> on Windows)
+        #[arg(long)]
+        socket: Option<String>,
+    },
+
+    /// Serve the vault tree over WebDAV until stopped, to map it as a network drive
+    Serve {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// Address to listen on, e.g. 127.0.0.1:8080
+        #[arg(long)]
+        webdav: SocketAddr,
+        /// Require a random token, made anew at each start, as the first part of every
+        /// URL; the address to map is printed with it
+        #[arg(long)]
+        url_token: bool,
+        /// Allow an address other machines can reach; the traffic is not encrypted
+        #[arg(long)]
+        allow_remote: bool,
+    },
+
+    /// Run commands read from standard input, one per line, with the vault kept open,
+    /// so that `undo` and `redo` reach back over earlier ones (`help` lists them)
+    Shell {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+
+    /// Measure Argon2 unlock times and AEAD throughput on this machine and suggest
+    /// `init` parameters
+    Bench {
+        /// Unlock time to suggest Argon2 costs for
+        #[arg(long, default_value_t = 750)]
+        target_ms: u64,
+        /// Data encrypted and decrypted per cipher, in MiB
+        #[arg(long, default_value_t = 256)]
+        data_mib: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum RecoveryCmd {
+    /// Split a new recovery key into Shamir shares written as text files (shares from
+    /// an earlier `create` stop working)
+    Create {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long, default_value_t = 5)]
+        shares: u8,
+        /// Number of shares needed to restore access
+        #[arg(long, default_value_t = 3)]
+        threshold: u8,
+        /// Directory for the share files
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+    },
+
+    /// Print a new recovery code (an earlier code stops working)
+    Code {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+
+    /// Restore access from shares or a recovery code and set a new password
+    Restore {
+        #[arg(long)]
+        path: String,
+        /// Share files (at least the threshold)
+        #[arg(long, num_args = 1.., required_unless_present = "code", conflicts_with = "code")]
+        share: Vec<PathBuf>,
+        /// Recovery code from `init --recovery-code` or `recovery code`
+        #[arg(long)]
+        code: Option<String>,
+        #[arg(long)]
+        new_password: String,
+        /// Keyfile to require from now on (by default the vault no longer needs one)
+        #[arg(long)]
+        new_keyfile: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCmd {
+    /// Add tags to a node
+    Add {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        #[arg(long, required = true, num_args = 1..)]
+        tag: Vec<String>,
+    },
+
+    /// Remove tags from a node
+    Remove {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        #[arg(long, required = true, num_args = 1..)]
+        tag: Vec<String>,
+    },
+
+    /// List the tags of a node, or without `--id` every tag in the vault with the
+    /// number of nodes that have it
+    List {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum XattrCmd {
+    /// List the attribute names of a node with their sizes
+    List {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+    },
+
+    /// Write the value of an attribute to stdout, as stored
+    Get {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        #[arg(long)]
+        name: String,
+    },
+
+    /// Set an attribute to a text value or the contents of a file (up to 64 KiB)
+    Set {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        #[arg(long)]
+        name: String,
+        #[arg(long, required_unless_present = "file", conflicts_with = "file")]
+        value: Option<String>,
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Remove an attribute
+    Remove {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        id: u64,
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCmd {
+    /// Enroll the token (replaces an enrolled one); the vault then also opens with
+    /// `--token`
+    Enroll {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// PIN to require along with the token
+        #[arg(long)]
+        pin: Option<String>,
+    },
+
+    /// Remove the enrolled token
+    Remove {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TpmCmd {
+    /// Seal a new key to the TPM and the current values of `--pcrs` (replaces an
+    /// enrolled one); the vault then also opens with `--tpm` on this machine
+    Enroll {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// SHA-256 PCRs the key is sealed to (the default: firmware, boot loader and
+        /// Secure Boot policy)
+        #[arg(long, default_value = crypto::tpm::DEFAULT_PCRS)]
+        pcrs: String,
+    },
+
+    /// Remove the TPM key slot
+    Unenroll {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DuressCmd {
+    /// Set the duress password (replaces a set one, and its decoy tree with an empty
+    /// one); opening the vault with it shows the decoy tree
+    Set {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        duress_password: String,
+    },
+
+    /// Remove the duress password and its decoy tree (`compact` reclaims its space)
+    Remove {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PqCmd {
+    /// Write a new key file and add the post-quantum key slot for it (a key file from
+    /// an earlier `create` stops working); the vault then also opens with `--pq-key`
+    Create {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+        /// Key file to write (must not exist yet)
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Remove the post-quantum key slot
+    Remove {
+        #[arg(long)]
+        path: String,
+        #[arg(long)]
+        password: String,
+    },
+}
+
+fn print_stats(s: &fsmeta::Stats) {
+    println!("files:  {}", s.files);
+    println!("dirs:   {}", s.dirs);
+    println!("size:   {} bytes", s.size);
+    println!("stored: {} bytes (encrypted, with versions)", s.stored);
+    println!("depth:  {}", s.depth);
+}
+
+/// Parses a byte count with an optional `K`/`M` suffix.
+fn parse_size(s: &str) -> anyhow::Result<usize> {
+    let (num, mul) = if let Some(n) = s.strip_suffix(['k', 'K']) {
+        (n, 1024)
+    } else if let Some(n) = s.strip_suffix(['m', 'M']) {
+        (n, 1024 * 1024)
+    } else {
+        (s, 1)
+    };
+    let size = num.parse::<usize>()? * mul;
+    if !(container::MIN_CHUNK_SIZE..=container::MAX_CHUNK_SIZE).contains(&size) {
+        anyhow::bail!(
+            "chunk size must be between {} KiB and {} MiB",
+            container::MIN_CHUNK_SIZE / 1024,
+            container::MAX_CHUNK_SIZE / (1024 * 1024)
+        );
+    }
+    Ok(size)
+}
+
+/// Progress of an import, export or copy on stderr, redrawn in place: a bar, the share
+/// and the bytes done, and the current file. Drawn only on a terminal.
+struct ProgressBar {
+    // bytes of all the files
+    total: u64,
+    done: u64,
+    file: String,
+    drawn: Option<Instant>,
+}
+
+impl ProgressBar {
+    const WIDTH: usize = 30;
+    const REDRAW: Duration = Duration::from_millis(100);
+
+    fn new(total: u64) -> Self {
+        ProgressBar {
+            total,
+            done: 0,
+            file: String::new(),
+            drawn: None,
+        }
+    }
+
+    fn draw(&mut self) {
+        if !std::io::stderr().is_terminal() {
+            return;
+        }
+        let share = match self.total {
+            0 => 1.0,
+            total => (self.done as f64 / total as f64).min(1.0),
+        };
+        let filled = (share * Self::WIDTH as f64) as usize;
+        let mib = |b: u64| b as f64 / (1024.0 * 1024.0);
+        eprint!(
+            "\r[{}{}] {:3.0}%  {:.1}/{:.1} MiB  {}\x1b[K",
+            "#".repeat(filled),
+            "-".repeat(Self::WIDTH - filled),
+            share * 100.0,
+            mib(self.done),
+            mib(self.total),
+            self.file
+        );
+        self.drawn = Some(Instant::now());
+    }
+}
+
+impl container::Progress for ProgressBar {
+    fn file(&mut self, path: &Path, _: u64) {
+        self.file = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        self.draw();
+    }
+
+    fn advance(&mut self, bytes: u64) -> bool {
+        self.done += bytes;
+        if self.drawn.is_none_or(|at| at.elapsed() >= Self::REDRAW) {
+            self.draw();
+        }
+        true
+    }
+}
+
+// Ends the line of the bar, also when the operation failed.
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        if self.drawn.is_some() {
+            self.draw();
+            eprintln!();
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    // Если запуск без аргументов (двойной клик по .exe) — открываем GUI.
+    if std::env::args().len() == 1 {
+        return gui::run();
+    }
+
+    // Сообщения об ошибках — по-английски, если VAULT_LANG не задаёт другой язык.
+    i18n::set(i18n::from_env().unwrap_or(i18n::Lang::En));
+    let cli = Cli::parse();
+    let verbose_errors = cli.verbose_errors;
+    let res = run(cli);
+    let unlock = res.as_ref().err().and_then(|e| e.downcast_ref::<VaultError>()).map(VaultError::root);
+    if let Some(VaultError::WrongPassword { detail, .. }) = unlock {
+        if verbose_errors {
+            eprintln!("unlock failed: {}", i18n::error(&**detail));
+        }
+    }
+    if let Err(e) = res {
+        eprintln!("{}", i18n::report(&e));
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Fails on a weak new password unless `force`, then only warns; `action` completes
+/// the hint about `--force`.
+fn check_strength(password: &str, path: &str, force: bool, action: &str) -> anyhow::Result<()> {
+    let strength = strength::estimate(password, &strength::path_words(path));
+    if strength.is_weak() {
+        let mut msg = format!(
+            "weak password (score {}/4, time to guess at 10^4 guesses per second: {}).",
+            strength.score, strength.crack_time
+        );
+        if let Some(feedback) = &strength.feedback {
+            msg.push_str(&format!(" {feedback}"));
+        }
+        if !force {
+            anyhow::bail!("{msg} Use --force to {action} anyway.");
+        }
+        eprintln!("WARNING: {msg}");
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let keyfile = cli.keyfile.as_deref().map(crypto::keyfile_digest).transpose()?;
+    let pq_seed = match &cli.pq_key {
+        Some(p) => {
+            let mut text = std::fs::read_to_string(p).with_context(|| format!("read {}", p.display()))?;
+            let seed = recovery::decode_pq_key(&text);
+            text.zeroize();
+            Some(seed.with_context(|| format!("post-quantum key {}", p.display()))?)
+        }
+        None => None,
+    };
+    let track = |mut sess: container::Session| -> anyhow::Result<container::Session> {
+        if let Some(counter) = &cli.counter_file {
+            if let Some(known) = container::track_generation(&mut sess, counter)? {
+                eprintln!(
+                    "WARNING: vault generation {} is older than the last known generation {known}. \
+                     The vault may have been replaced with an older copy!",
+                    sess.meta.generation
+                );
+            }
+        }
+        Ok(sess)
+    };
+    // S3 vaults open with the password (and keyfile) only
+    let check_s3_credential = || match pq_seed.is_some() || cli.tpm || cli.token {
+        true => Err(anyhow::anyhow!("a vault in S3 opens with its password only")),
+        false => Ok(()),
+    };
+    let token_pin = |password: &str| Some(password.to_string()).filter(|p| !p.is_empty());
+    let open = |path: &str, password: &str| {
+        if s3::is_url(path) {
+            check_s3_credential()?;
+            return track(s3::open(path, password, keyfile.as_deref(), cli.read_only)?);
+        }
+        if let Some(seed) = &pq_seed {
+            return track(container::open_vault_pq(path, seed, cli.read_only)?);
+        }
+        if cli.tpm {
+            return track(container::open_vault_tpm(path, cli.read_only)?);
+        }
+        track(match (cli.token, cli.read_only) {
+            (true, read_only) => {
+                container::open_vault_token(path, cli.token_cmd.as_deref(), token_pin(password).as_deref(), read_only)?
+            }
+            (false, true) => container::open_vault_readonly(path, password, keyfile.as_deref())?,
+            (false, false) => container::open_vault(path, password, keyfile.as_deref())?,
+        })
+    };
+    let open_readonly = |path: &str, password: &str| {
+        if s3::is_url(path) {
+            check_s3_credential()?;
+            return track(s3::open(path, password, keyfile.as_deref(), true)?);
+        }
+        if let Some(seed) = &pq_seed {
+            return track(container::open_vault_pq(path, seed, true)?);
+        }
+        if cli.tpm {
+            return track(container::open_vault_tpm(path, true)?);
+        }
+        track(match cli.token {
+            true => container::open_vault_token(path, cli.token_cmd.as_deref(), token_pin(password).as_deref(), true)?,
+            false => container::open_vault_readonly(path, password, keyfile.as_deref())?,
+        })
+    };
+
+    match cli.cmd {
+        Cmd::Init {
+            path,
+            password,
+            m_cost_kib,
+            t_cost,
+            p_cost,
+            target_ms,
+            padding,
+            part_size_mib,
+            chunk_size,
+            cipher,
+            recovery_code,
+            force,
+        } => {
+            check_strength(&password, &path, force, "create the vault")?;
+            let (m_cost_kib, t_cost) = match target_ms {
+                Some(ms) => {
+                    let (m, t, took) = crypto::calibrate_kdf(std::time::Duration::from_millis(ms), p_cost)?;
+                    println!("Argon2id: {m} KiB, {t} passes ({} ms to unlock on this machine)", took.as_millis());
+                    (m, t)
+                }
+                None => (m_cost_kib, t_cost),
+            };
+            let (code, code_key) = match recovery_code {
+                true => {
+                    let (code, key) = recovery::new_code();
+                    (Some(code), Some(key))
+                }
+                false => (None, None),
+            };
+            let params = container::NewVault {
+                m_cost_kib,
+                t_cost,
+                p_cost,
+                padding,
+                part_size: part_size_mib * 1024 * 1024,
+                chunk_size,
+                cipher_suite: cipher,
+                keyfile,
+                recovery_code: code_key,
+            };
+            match s3::is_url(&path) {
+                true => s3::create(&path, &password, &params)?,
+                false => container::create_vault(&path, &password, &params)?,
+            }
+            println!("Created vault: {path} ({cipher})");
+            if let Some(mut code) = code {
+                println!("Recovery code (write it down, it is not shown again): {code}");
+                code.zeroize();
+            }
+        }
+
+        Cmd::Ls {
+            path,
+            password,
+            dir_id,
+            long,
+            favorites,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let children: Vec<(String, &fsmeta::Node)> = if favorites {
+                sess.meta.favorites()
+            } else {
+                sess.meta.children_of(dir_id).into_iter().map(|n| (n.name.clone(), n)).collect()
+            };
+            for (name, n) in children {
+                let t = match n.node_type {
+                    fsmeta::NodeType::Dir => "DIR ",
+                    fsmeta::NodeType::File => "FILE",
+                };
+                if long {
+                    println!(
+                        "{t}  id={}  uuid={}  parent={}  size={}  type={}  created={}  modified={}  source_mtime={}  name={}",
+                        n.id,
+                        n.uuid.map_or("-".to_string(), |u| u.to_string()),
+                        n.parent_id,
+                        n.size,
+                        n.mime.as_deref().unwrap_or("-"),
+                        fsmeta::format_time(n.created_at),
+                        fsmeta::format_time(n.modified_at),
+                        fsmeta::format_time(n.source_mtime.unwrap_or(0)),
+                        name
+                    );
+                } else {
+                    println!("{t}  id={}  parent={}  name={name}", n.id, n.parent_id);
+                }
+            }
+        }
+
+        Cmd::Find {
+            path,
+            password,
+            dir_id,
+            name,
+            tag,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            for (node_path, n) in sess.meta.find(dir_id, name.as_deref(), &tag) {
+                let t = match n.node_type {
+                    fsmeta::NodeType::Dir => "DIR ",
+                    fsmeta::NodeType::File => "FILE",
+                };
+                println!("{t}  id={}  tags={}  path={node_path}", n.id, n.tags.join(","));
+            }
+        }
+
+        Cmd::Search {
+            path,
+            password,
+            query,
+            limit,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let Some(fts) = &sess.meta.fts else {
+                anyhow::bail!("no search index; enable it with `search-index --enabled true`");
+            };
+            let hits = fts.search(&query).into_iter().filter(|(id, _)| !sess.meta.is_in_trash(*id));
+            for (id, score) in hits.take(limit) {
+                println!("{score:7.3}  id={id}  path={}", sess.meta.path_of(id));
+            }
+        }
+
+        Cmd::Favorite {
+            path,
+            password,
+            id,
+            enabled,
+        } => {
+            let mut sess = open(&path, &password)?;
+            sess.meta.set_favorite(id, enabled)?;
+            container::save_metadata(&mut sess, &password)?;
+            println!("{} favorites", if enabled { "added to" } else { "removed from" });
+        }
+
+        Cmd::Tag {
+            cmd: TagCmd::Add { path, password, id, tag },
+        } => {
+            let mut sess = open(&path, &password)?;
+            for t in &tag {
+                sess.meta.add_tag(id, t)?;
+            }
+            container::save_metadata(&mut sess, &password)?;
+            println!("tags: {}", sess.meta.get_node(id).map(|n| n.tags.join(",")).unwrap_or_default());
+        }
+
+        Cmd::Tag {
+            cmd: TagCmd::Remove { path, password, id, tag },
+        } => {
+            let mut sess = open(&path, &password)?;
+            for t in &tag {
+                if !sess.meta.remove_tag(id, t)? {
+                    eprintln!("id={id} has no tag {t:?}");
+                }
+            }
+            container::save_metadata(&mut sess, &password)?;
+            println!("tags: {}", sess.meta.get_node(id).map(|n| n.tags.join(",")).unwrap_or_default());
+        }
+
+        Cmd::Tag {
+            cmd: TagCmd::List { path, password, id },
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            match id {
+                Some(id) => {
+                    let n = sess.meta.get_node(id).ok_or_else(|| anyhow::anyhow!("not found"))?;
+                    for t in &n.tags {
+                        println!("{t}");
+                    }
+                }
+                None => {
+                    for (t, count) in sess.meta.all_tags() {
+                        println!("{count:>6}  {t}");
+                    }
+                }
+            }
+        }
+
+        Cmd::Xattr {
+            cmd: XattrCmd::List { path, password, id },
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let n = sess.meta.get_node(id).ok_or_else(|| anyhow::anyhow!("not found"))?;
+            for (name, value) in &n.xattrs {
+                println!("{:>6}  {name}", value.len());
+            }
+        }
+
+        Cmd::Xattr {
+            cmd: XattrCmd::Get { path, password, id, name },
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let value = sess.meta.get_xattr(id, &name)?.ok_or_else(|| anyhow::anyhow!("no attribute {name:?}"))?;
+            std::io::Write::write_all(&mut std::io::stdout(), value)?;
+        }
+
+        Cmd::Xattr {
+            cmd:
+                XattrCmd::Set {
+                    path,
+                    password,
+                    id,
+                    name,
+                    value,
+                    file,
+                },
+        } => {
+            let value = match (value, file) {
+                (Some(v), _) => v.into_bytes(),
+                (None, Some(f)) => std::fs::read(&f).with_context(|| format!("read {}", f.display()))?,
+                (None, None) => unreachable!("clap requires --value or --file"),
+            };
+            let mut sess = open(&path, &password)?;
+            sess.meta.set_xattr(id, &name, value)?;
+            container::save_metadata(&mut sess, &password)?;
+            println!("set {name}");
+        }
+
+        Cmd::Xattr {
+            cmd: XattrCmd::Remove { path, password, id, name },
+        } => {
+            let mut sess = open(&path, &password)?;
+            match sess.meta.remove_xattr(id, &name)? {
+                true => {
+                    container::save_metadata(&mut sess, &password)?;
+                    println!("removed {name}");
+                }
+                false => println!("id={id} has no attribute {name:?}"),
+            }
+        }
+
+        Cmd::Stat { path, password, dir_id } => {
+            let sess = open_readonly(&path, &password)?;
+            match dir_id {
+                Some(id) => print_stats(&sess.meta.dir_stats(id)?),
+                None => {
+                    print_stats(&sess.meta.stats());
+                    if sess.meta.trash_id != 0 {
+                        let trash = sess.meta.dir_stats(sess.meta.trash_id)?;
+                        println!("trash:  {} files, {} bytes", trash.files, trash.size);
+                    }
+                    let usage = container::usage(&sess)?;
+                    println!("vault:  {} bytes on disk", usage.vault);
+                    println!("free:   {} bytes (reclaimed by compact)", usage.free);
+                    println!("        {} more bytes only in the trash (reclaimed by empty-trash and compact)", usage.trash);
+                }
+            }
+        }
+
+        Cmd::Dupes {
+            path,
+            password,
+            hash_missing,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let computed = match hash_missing {
+                true => container::missing_sha256(&sess)?,
+                false => Default::default(),
+            };
+            let groups = sess.meta.duplicates(&computed);
+            let mut wasted = 0;
+            for g in &groups {
+                let extra = fsmeta::duplicate_bytes(g);
+                wasted += extra;
+                println!("{} files of {} bytes, {extra} bytes in extra copies:", g.len(), g[0].size);
+                for (i, n) in g.iter().enumerate() {
+                    match g[..i].iter().find(|earlier| n.shares_chunks(earlier)) {
+                        Some(earlier) => {
+                            println!("  id={}  {}  (shares storage with id={})", n.id, sess.meta.path_of(n.id), earlier.id)
+                        }
+                        None => println!("  id={}  {}", n.id, sess.meta.path_of(n.id)),
+                    }
+                }
+            }
+            println!("{} groups, {wasted} bytes in extra copies", groups.len());
+            let unhashed = sess
+                .meta
+                .nodes
+                .iter()
+                .filter(|n| n.node_type == fsmeta::NodeType::File && n.size > 0 && n.sha256.is_none())
+                .filter(|n| !computed.contains_key(&n.id) && !sess.meta.is_in_trash(n.id))
+                .count();
+            if unhashed > 0 {
+                eprintln!("note: {unhashed} file(s) without a recorded SHA-256 skipped; --hash-missing reads them");
+            }
+        }
+
+        Cmd::Resolve { path, password, uuid } => {
+            let sess = open_readonly(&path, &password)?;
+            let n = sess.meta.node_by_uuid(&uuid).ok_or_else(|| anyhow::anyhow!("no node with UUID {uuid}"))?;
+            println!("id={}  path={}", n.id, sess.meta.path_of(n.id));
+        }
+
+        Cmd::Mkdir {
+            path,
+            password,
+            parent_id,
+            name,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let id = sess.meta.mkdir(parent_id, name)?;
+            container::save_metadata(&mut sess, &password)?;
+            println!("mkdir id={id}");
+        }
+
+        Cmd::Import {
+            path,
+            password,
+            os_path,
+            parent_id,
+            name,
+            compress,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let mut bar = ProgressBar::new(os_path.iter().filter_map(|p| std::fs::metadata(p).ok()).map(|m| m.len()).sum());
+            if let [single] = os_path.as_slice() {
+                let id = container::import_file(&mut sess, &password, single, parent_id, name, compress, &mut bar)?;
+                drop(bar);
+                println!("imported file id={id}");
+            } else {
+                if name.is_some() {
+                    anyhow::bail!("--name needs a single --os-path");
+                }
+                sess.begin()?;
+                let mut ids = vec![];
+                for p in &os_path {
+                    match container::import_file(&mut sess, &password, p, parent_id, None, compress, &mut bar) {
+                        Ok(id) => ids.push(id),
+                        Err(e) => {
+                            sess.rollback()?;
+                            return Err(e).with_context(|| format!("import {}", p.display()));
+                        }
+                    }
+                }
+                sess.commit(&password)?;
+                drop(bar);
+                for id in ids {
+                    println!("imported file id={id}");
+                }
+            }
+        }
+
+        Cmd::Export {
+            path,
+            password,
+            file_id,
+            out_path,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let mut bar = ProgressBar::new(sess.meta.get_node(file_id).map_or(0, |n| n.size));
+            container::export_file(&sess, file_id, &out_path, &mut bar)
+                .with_context(|| format!("export id={file_id} -> {}", out_path.display()))?;
+            drop(bar);
+            println!("exported");
+        }
+
+        Cmd::Thumbnail {
+            path,
+            password,
+            file_id,
+            out_path,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let jpeg = container::read_thumbnail(&sess, file_id)?.ok_or_else(|| anyhow::anyhow!("no thumbnail"))?;
+            std::fs::write(&out_path, jpeg).with_context(|| out_path.display().to_string())?;
+            println!("exported");
+        }
+
+        Cmd::Thumbnails { path, password } => {
+            let mut sess = open(&path, &password)?;
+            let made = container::make_thumbnails(&mut sess, &password)?;
+            println!("{made} thumbnails made");
+        }
+
+        Cmd::Hash { path, password, file_id } => {
+            let sess = open_readonly(&path, &password)?;
+            let node = sess.meta.get_node(file_id).ok_or_else(|| anyhow::anyhow!("not found"))?;
+            if node.node_type != fsmeta::NodeType::File {
+                anyhow::bail!("not a file");
+            }
+            let sha256 = match node.sha256 {
+                Some(h) => h,
+                None => {
+                    eprintln!("no SHA-256 was recorded for this file (written in place or imported by an older version); computed from its contents");
+                    container::file_sha256(&sess, file_id)?
+                }
+            };
+            let hex: String = sha256.iter().map(|b| format!("{b:02x}")).collect();
+            println!("{hex}  {}", node.name);
+        }
+
+        Cmd::Write {
+            path,
+            password,
+            file_id,
+            offset,
+            os_path,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let data = std::fs::read(&os_path).with_context(|| format!("read {}", os_path.display()))?;
+            container::write_file(&mut sess, &password, file_id, offset, &data)?;
+            println!("written {} bytes at {offset}", data.len());
+        }
+
+        Cmd::Append {
+            path,
+            password,
+            file_id,
+            os_path,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let data = std::fs::read(&os_path).with_context(|| format!("read {}", os_path.display()))?;
+            container::append_file(&mut sess, &password, file_id, &data)?;
+            println!("appended {} bytes", data.len());
+        }
+
+        Cmd::Replace {
+            path,
+            password,
+            file_id,
+            os_path,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let data = std::fs::read(&os_path).with_context(|| format!("read {}", os_path.display()))?;
+            container::replace_file(&mut sess, &password, file_id, &data)?;
+            println!("replaced with {} bytes", data.len());
+        }
+
+        Cmd::Rename {
+            path,
+            password,
+            id,
+            new_name,
+        } => {
+            let mut sess = open(&path, &password)?;
+            sess.meta.rename(id, new_name)?;
+            container::save_metadata(&mut sess, &password)?;
+            println!("renamed");
+        }
+
+        Cmd::Mv {
+            path,
+            password,
+            id,
+            parent_id,
+        } => {
+            let mut sess = open(&path, &password)?;
+            for &id in &id {
+                sess.meta.move_node(id, parent_id).with_context(|| format!("move {id}"))?;
+            }
+            container::save_metadata(&mut sess, &password)?;
+            println!("moved {}", id.len());
+        }
+
+        Cmd::Cp {
+            path,
+            password,
+            id,
+            parent_id,
+            name,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let parent_id = match parent_id {
+                Some(p) => p,
+                None => sess.meta.get_node(id).ok_or_else(|| anyhow::anyhow!("not found"))?.parent_id,
+            };
+            let new_id = sess.meta.clone_file(id, parent_id, name)?;
+            container::save_metadata(&mut sess, &password)?;
+            println!("copied to id={new_id}");
+        }
+
+        Cmd::Rm {
+            path,
+            password,
+            id,
+            permanent,
+            shred,
+        } => {
+            let mut sess = open(&path, &password)?;
+            if permanent {
+                container::remove_node(&mut sess, &password, id, shred)?;
+                println!("removed");
+            } else {
+                sess.meta.trash(id)?;
+                container::save_metadata(&mut sess, &password)?;
+                println!("moved to trash");
+            }
+        }
+
+        Cmd::Trash { path, password } => {
+            let sess = open_readonly(&path, &password)?;
+            for n in sess.meta.trash_items() {
+                let t = match n.node_type {
+                    fsmeta::NodeType::Dir => "DIR ",
+                    fsmeta::NodeType::File => "FILE",
+                };
+                let from = n.trashed_from.unwrap_or(sess.meta.root_id);
+                println!("{t}  id={}  from={from}  name={}", n.id, n.name);
+            }
+        }
+
+        Cmd::TrashRestore { path, password, id } => {
+            let mut sess = open(&path, &password)?;
+            let parent_id = sess.meta.restore_from_trash(id)?;
+            container::save_metadata(&mut sess, &password)?;
+            println!("restored to parent={parent_id}");
+        }
+
+        Cmd::EmptyTrash {
+            path,
+            password,
+            shred,
+        } => {
+            let mut sess = open(&path, &password)?;
+            container::empty_trash(&mut sess, &password, shred)?;
+            println!("trash emptied");
+        }
+
+        Cmd::Versioning {
+            path,
+            password,
+            enabled,
+        } => {
+            let mut sess = open(&path, &password)?;
+            sess.meta.versioning = enabled;
+            container::save_metadata(&mut sess, &password)?;
+            println!("versioning {}", if enabled { "on" } else { "off" });
+        }
+
+        Cmd::NameLimit {
+            path,
+            password,
+            max_len,
+        } => {
+            if max_len == 0 {
+                anyhow::bail!("--max-len must be at least 1");
+            }
+            let mut sess = open(&path, &password)?;
+            sess.meta.max_name_len = max_len;
+            container::save_metadata(&mut sess, &password)?;
+            println!("names up to {max_len} bytes");
+        }
+
+        Cmd::CaseInsensitive {
+            path,
+            password,
+            enabled,
+        } => {
+            let mut sess = open(&path, &password)?;
+            sess.meta.case_insensitive = enabled;
+            container::save_metadata(&mut sess, &password)?;
+            println!("case-insensitive names {}", if enabled { "on" } else { "off" });
+            if enabled {
+                for (dir_id, names) in sess.meta.case_collisions() {
+                    eprintln!("warning: dir id={dir_id} already holds {}; rename all but one", names.join(", "));
+                }
+            }
+        }
+
+        Cmd::SearchIndex {
+            path,
+            password,
+            enabled,
+        } => {
+            let mut sess = open(&path, &password)?;
+            let count = container::set_search_index(&mut sess, &password, enabled)?;
+            if enabled {
+                println!("search index on, {count} text files indexed");
+            } else {
+                println!("search index off");
+            }
+        }
+
+        Cmd::Padding {
+            path,
+            password,
+            policy,
+        } => {
+            let mut sess = open(&path, &password)?;
+            sess.padding = policy;
+            container::save_metadata(&mut sess, &password)?;
+            println!("padding {policy}");
+        }
+
+        Cmd::Versions {
+            path,
+            password,
+            file_id,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let n = sess
+                .meta
+                .get_node(file_id)
+                .ok_or_else(|| anyhow::anyhow!("not found"))?;
+            for (i, v) in n.versions.iter().enumerate() {
+                println!(
+                    "version={}  size={}  chunks={}  modified={}",
+                    i + 1,
+                    v.size,
+                    v.chunks.len(),
+                    fsmeta::format_time(v.modified_at)
+                );
+            }
+            println!(
+                "current  size={}  chunks={}  modified={}",
+                n.size,
+                n.chunks.len(),
+                fsmeta::format_time(n.modified_at)
+            );
+        }
+
+        Cmd::Restore {
+            path,
+            password,
+            file_id,
+            version,
+        } => {
+            let mut sess = open(&path, &password)?;
+            container::restore_version(&mut sess, &password, file_id, version)?;
+            println!("restored version {version}");
+        }
+
+        Cmd::Compact { path, password } => {
+            let mut sess = open(&path, &password)?;
+            let freed = container::compact(&mut sess, &password)?;
+            println!("compacted, freed {freed} bytes");
+        }
+
+        Cmd::Verify {
+            path,
+            password,
+            fast,
+            file_id,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let problems = if fast {
+                let report = container::verify_fast(&sess)?;
+                println!(
+                    "checked {} chunks ({} without hash)",
+                    report.chunks_checked, report.chunks_unhashed
+                );
+                report.problems
+            } else {
+                let ids: Vec<u64> = match file_id {
+                    Some(id) => vec![id],
+                    None => sess
+                        .meta
+                        .nodes
+                        .iter()
+                        .filter(|n| n.node_type == fsmeta::NodeType::File)
+                        .map(|n| n.id)
+                        .collect(),
+                };
+                ids.into_iter()
+                    .filter_map(|id| container::verify_file(&sess, id).err().map(|e| format!("id={id}: {e:#}")))
+                    .collect()
+            };
+            for p in &problems {
+                println!("{p}");
+            }
+            if !problems.is_empty() {
+                anyhow::bail!("{} problem(s) found", problems.len());
+            }
+            println!("ok");
+        }
+
+        Cmd::Rekey { path, password, cipher } => {
+            let mut sess = open(&path, &password)?;
+            let n = container::rekey(&mut sess, &password, cipher)?;
+            println!("rekeyed, {n} chunks re-encrypted ({})", sess.suite);
+        }
+
+        Cmd::Passwd {
+            path,
+            password,
+            new_password,
+            force,
+        } => {
+            check_strength(&new_password, &path, force, "change the password")?;
+            let mut sess = open(&path, &password)?;
+            container::change_password(&mut sess, &password, &new_password)?;
+            println!("password changed");
+        }
+
+        Cmd::Recovery {
+            cmd:
+                RecoveryCmd::Create {
+                    path,
+                    password,
+                    shares,
+                    threshold,
+                    out_dir,
+                },
+        } => {
+            let mut sess = open(&path, &password)?;
+            let id = crypto::random_bytes::<8>();
+            let key = crypto::SecretKey::new(crypto::random_bytes());
+            let split = recovery::split_key(id, &key, shares, threshold);
+            // Shares are written before the slot, so a failure can't leave a slot
+            // nobody has the key to.
+            let written = split.map_err(anyhow::Error::from).and_then(|split| {
+                let stem = std::path::Path::new(&path).file_name().map(|n| n.to_string_lossy().to_string());
+                let stem = stem.unwrap_or_else(|| "vault".to_string());
+                std::fs::create_dir_all(&out_dir)?;
+                let mut files = Vec::new();
+                for share in &split {
+                    let file = out_dir.join(format!("{stem}.share-{}-of-{shares}.txt", share.x));
+                    let text = format!(
+                        "# Recovery share {} of {shares} for {stem}\n\
+                         # Any {threshold} shares restore access to the vault. Keep them in different places.\n\
+                         {}\n",
+                        share.x,
+                        share.encode()
+                    );
+                    std::fs::write(&file, text).with_context(|| format!("write {}", file.display()))?;
+                    files.push(file);
+                }
+                Ok(files)
+            });
+            let res = written.and_then(|files| {
+                container::add_key_slot(&mut sess, &password, container::SlotKind::Shares, id, &key)?;
+                Ok(files)
+            });
+            for file in res? {
+                println!("{}", file.display());
+            }
+            println!("any {threshold} of these {shares} shares restore access (recovery restore)");
+        }
+
+        Cmd::Recovery {
+            cmd: RecoveryCmd::Code { path, password },
+        } => {
+            let mut sess = open(&path, &password)?;
+            let (mut code, key) = recovery::new_code();
+            container::add_key_slot(&mut sess, &password, container::SlotKind::Code, crypto::random_bytes::<8>(), &key)?;
+            println!("Recovery code (write it down, it is not shown again): {code}");
+            code.zeroize();
+        }
+
+        Cmd::Recovery {
+            cmd:
+                RecoveryCmd::Restore {
+                    path,
+                    share,
+                    code,
+                    new_password,
+                    new_keyfile,
+                },
+        } => {
+            let sess = match code {
+                Some(code) => {
+                    let key = recovery::parse_code(&code)?;
+                    container::open_vault_slot(&path, container::SlotKind::Code, None, &key, false)
+                }
+                None => {
+                    let shares = share
+                        .iter()
+                        .map(|p| {
+                            let text = std::fs::read_to_string(p).with_context(|| format!("read {}", p.display()))?;
+                            recovery::Share::decode(&text).with_context(|| format!("share {}", p.display()))
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    let (id, key) = recovery::combine_key(&shares)?;
+                    container::open_vault_slot(&path, container::SlotKind::Shares, Some(&id), &key, false)
+                }
+            };
+            let mut sess = track(sess?)?;
+            let new_keyfile = new_keyfile.as_deref().map(crypto::keyfile_digest).transpose()?;
+            container::reset_password(&mut sess, &new_password, new_keyfile.as_deref())?;
+            println!("access restored, the vault now opens with the new password");
+        }
+
+        Cmd::Token {
+            cmd: TokenCmd::Enroll { path, password, pin },
+        } => {
+            let mut sess = open(&path, &password)?;
+            container::add_token_slot(&mut sess, &password, cli.token_cmd.as_deref(), pin.as_deref())?;
+            println!("token enrolled");
+        }
+
+        Cmd::Token {
+            cmd: TokenCmd::Remove { path, password },
+        } => {
+            let mut sess = open(&path, &password)?;
+            match container::remove_key_slots(&mut sess, &password, container::TOKEN_SLOTS)? {
+                true => println!("token removed"),
+                false => println!("no token enrolled"),
+            }
+        }
+
+        Cmd::Pq {
+            cmd: PqCmd::Create { path, password, out },
+        } => {
+            let mut sess = open(&path, &password)?;
+            let seed = crypto::pq::new_seed();
+            let stem = std::path::Path::new(&path).file_name().map(|n| n.to_string_lossy().to_string());
+            let stem = stem.unwrap_or_else(|| "vault".to_string());
+            let mut text = format!(
+                "# Post-quantum key (X25519 + ML-KEM-768) for {stem}\n\
+                 # Opens the vault without the password: vault --pq-key FILE ... Keep it apart from the vault.\n\
+                 {}\n",
+                recovery::encode_pq_key(&seed)
+            );
+            // The key file is written before the slot, as shares are.
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&out)
+                .with_context(|| format!("create {}", out.display()))?;
+            std::io::Write::write_all(&mut file, text.as_bytes()).with_context(|| format!("write {}", out.display()))?;
+            file.sync_all()?;
+            drop(file);
+            text.zeroize();
+            container::add_pq_slot(&mut sess, &password, &seed)?;
+            println!("{}", out.display());
+            println!("post-quantum key slot added; this file opens the vault with --pq-key");
+        }
+
+        Cmd::Pq {
+            cmd: PqCmd::Remove { path, password },
+        } => {
+            let mut sess = open(&path, &password)?;
+            match container::remove_key_slots(&mut sess, &password, &[container::SlotKind::PostQuantum])? {
+                true => println!("post-quantum key slot removed"),
+                false => println!("the vault has no post-quantum key slot"),
+            }
+        }
+
+        Cmd::Tpm {
+            cmd: TpmCmd::Enroll { path, password, pcrs },
+        } => {
+            let mut sess = open(&path, &password)?;
+            container::add_tpm_slot(&mut sess, &password, &pcrs)?;
+            println!("key sealed to the TPM (PCRs {pcrs}); the vault now also opens with --tpm on this machine");
+        }
+
+        Cmd::Tpm {
+            cmd: TpmCmd::Unenroll { path, password },
+        } => {
+            let mut sess = open(&path, &password)?;
+            match container::remove_key_slots(&mut sess, &password, &[container::SlotKind::Tpm])? {
+                true => println!("TPM key slot removed"),
+                false => println!("no TPM key slot"),
+            }
+        }
+
+        Cmd::Duress {
+            cmd: DuressCmd::Set { path, password, duress_password },
+        } => {
+            let mut sess = open(&path, &password)?;
+            container::set_duress_password(&mut sess, &password, &duress_password)?;
+            println!("duress password set; it opens an empty decoy tree to fill");
+        }
+
+        Cmd::Duress {
+            cmd: DuressCmd::Remove { path, password },
+        } => {
+            let mut sess = open(&path, &password)?;
+            match container::remove_duress_password(&mut sess, &password)? {
+                true => println!("duress password removed"),
+                false => println!("no duress password set"),
+            }
+        }
+
+        Cmd::Forget { path, password } => {
+            let mut sess = open(&path, &password)?;
+            let removed = container::remove_key_slots(&mut sess, &password, &[container::SlotKind::Remembered])?;
+            // The slot is what matters; a keychain without an entry (or none at all,
+            // e.g. no Secret Service) is only worth a warning.
+            let deleted = keychain::delete(&path).unwrap_or_else(|e| {
+                eprintln!("warning: {e}");
+                false
+            });
+            match removed || deleted {
+                true => println!("remembered unlock revoked"),
+                false => println!("the vault was not remembered"),
+            }
+        }
+
+        Cmd::Copy {
+            src,
+            src_password,
+            src_keyfile,
+            src_id,
+            dst,
+            dst_password,
+            parent_id,
+            merge,
+        } => {
+            let src_keyfile = src_keyfile.as_deref().map(crypto::keyfile_digest).transpose()?;
+            let src_sess = match s3::is_url(&src) {
+                true => s3::open(&src, &src_password, src_keyfile.as_deref(), true),
+                false => container::open_vault_readonly(&src, &src_password, src_keyfile.as_deref()).map_err(Into::into),
+            }
+            .context("open source")?;
+            let mut dst_sess = open(&dst, &dst_password).context("open destination")?;
+            let total = match src_sess.meta.get_node(src_id) {
+                Some(n) if n.node_type == fsmeta::NodeType::Dir => src_sess.meta.dir_stats(src_id)?.size,
+                n => n.map_or(0, |n| n.size),
+            };
+            let mut bar = ProgressBar::new(total);
+            let n = container::copy_from(&src_sess, &mut dst_sess, &dst_password, src_id, parent_id, merge, &mut bar)?;
+            drop(bar);
+            println!("copied {n} file(s)");
+        }
+
+        Cmd::Sync {
+            local,
+            password,
+            remote,
+            remote_password,
+            remote_keyfile,
+            dry_run,
+        } => {
+            let remote_password = remote_password.unwrap_or_else(|| password.clone());
+            let remote_keyfile = remote_keyfile.as_deref().map(crypto::keyfile_digest).transpose()?;
+            let mut local_sess = open(&local, &password).context("open local vault")?;
+            let mut remote_sess = match s3::is_url(&remote) {
+                true => s3::open(&remote, &remote_password, remote_keyfile.as_deref(), false),
+                false => container::open_vault(&remote, &remote_password, remote_keyfile.as_deref()).map_err(Into::into),
+            }
+            .context("open remote vault")?;
+            let plan = sync::plan(&mut local_sess, &mut remote_sess)?;
+            if plan.is_empty() {
+                println!("already in sync");
+            }
+            for (side, name) in [(sync::Side::Local, "local"), (sync::Side::Remote, "remote")] {
+                let c = plan.changes(side);
+                if c != sync::Changes::default() {
+                    println!(
+                        "{name}: {} added, {} updated, {} moved, {} deleted",
+                        c.added, c.updated, c.moved, c.deleted
+                    );
+                }
+            }
+            for path in plan.conflicts() {
+                println!("conflict: {path} (the remote version is kept next to it)");
+            }
+            if !dry_run {
+                let mut bar = ProgressBar::new(plan.bytes());
+                sync::apply(&mut local_sess, &password, &mut remote_sess, &remote_password, &plan, &mut bar)?;
+            }
+        }
+
+        Cmd::Backup {
+            path,
+            password,
+            out,
+            passphrase,
+            recipient,
+        } => {
+            let sess = open_readonly(&path, &password)?;
+            let recipients = match passphrase {
+                Some(p) => vec![age::Recipient::Passphrase(Zeroizing::new(p))],
+                None => recipient.iter().map(|r| age::Recipient::parse(r)).collect::<Result<_, _>>()?,
+            };
+            let mut total = sess.meta.dir_stats(sess.meta.root_id)?.size;
+            if sess.meta.trash_id != 0 {
+                total -= sess.meta.dir_stats(sess.meta.trash_id)?.size;
+            }
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&out)
+                .with_context(|| format!("create {}", out.display()))?;
+            let mut bar = ProgressBar::new(total);
+            let res = backup::backup(&sess, std::io::BufWriter::new(&file), &recipients, age::SCRYPT_WORK_FACTOR, &mut bar)
+                .and_then(|stats| Ok(file.sync_all().map(|()| stats)?));
+            drop(bar);
+            let stats = match res {
+                Ok(stats) => stats,
+                Err(e) => {
+                    drop(file);
+                    let _ = std::fs::remove_file(&out);
+                    return Err(anyhow::Error::from(e).context(format!("write {}", out.display())));
+                }
+            };
+            println!("{} file(s), {} dir(s), {} bytes -> {}", stats.files, stats.dirs, stats.bytes, out.display());
+        }
+
+        Cmd::BackupRestore {
+            path,
+            password,
+            input,
+            passphrase,
+            identity,
+            parent_id,
+        } => {
+            let identities = match (passphrase, identity) {
+                (Some(p), _) => vec![age::Identity::Passphrase(Zeroizing::new(p))],
+                (None, Some(file)) => {
+                    let text = std::fs::read_to_string(&file).with_context(|| file.display().to_string())?;
+                    let text = Zeroizing::new(text);
+                    age::parse_identities(&text).with_context(|| file.display().to_string())?
+                }
+                (None, None) => unreachable!("clap requires one"),
+            };
+            let mut sess = open(&path, &password)?;
+            let file = std::fs::File::open(&input).with_context(|| input.display().to_string())?;
+            let mut bar = ProgressBar::new(file.metadata()?.len());
+            let input_file = std::io::BufReader::new(file);
+            let stats = backup::restore(&mut sess, &password, parent_id, input_file, &identities, &mut bar)
+                .with_context(|| format!("restore {}", input.display()))?;
+            drop(bar);
+            println!("restored {} file(s), {} dir(s), {} bytes", stats.files, stats.dirs, stats.bytes);
+            if stats.skipped > 0 {
+                println!("skipped {} entries that are neither files nor directories", stats.skipped);
+            }
+        }
+
+        Cmd::Daemon { socket } => {
+            daemon::run(&socket.unwrap_or_else(daemon::default_socket))?;
+        }
+
+        Cmd::Serve {
+            path,
+            password,
+            webdav,
+            url_token,
+            allow_remote,
+        } => {
+            if !webdav.ip().is_loopback() && !allow_remote {
+                anyhow::bail!("{webdav} can be reached from other machines and the traffic is not encrypted: use --allow-remote to serve there anyway");
+            }
+            let sess = open(&path, &password)?;
+            webdav::serve(sess, &password, webdav, url_token)?;
+        }
+
+        Cmd::Shell { path, password } => {
+            let mut sess = open(&path, &password)?;
+            shell::run(&mut sess, &password)?;
+        }
+
+        Cmd::Bench { target_ms, data_mib } => {
+            let target = std::time::Duration::from_millis(target_ms);
+            println!("Argon2id unlock time, 3 passes, 1 lane:");
+            for m_cost_kib in [64 * 1024, 128 * 1024, 256 * 1024, 512 * 1024, 1024 * 1024] {
+                let took = crypto::time_kdf(m_cost_kib, 3, 1)?;
+                println!("  --m-cost-kib {m_cost_kib:>7}  {:>6} ms", took.as_millis());
+                // the larger sets would only take longer
+                if took > target * 4 {
+                    break;
+                }
+            }
+
+            println!("AEAD throughput, {data_mib} MiB in 1 MiB chunks:");
+            for suite in crypto::CipherSuite::ALL {
+                let (encrypt, decrypt) = crypto::time_aead(suite, data_mib * 1024 * 1024, 1024 * 1024)?;
+                let rate = |d: std::time::Duration| data_mib as f64 / d.as_secs_f64().max(1e-9);
+                println!("  {:<20} encrypt {:>7.0} MiB/s, decrypt {:>7.0} MiB/s", suite.to_string(), rate(encrypt), rate(decrypt));
+            }
+
+            let (m_cost_kib, t_cost, took) = crypto::calibrate_kdf(target, 1)?;
+            println!(
+                "suggested: init --m-cost-kib {m_cost_kib} --t-cost {t_cost} ({} ms to unlock here; \
+                 or init --target-ms {target_ms}), --cipher auto picks {}",
+                took.as_millis(),
+                crypto::CipherSuite::preferred()
+            );
         }
     }
 
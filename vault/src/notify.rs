@@ -0,0 +1,105 @@
+//! Messages of the GUI about what its operations did. Each one shows up as a toast for
+//! a few seconds; errors additionally stay in a list of recent errors until it is
+//! cleared, so one that flashed by while the user looked elsewhere isn't lost.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use vault_core::fsmeta;
+
+/// Toasts shown at once; an older one makes room for a new one.
+const MAX_TOASTS: usize = 5;
+/// Errors kept in the list.
+const MAX_ERRORS: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    /// How long a toast of this level stays up: warnings and errors take longer to read.
+    fn lifetime(self) -> Duration {
+        match self {
+            Level::Info => Duration::from_secs(4),
+            Level::Warning => Duration::from_secs(7),
+            Level::Error => Duration::from_secs(10),
+        }
+    }
+}
+
+pub struct Toast {
+    pub level: Level,
+    pub text: String,
+    shown_at: Instant,
+}
+
+/// A recorded error with the wall-clock time (Unix seconds) it happened at.
+pub struct ErrorEntry {
+    pub time: u64,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Notifier {
+    toasts: VecDeque<Toast>,
+    errors: VecDeque<ErrorEntry>,
+}
+
+impl Notifier {
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(Level::Info, text.into());
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.push(Level::Warning, text.into());
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(Level::Error, text.into());
+    }
+
+    pub fn push(&mut self, level: Level, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        if level == Level::Error {
+            if self.errors.len() == MAX_ERRORS {
+                self.errors.pop_front();
+            }
+            self.errors.push_back(ErrorEntry { time: fsmeta::now(), text: text.clone() });
+        }
+        // The same message again only restarts its toast.
+        self.toasts.retain(|t| t.text != text);
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast { level, text, shown_at: Instant::now() });
+    }
+
+    /// Toasts still up, oldest first, and how soon the next one goes away.
+    pub fn toasts(&mut self) -> (&VecDeque<Toast>, Option<Duration>) {
+        let now = Instant::now();
+        self.toasts.retain(|t| now - t.shown_at < t.level.lifetime());
+        let next = self.toasts.iter().map(|t| (t.shown_at + t.level.lifetime()).saturating_duration_since(now)).min();
+        (&self.toasts, next)
+    }
+
+    pub fn dismiss(&mut self, index: usize) {
+        self.toasts.remove(index);
+    }
+
+    pub fn clear_toasts(&mut self) {
+        self.toasts.clear();
+    }
+
+    /// Recorded errors, oldest first.
+    pub fn errors(&self) -> &VecDeque<ErrorEntry> {
+        &self.errors
+    }
+
+    pub fn clear_errors(&mut self) {
+        self.errors.clear();
+    }
+}
@@ -0,0 +1,85 @@
+//! PDF pages for the GUI viewer, drawn by pdfium through `pdfium-render`: text, vector
+//! drawings and images alike, at the resolution the viewer asks for, so that zooming
+//! in stays sharp.
+//!
+//! The pdfium library (`pdfium.dll`, `libpdfium.so`, `libpdfium.dylib`) is loaded at
+//! run time, from the directory of the executable or else from the system's library
+//! path. Without it PDF files are not shown, and say so.
+
+use anyhow::Context;
+use pdfium_render::prelude::*;
+use std::cell::OnceCell;
+
+/// Points per inch in PDF, and pixels per inch of a page shown at 100%.
+const POINTS_PER_INCH: f32 = 72.0;
+const PIXELS_PER_INCH: f32 = 96.0;
+/// Limit on the longer side of a rendered page, in pixels, however far it is zoomed.
+pub const MAX_RENDER_SIDE: u32 = 8192;
+
+thread_local! {
+    // Loaded once for the life of the program (pdfium is initialized only once); the
+    // viewer runs on the GUI thread.
+    static PDFIUM: OnceCell<Result<&'static Pdfium, String>> = const { OnceCell::new() };
+}
+
+/// The pdfium library, loaded on first use.
+fn pdfium() -> anyhow::Result<&'static Pdfium> {
+    PDFIUM.with(|cell| {
+        let loaded = cell.get_or_init(|| {
+            let next_to_exe = std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(Pdfium::pdfium_platform_library_name_at_path));
+            next_to_exe
+                .map_or_else(Pdfium::bind_to_system_library, Pdfium::bind_to_library)
+                .or_else(|_| Pdfium::bind_to_system_library())
+                .map(|bindings| &*Box::leak(Box::new(Pdfium::new(bindings))))
+                .map_err(|e| e.to_string())
+        });
+        loaded.clone().map_err(|e| anyhow::anyhow!("the pdfium library is not available: {e}"))
+    })
+}
+
+pub struct Document {
+    doc: PdfDocument<'static>,
+}
+
+impl Document {
+    /// Opens a PDF file; one that needs a password to open is refused.
+    pub fn open(bytes: Vec<u8>) -> anyhow::Result<Document> {
+        let doc = pdfium()?.load_pdf_from_byte_vec(bytes, None).map_err(|e| anyhow::anyhow!("{e}"))?;
+        if doc.pages().is_empty() {
+            anyhow::bail!("no pages found in the PDF file");
+        }
+        Ok(Document { doc })
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.doc.pages().len() as usize
+    }
+
+    fn page(&self, index: usize) -> anyhow::Result<PdfPage<'_>> {
+        let index = PdfPageIndex::try_from(index).ok().context("no such page")?;
+        self.doc.pages().get(index).map_err(|e| anyhow::anyhow!("{e}"))
+    }
+
+    /// Size of page `index` (0-based) shown at 100%, in logical pixels.
+    pub fn page_size(&self, index: usize) -> anyhow::Result<(f32, f32)> {
+        let page = self.page(index)?;
+        let to_px = PIXELS_PER_INCH / POINTS_PER_INCH;
+        Ok((page.width().value * to_px, page.height().value * to_px))
+    }
+
+    /// Page `index` drawn `width` pixels wide, on white; the longer side is kept to
+    /// `MAX_RENDER_SIDE`.
+    pub fn render(&self, index: usize, width: u32) -> anyhow::Result<image::DynamicImage> {
+        let page = self.page(index)?;
+        let side = MAX_RENDER_SIDE as Pixels;
+        let config = PdfRenderConfig::new()
+            .set_target_width(width.clamp(1, MAX_RENDER_SIDE) as Pixels)
+            .set_maximum_width(side)
+            .set_maximum_height(side)
+            .render_form_data(true);
+        let bitmap = page.render_with_config(&config).map_err(|e| anyhow::anyhow!("{e}"))?;
+        Ok(bitmap.as_image())
+    }
+}
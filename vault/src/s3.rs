@@ -0,0 +1,438 @@
+//! Vaults in S3-compatible object storage: `--path s3://bucket/key` opens the vault
+//! stored under `key` in `bucket` as a `vault_core::backend::Backend`, with no copy of
+//! it on the local disk.
+//!
+//! Objects can't be written in part, so the vault is kept as blocks of `BLOCK_SIZE`
+//! bytes (objects `key/00000000`, `key/00000001`, ...) and its length (object
+//! `key/size`). A block of zeros isn't stored and a missing one reads as zeros, so
+//! the header reservation costs nothing. Writes stay in memory until `flush` (up to `MAX_DIRTY` blocks) and
+//! the size goes last.
+//!
+//! Requests are signed with AWS Signature Version 4 and sent over plain HTTP: the
+//! vault is encrypted anyway, but sizes and access patterns can be seen on the way.
+//! Settings come from the environment: `AWS_ACCESS_KEY_ID`,
+//! `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN`, `AWS_REGION` (else us-east-1), and
+//! `VAULT_S3_ENDPOINT` for a service other than AWS (`http://host:port`, path-style).
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use vault_core::backend::Backend;
+use vault_core::container::{self, Session};
+use vault_core::crypto::KEY_LEN;
+use vault_core::fsmeta;
+use zeroize::Zeroizing;
+
+const BLOCK_SIZE: u64 = 1024 * 1024;
+// blocks kept in memory before writes go out without waiting for `flush`
+const MAX_DIRTY: usize = 64;
+// blocks read and still unchanged kept in memory
+const MAX_CLEAN: usize = 16;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("s3://")
+}
+
+/// Opens the vault at `url`. A read-only session keeps nothing on the local disk, a
+/// writable one its journal and lock files under `state_path`.
+pub fn open(url: &str, password: &str, keyfile: Option<&[u8; KEY_LEN]>, read_only: bool) -> anyhow::Result<Session> {
+    let backend: Arc<dyn Backend> = Arc::new(S3Backend::open(url)?);
+    Ok(match read_only {
+        true => container::open_vault_backend(backend, password, keyfile)?,
+        false => container::open_vault_in(backend, &state_path(url)?, password, keyfile)?,
+    })
+}
+
+pub fn create(url: &str, password: &str, params: &container::NewVault) -> anyhow::Result<()> {
+    let backend: Arc<dyn Backend> = Arc::new(S3Backend::open(url)?);
+    container::create_vault_in(&backend, password, params)?;
+    Ok(backend.flush()?)
+}
+
+/// Local base path for the journal, lock and counter files of the vault at `url`
+/// (see `container::open_vault_in`), in the config directory.
+pub fn state_path(url: &str) -> anyhow::Result<String> {
+    let dir = crate::settings::config_dir().context("no config directory (HOME or APPDATA isn't set)")?.join("s3");
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let name: String = url["s3://".len()..].chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' }).collect();
+    Ok(dir.join(name).to_string_lossy().into_owned())
+}
+
+struct Client {
+    // host:port to connect to, and the Host header
+    addr: String,
+    host: String,
+    // path prefix of every object: `/bucket` with path-style addressing, else empty
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: Zeroizing<String>,
+    session_token: Option<String>,
+}
+
+impl Client {
+    fn from_env(bucket: &str) -> anyhow::Result<Self> {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let region = var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".into());
+        let access_key = var("AWS_ACCESS_KEY_ID").context("AWS_ACCESS_KEY_ID isn't set")?;
+        let secret_key = Zeroizing::new(var("AWS_SECRET_ACCESS_KEY").context("AWS_SECRET_ACCESS_KEY isn't set")?);
+        let (addr, host, prefix) = match var("VAULT_S3_ENDPOINT") {
+            Some(endpoint) => {
+                if endpoint.starts_with("https://") {
+                    anyhow::bail!("https endpoints are not supported: use http:// (through a TLS proxy if the service requires it)");
+                }
+                let host = endpoint.trim_start_matches("http://").trim_end_matches('/').to_string();
+                let addr = if host.contains(':') { host.clone() } else { format!("{host}:80") };
+                (addr, host, format!("/{bucket}"))
+            }
+            None => {
+                let host = format!("{bucket}.s3.{region}.amazonaws.com");
+                (format!("{host}:80"), host, String::new())
+            }
+        };
+        Ok(Self {
+            addr,
+            host,
+            prefix,
+            region,
+            access_key,
+            secret_key,
+            session_token: var("AWS_SESSION_TOKEN"),
+        })
+    }
+
+    /// Sends a signed request for `object`; returns the status and the body.
+    fn request(&self, method: &str, object: &str, body: &[u8]) -> std::io::Result<(u16, Vec<u8>)> {
+        let path = format!("{}/{}", self.prefix, uri_encode(object));
+        let payload_hash = hex(&Sha256::digest(body));
+        let amz_date = amz_date(fsmeta::now());
+        let mut headers = vec![
+            ("host", self.host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let authorization = self.authorization(method, &path, &headers, &payload_hash, &amz_date);
+
+        let mut stream = TcpStream::connect(&self.addr)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut head = format!("{method} {path} HTTP/1.1\r\n");
+        for (name, value) in &headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("authorization: {authorization}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n", body.len()));
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        parse_response(&response)
+    }
+
+    /// `Authorization` header of Signature Version 4 for a request without a query.
+    fn authorization(&self, method: &str, path: &str, headers: &[(&str, String)], payload_hash: &str, amz_date: &str) -> String {
+        let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+        let signed_headers = names.join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{name}:{}\n", value.trim())).collect();
+        let canonical_request = format!("{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}");
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex(&Sha256::digest(canonical_request.as_bytes())));
+        let secret = Zeroizing::new(format!("AWS4{}", self.secret_key.as_str()));
+        let mut key = hmac(secret.as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+        format!("AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}", self.access_key)
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Zeroizing<Vec<u8>> {
+    // HMAC takes a key of any length
+    let mut mac = <Hmac<Sha256>>::new_from_slice(key).expect("HMAC key");
+    mac.update(data);
+    Zeroizing::new(mac.finalize().into_bytes().to_vec())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `YYYYMMDDTHHMMSSZ`, from the `YYYY-MM-DD HH:MM:SS` of `fsmeta::format_time`.
+fn amz_date(secs: u64) -> String {
+    let formatted = fsmeta::format_time(secs).replace(['-', ':'], "");
+    format!("{}Z", formatted.replace(' ', "T"))
+}
+
+/// Percent-encodes an object key for the request path, as SigV4 expects: all but
+/// unreserved characters and `/`.
+fn uri_encode(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// Status and body of a whole HTTP/1.1 response (the connection was closed after it).
+fn parse_response(response: &[u8]) -> std::io::Result<(u16, Vec<u8>)> {
+    let bad = || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed HTTP response");
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(bad)?;
+    let head = std::str::from_utf8(&response[..end]).map_err(|_| bad())?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next().and_then(|l| l.split(' ').nth(1)).and_then(|s| s.parse().ok()).ok_or_else(bad)?;
+    let header = |name: &str| {
+        lines.clone().find_map(|l| l.split_once(':').filter(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.trim().to_string()))
+    };
+    let mut body = &response[end + 4..];
+    if header("transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        let mut out = vec![];
+        loop {
+            let line_end = body.windows(2).position(|w| w == b"\r\n").ok_or_else(bad)?;
+            let size_text = std::str::from_utf8(&body[..line_end]).map_err(|_| bad())?;
+            let size = usize::from_str_radix(size_text.split(';').next().unwrap_or("").trim(), 16).map_err(|_| bad())?;
+            body = &body[line_end + 2..];
+            if size == 0 {
+                return Ok((status, out));
+            }
+            out.extend_from_slice(body.get(..size).ok_or_else(bad)?);
+            body = body.get(size + 2..).ok_or_else(bad)?;
+        }
+    }
+    if let Some(len) = header("content-length").and_then(|v| v.parse::<usize>().ok()) {
+        body = body.get(..len).ok_or_else(bad)?;
+    }
+    Ok((status, body.to_vec()))
+}
+
+/// The error of a failed request, with `Code` and `Message` from the XML S3 sends.
+fn request_error(method: &str, object: &str, status: u16, body: &[u8]) -> std::io::Error {
+    let text = String::from_utf8_lossy(body);
+    let element = |name: &str| {
+        let start = text.find(&format!("<{name}>"))? + name.len() + 2;
+        let len = text[start..].find(&format!("</{name}>"))?;
+        Some(text[start..start + len].to_string())
+    };
+    let detail = [element("Code"), element("Message")].into_iter().flatten().collect::<Vec<_>>().join(": ");
+    std::io::Error::other(format!("S3 {method} {object}: HTTP {status} {detail}"))
+}
+
+pub struct S3Backend {
+    client: Client,
+    url: String,
+    key: String,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    len: u64,
+    // length as the size object has it
+    stored_len: u64,
+    blocks: BTreeMap<u64, Block>,
+    dirty: BTreeSet<u64>,
+}
+
+struct Block {
+    // shorter than `BLOCK_SIZE` if the rest is zeros
+    data: Vec<u8>,
+    // whether there is an object for it
+    stored: bool,
+}
+
+impl fmt::Debug for S3Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Backend").field("url", &self.url).finish()
+    }
+}
+
+impl S3Backend {
+    /// The vault at `url` (`s3://bucket/key`); one that doesn't exist yet is empty.
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        let (bucket, key) = url
+            .strip_prefix("s3://")
+            .and_then(|rest| rest.split_once('/'))
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .with_context(|| format!("{url}: expected s3://bucket/key"))?;
+        let backend = Self {
+            client: Client::from_env(bucket)?,
+            url: url.to_string(),
+            key: key.trim_end_matches('/').to_string(),
+            state: Mutex::default(),
+        };
+        let size = backend.size_object();
+        let len = match backend.client.request("GET", &size, &[]).with_context(|| format!("connect to S3 for {url}"))? {
+            (200, body) => String::from_utf8_lossy(&body).trim().parse().with_context(|| format!("bad size object {size}"))?,
+            (404, _) => 0,
+            (status, body) => return Err(request_error("GET", &size, status, &body).into()),
+        };
+        let mut st = backend.lock();
+        (st.len, st.stored_len) = (len, len);
+        drop(st);
+        Ok(backend)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn size_object(&self) -> String {
+        format!("{}/size", self.key)
+    }
+
+    fn block_object(&self, index: u64) -> String {
+        format!("{}/{index:08}", self.key)
+    }
+
+    /// Block `index`, read unless it is in memory. With `whole` it is about to be
+    /// overwritten entirely, so its contents aren't read.
+    fn block<'a>(&self, st: &'a mut State, index: u64, whole: bool) -> std::io::Result<&'a mut Block> {
+        if !st.blocks.contains_key(&index) {
+            let clean: Vec<u64> = st.blocks.keys().filter(|i| !st.dirty.contains(i)).copied().collect();
+            if clean.len() >= MAX_CLEAN {
+                st.blocks.remove(&clean[0]);
+            }
+            let maybe_stored = index * BLOCK_SIZE < st.stored_len;
+            let block = match maybe_stored && !whole {
+                false => Block { data: vec![], stored: maybe_stored },
+                true => {
+                    let object = self.block_object(index);
+                    match self.client.request("GET", &object, &[])? {
+                        (200, data) => Block { data, stored: true },
+                        (404, _) => Block { data: vec![], stored: false },
+                        (status, body) => return Err(request_error("GET", &object, status, &body)),
+                    }
+                }
+            };
+            st.blocks.insert(index, block);
+        }
+        Ok(st.blocks.get_mut(&index).expect("block just loaded"))
+    }
+
+    /// Uploads the changed blocks; one of zeros is deleted instead.
+    fn put_blocks(&self, st: &mut State) -> std::io::Result<()> {
+        while let Some(&index) = st.dirty.first() {
+            // only what is inside the vault
+            let valid = st.len.saturating_sub(index * BLOCK_SIZE).min(BLOCK_SIZE) as usize;
+            let block = st.blocks.get_mut(&index).expect("dirty block in memory");
+            block.data.truncate(valid);
+            let used = block.data.iter().rposition(|&b| b != 0).map_or(0, |last| last + 1);
+            block.data.truncate(used);
+            let object = self.block_object(index);
+            match used == 0 {
+                true if !block.stored => {}
+                true => match self.client.request("DELETE", &object, &[])? {
+                    (200 | 204 | 404, _) => block.stored = false,
+                    (status, body) => return Err(request_error("DELETE", &object, status, &body)),
+                },
+                false => match self.client.request("PUT", &object, &block.data)? {
+                    (200, _) => block.stored = true,
+                    (status, body) => return Err(request_error("PUT", &object, status, &body)),
+                },
+            }
+            st.dirty.remove(&index);
+        }
+        Ok(())
+    }
+
+    fn put_size(&self, st: &mut State) -> std::io::Result<()> {
+        if st.len != st.stored_len {
+            let object = self.size_object();
+            match self.client.request("PUT", &object, st.len.to_string().as_bytes())? {
+                (200, _) => st.stored_len = st.len,
+                (status, body) => return Err(request_error("PUT", &object, status, &body)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Backend for S3Backend {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut st = self.lock();
+        if offset >= st.len {
+            return Ok(0);
+        }
+        let (index, start) = (offset / BLOCK_SIZE, (offset % BLOCK_SIZE) as usize);
+        let n = (buf.len() as u64).min(BLOCK_SIZE - start as u64).min(st.len - offset) as usize;
+        let block = &self.block(&mut st, index, false)?.data;
+        let have = block.len().saturating_sub(start).min(n);
+        buf[..have].copy_from_slice(&block[start..start + have]);
+        buf[have..n].fill(0);
+        Ok(n)
+    }
+
+    fn write_at(&self, mut offset: u64, mut buf: &[u8]) -> std::io::Result<()> {
+        let mut st = self.lock();
+        while !buf.is_empty() {
+            let (index, start) = (offset / BLOCK_SIZE, (offset % BLOCK_SIZE) as usize);
+            let n = buf.len().min(BLOCK_SIZE as usize - start);
+            let block = &mut self.block(&mut st, index, n == BLOCK_SIZE as usize)?.data;
+            if block.len() < start + n {
+                block.resize(start + n, 0);
+            }
+            block[start..start + n].copy_from_slice(&buf[..n]);
+            st.dirty.insert(index);
+            st.len = st.len.max(offset + n as u64);
+            (offset, buf) = (offset + n as u64, &buf[n..]);
+            if st.dirty.len() >= MAX_DIRTY {
+                self.put_blocks(&mut st)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.lock().len)
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        let mut st = self.lock();
+        if len < st.len {
+            let keep = len.div_ceil(BLOCK_SIZE);
+            // blocks past the new end go now, so growing again reads zeros there
+            for index in keep..st.len.max(st.stored_len).div_ceil(BLOCK_SIZE) {
+                st.blocks.remove(&index);
+                st.dirty.remove(&index);
+                if index * BLOCK_SIZE < st.stored_len {
+                    let object = self.block_object(index);
+                    match self.client.request("DELETE", &object, &[])? {
+                        (200 | 204 | 404, _) => {}
+                        (status, body) => return Err(request_error("DELETE", &object, status, &body)),
+                    }
+                }
+            }
+            let tail = (len % BLOCK_SIZE) as usize;
+            if tail > 0 {
+                let block = &mut self.block(&mut st, keep - 1, false)?.data;
+                if block.len() > tail {
+                    block.truncate(tail);
+                    st.dirty.insert(keep - 1);
+                }
+            }
+        }
+        st.len = len;
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        let mut st = self.lock();
+        self.put_blocks(&mut st)?;
+        self.put_size(&mut st)
+    }
+}
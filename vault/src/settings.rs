@@ -0,0 +1,142 @@
+//! Preferences of the GUI. They apply before any vault is open, so they live in a
+//! small `key = value` file in the user's config directory rather than in a vault,
+//! and hold nothing secret.
+
+use crate::i18n::Lang;
+use anyhow::Context;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// Idle minutes before an open vault is locked, unless set otherwise.
+pub const DEFAULT_LOCK_AFTER_MIN: u32 = 15;
+
+/// Seconds before copied file contents are cleared from the clipboard, unless set
+/// otherwise.
+pub const DEFAULT_CLIPBOARD_CLEAR_SEC: u32 = 30;
+
+/// Scale of the interface in percent: the allowed range and the step of the buttons.
+pub const UI_SCALE_PCT: RangeInclusive<u32> = 50..=300;
+pub const UI_SCALE_STEP_PCT: u32 = 10;
+
+/// Colors of the interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// As set in the system; dark where that can't be told.
+    #[default]
+    System,
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::System, Theme::Dark, Theme::Light];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Theme::System => "system",
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Theme> {
+        Theme::ALL.into_iter().find(|t| t.code() == code)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Interface language; None follows `VAULT_LANG`, else Russian.
+    pub lang: Option<Lang>,
+    /// Minutes without input after which an open vault is locked; 0 never.
+    pub lock_after_min: u32,
+    /// Also lock when the window is minimized or loses focus.
+    pub lock_on_blur: bool,
+    /// Blank file contents (viewer, thumbnails, slideshow) while the window is out of
+    /// focus, and keep the window out of screen captures where the system allows it.
+    pub hide_content: bool,
+    /// Seconds after which copied file contents are cleared from the clipboard; 0 never.
+    pub clipboard_clear_sec: u32,
+    pub theme: Theme,
+    /// Scale of the whole interface (text included) in percent, within `UI_SCALE_PCT`.
+    pub ui_scale_pct: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            lang: None,
+            lock_after_min: DEFAULT_LOCK_AFTER_MIN,
+            lock_on_blur: false,
+            hide_content: false,
+            clipboard_clear_sec: DEFAULT_CLIPBOARD_CLEAR_SEC,
+            theme: Theme::default(),
+            ui_scale_pct: 100,
+        }
+    }
+}
+
+/// `vault` in the config directory of the platform.
+pub fn config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let dir = if cfg!(windows) {
+        var("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        var("HOME")?.join("Library/Application Support")
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| Some(var("HOME")?.join(".config")))?
+    };
+    Some(dir.join("vault"))
+}
+
+/// `vault/gui.conf` in the config directory.
+fn path() -> Option<PathBuf> {
+    Some(config_dir()?.join("gui.conf"))
+}
+
+impl Settings {
+    /// The saved settings; defaults for a missing or unreadable file and for lines
+    /// it doesn't know.
+    pub fn load() -> Settings {
+        let mut s = Settings::default();
+        let Some(text) = path().and_then(|p| std::fs::read_to_string(p).ok()) else {
+            return s;
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match (key.trim(), value.trim()) {
+                ("lang", v) => s.lang = Lang::from_code(v),
+                ("lock_after_min", v) => s.lock_after_min = v.parse().unwrap_or(DEFAULT_LOCK_AFTER_MIN),
+                ("lock_on_blur", v) => s.lock_on_blur = v == "true",
+                ("hide_content", v) => s.hide_content = v == "true",
+                ("clipboard_clear_sec", v) => s.clipboard_clear_sec = v.parse().unwrap_or(DEFAULT_CLIPBOARD_CLEAR_SEC),
+                ("theme", v) => s.theme = Theme::from_code(v).unwrap_or_default(),
+                ("ui_scale_pct", v) => {
+                    s.ui_scale_pct = v.parse().unwrap_or(100).clamp(*UI_SCALE_PCT.start(), *UI_SCALE_PCT.end())
+                }
+                _ => {}
+            }
+        }
+        s
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = path().context("no config directory (HOME or APPDATA isn't set)")?;
+        let mut text = String::new();
+        if let Some(lang) = self.lang {
+            text.push_str(&format!("lang = {}\n", lang.code()));
+        }
+        text.push_str(&format!("lock_after_min = {}\n", self.lock_after_min));
+        text.push_str(&format!("lock_on_blur = {}\n", self.lock_on_blur));
+        text.push_str(&format!("hide_content = {}\n", self.hide_content));
+        text.push_str(&format!("clipboard_clear_sec = {}\n", self.clipboard_clear_sec));
+        text.push_str(&format!("theme = {}\n", self.theme.code()));
+        text.push_str(&format!("ui_scale_pct = {}\n", self.ui_scale_pct));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        }
+        std::fs::write(&path, text).with_context(|| format!("write {}", path.display()))
+    }
+}
@@ -0,0 +1,182 @@
+//! `vault shell`: commands read from standard input, one per line, against one open
+//! session. Unlike the one-shot commands, which reopen the vault every time, it keeps
+//! the session's change log (`Session::undo`) between them, so `undo` and `redo` take
+//! back and apply again renames, moves, deletions to the trash and new directories.
+//!
+//! Words are split at spaces; a name with spaces goes in double or single quotes.
+
+use crate::i18n;
+use anyhow::Context;
+use clap::Parser;
+use std::io::{BufRead, IsTerminal, Write};
+use vault_core::container::Session;
+use vault_core::fsmeta;
+
+/// Commands of the shell, one per line (`help <command>` shows its options)
+#[derive(Parser)]
+#[command(no_binary_name = true, disable_version_flag = true, override_usage = "<COMMAND> [OPTIONS]")]
+enum Line {
+    /// List children of a directory id (default: root)
+    Ls {
+        #[arg(long, default_value_t = 1)]
+        dir_id: u64,
+    },
+    /// Create directory
+    Mkdir {
+        #[arg(long, default_value_t = 1)]
+        parent_id: u64,
+        #[arg(long)]
+        name: String,
+    },
+    /// Rename node by id
+    Rename {
+        #[arg(long)]
+        id: u64,
+        #[arg(long)]
+        new_name: String,
+    },
+    /// Move nodes into another directory (several nodes are moved, and undone, all or
+    /// nothing)
+    Mv {
+        #[arg(long, required = true, num_args = 1..)]
+        id: Vec<u64>,
+        #[arg(long)]
+        parent_id: u64,
+    },
+    /// Move node by id to the trash
+    Rm {
+        #[arg(long)]
+        id: u64,
+    },
+    /// Take back the last change
+    Undo,
+    /// Apply again the last change taken back
+    Redo,
+    /// Leave the shell (so does the end of input)
+    Exit,
+}
+
+/// Runs the shell until `exit` or the end of standard input. A failed command is
+/// reported and the shell goes on.
+pub fn run(sess: &mut Session, password: &str) -> anyhow::Result<()> {
+    let prompt = std::io::stdin().is_terminal();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        if prompt {
+            print!("vault> ");
+            std::io::stdout().flush()?;
+        }
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        let words = match split(&line) {
+            Ok(words) if words.is_empty() => continue,
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+        let cmd = match Line::try_parse_from(words) {
+            Ok(cmd) => cmd,
+            // help included
+            Err(e) => {
+                let _ = e.print();
+                continue;
+            }
+        };
+        if matches!(cmd, Line::Exit) {
+            return Ok(());
+        }
+        if let Err(e) = exec(sess, password, cmd) {
+            eprintln!("{}", i18n::report(&e));
+        }
+    }
+}
+
+fn exec(sess: &mut Session, password: &str, cmd: Line) -> anyhow::Result<()> {
+    match cmd {
+        Line::Ls { dir_id } => {
+            for n in sess.meta.children_of(dir_id) {
+                let t = match n.node_type {
+                    fsmeta::NodeType::Dir => "DIR ",
+                    fsmeta::NodeType::File => "FILE",
+                };
+                println!("{t}  id={}  parent={}  name={}", n.id, n.parent_id, n.name);
+            }
+        }
+        Line::Mkdir { parent_id, name } => {
+            let id = change(sess, password, |sess| Ok(sess.mkdir(parent_id, name)?))?;
+            println!("mkdir id={id}");
+        }
+        Line::Rename { id, new_name } => {
+            change(sess, password, |sess| Ok(sess.rename(id, new_name)?))?;
+            println!("renamed");
+        }
+        Line::Mv { id, parent_id } => {
+            change(sess, password, |sess| {
+                for &id in &id {
+                    sess.move_node(id, parent_id).with_context(|| format!("move {id}"))?;
+                }
+                Ok(())
+            })?;
+            println!("moved {}", id.len());
+        }
+        Line::Rm { id } => {
+            change(sess, password, |sess| Ok(sess.trash(id)?))?;
+            println!("moved to trash");
+        }
+        Line::Undo => match sess.undo(password)? {
+            true => println!("undone"),
+            false => println!("nothing to undo"),
+        },
+        Line::Redo => match sess.redo(password)? {
+            true => println!("redone"),
+            false => println!("nothing to redo"),
+        },
+        Line::Exit => {}
+    }
+    Ok(())
+}
+
+/// Makes a change in a transaction and saves it. That way it is one step for `undo`
+/// (all of `mv` at once), and a change that fails, or fails to be saved, leaves
+/// neither the tree nor the change log of the session behind the vault's.
+fn change<T>(sess: &mut Session, password: &str, f: impl FnOnce(&mut Session) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    sess.begin()?;
+    match f(sess) {
+        Ok(out) => {
+            sess.commit(password)?;
+            Ok(out)
+        }
+        Err(e) => {
+            sess.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// Splits a line into words at spaces, keeping what is in double or single quotes
+/// together.
+fn split(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        anyhow::bail!("unclosed quote");
+    }
+    words.extend(word);
+    Ok(words)
+}
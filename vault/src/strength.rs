@@ -0,0 +1,40 @@
+//! Password strength estimate for new vaults (zxcvbn: dictionaries, keyboard
+//! patterns, dates, repeats and l33t substitutions rather than character classes).
+
+use zxcvbn::time_estimates::CrackTimeSeconds;
+
+/// Lowest zxcvbn score (0..=4) a new vault's password may have without `--force`:
+/// more than 10^8 guesses.
+pub const MIN_SCORE: u8 = 3;
+
+pub struct Strength {
+    /// 0 (guessed within 10^3 tries) to 4 (more than 10^10).
+    pub score: u8,
+    /// At 10^4 guesses per second, an offline attack on a slow hash. The vault's
+    /// Argon2id is slower still; this is the conservative figure.
+    pub crack_time: CrackTimeSeconds,
+    /// What makes the password weak and how to do better, if zxcvbn has a hint.
+    pub feedback: Option<String>,
+}
+
+impl Strength {
+    pub fn is_weak(&self) -> bool {
+        self.score < MIN_SCORE
+    }
+}
+
+/// Estimates `password`; `user_inputs` are words an attacker would try first, such
+/// as the vault's file name.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let entropy = zxcvbn::zxcvbn(password, user_inputs);
+    Strength {
+        score: entropy.score().into(),
+        crack_time: entropy.crack_times().offline_slow_hashing_1e4_per_second(),
+        feedback: entropy.feedback().map(|f| f.to_string().trim().to_string()).filter(|f| !f.is_empty()),
+    }
+}
+
+/// File name of a vault without its extension, for `estimate`.
+pub fn path_words(path: &str) -> Vec<&str> {
+    std::path::Path::new(path).file_stem().and_then(|s| s.to_str()).into_iter().collect()
+}
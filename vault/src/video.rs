@@ -0,0 +1,233 @@
+//! Video files for the GUI viewer, played by GStreamer (`playbin`) with the `video`
+//! feature. The file is fed to it from the vault through `VaultFileReader`, chunk by
+//! chunk as GStreamer asks for them (an `appsrc` element that can seek), so the video
+//! is never decrypted to disk; frames come back as RGBA through an `appsink` and the
+//! sound goes to the default output device.
+//!
+//! GStreamer and its plugins (base, good, and libav or bad for the codecs) are loaded at
+//! run time. A build without the feature, or without GStreamer installed, shows video
+//! files as hex and says why.
+
+use eframe::egui;
+use std::time::Duration;
+use vault_core::container::Session;
+
+#[cfg(feature = "video")]
+pub use gst_player::Player;
+
+#[cfg(feature = "video")]
+mod gst_player {
+    use super::*;
+    use anyhow::Context;
+    use gstreamer as gst;
+    use gstreamer::prelude::*;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::{Arc, Mutex};
+    use vault_core::container::VaultFileReader;
+
+    /// Bytes handed to GStreamer at a time, unless it asks for a given amount.
+    const BLOCK: usize = 64 * 1024;
+
+    /// One file played by GStreamer; dropping it stops it.
+    pub struct Player {
+        playbin: gst::Element,
+        frame: Arc<Mutex<Option<egui::ColorImage>>>,
+        ended: bool,
+    }
+
+    impl Player {
+        /// Opens file `id` for playing, paused at its first frame. `ctx` is repainted
+        /// whenever a new frame is ready.
+        pub fn open(ctx: &egui::Context, sess: &Session, id: u64) -> anyhow::Result<Player> {
+            gst::init()?;
+            let reader = VaultFileReader::open(sess, id)?;
+            let size = reader.len();
+            let reader = Arc::new(Mutex::new(reader));
+
+            let sink = gst::ElementFactory::make("appsink")
+                .property("caps", gst::Caps::builder("video/x-raw").field("format", "RGBA").build())
+                .property("emit-signals", true)
+                // the GUI shows only the latest frame
+                .property("max-buffers", 1u32)
+                .property("drop", true)
+                .build()
+                .context("GStreamer has no appsink element (gst-plugins-base)")?;
+            let frame = Arc::new(Mutex::new(None));
+            // (paused, the frame comes as a preroll)
+            for (signal, pull) in [("new-preroll", "pull-preroll"), ("new-sample", "pull-sample")] {
+                let (shown, ctx) = (frame.clone(), ctx.clone());
+                sink.connect(signal, false, move |args| {
+                    let sink = args[0].get::<gst::Element>().ok()?;
+                    let sample = sink.emit_by_name::<Option<gst::Sample>>(pull, &[])?;
+                    if let Some(image) = color_image(&sample) {
+                        *shown.lock().unwrap() = Some(image);
+                        ctx.request_repaint();
+                    }
+                    Some(gst::FlowReturn::Ok.to_value())
+                });
+            }
+
+            let playbin = gst::ElementFactory::make("playbin")
+                .property("uri", "appsrc://")
+                .property("video-sink", &sink)
+                .build()
+                .context("GStreamer has no playbin element (gst-plugins-base)")?;
+            playbin.connect("source-setup", false, move |args| {
+                let src = args[1].get::<gst::Element>().ok()?;
+                src.set_property_from_str("stream-type", "random-access");
+                src.set_property("size", size as i64);
+                let feed = reader.clone();
+                src.connect("need-data", false, move |args| {
+                    let src = args[0].get::<gst::Element>().ok()?;
+                    let wanted = args[1].get::<u32>().ok().filter(|&n| n > 0).map_or(BLOCK, |n| n as usize);
+                    let mut data = vec![0u8; wanted];
+                    match feed.lock().unwrap().read(&mut data) {
+                        Ok(n) if n > 0 => {
+                            data.truncate(n);
+                            let _ = src.emit_by_name::<gst::FlowReturn>("push-buffer", &[&gst::Buffer::from_mut_slice(data)]);
+                        }
+                        // the end, or a chunk that does not decrypt
+                        _ => {
+                            let _ = src.emit_by_name::<gst::FlowReturn>("end-of-stream", &[]);
+                        }
+                    }
+                    None
+                });
+                let feed = reader.clone();
+                src.connect("seek-data", false, move |args| {
+                    let offset = args[1].get::<u64>().ok()?;
+                    Some(feed.lock().unwrap().seek(SeekFrom::Start(offset)).is_ok().to_value())
+                });
+                None
+            });
+
+            playbin.set_state(gst::State::Paused)?;
+            Ok(Player { playbin, frame, ended: false })
+        }
+
+        /// The frame shown since the last call, if a new one is ready.
+        pub fn take_frame(&self) -> Option<egui::ColorImage> {
+            self.frame.lock().unwrap().take()
+        }
+
+        /// Handles what GStreamer has reported since the last call: the end of the file,
+        /// or an error, which is returned.
+        pub fn poll(&mut self) -> Option<String> {
+            let bus = self.playbin.bus()?;
+            while let Some(msg) = bus.pop_filtered(&[gst::MessageType::Eos, gst::MessageType::Error]) {
+                match msg.view() {
+                    gst::MessageView::Eos(_) => {
+                        self.ended = true;
+                        let _ = self.playbin.set_state(gst::State::Paused);
+                    }
+                    gst::MessageView::Error(e) => {
+                        let _ = self.playbin.set_state(gst::State::Null);
+                        return Some(e.error().to_string());
+                    }
+                    _ => {}
+                }
+            }
+            None
+        }
+
+        /// Length of the file, once GStreamer knows it.
+        pub fn duration(&self) -> Option<Duration> {
+            self.playbin.query_duration::<gst::ClockTime>().map(Duration::from)
+        }
+
+        pub fn position(&self) -> Duration {
+            match (self.ended, self.duration()) {
+                (true, Some(end)) => end,
+                _ => self.playbin.query_position::<gst::ClockTime>().map_or(Duration::ZERO, Duration::from),
+            }
+        }
+
+        pub fn playing(&self) -> bool {
+            !self.ended && self.playbin.current_state() == gst::State::Playing
+        }
+
+        /// Plays, from the start if the file has been played to its end.
+        pub fn play(&mut self) -> anyhow::Result<()> {
+            if self.ended {
+                self.seek(Duration::ZERO)?;
+            }
+            self.playbin.set_state(gst::State::Playing)?;
+            Ok(())
+        }
+
+        pub fn pause(&mut self) -> anyhow::Result<()> {
+            self.playbin.set_state(gst::State::Paused)?;
+            Ok(())
+        }
+
+        /// Goes to the key frame nearest to `pos`.
+        pub fn seek(&mut self, pos: Duration) -> anyhow::Result<()> {
+            let pos = gst::ClockTime::try_from(pos)?;
+            self.playbin.seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT, pos)?;
+            self.ended = false;
+            Ok(())
+        }
+    }
+
+    impl Drop for Player {
+        fn drop(&mut self) {
+            let _ = self.playbin.set_state(gst::State::Null);
+        }
+    }
+
+    /// An RGBA frame from the appsink; rows may be padded.
+    fn color_image(sample: &gst::Sample) -> Option<egui::ColorImage> {
+        let caps = sample.caps()?.structure(0)?;
+        let (width, height) = (caps.get::<i32>("width").ok()? as usize, caps.get::<i32>("height").ok()? as usize);
+        let buffer = sample.buffer()?.map_readable().ok()?;
+        let stride = buffer.len().checked_div(height)?;
+        if stride < width * 4 {
+            return None;
+        }
+        let rows = buffer.chunks_exact(stride).flat_map(|row| &row[..width * 4]);
+        Some(egui::ColorImage::from_rgba_unmultiplied([width, height], &rows.copied().collect::<Vec<u8>>()))
+    }
+}
+
+/// Without the `video` feature nothing is played.
+#[cfg(not(feature = "video"))]
+pub enum Player {}
+
+#[cfg(not(feature = "video"))]
+impl Player {
+    pub fn open(_ctx: &egui::Context, _sess: &Session, _id: u64) -> anyhow::Result<Player> {
+        anyhow::bail!("this build plays no video (it is built without the `video` feature)")
+    }
+
+    pub fn take_frame(&self) -> Option<egui::ColorImage> {
+        match *self {}
+    }
+
+    pub fn poll(&mut self) -> Option<String> {
+        match *self {}
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        match *self {}
+    }
+
+    pub fn position(&self) -> Duration {
+        match *self {}
+    }
+
+    pub fn playing(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn play(&mut self) -> anyhow::Result<()> {
+        match *self {}
+    }
+
+    pub fn pause(&mut self) -> anyhow::Result<()> {
+        match *self {}
+    }
+
+    pub fn seek(&mut self, _pos: Duration) -> anyhow::Result<()> {
+        match *self {}
+    }
+}
@@ -0,0 +1,909 @@
+//! WebDAV server over an unlocked vault (`vault serve --webdav`), so that any OS can map
+//! the vault tree as a network drive with its built-in client, without filesystem
+//! drivers. Plain HTTP/1.1 on `std::net`, a thread per connection; requests take turns
+//! on the session, an upload holds it until it's stored.
+//!
+//! Class 1 of RFC 4918 plus the LOCK and UNLOCK that the Windows and macOS clients need
+//! before they write: locks are granted but not enforced, and properties set with
+//! PROPPATCH are accepted but not kept. DELETE moves to the trash.
+
+use crate::i18n;
+use anyhow::Context;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use vault_core::container::{self, Session, VaultFileReader};
+use vault_core::fsmeta::{self, Metadata, Node, NodeType};
+use vault_core::{mime, VaultError};
+use zeroize::Zeroizing;
+
+// limits of a request head and of the bodies read into memory (all but PUT)
+const MAX_HEAD: u64 = 64 * 1024;
+const MAX_BODY: u64 = 1024 * 1024;
+// a kept-alive connection is closed after this long without a request
+const IDLE: Duration = Duration::from_secs(60);
+const ALLOW: &str = "OPTIONS, PROPFIND, PROPPATCH, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+const XML_HEAD: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+
+struct Server {
+    sess: Mutex<Session>,
+    password: Zeroizing<String>,
+    // first segment of every URL, if the server was started with one
+    token: Option<String>,
+}
+
+/// Serves the tree of `sess` at `addr` until the process is stopped. With `url_token`
+/// every URL has to start with a random token made at this start, which is printed
+/// with the address.
+pub fn serve(sess: Session, password: &str, addr: SocketAddr, url_token: bool) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).with_context(|| format!("listen on {addr}"))?;
+    let addr = listener.local_addr()?;
+    let server = Arc::new(Server {
+        sess: Mutex::new(sess),
+        password: Zeroizing::new(password.to_string()),
+        token: url_token.then(|| uuid::Uuid::new_v4().simple().to_string()),
+    });
+    println!("Serving the vault over WebDAV at http://{addr}{} (Ctrl+C to stop)", server.href(&[], true));
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let server = Arc::clone(&server);
+        // A connection that breaks off only ends its thread.
+        std::thread::spawn(move || server.connection(stream));
+    }
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    target: String,
+    // HTTP/1.0 closes the connection after each request
+    http10: bool,
+    // names in lower case
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn keep_alive(&self) -> bool {
+        match self.header("connection").map(str::to_ascii_lowercase) {
+            Some(c) if c.contains("close") => false,
+            Some(c) if c.contains("keep-alive") => true,
+            _ => !self.http10,
+        }
+    }
+}
+
+/// The body of a request, read as it comes: `Content-Length` bytes or chunks.
+struct Body<'a> {
+    src: &'a mut BufReader<TcpStream>,
+    framing: Framing,
+}
+
+enum Framing {
+    Length(u64),
+    // bytes left in the current chunk; `done` after the last one
+    Chunked { left: u64, done: bool },
+}
+
+impl<'a> Body<'a> {
+    fn new(src: &'a mut BufReader<TcpStream>, req: &Request) -> anyhow::Result<Self> {
+        let chunked = req.header("transfer-encoding").is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        let framing = match (chunked, req.header("content-length")) {
+            (true, _) => Framing::Chunked { left: 0, done: false },
+            (false, Some(len)) => Framing::Length(len.trim().parse().context("bad Content-Length")?),
+            (false, None) => Framing::Length(0),
+        };
+        Ok(Body { src, framing })
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!(self.framing, Framing::Length(0))
+    }
+
+    /// Reads what a handler left of the body, up to `MAX_BODY`. Returns whether the
+    /// next request can be read after it.
+    fn finish(&mut self) -> bool {
+        if std::io::copy(&mut self.take(MAX_BODY), &mut std::io::sink()).is_err() {
+            return false;
+        }
+        matches!(self.framing, Framing::Length(0) | Framing::Chunked { done: true, .. })
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = match &mut self.framing {
+            Framing::Length(left) => left,
+            Framing::Chunked { done: true, .. } => return Ok(0),
+            Framing::Chunked { left, done } => {
+                if *left == 0 {
+                    let line = read_line(self.src)?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    *left = u64::from_str_radix(size, 16).map_err(|_| bad_data("bad chunk size"))?;
+                    if *left == 0 {
+                        // trailers, up to the empty line
+                        while !read_line(self.src)?.is_empty() {}
+                        *done = true;
+                        return Ok(0);
+                    }
+                }
+                left
+            }
+        };
+        let want = (*left).min(buf.len() as u64) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = self.src.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        *left -= n as u64;
+        if *left == 0 && matches!(self.framing, Framing::Chunked { .. }) {
+            // the line end after the chunk data
+            read_line(self.src)?;
+        }
+        Ok(n)
+    }
+}
+
+fn bad_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// A line of the request head or of the chunk framing, without the line end.
+fn read_line(src: &mut BufReader<TcpStream>) -> std::io::Result<String> {
+    let mut line = String::new();
+    if src.take(MAX_HEAD).read_line(&mut line)? == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    if !line.ends_with('\n') {
+        return Err(bad_data("line too long"));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// The next request head on the connection; None when the client closed it or was
+/// idle for `IDLE`.
+fn read_request(src: &mut BufReader<TcpStream>) -> anyhow::Result<Option<Request>> {
+    let mut request_line = String::new();
+    while request_line.is_empty() {
+        match read_line(src) {
+            Ok(line) => request_line = line,
+            Err(_) => return Ok(None),
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        anyhow::bail!("bad request line");
+    };
+    let mut req = Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        http10: version == "HTTP/1.0",
+        headers: vec![],
+    };
+    let mut size = request_line.len();
+    loop {
+        let line = read_line(src)?;
+        size += line.len();
+        if line.is_empty() {
+            break;
+        }
+        if size as u64 > MAX_HEAD {
+            anyhow::bail!("request head too large");
+        }
+        let (name, value) = line.split_once(':').context("bad header")?;
+        req.headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    Ok(Some(req))
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Payload,
+}
+
+enum Payload {
+    Bytes(Vec<u8>),
+    // `len` bytes from the reader's position
+    File { reader: Box<VaultFileReader>, len: u64 },
+}
+
+impl Response {
+    fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: Payload::Bytes(vec![]),
+        }
+    }
+
+    fn text(status: u16, text: &str) -> Self {
+        let mut r = Response::new(status).header("Content-Type", "text/plain; charset=utf-8");
+        r.body = Payload::Bytes(format!("{text}\n").into_bytes());
+        r
+    }
+
+    fn xml(status: u16, xml: String) -> Self {
+        let mut r = Response::new(status).header("Content-Type", "application/xml; charset=utf-8");
+        r.body = Payload::Bytes(xml.into_bytes());
+        r
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn send(self, out: &mut TcpStream, head_only: bool, keep_alive: bool) -> std::io::Result<()> {
+        let len = match &self.body {
+            Payload::Bytes(b) => b.len() as u64,
+            Payload::File { len, .. } => *len,
+        };
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+        let _ = write!(head, "Content-Length: {len}\r\n");
+        if !keep_alive {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        out.write_all(head.as_bytes())?;
+        if !head_only {
+            match self.body {
+                Payload::Bytes(b) => out.write_all(&b)?,
+                Payload::File { reader, len } => {
+                    std::io::copy(&mut reader.take(len), out)?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        502 => "Bad Gateway",
+        507 => "Insufficient Storage",
+        _ => "Internal Server Error",
+    }
+}
+
+/// The response to a failed request, by what kind of error it is.
+fn error_response(e: &anyhow::Error) -> Response {
+    let status = match e.downcast_ref::<VaultError>().map(VaultError::root) {
+        Some(VaultError::NotFound) => 404,
+        Some(VaultError::NameExists(_) | VaultError::NotADirectory | VaultError::NotAFile) => 409,
+        Some(VaultError::ReadOnly) => 403,
+        Some(VaultError::Invalid(_)) => 400,
+        Some(VaultError::Io(io)) if io.kind() == std::io::ErrorKind::StorageFull => 507,
+        _ => 500,
+    };
+    Response::text(status, &i18n::error(e))
+}
+
+impl Server {
+    fn connection(&self, stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(IDLE))?;
+        let mut src = BufReader::new(stream.try_clone()?);
+        let mut out = stream;
+        while let Some(req) = read_request(&mut src)? {
+            if req.header("expect").is_some_and(|e| e.eq_ignore_ascii_case("100-continue")) {
+                out.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            }
+            let mut body = Body::new(&mut src, &req)?;
+            let resp = self.handle(&req, &mut body).unwrap_or_else(|e| {
+                eprintln!("{} {}: {}", req.method, req.target, i18n::error(&e));
+                error_response(&e)
+            });
+            let keep_alive = body.finish() && req.keep_alive();
+            resp.send(&mut out, req.method == "HEAD", keep_alive)?;
+            if !keep_alive {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn session(&self) -> MutexGuard<'_, Session> {
+        // A handler that panicked left the session as it was before its change.
+        self.sess.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn handle(&self, req: &Request, body: &mut Body) -> anyhow::Result<Response> {
+        if req.method == "OPTIONS" {
+            return Ok(Response::new(200).header("DAV", "1, 2").header("MS-Author-Via", "DAV").header("Allow", ALLOW));
+        }
+        let Some(names) = self.names_of(&req.target) else {
+            return Ok(Response::text(404, "not found"));
+        };
+        match req.method.as_str() {
+            "PROPFIND" => self.propfind(req, &names),
+            "PROPPATCH" => self.proppatch(&names),
+            "GET" | "HEAD" => self.get(req, &names),
+            "PUT" => self.put(&names, body),
+            "DELETE" => self.delete(&names),
+            "MKCOL" => self.mkcol(&names, body),
+            "COPY" | "MOVE" => self.transfer(req, &names),
+            "LOCK" => self.lock(req, &names),
+            "UNLOCK" => Ok(Response::new(204)),
+            _ => Ok(Response::new(405).header("Allow", ALLOW)),
+        }
+    }
+
+    /// The names of the vault path a URL points to, below the root; None for a URL
+    /// outside the served tree (without the token, or with `..`).
+    fn names_of(&self, target: &str) -> Option<Vec<String>> {
+        // `Destination` has an absolute URL
+        let path = match target.split_once("://") {
+            Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+            None => target,
+        };
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let mut segments = path.split('/').filter(|s| !s.is_empty());
+        if let Some(token) = &self.token {
+            let given = segments.next()?;
+            // compared in constant time, it is the only secret a client shows
+            let diff = given.bytes().zip(token.bytes()).fold(0, |d, (a, b)| d | (a ^ b));
+            if given.len() != token.len() || diff != 0 {
+                return None;
+            }
+        }
+        let mut names = vec![];
+        for segment in segments {
+            let name = percent_decode(segment)?;
+            // refused rather than resolved, so no URL leaves the tree
+            if name == "." || name == ".." {
+                return None;
+            }
+            names.push(name);
+        }
+        Some(names)
+    }
+
+    /// The URL path of the vault path `names`; directories end with a slash.
+    fn href(&self, names: &[String], dir: bool) -> String {
+        let mut href = String::from("/");
+        if let Some(token) = &self.token {
+            href.push_str(token);
+            href.push('/');
+        }
+        href.push_str(&names.iter().map(|n| percent_encode(n)).collect::<Vec<_>>().join("/"));
+        if dir && !names.is_empty() {
+            href.push('/');
+        }
+        href
+    }
+
+    fn propfind(&self, req: &Request, names: &[String]) -> anyhow::Result<Response> {
+        let sess = self.session();
+        let Some(node) = lookup(&sess.meta, names) else {
+            return Ok(Response::text(404, "not found"));
+        };
+        let mut xml = format!("{XML_HEAD}<D:multistatus xmlns:D=\"DAV:\">");
+        let name = names.last().map_or("", String::as_str);
+        write_props(&mut xml, &self.href(names, node.node_type == NodeType::Dir), name, node);
+        // `infinity` is answered like 1
+        if node.node_type == NodeType::Dir && req.header("depth") != Some("0") {
+            for child in sess.meta.children_of(node.id) {
+                let path = [names, std::slice::from_ref(&child.name)].concat();
+                write_props(&mut xml, &self.href(&path, child.node_type == NodeType::Dir), &child.name, child);
+            }
+        }
+        xml.push_str("</D:multistatus>");
+        Ok(Response::xml(207, xml))
+    }
+
+    fn proppatch(&self, names: &[String]) -> anyhow::Result<Response> {
+        let sess = self.session();
+        let Some(node) = lookup(&sess.meta, names) else {
+            return Ok(Response::text(404, "not found"));
+        };
+        let href = self.href(names, node.node_type == NodeType::Dir);
+        Ok(Response::xml(
+            207,
+            format!(
+                "{XML_HEAD}<D:multistatus xmlns:D=\"DAV:\"><D:response><D:href>{}</D:href>\
+                 <D:propstat><D:prop/><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response></D:multistatus>",
+                escape(&href)
+            ),
+        ))
+    }
+
+    fn get(&self, req: &Request, names: &[String]) -> anyhow::Result<Response> {
+        let sess = self.session();
+        let Some(node) = lookup(&sess.meta, names) else {
+            return Ok(Response::text(404, "not found"));
+        };
+        if node.node_type == NodeType::Dir {
+            let mut html = String::from("<!DOCTYPE html>\n<meta charset=\"utf-8\">\n<ul>\n");
+            for child in sess.meta.children_of(node.id) {
+                let dir = child.node_type == NodeType::Dir;
+                let path = [names, std::slice::from_ref(&child.name)].concat();
+                let slash = if dir { "/" } else { "" };
+                let _ = writeln!(html, "<li><a href=\"{}\">{}{slash}</a></li>", escape(&self.href(&path, dir)), escape(&child.name));
+            }
+            html.push_str("</ul>\n");
+            let mut r = Response::new(200).header("Content-Type", "text/html; charset=utf-8");
+            r.body = Payload::Bytes(html.into_bytes());
+            return Ok(r);
+        }
+        let mut r = Response::new(200)
+            .header("Content-Type", node.mime.clone().unwrap_or_else(|| "application/octet-stream".into()))
+            .header("ETag", etag(node))
+            .header("Accept-Ranges", "bytes");
+        if let Some(date) = http_date(node.modified_at) {
+            r = r.header("Last-Modified", date);
+        }
+        let mut reader = VaultFileReader::open(&sess, node.id)?;
+        // the reader has its own handle on the vault file
+        drop(sess);
+        let size = reader.len();
+        let range = req.header("range").and_then(|r| r.strip_prefix("bytes=")).filter(|r| !r.contains(','));
+        let (start, end) = match range.map(|r| byte_range(r, size)) {
+            None => (0, size),
+            Some(Some((start, end))) => {
+                r.status = 206;
+                r = r.header("Content-Range", format!("bytes {start}-{}/{size}", end - 1));
+                (start, end)
+            }
+            Some(None) => return Ok(Response::new(416).header("Content-Range", format!("bytes */{size}"))),
+        };
+        reader.seek(SeekFrom::Start(start))?;
+        r.body = Payload::File { reader: Box::new(reader), len: end - start };
+        Ok(r)
+    }
+
+    fn put(&self, names: &[String], body: &mut Body) -> anyhow::Result<Response> {
+        let Some((name, parent)) = names.split_last() else {
+            return Ok(Response::new(405).header("Allow", ALLOW));
+        };
+        let mut sess = self.session();
+        let Some(parent_id) = lookup(&sess.meta, parent).filter(|n| n.node_type == NodeType::Dir).map(|n| n.id) else {
+            return Ok(Response::text(409, "no such directory"));
+        };
+        let password = self.password.as_str();
+        match lookup(&sess.meta, names).map(|n| (n.id, n.node_type)) {
+            Some((_, NodeType::Dir)) => Ok(Response::new(405).header("Allow", ALLOW)),
+            Some((id, NodeType::File)) => {
+                container::replace_file_from(&mut sess, password, id, body)?;
+                Ok(Response::new(204))
+            }
+            None => {
+                // A new file is created and written in one transaction, so a broken
+                // upload leaves nothing behind.
+                sess.begin()?;
+                let written = container::create_file(&mut sess, password, parent_id, name, None).and_then(|id| {
+                    container::replace_file_from(&mut sess, password, id, body)?;
+                    let head = container::read_file_range(&sess, id, 0, mime::HEAD_LEN)?;
+                    sess.meta.set_mime(id, mime::detect(&head, name))
+                });
+                if let Err(e) = written {
+                    sess.rollback()?;
+                    return Err(e.into());
+                }
+                sess.commit(password)?;
+                Ok(Response::new(201))
+            }
+        }
+    }
+
+    fn delete(&self, names: &[String]) -> anyhow::Result<Response> {
+        let mut sess = self.session();
+        let Some(id) = lookup(&sess.meta, names).map(|n| n.id) else {
+            return Ok(Response::text(404, "not found"));
+        };
+        if names.is_empty() {
+            return Ok(Response::text(403, "the root can't be deleted"));
+        }
+        self.change(&mut sess, |meta| meta.trash(id))?;
+        Ok(Response::new(204))
+    }
+
+    fn mkcol(&self, names: &[String], body: &Body) -> anyhow::Result<Response> {
+        if !body.is_empty() {
+            return Ok(Response::new(415));
+        }
+        let mut sess = self.session();
+        let Some((name, parent)) = names.split_last().filter(|_| lookup(&sess.meta, names).is_none()) else {
+            return Ok(Response::new(405).header("Allow", ALLOW));
+        };
+        let Some(parent_id) = lookup(&sess.meta, parent).filter(|n| n.node_type == NodeType::Dir).map(|n| n.id) else {
+            return Ok(Response::text(409, "no such directory"));
+        };
+        self.change(&mut sess, |meta| meta.mkdir(parent_id, name.clone()).map(drop))?;
+        Ok(Response::new(201))
+    }
+
+    /// COPY and MOVE. A node in the way is moved to the trash unless `Overwrite: F`.
+    fn transfer(&self, req: &Request, names: &[String]) -> anyhow::Result<Response> {
+        let Some(dest) = req.header("destination") else {
+            return Ok(Response::text(400, "no Destination"));
+        };
+        let Some(dest) = self.names_of(dest) else {
+            return Ok(Response::text(502, "the destination is not on this server"));
+        };
+        let overwrite = !req.header("overwrite").is_some_and(|o| o.eq_ignore_ascii_case("F"));
+        let mut sess = self.session();
+        let Some(id) = lookup(&sess.meta, names).map(|n| n.id) else {
+            return Ok(Response::text(404, "not found"));
+        };
+        let (Some((name, parent)), false) = (dest.split_last(), names.is_empty()) else {
+            return Ok(Response::text(403, "the root can't be moved or copied"));
+        };
+        let Some(parent_id) = lookup(&sess.meta, parent).filter(|n| n.node_type == NodeType::Dir).map(|n| n.id) else {
+            return Ok(Response::text(409, "no such directory"));
+        };
+        // the node itself when only the case of its name changes
+        let existing = lookup(&sess.meta, &dest).map(|n| n.id).filter(|&e| e != id);
+        if existing.is_some() && !overwrite {
+            return Ok(Response::new(412));
+        }
+        let copy = req.method == "COPY";
+        self.change(&mut sess, |meta| {
+            if let Some(existing) = existing {
+                meta.trash(existing)?;
+            }
+            match copy {
+                true => copy_node(meta, id, parent_id, name),
+                false => move_node(meta, id, parent_id, name),
+            }
+        })?;
+        Ok(Response::new(if existing.is_some() { 204 } else { 201 }))
+    }
+
+    fn lock(&self, req: &Request, names: &[String]) -> anyhow::Result<Response> {
+        let mut sess = self.session();
+        let mut status = 200;
+        if lookup(&sess.meta, names).is_none() {
+            // Locking a free name makes an empty file there (RFC 4918, 7.3); Windows
+            // saves new files that way.
+            let Some((name, parent)) = names.split_last() else {
+                return Ok(Response::text(404, "not found"));
+            };
+            let Some(parent_id) = lookup(&sess.meta, parent).filter(|n| n.node_type == NodeType::Dir).map(|n| n.id) else {
+                return Ok(Response::text(409, "no such directory"));
+            };
+            container::create_file(&mut sess, &self.password, parent_id, name, None)?;
+            status = 201;
+        }
+        let href = self.href(names, lookup(&sess.meta, names).is_some_and(|n| n.node_type == NodeType::Dir));
+        let token = format!("opaquelocktoken:{}", uuid::Uuid::new_v4());
+        let depth = match req.header("depth") {
+            Some("0") => "0",
+            _ => "infinity",
+        };
+        let xml = format!(
+            "{XML_HEAD}<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+             <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+             <D:depth>{depth}</D:depth><D:timeout>Second-3600</D:timeout>\
+             <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+             <D:lockroot><D:href>{}</D:href></D:lockroot>\
+             </D:activelock></D:lockdiscovery></D:prop>",
+            escape(&href)
+        );
+        Ok(Response::xml(status, xml).header("Lock-Token", format!("<{token}>")))
+    }
+
+    /// Applies `change` to the metadata and saves it; the metadata in memory is put
+    /// back if either fails.
+    fn change(
+        &self,
+        sess: &mut Session,
+        change: impl FnOnce(&mut Metadata) -> vault_core::Result<()>,
+    ) -> anyhow::Result<()> {
+        let backup = sess.meta.clone();
+        let res = change(&mut sess.meta).and_then(|()| container::save_metadata(sess, &self.password));
+        if res.is_err() {
+            sess.meta = backup;
+        }
+        Ok(res?)
+    }
+}
+
+/// The node at the vault path `names`, matched as names are (in NFC, and ignoring case
+/// in a case-insensitive vault).
+fn lookup<'m>(meta: &'m Metadata, names: &[String]) -> Option<&'m Node> {
+    let mut node = meta.get_node(meta.root_id)?;
+    for name in names {
+        let name = meta.normalize_name(name).ok()?;
+        node = meta.name_conflict(node.id, &name, None)?;
+    }
+    Some(node)
+}
+
+/// Moves node `id` into `parent_id` under `name`.
+fn move_node(meta: &mut Metadata, id: u64, parent_id: u64, name: &str) -> vault_core::Result<()> {
+    let node = meta.get_node(id).ok_or(VaultError::NotFound)?;
+    let (old_parent, old_name) = (node.parent_id, node.name.clone());
+    if old_parent == parent_id {
+        return meta.rename(id, name.to_string());
+    }
+    // Renaming first when the old name is taken in the new directory.
+    if meta.name_conflict(parent_id, &old_name, None).is_some() {
+        meta.rename(id, name.to_string())?;
+        return meta.move_node(id, parent_id);
+    }
+    meta.move_node(id, parent_id)?;
+    if name != old_name {
+        meta.rename(id, name.to_string())?;
+    }
+    Ok(())
+}
+
+/// Copies node `id` into `parent_id` under `name`: files copy-on-write, directories
+/// with everything in them.
+fn copy_node(meta: &mut Metadata, id: u64, parent_id: u64, name: &str) -> vault_core::Result<()> {
+    let node = meta.get_node(id).ok_or(VaultError::NotFound)?;
+    if node.node_type == NodeType::File {
+        return meta.clone_file(id, parent_id, name.to_string()).map(drop);
+    }
+    let mut p = parent_id;
+    while p != 0 {
+        if p == id {
+            return Err(VaultError::Invalid("cannot copy a directory into itself".into()));
+        }
+        p = meta.get_node(p).map_or(0, |n| n.parent_id);
+    }
+    let children: Vec<(u64, String)> = meta.children_of(id).iter().map(|c| (c.id, c.name.clone())).collect();
+    let dir = meta.mkdir(parent_id, name.to_string())?;
+    for (child, child_name) in children {
+        copy_node(meta, child, dir, &child_name)?;
+    }
+    Ok(())
+}
+
+/// The PROPFIND response for one node.
+fn write_props(xml: &mut String, href: &str, name: &str, n: &Node) {
+    let _ = write!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+        escape(href),
+        escape(name)
+    );
+    match n.node_type {
+        NodeType::Dir => xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+        NodeType::File => {
+            let _ = write!(
+                xml,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+                 <D:getcontenttype>{}</D:getcontenttype><D:getetag>{}</D:getetag>",
+                n.size,
+                escape(n.mime.as_deref().unwrap_or("application/octet-stream")),
+                escape(&etag(n))
+            );
+        }
+    }
+    if let Some(date) = http_date(n.modified_at) {
+        let _ = write!(xml, "<D:getlastmodified>{date}</D:getlastmodified>");
+    }
+    if n.created_at != 0 {
+        let created = fsmeta::format_time(n.created_at).replacen(' ', "T", 1);
+        let _ = write!(xml, "<D:creationdate>{created}Z</D:creationdate>");
+    }
+    xml.push_str(
+        "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+    );
+}
+
+fn etag(n: &Node) -> String {
+    format!("\"{}-{}-{}\"", n.id, n.size, n.modified_at)
+}
+
+/// `Sun, 06 Nov 1994 08:49:37 GMT` of a Unix time; None for 0 (unknown).
+fn http_date(secs: u64) -> Option<String> {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let formatted = fsmeta::format_time(secs);
+    let (date, time) = formatted.split_once(' ')?;
+    let mut ymd = date.split('-');
+    let (year, month, day) = (ymd.next()?, ymd.next()?.parse::<usize>().ok()?, ymd.next()?);
+    Some(format!("{}, {day} {} {year} {time} GMT", WEEKDAYS[(secs / 86400 % 7) as usize], MONTHS[month - 1]))
+}
+
+/// The range `start..end` of a `Range: bytes=` spec (one range) in a file of `len`
+/// bytes; None if it isn't satisfiable.
+fn byte_range(spec: &str, len: u64) -> Option<(u64, u64)> {
+    let (first, last) = spec.trim().split_once('-')?;
+    let (start, end) = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(first), Ok(last)) => (first, last.saturating_add(1).min(len)),
+        (Ok(first), Err(_)) if last.is_empty() => (first, len),
+        (Err(_), Ok(suffix)) if first.is_empty() => (len.saturating_sub(suffix), len),
+        _ => return None,
+    };
+    (start < end).then_some((start, end))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "%{b:02X}");
+            }
+        }
+    }
+    out
+}
+
+/// A percent-encoded URL segment; None if it isn't UTF-8 or has a `%` without two hex
+/// digits after it.
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // two hex digits (`from_str_radix` also takes a sign)
+            let hex = s.get(i + 1..i + 3).filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(dir: &std::path::Path, token: Option<&str>) -> Server {
+        let path = dir.join("vault.dat").to_string_lossy().to_string();
+        let params = container::NewVault { m_cost_kib: 8192, t_cost: 1, ..Default::default() };
+        container::create_vault(&path, "password", &params).unwrap();
+        Server {
+            sess: Mutex::new(container::open_vault(&path, "password", None).unwrap()),
+            password: Zeroizing::new("password".into()),
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(byte_range("0-99", 1000), Some((0, 100)));
+        assert_eq!(byte_range("990-2000", 1000), Some((990, 1000)));
+        // open-ended and suffix
+        assert_eq!(byte_range("400-", 1000), Some((400, 1000)));
+        assert_eq!(byte_range("-100", 1000), Some((900, 1000)));
+        assert_eq!(byte_range("-5000", 1000), Some((0, 1000)));
+        // not satisfiable
+        for spec in ["1000-", "1000-1200", "-0", "5-3", "-", "a-b", "10"] {
+            assert_eq!(byte_range(spec, 1000), None, "{spec}");
+        }
+        assert_eq!(byte_range("0-", 0), None);
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b%2Fc%c3%a9").as_deref(), Some("a b/cé"));
+        assert_eq!(percent_decode("100%25").as_deref(), Some("100%"));
+        for bad in ["%", "a%2", "%zz", "%+1", "%-1", "%ff"] {
+            assert_eq!(percent_decode(bad), None, "{bad}");
+        }
+        assert_eq!(percent_decode(&percent_encode("a b/../é~")).as_deref(), Some("a b/../é~"));
+    }
+
+    #[test]
+    fn url_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = server(dir.path(), None);
+        let names = |v: &[&str]| Some(v.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(open.names_of("/"), names(&[]));
+        assert_eq!(open.names_of("/a/b%20c/?x=1#y"), names(&["a", "b c"]));
+        assert_eq!(open.names_of("//a//b/"), names(&["a", "b"]));
+        // `Destination`
+        assert_eq!(open.names_of("http://host:8080/a/b"), names(&["a", "b"]));
+        assert_eq!(open.names_of("https://host"), names(&[]));
+        for bad in ["/a/../b", "/..", "/a/./b", "/%2e%2E/a", "/a/%2e", "http://host/../a", "/a%zz"] {
+            assert_eq!(open.names_of(bad), None, "{bad}");
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let token = "0123456789abcdef";
+        let closed = server(dir.path(), Some(token));
+        assert_eq!(closed.names_of(&format!("/{token}/a")), names(&["a"]));
+        assert_eq!(closed.names_of(&format!("http://host/{token}/")), names(&[]));
+        for bad in ["/", "/a", "/0123456789abcdee/a", "/0123456789abcde/a", "/0123456789abcdef0/a"] {
+            assert_eq!(closed.names_of(bad), None, "{bad}");
+        }
+        assert_eq!(closed.names_of(&format!("/{token}/../{token}")), None);
+        assert!(closed.href(&["a b".into()], true).starts_with(&format!("/{token}/")));
+    }
+
+    /// Sends `sent` on a loopback connection and reads it as the body of a request with
+    /// `headers`: what the body read, whether the next request can follow, and the
+    /// bytes left for it.
+    fn read_body(headers: &[(&str, &str)], sent: &[u8]) -> (std::io::Result<Vec<u8>>, bool, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(sent).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut src = BufReader::new(listener.accept().unwrap().0);
+
+        let req = Request {
+            method: "PUT".into(),
+            target: "/a".into(),
+            http10: false,
+            headers: headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+        };
+        let mut body = Body::new(&mut src, &req).unwrap();
+        let mut data = vec![];
+        let read = body.read_to_end(&mut data).map(|_| data);
+        let next = read.is_ok() && body.finish();
+        let mut rest = vec![];
+        let _ = src.read_to_end(&mut rest);
+        (read, next, rest)
+    }
+
+    #[test]
+    fn request_bodies() {
+        let (data, next, rest) = read_body(&[("content-length", "3")], b"abcGET");
+        assert_eq!((data.unwrap(), next, rest), (b"abc".to_vec(), true, b"GET".to_vec()));
+
+        let (data, next, rest) = read_body(&[], b"GET");
+        assert_eq!((data.unwrap(), next, rest), (vec![], true, b"GET".to_vec()));
+
+        // sizes in either case, extensions and trailers; the framing wins over a length
+        let chunked = b"5\r\nhello\r\na;ext=1\r\n, world!!!\r\n0\r\nExpires: never\r\n\r\nGET";
+        let headers = [("transfer-encoding", "Chunked"), ("content-length", "1")];
+        let (data, next, rest) = read_body(&headers, chunked);
+        assert_eq!((data.unwrap(), next, rest), (b"hello, world!!!".to_vec(), true, b"GET".to_vec()));
+
+        let chunked = [("transfer-encoding", "chunked")];
+        let err = read_body(&chunked, b"zz\r\nhello\r\n0\r\n\r\n").0.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // cut off inside a chunk, and before the last one
+        for cut in [&b"5\r\nhel"[..], b"5\r\nhello\r\n"] {
+            let (data, next, _) = read_body(&chunked, cut);
+            assert_eq!(data.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+            assert!(!next);
+        }
+    }
+
+    #[test]
+    fn bad_content_length_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut src = BufReader::new(listener.accept().unwrap().0);
+        let req = Request {
+            method: "PUT".into(),
+            target: "/a".into(),
+            http10: false,
+            headers: vec![("content-length".into(), "-1".into())],
+        };
+        assert!(Body::new(&mut src, &req).is_err());
+    }
+}
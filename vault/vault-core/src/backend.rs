@@ -0,0 +1,265 @@
+//! The raw bytes of a vault, behind [`Backend`]: every part of a vault on disk is a
+//! [`FileBackend`], and a vault can also live in memory ([`MemoryBackend`]), in a
+//! web page (vault-wasm) or in object storage (the S3 backend of the CLI).
+//!
+//! Only the bytes go through a backend. The journal, lock and counter files of a vault
+//! stay on the local disk, next to the path a vault in a backend is opened with
+//! (`container::open_vault_in`); one opened without them
+//! (`container::open_vault_backend`) is read-only.
+
+use std::fmt;
+use std::fs::File;
+use std::sync::{PoisonError, RwLock};
+
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Reads up to `buf.len()` bytes at `offset`; fewer (0 at or past the end) only at
+    /// the end of the vault.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// Writes all of `buf` at `offset`, growing the vault if it ends past the end.
+    fn write_at(&self, offset: u64, buf: &[u8]) -> std::io::Result<()>;
+
+    /// Length of the vault in bytes.
+    fn len(&self) -> std::io::Result<u64>;
+
+    fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Truncates or extends (with zeros) the vault to `len` bytes.
+    fn set_len(&self, len: u64) -> std::io::Result<()>;
+
+    /// Makes the writes so far durable: the vault code calls it before it relies on
+    /// them, e.g. before the journal is finished.
+    fn flush(&self) -> std::io::Result<()>;
+}
+
+/// A file on the local disk.
+#[derive(Debug)]
+pub struct FileBackend {
+    file: File,
+}
+
+impl FileBackend {
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+}
+
+#[cfg(unix)]
+impl Backend for FileBackend {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(&self.file, buf, offset)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> std::io::Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(&self.file, buf, offset)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+#[cfg(windows)]
+impl Backend for FileBackend {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)
+    }
+
+    fn write_at(&self, mut offset: u64, mut buf: &[u8]) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_write(&self.file, buf, offset)? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                n => (offset, buf) = (offset + n as u64, &buf[n..]),
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+// no positioned I/O, but no files to open either (wasm32-unknown-unknown)
+#[cfg(not(any(unix, windows)))]
+impl Backend for FileBackend {
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// A whole vault in memory, e.g. one a web page read from a file or a scratch vault.
+#[derive(Default)]
+pub struct MemoryBackend {
+    bytes: RwLock<Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes: RwLock::new(bytes) }
+    }
+
+    /// The vault as it is now, e.g. to save it once done.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.bytes.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+// not the bytes: `Session` is Debug and holds its backend
+impl fmt::Debug for MemoryBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.bytes.read().unwrap_or_else(PoisonError::into_inner).len();
+        f.debug_struct("MemoryBackend").field("len", &len).finish()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes = self.bytes.read().unwrap_or_else(PoisonError::into_inner);
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(bytes.len());
+        let n = buf.len().min(bytes.len() - start);
+        buf[..n].copy_from_slice(&bytes[start..start + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> std::io::Result<()> {
+        let start = usize::try_from(offset).map_err(|_| std::io::Error::from(std::io::ErrorKind::OutOfMemory))?;
+        let end = start.checked_add(buf.len()).ok_or(std::io::ErrorKind::OutOfMemory)?;
+        let mut bytes = self.bytes.write().unwrap_or_else(PoisonError::into_inner);
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.bytes.read().unwrap_or_else(PoisonError::into_inner).len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        let len = usize::try_from(len).map_err(|_| std::io::Error::from(std::io::ErrorKind::OutOfMemory))?;
+        self.bytes.write().unwrap_or_else(PoisonError::into_inner).resize(len, 0);
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{self, NewVault, MIN_CHUNK_SIZE};
+    use std::sync::Arc;
+
+    /// What `Backend` promises, on an empty store.
+    fn check_contract(b: &dyn Backend) {
+        assert_eq!(b.len().unwrap(), 0);
+        assert!(b.is_empty().unwrap());
+        let mut buf = [7u8; 8];
+        assert_eq!(b.read_at(0, &mut buf).unwrap(), 0);
+
+        b.write_at(0, b"hello").unwrap();
+        assert_eq!(b.len().unwrap(), 5);
+        // past the end grows the store, with zeros in between
+        b.write_at(10, b"world").unwrap();
+        assert_eq!(b.len().unwrap(), 15);
+        let mut buf = [7u8; 15];
+        assert_eq!(b.read_at(0, &mut buf).unwrap(), 15);
+        assert_eq!(&buf, b"hello\0\0\0\0\0world");
+        // within it, overwrites
+        b.write_at(3, b"LO").unwrap();
+        assert_eq!(b.len().unwrap(), 15);
+
+        // reads stop at the end, and give nothing past it
+        let mut buf = [7u8; 8];
+        assert_eq!(b.read_at(12, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"rld");
+        assert_eq!(b.read_at(15, &mut buf).unwrap(), 0);
+        assert_eq!(b.read_at(1 << 40, &mut buf).unwrap(), 0);
+
+        b.set_len(4).unwrap();
+        assert_eq!(b.len().unwrap(), 4);
+        b.set_len(6).unwrap();
+        let mut buf = [7u8; 8];
+        assert_eq!(b.read_at(0, &mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"helL\0\0");
+        b.flush().unwrap();
+    }
+
+    #[test]
+    fn memory_backend_keeps_the_contract() {
+        check_contract(&MemoryBackend::default());
+    }
+
+    #[test]
+    fn file_backend_keeps_the_contract() {
+        check_contract(&FileBackend::new(tempfile::tempfile().unwrap()));
+    }
+
+    #[test]
+    fn vault_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        // the journal and lock files go here; the vault itself doesn't
+        let path = dir.path().join("vault.dat").to_string_lossy().to_string();
+        let memory = Arc::new(MemoryBackend::default());
+        let backend: Arc<dyn Backend> = memory.clone();
+        let params = NewVault { m_cost_kib: 8192, t_cost: 1, chunk_size: MIN_CHUNK_SIZE, ..NewVault::default() };
+        container::create_vault_in(&backend, "password", &params).unwrap();
+
+        let data: Vec<u8> = (0..3 * MIN_CHUNK_SIZE + 5).map(|i| i as u8).collect();
+        let mut sess = container::open_vault_in(backend.clone(), &path, "password", None).unwrap();
+        let root = sess.meta.root_id;
+        let id = container::create_file(&mut sess, "password", root, "a.bin", None).unwrap();
+        container::replace_file(&mut sess, "password", id, &data).unwrap();
+        drop(sess);
+        assert!(!std::path::Path::new(&path).exists());
+
+        // reopened for writing, and as a copy of the bytes read-only
+        let sess = container::open_vault_in(backend, &path, "password", None).unwrap();
+        assert_eq!(container::read_file_bytes(&sess, id).unwrap(), data);
+        drop(sess);
+        let copy = Arc::new(MemoryBackend::new(memory.to_vec()));
+        let sess = container::open_vault_backend(copy, "password", None).unwrap();
+        assert!(sess.read_only);
+        assert_eq!(sess.meta.child_named(root, "a.bin").map(|n| n.id), Some(id));
+        assert_eq!(container::read_file_bytes(&sess, id).unwrap(), data);
+        assert!(container::open_vault_backend(Arc::new(MemoryBackend::new(memory.to_vec())), "wrong", None).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+//! Backups of a whole vault as one archive, encrypted with age ([`crate::crypto::age`])
+//! to a passphrase or to public keys, so that it can be kept where neither the vault
+//! nor its password goes, and opened with a key that isn't the vault's.
+//!
+//! The archive is a POSIX tar (pax) stream of the tree outside the trash: `age -d
+//! backup.age | tar x` gets the files back without this program. Tags, extended
+//! attributes, MIME types and favorites go into pax records (`VAULT.tags`,
+//! `SCHILY.xattr.*`, ...) that [`restore`] reads back and other tar programs skip.
+//! File versions, the trash and key slots aren't in a backup.
+
+use crate::container::{self, Progress, Session, VaultFileReader};
+use crate::crypto::age::{self, Identity, Recipient};
+use crate::error::{Result, VaultError};
+use crate::fsmeta::{Node, NodeType};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+const BLOCK: usize = 512;
+/// Largest size in the 11 octal digits of a ustar header; larger ones go in pax.
+const MAX_USTAR_SIZE: u64 = 0o77777777777;
+
+/// What a backup or a restore went through.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+    /// Entries of a restored archive that aren't files or directories (links,
+    /// devices), left out.
+    pub skipped: u64,
+}
+
+/// Writes the tree of `sess` outside the trash to `out` as an age-encrypted tar.
+/// A passphrase is stretched with the scrypt work factor 2^`work_factor`
+/// (`age::SCRYPT_WORK_FACTOR` but in tests). `progress` is told of each file by its
+/// path in the vault.
+pub fn backup(
+    sess: &Session,
+    out: impl Write,
+    recipients: &[Recipient],
+    work_factor: u8,
+    progress: &mut dyn Progress,
+) -> Result<Stats> {
+    let mut tar = age::Writer::new(out, recipients, work_factor)?;
+    let mut stats = Stats::default();
+    write_dir(sess, sess.meta.root_id, "", &mut tar, &mut stats, progress)?;
+    // end of the archive
+    tar.write_all(&[0u8; 2 * BLOCK])?;
+    tar.finish()?;
+    Ok(stats)
+}
+
+fn write_dir(
+    sess: &Session,
+    dir_id: u64,
+    prefix: &str,
+    out: &mut impl Write,
+    stats: &mut Stats,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    let mut buf = vec![0u8; sess.chunk_size];
+    for n in sess.meta.children_of(dir_id) {
+        if n.id == sess.meta.trash_id {
+            continue;
+        }
+        let path = format!("{prefix}{}", n.name);
+        match n.node_type {
+            NodeType::Dir => {
+                write_header(out, &format!("{path}/"), b'5', 0, n, &labels(n))?;
+                stats.dirs += 1;
+                write_dir(sess, n.id, &format!("{path}/"), out, stats, progress)?;
+            }
+            NodeType::File => {
+                let mut records = labels(n);
+                if let Some(mime) = &n.mime {
+                    records.push(("VAULT.mime".into(), mime.clone().into_bytes()));
+                }
+                let mut reader = VaultFileReader::open(sess, n.id)?;
+                let len = reader.len();
+                write_header(out, &path, b'0', len, n, &records)?;
+                progress.file(Path::new(&path), len);
+                let mut copied = 0u64;
+                loop {
+                    let k = reader.read(&mut buf)?;
+                    if k == 0 {
+                        break;
+                    }
+                    out.write_all(&buf[..k])?;
+                    copied += k as u64;
+                    if !progress.advance(k as u64) {
+                        return Err(VaultError::Cancelled);
+                    }
+                }
+                if copied != len {
+                    return Err(VaultError::corrupted(format!("{path}: {copied} bytes read, {len} expected")));
+                }
+                out.write_all(&[0u8; BLOCK][..padding(len)])?;
+                stats.files += 1;
+                stats.bytes += len;
+            }
+        }
+    }
+    crate::crypto::zeroize_vec(buf);
+    Ok(())
+}
+
+/// The pax records of the labels of `n` that aren't in a ustar header.
+fn labels(n: &Node) -> Vec<(String, Vec<u8>)> {
+    let mut records = Vec::new();
+    if !n.tags.is_empty() {
+        records.push(("VAULT.tags".into(), n.tags.join("\n").into_bytes()));
+    }
+    if n.favorite {
+        records.push(("VAULT.favorite".into(), b"1".to_vec()));
+    }
+    for (name, value) in &n.xattrs {
+        records.push((format!("SCHILY.xattr.{name}"), value.clone()));
+    }
+    records
+}
+
+/// Header of an entry at `path`, after a pax header for what ustar can't hold.
+fn write_header(
+    out: &mut impl Write,
+    path: &str,
+    kind: u8,
+    size: u64,
+    n: &Node,
+    records: &[(String, Vec<u8>)],
+) -> Result<()> {
+    let mtime = n.source_mtime.unwrap_or(n.modified_at);
+    let mut records = records.to_vec();
+    let short = path.len() <= 100 && path.is_ascii();
+    if !short {
+        records.push(("path".into(), path.as_bytes().to_vec()));
+    }
+    if size > MAX_USTAR_SIZE {
+        records.push(("size".into(), size.to_string().into_bytes()));
+    }
+    // a name other tar programs can fall back to without pax: ASCII, at most 100 bytes
+    let fallback: String = path.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+    let name = &fallback[fallback.len().saturating_sub(100)..];
+    if !records.is_empty() {
+        let mut data = Vec::new();
+        for (key, value) in &records {
+            // the length counts itself
+            let base = key.len() + value.len() + 3;
+            let mut len = base + 1;
+            while len != base + len.to_string().len() {
+                len = base + len.to_string().len();
+            }
+            data.extend_from_slice(format!("{len} {key}=").as_bytes());
+            data.extend_from_slice(value);
+            data.push(b'\n');
+        }
+        let pax_name = format!("PaxHeaders/{}", &name[name.len().saturating_sub(89)..]);
+        out.write_all(&ustar_header(&pax_name, b'x', data.len() as u64, mtime))?;
+        out.write_all(&data)?;
+        out.write_all(&[0u8; BLOCK][..padding(data.len() as u64)])?;
+    }
+    let name = if short { path } else { name };
+    out.write_all(&ustar_header(name, kind, if size > MAX_USTAR_SIZE { 0 } else { size }, mtime))?;
+    Ok(())
+}
+
+fn ustar_header(name: &str, kind: u8, size: u64, mtime: u64) -> [u8; BLOCK] {
+    let mut h = [0u8; BLOCK];
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    let mode: &[u8] = if kind == b'5' { b"0000755\0" } else { b"0000644\0" };
+    h[100..108].copy_from_slice(mode);
+    h[108..116].copy_from_slice(b"0000000\0");
+    h[116..124].copy_from_slice(b"0000000\0");
+    h[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    h[136..148].copy_from_slice(format!("{:011o}\0", mtime.min(MAX_USTAR_SIZE)).as_bytes());
+    h[156] = kind;
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+    h[148..156].fill(b' ');
+    let sum: u32 = h.iter().map(|&b| b as u32).sum();
+    h[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+    h
+}
+
+fn padding(len: u64) -> usize {
+    (BLOCK - (len % BLOCK as u64) as usize) % BLOCK
+}
+
+/// Restores a backup from `input` into directory `parent_id` of `sess`, in one
+/// transaction. Directories merge with those already there; a file whose name is
+/// taken gets a free one (`name (2)`). `progress` is told of each file by its path in
+/// the archive.
+pub fn restore(
+    sess: &mut Session,
+    password: &str,
+    parent_id: u64,
+    input: impl BufRead,
+    identities: &[Identity],
+    progress: &mut dyn Progress,
+) -> Result<Stats> {
+    match sess.meta.get_node(parent_id) {
+        Some(n) if n.node_type == NodeType::Dir => {}
+        Some(_) => return Err(VaultError::NotADirectory),
+        None => return Err(VaultError::NotFound),
+    }
+    let mut input = age::Reader::new(input, identities)?;
+    sess.begin()?;
+    match read_archive(sess, password, parent_id, &mut input, progress) {
+        Ok(stats) => {
+            sess.commit(password)?;
+            Ok(stats)
+        }
+        Err(e) => {
+            sess.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+fn read_archive(
+    sess: &mut Session,
+    password: &str,
+    parent_id: u64,
+    input: &mut impl Read,
+    progress: &mut dyn Progress,
+) -> Result<Stats> {
+    let damaged = |msg: &str| VaultError::corrupted(format!("the backup isn't a valid tar archive: {msg}"));
+    let mut stats = Stats::default();
+    // directories by their path in the archive, "" for `parent_id`
+    let mut dirs: HashMap<String, u64> = HashMap::from([(String::new(), parent_id)]);
+    let mut pax: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut h = [0u8; BLOCK];
+    loop {
+        input.read_exact(&mut h).map_err(|e| truncated(e, "the end of the archive is missing"))?;
+        if h.iter().all(|&b| b == 0) {
+            break;
+        }
+        let sum: u32 = h[..148].iter().chain(&[b' '; 8]).chain(&h[156..]).map(|&b| b as u32).sum();
+        if octal(&h[148..156]) != Some(sum as u64) {
+            return Err(damaged("bad header checksum"));
+        }
+        let mut size = octal(&h[124..136]).ok_or_else(|| damaged("bad size"))?;
+        let mut mtime = octal(&h[136..148]).unwrap_or(0);
+        let kind = h[156];
+        let mut path = field(&h[..100]);
+        if &h[257..262] == b"ustar" && h[345] != 0 {
+            path = format!("{}/{path}", field(&h[345..500]));
+        }
+        let mut records = std::mem::take(&mut pax);
+        if let Some(p) = records.remove("path") {
+            path = String::from_utf8(p).map_err(|_| damaged("a path isn't UTF-8"))?;
+        }
+        if let Some(s) = records.remove("size") {
+            size = std::str::from_utf8(&s).ok().and_then(|s| s.parse().ok()).ok_or_else(|| damaged("bad size"))?;
+        }
+        if let Some(t) = records.remove("mtime") {
+            // seconds, maybe with a fraction
+            let t = String::from_utf8_lossy(&t).split('.').next().and_then(|s| s.parse().ok());
+            mtime = t.unwrap_or(mtime);
+        }
+        match kind {
+            b'x' => {
+                pax = parse_pax(&read_data(input, size)?).ok_or_else(|| damaged("bad pax header"))?;
+                continue;
+            }
+            // GNU tar's name of the next entry, when it doesn't fit the header
+            b'L' => {
+                let name = read_data(input, size)?;
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                records.insert("path".into(), name[..end].to_vec());
+                pax = records;
+                continue;
+            }
+            b'5' => {
+                // "./" of `tar c .` is the target itself
+                let id = ensure_dir(sess, &mut dirs, &path, mtime)?;
+                if id != parent_id {
+                    label(sess, id, &records)?;
+                    stats.dirs += 1;
+                }
+            }
+            b'0' | b'\0' | b'7' => {
+                let (dir, name) = match path.trim_end_matches('/').rsplit_once('/') {
+                    Some((dir, name)) => (ensure_dir(sess, &mut dirs, dir, 0)?, name),
+                    None => (parent_id, path.as_str()),
+                };
+                let name = sess.meta.normalize_name(name).map_err(|e| e.wrapped(&path))?;
+                let name = sess.meta.unique_name(dir, &name);
+                let mime = records.get("VAULT.mime").and_then(|m| String::from_utf8(m.clone()).ok());
+                let id = container::create_file(sess, password, dir, &name, mime)?;
+                progress.file(Path::new(&path), size);
+                let mut src = Counted { inner: (&mut *input).take(size), progress: &mut *progress, cancelled: false };
+                let copied = container::replace_file_from(sess, password, id, &mut src);
+                if src.cancelled {
+                    return Err(VaultError::Cancelled);
+                }
+                let copied = copied.map_err(|e| match e {
+                    VaultError::Io(e) => truncated(e, &path),
+                    e => e,
+                })?;
+                if copied != size {
+                    return Err(truncated(io::ErrorKind::UnexpectedEof.into(), &path));
+                }
+                sess.meta.set_times(id, mtime, Some(mtime).filter(|&t| t != 0))?;
+                label(sess, id, &records)?;
+                stats.files += 1;
+                stats.bytes += size;
+            }
+            b'g' => {
+                read_data(input, size)?;
+                continue;
+            }
+            _ => {
+                io::copy(&mut (&mut *input).take(size), &mut io::sink()).map_err(|e| truncated(e, &path))?;
+                stats.skipped += 1;
+            }
+        }
+        skip(input, padding(size))?;
+    }
+    // read to the end, so that the last chunk is authenticated too
+    io::copy(input, &mut io::sink()).map_err(|e| truncated(e, "after the end of the archive"))?;
+    Ok(stats)
+}
+
+/// The error of a read of the archive that ended early or failed to decrypt.
+fn truncated(e: io::Error, what: &str) -> VaultError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => VaultError::corrupted(format!("the backup is truncated: {what}")),
+        io::ErrorKind::InvalidData => VaultError::corrupted(e),
+        _ => VaultError::Io(e),
+    }
+}
+
+/// The data of a pax or GNU long name header, with its padding.
+fn read_data(input: &mut impl Read, size: u64) -> Result<Vec<u8>> {
+    if size > 1 << 20 {
+        return Err(VaultError::corrupted("the backup isn't a valid tar archive: pax header too large"));
+    }
+    let mut data = vec![0u8; size as usize];
+    input.read_exact(&mut data).map_err(|e| truncated(e, "a pax header"))?;
+    skip(input, padding(size))?;
+    Ok(data)
+}
+
+fn skip(input: &mut impl Read, n: usize) -> Result<()> {
+    let mut pad = [0u8; BLOCK];
+    input.read_exact(&mut pad[..n]).map_err(|e| truncated(e, "padding"))?;
+    Ok(())
+}
+
+fn parse_pax(mut data: &[u8]) -> Option<HashMap<String, Vec<u8>>> {
+    let mut records = HashMap::new();
+    while !data.is_empty() {
+        let space = data.iter().position(|&b| b == b' ')?;
+        let len: usize = std::str::from_utf8(&data[..space]).ok()?.parse().ok()?;
+        let record = data.get(space + 1..len)?.strip_suffix(b"\n")?;
+        let eq = record.iter().position(|&b| b == b'=')?;
+        records.insert(String::from_utf8(record[..eq].to_vec()).ok()?, record[eq + 1..].to_vec());
+        data = &data[len..];
+    }
+    Some(records)
+}
+
+/// A NUL- or space-terminated octal header field.
+fn octal(field: &[u8]) -> Option<u64> {
+    let digits = std::str::from_utf8(field).ok()?.trim_matches(|c| c == ' ' || c == '\0');
+    u64::from_str_radix(digits, 8).ok()
+}
+
+fn field(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Directory `path` (relative to the restore target), made with the missing ones
+/// above it.
+fn ensure_dir(sess: &mut Session, dirs: &mut HashMap<String, u64>, path: &str, mtime: u64) -> Result<u64> {
+    let mut parts = Vec::new();
+    for part in path.split('/').filter(|p| !p.is_empty() && *p != ".") {
+        if part == ".." {
+            return Err(VaultError::corrupted(format!("unsafe path in the backup: {path}")));
+        }
+        parts.push(sess.meta.normalize_name(part).map_err(|e| e.wrapped(path))?);
+    }
+    let mut id = dirs[""];
+    for i in 0..parts.len() {
+        let key = parts[..=i].join("/");
+        if let Some(&known) = dirs.get(&key) {
+            id = known;
+            continue;
+        }
+        id = match sess.meta.name_conflict(id, &parts[i], None) {
+            Some(n) if n.node_type == NodeType::Dir => n.id,
+            _ => {
+                let name = sess.meta.unique_name(id, &parts[i]);
+                let new = sess.mkdir(id, name)?;
+                if mtime != 0 && i == parts.len() - 1 {
+                    sess.meta.set_times(new, mtime, None)?;
+                }
+                new
+            }
+        };
+        dirs.insert(key, id);
+    }
+    Ok(id)
+}
+
+fn label(sess: &mut Session, id: u64, records: &HashMap<String, Vec<u8>>) -> Result<()> {
+    for (key, value) in records {
+        if key == "VAULT.tags" {
+            for tag in String::from_utf8_lossy(value).split('\n').filter(|t| !t.trim().is_empty()) {
+                sess.meta.add_tag(id, tag)?;
+            }
+        } else if key == "VAULT.favorite" {
+            sess.meta.set_favorite(id, value == b"1")?;
+        } else if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+            sess.meta.set_xattr(id, name, value.clone())?;
+        }
+    }
+    Ok(())
+}
+
+/// The data of a file entry, reported to `progress` as it is read.
+struct Counted<'a, R> {
+    inner: R,
+    progress: &'a mut dyn Progress,
+    cancelled: bool,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if !self.progress.advance(n as u64) {
+            self.cancelled = true;
+            return Err(io::Error::other("cancelled"));
+        }
+        Ok(n)
+    }
+}
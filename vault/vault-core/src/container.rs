@@ -0,0 +1,4146 @@
+//! The vault file: a header with the key slots and the encrypted metadata, followed
+//! by the encrypted chunks of file contents. Opening a vault with any of its keys
+//! (password, keyfile, recovery code, token, post-quantum key, TPM) gives a
+//! [`Session`]; the functions here take it to read and change the vault, and every
+//! change is made crash-safe by the journal and recorded by `save_metadata`.
+
+use crate::backend::Backend;
+use crate::counter;
+use crate::crypto::{
+    aead_decrypt, aead_encrypt, hkdf_derive, pq, random_bytes, seal, stream_suffix, token, tpm, unseal, CipherSuite, Kdf,
+    SecretKey, KEY_LEN, TAG_LEN,
+};
+use crate::error::{Context, Result, VaultError};
+use crate::fsmeta::{ChunkRef, Compression, FreeRange, Metadata, Node, NodeType};
+use crate::journal;
+use crate::merkle::{self, Hash};
+use crate::staging::{self, StagedImport};
+use crate::volume::{self, Volume};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+const MAGIC: &[u8; 4] = b"VLT1";
+const BACKUP_MAGIC: &[u8; 8] = b"VLT1BKUP";
+/// Current format version. v1 (no algorithm identifiers), v2 (metadata AAD over the
+/// key wrap fields only) and v3 (12-byte nonces only) are still readable and upgraded
+/// on the next metadata write. v4 adds XChaCha20-Poly1305, whose nonces are 24 bytes,
+/// v5 the keyfile flag, v6 the recovery slots, v7 the Argon2 lane count, v8
+/// per-file data keys and STREAM chunks, which older builds would fail to decrypt,
+/// v9 the KEM ciphertext of the post-quantum key slot, v10 the decoy tree of the
+/// duress slot, v11 the sealed key of the TPM slot and v12 `Header::decoy`, which
+/// takes the duress slot out of the key slots.
+const VERSION: u32 = 12;
+
+/// Format epoch label in the v3 metadata AAD, so it can't be mistaken for another layout.
+const META_AAD_EPOCH: &[u8] = b"VLT1 header v3";
+
+/// Plaintext size of every chunk but the last one of a file, unless the header says otherwise.
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB
+
+/// Accepted range for `Header::chunk_size`.
+pub const MIN_CHUNK_SIZE: usize = 4 * 1024;
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Minimum space reserved for the header so metadata saves can be done in place.
+const MIN_HEADER_RESERVE: u64 = 64 * 1024;
+/// Plaintext size of `Decoy::meta`, the decoy tree padded (or, without a duress
+/// password, random bytes as long): fixed, so that a header is as long with a duress
+/// password as without, and a duress session, which can't move the data region (the
+/// reservation is covered by the real metadata AAD), never outgrows the header. The
+/// tree is compressed first; this holds a few hundred files.
+const DECOY_META_SIZE: usize = 64 * 1024;
+/// `KeySlot::kind` of the duress slot of v10 and v11 headers (see `Header::decoy`).
+const LEGACY_DURESS_KIND: u16 = 7;
+/// Refusal of a write that would have to move the header to the current format
+/// without the password to wrap the master key under.
+const UPGRADE_REFUSED: &str = "the vault format has to be upgraded: open it with the password once";
+/// Header reservation of a vault created in a `Backend`, which can't move its data
+/// region: with no file to rename a rewritten vault over, the header has to fit in
+/// place for good, enough for some tens of thousands of files.
+const BACKEND_HEADER_RESERVE: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub version: u32,
+
+    // algorithm identifiers (v2+, absent in v1)
+    #[serde(default)]
+    pub cipher_suite: u16,
+    #[serde(default)]
+    pub kdf: u16,
+
+    // KDF params
+    pub kdf_m_cost_kib: u32,
+    pub kdf_t_cost: u32,
+    // Argon2 lanes (v7+; 0 = 1, the only value before)
+    #[serde(default)]
+    pub kdf_p_cost: u32,
+    pub salt: [u8; 16],
+
+    // wrapped master key
+    pub mk_wrap_nonce: Vec<u8>,
+    pub wrapped_master_key: Vec<u8>,
+
+    // encrypted metadata
+    pub meta_nonce: Vec<u8>,
+    pub meta_len: u32,
+    pub meta_cipher: Vec<u8>,
+
+    // bytes reserved for cbor(header), data region starts after them (0 = no slack)
+    #[serde(default)]
+    pub header_reserved: u64,
+
+    // padding applied to newly written chunks
+    #[serde(default)]
+    pub padding: Padding,
+
+    // size of each part of a split vault (`<path>.001`, `<path>.002`, ...; 0 = one file)
+    #[serde(default)]
+    pub part_size: u64,
+
+    // plaintext bytes per chunk for new files (0 = DEFAULT_CHUNK_SIZE)
+    #[serde(default)]
+    pub chunk_size: u32,
+
+    // the KEK also depends on a keyfile (v5+)
+    #[serde(default)]
+    pub keyfile: bool,
+
+    // master key wrapped under recovery keys and tokens, at most one slot per kind (v6+)
+    #[serde(default)]
+    pub recovery_slots: Vec<KeySlot>,
+
+    // duress slot, or random bytes in its place (v12+)
+    #[serde(default)]
+    pub decoy: Option<Decoy>,
+}
+
+/// How chunk plaintexts are padded before encryption so ciphertext lengths leak less
+/// about file sizes. Padding goes after the (possibly compressed) data.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Padding {
+    #[default]
+    None,
+    /// Round up to a multiple of this many bytes.
+    Multiple(u32),
+    /// Round up to the next power of two.
+    PowerOfTwo,
+}
+
+impl Padding {
+    /// Fixed-size encoding for the header AAD.
+    fn aad_bytes(self) -> [u8; 5] {
+        let (tag, n) = match self {
+            Padding::None => (0u8, 0u32),
+            Padding::Multiple(n) => (1, n),
+            Padding::PowerOfTwo => (2, 0),
+        };
+        let mut out = [tag, 0, 0, 0, 0];
+        out[1..].copy_from_slice(&n.to_le_bytes());
+        out
+    }
+
+    fn padded_len(self, len: usize) -> usize {
+        match self {
+            Padding::None => len,
+            Padding::Multiple(n) => len.next_multiple_of(n.max(1) as usize),
+            Padding::PowerOfTwo => len.next_power_of_two(),
+        }
+    }
+}
+
+impl std::str::FromStr for Padding {
+    type Err = VaultError;
+
+    /// `none`, `pow2` or a bucket size in bytes.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Padding::None),
+            "pow2" => Ok(Padding::PowerOfTwo),
+            // Buckets are capped so a padded chunk always fits `ChunkRef.len`.
+            _ => match s.parse::<u32>() {
+                Ok(n) if n > 0 && n as usize <= MAX_CHUNK_SIZE => Ok(Padding::Multiple(n)),
+                _ => Err(VaultError::invalid(format!(
+                    "padding must be none, pow2 or a number of bytes up to {} MiB",
+                    MAX_CHUNK_SIZE / (1024 * 1024)
+                ))),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Padding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Padding::None => write!(f, "none"),
+            Padding::Multiple(n) => write!(f, "{n}"),
+            Padding::PowerOfTwo => write!(f, "pow2"),
+        }
+    }
+}
+
+/// What opens a key slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotKind {
+    /// Recovery key split into Shamir shares (see `recovery`).
+    Shares,
+    /// Recovery code (see `recovery`).
+    Code,
+    /// Hardware token response (see `crypto::token`).
+    Token,
+    /// Hardware token response plus a PIN.
+    TokenWithPin,
+    /// Key kept in the OS keychain of one machine (see `keychain`).
+    Remembered,
+    /// Hybrid X25519 + ML-KEM-768 key pair from a key file (see `crypto::pq`).
+    PostQuantum,
+    /// Key sealed to this machine's TPM and boot state (see `crypto::tpm`).
+    Tpm,
+}
+
+/// Slot kinds of an enrolled hardware token (one at most).
+pub const TOKEN_SLOTS: &[SlotKind] = &[SlotKind::Token, SlotKind::TokenWithPin];
+
+impl SlotKind {
+    pub const fn id(self) -> u16 {
+        match self {
+            SlotKind::Shares => 1,
+            SlotKind::Code => 2,
+            SlotKind::Token => 3,
+            SlotKind::TokenWithPin => 4,
+            SlotKind::Remembered => 5,
+            SlotKind::PostQuantum => 6,
+            // 7 was the duress slot (`LEGACY_DURESS_KIND`)
+            SlotKind::Tpm => 8,
+        }
+    }
+
+    /// What the user gave to open the slot, for `VaultError::WrongPassword`.
+    const fn credential(self) -> &'static str {
+        match self {
+            SlotKind::Shares => "recovery shares",
+            SlotKind::Code => "recovery code",
+            SlotKind::Token | SlotKind::TokenWithPin => "token or PIN",
+            SlotKind::Remembered => "remembered key",
+            SlotKind::PostQuantum => "post-quantum key file",
+            SlotKind::Tpm => "TPM or boot state",
+        }
+    }
+}
+
+const PASSWORD_CREDENTIAL: &str = "password or keyfile";
+
+/// The master key wrapped under a key other than the password's KEK.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySlot {
+    /// `SlotKind::id`.
+    pub kind: u16,
+    /// Random, changes whenever the slot is replaced, so stale shares are recognized.
+    pub id: [u8; 8],
+    pub nonce: Vec<u8>,
+    pub wrapped_master_key: Vec<u8>,
+    /// Encapsulated slot key of a `SlotKind::PostQuantum` slot, empty otherwise.
+    #[serde(default)]
+    pub kem_ciphertext: Vec<u8>,
+    /// Decoy tree of the duress slot of a v10 or v11 header (`LEGACY_DURESS_KIND`),
+    /// whose `wrapped_master_key` holds the decoy key instead of the master key.
+    #[serde(default)]
+    pub decoy: Option<LegacyDecoy>,
+    /// Slot key of a `SlotKind::Tpm` slot, sealed by the TPM.
+    #[serde(default)]
+    pub tpm: Option<tpm::Sealed>,
+}
+
+/// What a duress password opens: a separate metadata tree under its own key, whose
+/// files live in the same data region as the real ones.
+///
+/// Every header from v12 on has one. Without a duress password its fields are random
+/// bytes of the same lengths (`Decoy::cover`), so the header doesn't tell whether
+/// there is a duress password, and a session opened with one can't tell from the
+/// header that it isn't the real tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decoy {
+    /// Random, part of the AADs of the key wrap and the tree.
+    pub id: [u8; 8],
+    /// Argon2id salt of the duress password (with the KDF costs of the header).
+    pub salt: [u8; 16],
+    /// `CipherSuite` of `wrapped_key`: the vault's when the duress password was set.
+    /// `rekey` can't wrap the decoy key again without the duress password, so it
+    /// keeps this one when the vault switches suites.
+    pub wrap_suite: u16,
+    /// The decoy key sealed under a key from the duress password.
+    pub wrapped_key: Vec<u8>,
+    /// The decoy key sealed under the real master key, so that compaction and rekeying
+    /// of the real vault keep the decoy files.
+    pub sealed_key: Vec<u8>,
+    /// The decoy tree (`pack_decoy_tree`) sealed under the decoy key. Rewritten by
+    /// duress sessions, which can't reseal the real metadata, so it is left out of
+    /// the metadata AAD, as are the salt and the wrap. Writes of the real tree keep it
+    /// as it is, but for `compact` and `rekey`, which move the decoy chunks too.
+    pub meta: Vec<u8>,
+}
+
+impl Decoy {
+    /// Random bytes in the shape of a duress slot, for a header without one: each
+    /// field is sealed under a random key, so it reads like a real seal.
+    fn cover(suite: CipherSuite) -> Result<Decoy> {
+        let noise = |len| seal(suite, &random_bytes::<KEY_LEN>(), b"", &vec![0u8; len]);
+        Ok(Decoy {
+            id: random_bytes(),
+            salt: random_bytes(),
+            wrap_suite: suite.id(),
+            wrapped_key: noise(KEY_LEN)?,
+            sealed_key: noise(KEY_LEN)?,
+            meta: noise(DECOY_META_SIZE)?,
+        })
+    }
+}
+
+/// The decoy tree of the duress slot of a v10 or v11 header; `set_current_version`
+/// moves it to `Header::decoy`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyDecoy {
+    pub salt: [u8; 16],
+    pub sealed_key: Vec<u8>,
+    /// Unpadded, unlike `Decoy::meta`.
+    pub meta: Vec<u8>,
+}
+
+impl Header {
+    pub fn suite(&self) -> Result<CipherSuite> {
+        match self.version {
+            1 => Ok(CipherSuite::ChaCha20Poly1305),
+            _ => CipherSuite::from_id(self.cipher_suite),
+        }
+    }
+
+    pub fn kdf(&self) -> Result<Kdf> {
+        match self.version {
+            1 => Ok(Kdf::Argon2idPhcHkdf),
+            _ => Kdf::from_id(self.kdf),
+        }
+    }
+
+    pub fn kdf_p_cost(&self) -> u32 {
+        self.kdf_p_cost.max(1)
+    }
+
+    pub fn chunk_size(&self) -> Result<usize> {
+        match self.chunk_size as usize {
+            0 => Ok(DEFAULT_CHUNK_SIZE),
+            n if (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&n) => Ok(n),
+            n => Err(VaultError::Unsupported(format!("unsupported chunk size {n}"))),
+        }
+    }
+
+    /// `keyfile` is the digest from `crypto::keyfile_digest`; it is required exactly
+    /// when the vault was created with one.
+    fn derive_kek(&self, password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<SecretKey> {
+        self.derive_kek_salted(password, &self.salt, keyfile)
+    }
+
+    /// `derive_kek` with another salt (a duress password's).
+    fn derive_kek_salted(&self, password: &str, salt: &[u8; 16], keyfile: Option<&[u8; KEY_LEN]>) -> Result<SecretKey> {
+        match (self.keyfile, keyfile) {
+            (true, None) => return Err(VaultError::invalid("this vault needs a keyfile")),
+            (false, Some(_)) => return Err(VaultError::invalid("this vault doesn't use a keyfile")),
+            _ => {}
+        }
+        let kek = crate::crypto::derive_kek(
+            self.kdf()?,
+            password,
+            salt,
+            self.kdf_m_cost_kib,
+            self.kdf_t_cost,
+            self.kdf_p_cost(),
+        )?;
+        match keyfile {
+            Some(keyfile) => crate::crypto::mix_keyfile(&kek, keyfile),
+            None => Ok(kek),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Session {
+    pub path: String,
+    pub suite: CipherSuite,
+    /// Stored in the header by `save_metadata`.
+    pub padding: Padding,
+    pub chunk_size: usize,
+    pub master_key: SecretKey,
+    pub meta: Metadata,
+    /// Set by `open_vault_readonly`: every operation that writes to the vault fails.
+    pub read_only: bool,
+    // keyfile digest the session was opened with, needed to re-derive the KEK on writes
+    keyfile: Option<SecretKey>,
+    // kind and key of the slot the session was opened with instead of the password
+    slot: Option<(SlotKind, SecretKey)>,
+    // opened with the duress password: `meta` is the decoy tree and `master_key` the
+    // decoy key
+    decoy: bool,
+    txn: Option<Transaction>,
+    // changes `undo` and `redo` can replay; lost when the session is closed
+    history: History,
+    // counter file and the highest generation known to it (see `track_generation`)
+    counter: Option<(String, u64)>,
+    // exclusive lock on `<path>.lock`, released on drop
+    _lock: Option<File>,
+    // storage of a session from `open_vault_in` or `open_vault_backend`, used instead
+    // of the file at `path`
+    backend: Option<Arc<dyn Backend>>,
+}
+
+/// Where a vault is: its path, the base of the journal, lock and other files next to
+/// it, and for a vault in a `Backend` that storage instead of the file at `path`.
+#[derive(Clone, Copy)]
+struct Place<'a> {
+    path: &'a str,
+    backend: Option<&'a Arc<dyn Backend>>,
+}
+
+impl<'a> Place<'a> {
+    fn file(path: &'a str) -> Self {
+        Self { path, backend: None }
+    }
+
+    fn open(&self, write: bool) -> Result<Volume> {
+        match self.backend {
+            Some(backend) => Ok(Volume::from_backend(backend.clone())),
+            None => Ok(Volume::open(self.path, write)?),
+        }
+    }
+
+    /// Refuses what rewrites the vault into a temporary copy renamed over it: there is
+    /// no such copy of a vault in a backend.
+    fn ensure_file(&self) -> Result<()> {
+        match self.backend {
+            Some(_) => Err(VaultError::Unsupported(
+                "not possible for a vault in a storage backend: copy it to a file first".into(),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State of a transaction started by `Session::begin`.
+#[derive(Debug)]
+struct Transaction {
+    /// Metadata to return to on rollback.
+    meta: Metadata,
+    /// Ranges to shred once the transaction is committed.
+    shred: Vec<FreeRange>,
+    /// Changes made in it, undone together.
+    changes: Vec<Change>,
+}
+
+/// Changes to the tree recorded by the `Session` methods of the same names, in
+/// batches: one per change, or all of a transaction's.
+#[derive(Debug, Default)]
+struct History {
+    done: Vec<Vec<Change>>,
+    undone: Vec<Vec<Change>>,
+}
+
+#[derive(Debug, Clone)]
+enum Change {
+    Rename { id: u64, from: String, to: String },
+    Move { id: u64, from: u64, to: u64, trashed_from: Option<u64> },
+    Trash { id: u64, from: u64 },
+    Mkdir(Box<Node>),
+}
+
+impl Change {
+    fn undo(&self, meta: &mut Metadata) -> Result<()> {
+        match self {
+            Change::Rename { id, from, .. } => meta.rename(*id, from.clone()),
+            // back into the trash, where `move_node` can't put it
+            Change::Move { id, from, trashed_from: Some(trashed_from), .. } if *from == meta.trash_id => {
+                meta.trash(*id)?;
+                meta.get_node_mut(*id).ok_or(VaultError::NotFound)?.trashed_from = Some(*trashed_from);
+                Ok(())
+            }
+            Change::Move { id, from, .. } | Change::Trash { id, from } => meta.move_node(*id, *from),
+            Change::Mkdir(dir) => {
+                if !meta.children_of(dir.id).is_empty() {
+                    return Err(VaultError::invalid(format!("directory {:?} is no longer empty", dir.name)));
+                }
+                meta.remove_subtree(dir.id).map(drop)
+            }
+        }
+    }
+
+    fn redo(&self, meta: &mut Metadata) -> Result<()> {
+        match self {
+            Change::Rename { id, to, .. } => meta.rename(*id, to.clone()),
+            Change::Move { id, to, .. } => meta.move_node(*id, *to),
+            Change::Trash { id, .. } => meta.trash(*id),
+            Change::Mkdir(dir) => meta.restore_dir((**dir).clone()),
+        }
+    }
+}
+
+impl Session {
+    fn place(&self) -> Place<'_> {
+        Place {
+            path: &self.path,
+            backend: self.backend.as_ref(),
+        }
+    }
+
+    /// The vault storage, for reading.
+    fn volume(&self) -> Result<Volume> {
+        self.place().open(false)
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(VaultError::ReadOnly);
+        }
+        if self.meta.is_newer_schema() {
+            return Err(VaultError::Unsupported(format!(
+                "the vault metadata is of schema {}, newer than this program's {}: update the program to change it",
+                self.meta.schema_version,
+                crate::fsmeta::SCHEMA_VERSION
+            )));
+        }
+        Ok(())
+    }
+
+    fn ensure_no_transaction(&self) -> Result<()> {
+        if self.txn.is_some() {
+            return Err(VaultError::invalid("not allowed inside a transaction"));
+        }
+        Ok(())
+    }
+
+    /// Refuses changes to the key slots in a duress session, which would have to
+    /// reseal the real metadata. Saying so would give the decoy tree away, so
+    /// `password` is checked against the wrap of the master key, which it doesn't
+    /// open: the change fails as with a wrong password in any session.
+    fn ensure_real_tree(&self, password: &str) -> Result<()> {
+        if !self.decoy {
+            return Ok(());
+        }
+        let (_, header) = read_header(&mut self.volume()?)?;
+        let kek = header.derive_kek(password, self.keyfile.as_deref())?;
+        let mk_plain = aead_decrypt(header.suite()?, &kek, &header.mk_wrap_nonce, &wrap_aad(&header), &header.wrapped_master_key)?;
+        crate::crypto::zeroize_vec(mk_plain);
+        Err(VaultError::corrupted("master key mismatch"))
+    }
+
+    /// Starts a transaction. Until `commit`, metadata changes (`save_metadata`,
+    /// `import_file`, ...) stay in memory and appended chunks are covered by the
+    /// journal, so the whole batch is persisted with one metadata write or not at all.
+    /// A session dropped without `commit` is rolled back on the next open.
+    pub fn begin(&mut self) -> Result<()> {
+        self.ensure_writable()?;
+        self.ensure_no_transaction()?;
+        let mut f = self.volume()?;
+        let (data_start, _) = read_header(&mut f)?;
+        let data_len = data_len(&mut f, data_start)?;
+        journal::begin(&self.path, data_start, data_len)?;
+        self.txn = Some(Transaction {
+            meta: self.meta.clone(),
+            shred: vec![],
+            changes: vec![],
+        });
+        Ok(())
+    }
+
+    /// Writes the metadata of the current transaction. If that fails the
+    /// transaction is rolled back (unless it was already committed on disk).
+    pub fn commit(&mut self, password: &str) -> Result<()> {
+        let txn = self.txn.take().ok_or_else(|| VaultError::invalid("no transaction in progress"))?;
+        if let Err(e) = save_metadata(self, password) {
+            if !abort_append(self)? {
+                self.meta = txn.meta;
+                return Err(e);
+            }
+        }
+        self.push_changes(txn.changes);
+        shred_ranges(self.place(), &txn.shred)
+    }
+
+    /// Drops all changes made since `begin`, including appended chunks.
+    pub fn rollback(&mut self) -> Result<()> {
+        let txn = self.txn.take().ok_or_else(|| VaultError::invalid("no transaction in progress"))?;
+        self.meta = txn.meta;
+        recover_at(self.place())
+    }
+
+    /// `Metadata::rename`, recorded for `undo`. Like it, changes only the metadata in
+    /// memory, to be saved with `save_metadata`.
+    pub fn rename(&mut self, id: u64, new_name: String) -> Result<()> {
+        let from = self.meta.get_node(id).ok_or(VaultError::NotFound)?.name.clone();
+        self.meta.rename(id, new_name)?;
+        let to = self.meta.get_node(id).map(|n| n.name.clone()).unwrap_or_default();
+        self.record(Change::Rename { id, from, to });
+        Ok(())
+    }
+
+    /// `Metadata::move_node`, recorded for `undo`.
+    pub fn move_node(&mut self, id: u64, parent_id: u64) -> Result<()> {
+        let n = self.meta.get_node(id).ok_or(VaultError::NotFound)?;
+        let (from, trashed_from) = (n.parent_id, n.trashed_from);
+        self.meta.move_node(id, parent_id)?;
+        if from != parent_id {
+            self.record(Change::Move { id, from, to: parent_id, trashed_from });
+        }
+        Ok(())
+    }
+
+    /// `Metadata::trash`, recorded for `undo`.
+    pub fn trash(&mut self, id: u64) -> Result<()> {
+        let from = self.meta.get_node(id).ok_or(VaultError::NotFound)?.parent_id;
+        self.meta.trash(id)?;
+        self.record(Change::Trash { id, from });
+        Ok(())
+    }
+
+    /// `Metadata::mkdir`, recorded for `undo`.
+    pub fn mkdir(&mut self, parent_id: u64, name: String) -> Result<u64> {
+        let id = self.meta.mkdir(parent_id, name)?;
+        if let Some(dir) = self.meta.get_node(id) {
+            self.record(Change::Mkdir(Box::new(dir.clone())));
+        }
+        Ok(id)
+    }
+
+    fn record(&mut self, change: Change) {
+        match &mut self.txn {
+            Some(txn) => txn.changes.push(change),
+            None => self.push_changes(vec![change]),
+        }
+    }
+
+    fn push_changes(&mut self, changes: Vec<Change>) {
+        if !changes.is_empty() {
+            self.history.done.push(changes);
+            self.history.undone.clear();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.history.undone.is_empty()
+    }
+
+    /// Takes back the last recorded change (all of a transaction's at once) by
+    /// applying the inverse operations, and saves the metadata. Returns false if
+    /// there is nothing to undo. Fails, changing nothing, if the tree has changed in
+    /// a way that rules it out (say, the old name is taken since); the change stays
+    /// next in line.
+    pub fn undo(&mut self, password: &str) -> Result<bool> {
+        self.replay(password, true)
+    }
+
+    /// Applies again the last change taken back by `undo`, and saves the metadata.
+    /// Any newly recorded change drops the changes that could be redone.
+    pub fn redo(&mut self, password: &str) -> Result<bool> {
+        self.replay(password, false)
+    }
+
+    fn replay(&mut self, password: &str, undo: bool) -> Result<bool> {
+        self.ensure_writable()?;
+        self.ensure_no_transaction()?;
+        let stack = match undo {
+            true => &mut self.history.done,
+            false => &mut self.history.undone,
+        };
+        let Some(batch) = stack.pop() else {
+            return Ok(false);
+        };
+        let backup = self.meta.clone();
+        let res = match undo {
+            true => batch.iter().rev().try_for_each(|c| c.undo(&mut self.meta)),
+            false => batch.iter().try_for_each(|c| c.redo(&mut self.meta)),
+        }
+        .and_then(|()| save_metadata(self, password));
+        let (back, other) = match undo {
+            true => (&mut self.history.done, &mut self.history.undone),
+            false => (&mut self.history.undone, &mut self.history.done),
+        };
+        if let Err(e) = res {
+            self.meta = backup;
+            back.push(batch);
+            return Err(e);
+        }
+        other.push(batch);
+        Ok(true)
+    }
+}
+
+/// AAD of the wrapped master key: the fields needed to unwrap it.
+fn wrap_aad(h: &Header) -> Vec<u8> {
+    let mut aad = Vec::new();
+    aad.extend_from_slice(&h.magic);
+    aad.extend_from_slice(&h.version.to_le_bytes());
+    if h.version >= 2 {
+        aad.extend_from_slice(&h.cipher_suite.to_le_bytes());
+        aad.extend_from_slice(&h.kdf.to_le_bytes());
+    }
+    aad.extend_from_slice(&h.kdf_m_cost_kib.to_le_bytes());
+    aad.extend_from_slice(&h.kdf_t_cost.to_le_bytes());
+    aad.extend_from_slice(&h.salt);
+    aad.extend_from_slice(&h.mk_wrap_nonce);
+    if h.version >= 5 {
+        aad.push(h.keyfile as u8);
+    }
+    if h.version >= 7 {
+        aad.extend_from_slice(&h.kdf_p_cost.to_le_bytes());
+    }
+    aad
+}
+
+/// AAD of the metadata. From v3 it covers every header field but the metadata
+/// ciphertext itself, so none of them can be changed or swapped with another
+/// header's; a new header field has to be added here with a version bump. Up to v2
+/// it was `wrap_aad`.
+fn meta_aad(h: &Header) -> Vec<u8> {
+    let mut aad = wrap_aad(h);
+    if h.version < 3 {
+        return aad;
+    }
+    aad.extend_from_slice(META_AAD_EPOCH);
+    aad.extend_from_slice(&(h.wrapped_master_key.len() as u32).to_le_bytes());
+    aad.extend_from_slice(&h.wrapped_master_key);
+    aad.extend_from_slice(&h.meta_nonce);
+    aad.extend_from_slice(&h.meta_len.to_le_bytes());
+    aad.extend_from_slice(&h.header_reserved.to_le_bytes());
+    aad.extend_from_slice(&h.padding.aad_bytes());
+    aad.extend_from_slice(&h.part_size.to_le_bytes());
+    aad.extend_from_slice(&h.chunk_size.to_le_bytes());
+    if h.version >= 6 {
+        aad.extend_from_slice(&(h.recovery_slots.len() as u32).to_le_bytes());
+        for slot in &h.recovery_slots {
+            aad.extend_from_slice(&slot_aad(h, slot.kind, &slot.id));
+            aad.extend_from_slice(&slot.nonce);
+            aad.extend_from_slice(&(slot.wrapped_master_key.len() as u32).to_le_bytes());
+            aad.extend_from_slice(&slot.wrapped_master_key);
+            if h.version >= 9 {
+                aad.extend_from_slice(&(slot.kem_ciphertext.len() as u32).to_le_bytes());
+                aad.extend_from_slice(&slot.kem_ciphertext);
+            }
+            if let Some(decoy) = slot.decoy.as_ref().filter(|_| h.version >= 10) {
+                aad.extend_from_slice(&decoy.salt);
+                aad.extend_from_slice(&(decoy.sealed_key.len() as u32).to_le_bytes());
+                aad.extend_from_slice(&decoy.sealed_key);
+            }
+            if let Some(sealed) = slot.tpm.as_ref().filter(|_| h.version >= 11) {
+                for field in [sealed.pcrs.as_bytes(), &sealed.public, &sealed.private] {
+                    aad.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                    aad.extend_from_slice(field);
+                }
+            }
+        }
+    }
+    // what a duress session never rewrites
+    if let Some(decoy) = h.decoy.as_ref().filter(|_| h.version >= 12) {
+        aad.extend_from_slice(&decoy.id);
+        aad.extend_from_slice(&decoy.wrap_suite.to_le_bytes());
+        aad.extend_from_slice(&(decoy.sealed_key.len() as u32).to_le_bytes());
+        aad.extend_from_slice(&decoy.sealed_key);
+    }
+    aad
+}
+
+/// AAD of a recovery slot's wrapped master key.
+fn slot_aad(h: &Header, kind: u16, id: &[u8; 8]) -> Vec<u8> {
+    let mut aad = b"VLT1 recovery slot".to_vec();
+    aad.extend_from_slice(&h.cipher_suite.to_le_bytes());
+    aad.extend_from_slice(&kind.to_le_bytes());
+    aad.extend_from_slice(id);
+    aad
+}
+
+/// Wraps `master_key` under the KEK derived from `password` with a fresh nonce.
+fn wrap_master_key(
+    header: &mut Header,
+    password: &str,
+    keyfile: Option<&[u8; KEY_LEN]>,
+    master_key: &[u8; KEY_LEN],
+) -> Result<()> {
+    header.mk_wrap_nonce = header.suite()?.random_nonce();
+    let kek = header.derive_kek(password, keyfile)?;
+    header.wrapped_master_key = aead_encrypt(header.suite()?, &kek, &header.mk_wrap_nonce, &wrap_aad(header), master_key)?;
+    Ok(())
+}
+
+/// True if the header is on an older format version or an older KDF.
+fn needs_upgrade(header: &Header) -> Result<bool> {
+    Ok(header.version < VERSION || header.kdf()? != Kdf::DEFAULT)
+}
+
+/// Moves an older header to the current format version and KDF. The key wrap covers
+/// both, so the master key has to be wrapped again afterwards.
+fn set_current_version(header: &mut Header) -> Result<()> {
+    header.cipher_suite = header.suite()?.id();
+    header.kdf = Kdf::DEFAULT.id();
+    header.kdf_p_cost = header.kdf_p_cost();
+    if header.decoy.is_none() {
+        header.decoy = Some(match header_decoy(header) {
+            Some(decoy) => decoy,
+            None => Decoy::cover(header.suite()?)?,
+        });
+        header.recovery_slots.retain(|s| s.kind != LEGACY_DURESS_KIND);
+    }
+    header.version = VERSION;
+    Ok(())
+}
+
+/// `set_current_version` for a write of the real tree that keeps the decoy tree: the
+/// tree of a v10 or v11 duress slot is sealed again, padded.
+fn upgrade_header(sess: &Session, header: &mut Header) -> Result<()> {
+    let legacy = header.decoy.is_none();
+    set_current_version(header)?;
+    if legacy {
+        if let Some((key, meta)) = open_decoy_tree(sess, header)? {
+            store_decoy(header, Some((&key, &meta)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Offset of the data region. Layout:
+/// `[u32 header_len][cbor(header)][zero padding up to header_reserved][data...][backup]`.
+fn data_start(header_len: u64, header: &Header) -> u64 {
+    4 + header_len.max(header.header_reserved)
+}
+
+/// Reads the primary header, returns it with the data region offset.
+fn read_primary_header(f: &mut Volume) -> Result<(u64, Header)> {
+    f.seek(SeekFrom::Start(0))?;
+    let mut len4 = [0u8; 4];
+    f.read_exact(&mut len4)?;
+    let header_len = u32::from_le_bytes(len4) as usize;
+
+    let mut header_buf = vec![0u8; header_len];
+    f.read_exact(&mut header_buf)?;
+    let header: Header = serde_cbor::from_slice(&header_buf)?;
+    f.set_part_size(header.part_size);
+    Ok((data_start(header_len as u64, &header), header))
+}
+
+/// Locates the backup header at the end of the file:
+/// `[cbor(header)][u32 header_len][BACKUP_MAGIC]`. Returns its offset and bytes.
+fn read_backup_bytes(f: &mut Volume) -> Result<Option<(u64, Vec<u8>)>> {
+    let file_len = f.len()?;
+    if file_len < 12 {
+        return Ok(None);
+    }
+    let mut tail = [0u8; 12];
+    f.seek(SeekFrom::Start(file_len - 12))?;
+    f.read_exact(&mut tail)?;
+    if &tail[4..] != BACKUP_MAGIC {
+        return Ok(None);
+    }
+    let header_len = u32::from_le_bytes(tail[..4].try_into().map_err(VaultError::corrupted)?) as u64;
+    if header_len + 12 > file_len {
+        return Ok(None);
+    }
+    let start = file_len - 12 - header_len;
+    let mut buf = vec![0u8; header_len as usize];
+    f.seek(SeekFrom::Start(start))?;
+    f.read_exact(&mut buf)?;
+    Ok(Some((start, buf)))
+}
+
+fn read_backup_header(f: &mut Volume) -> Result<Option<(u64, Header)>> {
+    let Some((_, buf)) = read_backup_bytes(f)? else {
+        return Ok(None);
+    };
+    let header: Header = serde_cbor::from_slice(&buf)?;
+    f.set_part_size(header.part_size);
+    // The backup is a verbatim copy of the primary, so it has the same length.
+    Ok(Some((data_start(buf.len() as u64, &header), header)))
+}
+
+/// Reads the primary header, falling back to the backup copy if it can't be parsed.
+fn read_header(f: &mut Volume) -> Result<(u64, Header)> {
+    match read_primary_header(f) {
+        Ok(v) => Ok(v),
+        Err(e) => read_backup_header(f).ok().flatten().ok_or(e),
+    }
+}
+
+/// End of the data region (start of the backup header, or end of file without one).
+fn data_end(f: &mut Volume, data_start: u64) -> Result<u64> {
+    match read_backup_bytes(f)? {
+        Some((start, _)) if start >= data_start => Ok(start),
+        _ => Ok(f.len()?),
+    }
+}
+
+fn data_len(f: &mut Volume, data_start: u64) -> Result<u64> {
+    Ok(data_end(f, data_start)?.saturating_sub(data_start))
+}
+
+/// Writes a header region and zero-fills the rest of its reservation.
+fn write_region(f: &mut Volume, region: &[u8], data_start: u64) -> Result<()> {
+    f.write_all(region)?;
+    f.write_all(&vec![0u8; (data_start - region.len() as u64) as usize])?;
+    Ok(())
+}
+
+/// Truncates the file to `data_end` and appends the backup copy of `region`.
+fn write_backup(f: &mut Volume, data_end: u64, region: &[u8]) -> Result<()> {
+    f.set_len(data_end)?;
+    f.seek(SeekFrom::Start(data_end))?;
+    f.write_all(&region[4..])?;
+    f.write_all(&region[..4])?;
+    f.write_all(BACKUP_MAGIC)?;
+    Ok(())
+}
+
+/// Unwraps the master key with `password` and checks it matches the session. A
+/// session opened through a key slot is checked against that slot instead.
+fn check_password(sess: &Session, header: &Header, password: &str) -> Result<()> {
+    let mk_plain = match &sess.slot {
+        Some((kind, key)) => unwrap_slot(header, *kind, None, key)?.to_vec(),
+        None => unwrap_with_password(sess, header, password)?,
+    };
+
+    if mk_plain.len() != KEY_LEN {
+        return Err(VaultError::corrupted("invalid master key length"));
+    }
+    let matches = mk_plain.as_slice() == sess.master_key.as_slice();
+    crate::crypto::zeroize_vec(mk_plain);
+    if !matches {
+        // defensive: shouldn't happen
+        return Err(VaultError::corrupted("master key mismatch"));
+    }
+    Ok(())
+}
+
+/// Unwraps the key of `sess` with `password`: the master key, or in a duress session
+/// the decoy key, which it holds in place of the master key.
+fn unwrap_with_password(sess: &Session, header: &Header, password: &str) -> Result<Vec<u8>> {
+    if sess.decoy {
+        let decoy = header_decoy(header).ok_or_else(|| VaultError::corrupted("corrupted header"))?;
+        let kek = header.derive_kek_salted(password, &decoy.salt, sess.keyfile.as_deref())?;
+        return Ok(unwrap_decoy_key(&decoy, &kek)?.to_vec());
+    }
+    let kek = header.derive_kek(password, sess.keyfile.as_deref())?;
+    aead_decrypt(header.suite()?, &kek, &header.mk_wrap_nonce, &wrap_aad(header), &header.wrapped_master_key)
+}
+
+/// Encrypts `meta` into `header` and returns the serialized header region
+/// (`[u32 header_len][cbor(header)]`).
+fn seal_metadata(header: &mut Header, master_key: &[u8; KEY_LEN], meta: &Metadata) -> Result<Vec<u8>> {
+    let meta_plain = serde_cbor::to_vec(meta)?;
+    // Known up front, since it is part of the AAD.
+    header.meta_len = u32::try_from(meta_plain.len() + TAG_LEN).map_err(|_| VaultError::invalid("metadata too large"))?;
+    let header_bytes = loop {
+        header.meta_nonce = header.suite()?.random_nonce();
+        header.meta_cipher = aead_encrypt(header.suite()?, master_key, &header.meta_nonce, &meta_aad(header), &meta_plain)?;
+        let header_bytes = serde_cbor::to_vec(header)?;
+        if header_bytes.len() as u64 <= header.header_reserved {
+            break header_bytes;
+        }
+        // Outgrew the reservation: leave 2x slack so the next saves stay in place.
+        // The reservation is authenticated, so seal again.
+        header.header_reserved = (2 * header_bytes.len() as u64).max(MIN_HEADER_RESERVE).next_multiple_of(4096);
+    };
+    header_region(&header_bytes)
+}
+
+fn header_region(header_bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut region = Vec::with_capacity(4 + header_bytes.len());
+    let header_len = u32::try_from(header_bytes.len()).map_err(|_| VaultError::invalid("metadata too large"))?;
+    region.extend_from_slice(&header_len.to_le_bytes());
+    region.extend_from_slice(header_bytes);
+    Ok(region)
+}
+
+fn decoy_aad(slot_id: &[u8; 8]) -> Vec<u8> {
+    let mut aad = b"VLT1 decoy tree".to_vec();
+    aad.extend_from_slice(slot_id);
+    aad
+}
+
+/// AAD of `Decoy::wrapped_key`: that of the duress slot of v10 and v11 headers, a key
+/// slot then, so that their wraps carry over.
+fn duress_wrap_aad(decoy: &Decoy) -> Vec<u8> {
+    let mut aad = b"VLT1 recovery slot".to_vec();
+    aad.extend_from_slice(&decoy.wrap_suite.to_le_bytes());
+    aad.extend_from_slice(&LEGACY_DURESS_KIND.to_le_bytes());
+    aad.extend_from_slice(&decoy.id);
+    aad
+}
+
+/// Key that seals the decoy key under the master key (see `Decoy::sealed_key`).
+fn decoy_wrap_key(master_key: &[u8; KEY_LEN]) -> Result<SecretKey> {
+    hkdf_derive(master_key, b"decoy key")
+}
+
+/// The duress slot of `header`, or the random bytes in its place. The duress slot of
+/// a v10 or v11 header, a key slot, is brought into the same shape (with its tree in
+/// the older layout, see `unseal_decoy_tree`); such a header without one has none.
+fn header_decoy(header: &Header) -> Option<Decoy> {
+    if let Some(decoy) = &header.decoy {
+        return Some(decoy.clone());
+    }
+    let slot = header.recovery_slots.iter().find(|s| s.kind == LEGACY_DURESS_KIND)?;
+    let legacy = slot.decoy.as_ref()?;
+    Some(Decoy {
+        id: slot.id,
+        salt: legacy.salt,
+        wrap_suite: header.cipher_suite,
+        wrapped_key: [slot.nonce.as_slice(), &slot.wrapped_master_key].concat(),
+        sealed_key: legacy.sealed_key.clone(),
+        meta: legacy.meta.clone(),
+    })
+}
+
+/// Unwraps the decoy key with the KEK of the duress password.
+fn unwrap_decoy_key(decoy: &Decoy, kek: &[u8; KEY_LEN]) -> Result<SecretKey> {
+    let slot_key = hkdf_derive(kek, b"recovery:slot")?;
+    let key_plain = unseal(CipherSuite::from_id(decoy.wrap_suite)?, &slot_key, &duress_wrap_aad(decoy), &decoy.wrapped_key)?;
+    let key = key_plain.as_slice().try_into().map(SecretKey::new).map_err(|_| VaultError::corrupted("invalid master key length"));
+    crate::crypto::zeroize_vec(key_plain);
+    key
+}
+
+/// Wraps `decoy_key` under the KEK of a duress password with the salt `salt`.
+fn wrap_decoy_key(decoy: &mut Decoy, salt: [u8; 16], kek: &[u8; KEY_LEN], decoy_key: &[u8; KEY_LEN]) -> Result<()> {
+    let slot_key = hkdf_derive(kek, b"recovery:slot")?;
+    decoy.salt = salt;
+    decoy.wrapped_key = seal(CipherSuite::from_id(decoy.wrap_suite)?, &slot_key, &duress_wrap_aad(decoy), decoy_key)?;
+    Ok(())
+}
+
+/// Plaintext of `Decoy::meta`: `[u32 len][zstd(cbor(meta))]`, zero-padded to
+/// `DECOY_META_SIZE`. A tree that doesn't fit is refused, and so is the write that
+/// would have stored it.
+fn pack_decoy_tree(meta: &Metadata) -> Result<Vec<u8>> {
+    let packed = zstd::bulk::compress(&serde_cbor::to_vec(meta)?, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    if 4 + packed.len() > DECOY_META_SIZE {
+        return Err(VaultError::invalid(format!(
+            "metadata too large: {} bytes compressed, at most {}",
+            packed.len(),
+            DECOY_META_SIZE - 4
+        )));
+    }
+    let mut plain = Vec::with_capacity(DECOY_META_SIZE);
+    plain.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+    plain.extend_from_slice(&packed);
+    plain.resize(DECOY_META_SIZE, 0);
+    Ok(plain)
+}
+
+/// Decrypts the decoy tree. One of a v10 or v11 header is plain CBOR, which is never
+/// exactly `DECOY_META_SIZE` long; `upgrade_header` pads it, and `rekey` and
+/// `compact` seal it again anyway.
+fn unseal_decoy_tree(suite: CipherSuite, decoy: &Decoy, decoy_key: &[u8; KEY_LEN]) -> Result<Metadata> {
+    let plain = unseal(suite, decoy_key, &decoy_aad(&decoy.id), &decoy.meta)?;
+    if plain.len() != DECOY_META_SIZE {
+        return decode_metadata(&plain);
+    }
+    let len = u32::from_le_bytes(plain[..4].try_into().expect("4 bytes")) as usize;
+    let packed = plain.get(4..4 + len).ok_or_else(|| VaultError::corrupted("corrupted metadata"))?;
+    decode_metadata(&zstd::decode_all(packed)?)
+}
+
+/// Seals the decoy tree of a duress session into the header and returns the header
+/// region. Nothing the real metadata AAD covers changes, the reservation included.
+fn seal_decoy(header: &mut Header, decoy_key: &[u8; KEY_LEN], meta: &Metadata) -> Result<Vec<u8>> {
+    store_decoy(header, Some((decoy_key, meta)))?;
+    let header_bytes = serde_cbor::to_vec(header)?;
+    if header_bytes.len() as u64 > header.header_reserved {
+        return Err(VaultError::invalid("metadata too large"));
+    }
+    header_region(&header_bytes)
+}
+
+/// Seals `tree`, a decoy key and tree, into the duress slot, or without one puts new
+/// random bytes in place of the tree, so that those bytes change the same way
+/// whether there is a duress password or not.
+fn store_decoy(header: &mut Header, tree: Option<(&[u8; KEY_LEN], &Metadata)>) -> Result<()> {
+    let suite = header.suite()?;
+    let seal_tree = |id: &[u8; 8]| match tree {
+        Some((decoy_key, meta)) => seal(suite, decoy_key, &decoy_aad(id), &pack_decoy_tree(meta)?),
+        None => Ok(Decoy::cover(suite)?.meta),
+    };
+    match &mut header.decoy {
+        Some(decoy) => decoy.meta = seal_tree(&decoy.id)?,
+        // The duress slot of a v10 or v11 header, which a duress session can't upgrade.
+        None => {
+            let legacy = header.recovery_slots.iter_mut().find(|s| s.kind == LEGACY_DURESS_KIND);
+            if let Some((id, decoy)) = legacy.and_then(|s| Some((s.id, s.decoy.as_mut()?))) {
+                if tree.is_some() {
+                    decoy.meta = seal_tree(&id)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decoy key and tree of the duress slot, for a session of the real tree; `None`
+/// without a duress password.
+fn open_decoy_tree(sess: &Session, header: &Header) -> Result<Option<(SecretKey, Metadata)>> {
+    let Some(decoy) = header_decoy(header) else {
+        return Ok(None);
+    };
+    // The random bytes in place of a duress slot don't unseal. The metadata AAD
+    // covers the sealed key, so a damaged one doesn't get this far.
+    let Ok(key_plain) = unseal(sess.suite, &*decoy_wrap_key(&sess.master_key)?, b"decoy key", &decoy.sealed_key) else {
+        return Ok(None);
+    };
+    let decoy_key = key_plain.as_slice().try_into().map(SecretKey::new).map_err(|_| VaultError::corrupted("invalid decoy key length"));
+    crate::crypto::zeroize_vec(key_plain);
+    let decoy_key = decoy_key?;
+    let meta = unseal_decoy_tree(sess.suite, &decoy, &decoy_key).context("decoy tree auth failed")?;
+    Ok(Some((decoy_key, meta)))
+}
+
+/// Deserializes decrypted metadata and brings it to the current schema.
+fn decode_metadata(plain: &[u8]) -> Result<Metadata> {
+    let mut meta: Metadata = serde_cbor::from_slice(plain)?;
+    meta.migrate();
+    Ok(meta)
+}
+
+/// Replaces the header region and the backup header of the vault file, keeping the
+/// data region verbatim. `old_data_start` is where the data region currently begins.
+fn write_header_region(place: Place, region: &[u8], old_data_start: u64) -> Result<()> {
+    let header: Header = serde_cbor::from_slice(&region[4..])?;
+    let new_data_start = data_start(region.len() as u64 - 4, &header);
+
+    let mut f = place.open(true)?;
+    f.set_part_size(header.part_size);
+    let old_data_end = data_end(&mut f, old_data_start)?;
+
+    if new_data_start == old_data_start {
+        // Fits into the reservation: overwrite in place (the journal covers torn writes).
+        f.seek(SeekFrom::Start(0))?;
+        write_region(&mut f, region, new_data_start)?;
+        write_backup(&mut f, old_data_end, region)?;
+        f.sync_all()?;
+        return Ok(());
+    }
+
+    // Reservation changed: relocate the data region via temp and rename.
+    place.ensure_file()?;
+    let path = place.path;
+    let mut tmp = Volume::create(&tmp_path(path), f.part_size())?;
+    write_region(&mut tmp, region, new_data_start)?;
+
+    // Copy data region verbatim (everything between old header and old backup)
+    f.seek(SeekFrom::Start(old_data_start))?;
+    let copied = std::io::copy(&mut (&mut f).take(old_data_end - old_data_start), &mut tmp)?;
+    write_backup(&mut tmp, new_data_start + copied, region)?;
+    tmp.sync_all()?;
+    drop(f);
+    replace_vault(path, tmp)
+}
+
+fn tmp_path(path: &str) -> String {
+    format!("{path}.tmp")
+}
+
+/// Moves the fully written `<path>.tmp` volume over the vault. A split vault is
+/// renamed part by part, so the journal makes the move restartable.
+fn replace_vault(path: &str, tmp: Volume) -> Result<()> {
+    let (parts, split) = (tmp.part_count(), tmp.is_split());
+    drop(tmp);
+    journal::begin_replace(path, parts)?;
+    volume::replace(&tmp_path(path), path, parts, split)?;
+    journal::finish(path)
+}
+
+/// Finishes or rolls back an operation interrupted by a crash (see `journal`).
+pub fn recover(path: &str) -> Result<()> {
+    recover_at(Place::file(path))
+}
+
+fn recover_at(place: Place) -> Result<()> {
+    let path = place.path;
+    if let Some(j) = journal::load(path).context("read journal")? {
+        match (j.replace_parts, j.header) {
+            // Interrupted move of a rewritten vault: finish it.
+            (Some(parts), _) => {
+                place.ensure_file()?;
+                volume::replace(&tmp_path(path), path, parts, volume::is_split(path))?
+            }
+            // Committed: replay the header write.
+            (None, Some(region)) => write_header_region(place, &region, j.data_start)?,
+            // Not committed: drop ciphertext appended after the recorded length
+            // and restore the backup header the append overwrote.
+            (None, None) => {
+                let mut f = place.open(true)?;
+                let (_, header) = read_primary_header(&mut f)?;
+                let header_bytes = serde_cbor::to_vec(&header)?;
+                let mut region = (header_bytes.len() as u32).to_le_bytes().to_vec();
+                region.extend_from_slice(&header_bytes);
+                write_backup(&mut f, j.data_start + j.data_len, &region)?;
+                f.sync_all()?;
+            }
+        }
+        journal::finish(path)?;
+    }
+
+    if place.backend.is_none() && volume::exists(&tmp_path(path)) {
+        volume::remove(&tmp_path(path))?;
+    }
+    Ok(())
+}
+
+/// Parameters of a new vault.
+#[derive(Debug, Clone)]
+pub struct NewVault {
+    pub m_cost_kib: u32,
+    pub t_cost: u32,
+    /// Argon2 lanes.
+    pub p_cost: u32,
+    pub padding: Padding,
+    /// Split into parts of this many bytes (0 = a single file).
+    pub part_size: u64,
+    pub chunk_size: usize,
+    pub cipher_suite: CipherSuite,
+    /// Keyfile digest (`crypto::keyfile_digest`) required to open the vault besides
+    /// the password.
+    pub keyfile: Option<SecretKey>,
+    /// Recovery key of a recovery code (`recovery::new_code`) that also opens the vault.
+    pub recovery_code: Option<SecretKey>,
+}
+
+impl Default for NewVault {
+    fn default() -> Self {
+        NewVault {
+            m_cost_kib: 131072,
+            t_cost: 3,
+            p_cost: 1,
+            padding: Padding::None,
+            part_size: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cipher_suite: CipherSuite::preferred(),
+            keyfile: None,
+            recovery_code: None,
+        }
+    }
+}
+
+pub fn create_vault(path: &str, password: &str, params: &NewVault) -> Result<()> {
+    let (region, data_start) = new_header_region(password, params, 0)?;
+    write_new_vault(Volume::create(path, params.part_size)?, &region, data_start)
+}
+
+/// Creates a vault in `backend`, replacing what it holds. It can't be split, and its
+/// header gets room to grow in place (see `BACKEND_HEADER_RESERVE`).
+pub fn create_vault_in(backend: &Arc<dyn Backend>, password: &str, params: &NewVault) -> Result<()> {
+    if params.part_size > 0 {
+        return Err(VaultError::Unsupported("a vault in a storage backend can't be split into parts".into()));
+    }
+    let (region, data_start) = new_header_region(password, params, BACKEND_HEADER_RESERVE)?;
+    backend.set_len(0)?;
+    write_new_vault(Volume::from_backend(backend.clone()), &region, data_start)
+}
+
+/// Header region of a new vault with `reserve` bytes kept for the header, and the
+/// offset of its data region.
+fn new_header_region(password: &str, params: &NewVault, reserve: u64) -> Result<(Vec<u8>, u64)> {
+    let master_key = SecretKey::new(random_bytes::<KEY_LEN>());
+    let NewVault {
+        m_cost_kib,
+        t_cost,
+        p_cost,
+        padding,
+        part_size,
+        chunk_size,
+        cipher_suite,
+        keyfile,
+        recovery_code,
+    } = params.clone();
+
+    let mut header = Header {
+        magic: *MAGIC,
+        version: VERSION,
+        cipher_suite: cipher_suite.id(),
+        kdf: Kdf::DEFAULT.id(),
+        kdf_m_cost_kib: m_cost_kib,
+        kdf_t_cost: t_cost,
+        kdf_p_cost: p_cost,
+        salt: random_bytes::<16>(),
+        mk_wrap_nonce: vec![],
+        wrapped_master_key: vec![],
+        meta_nonce: vec![],
+        meta_len: 0,
+        meta_cipher: vec![],
+        header_reserved: reserve,
+        padding,
+        part_size,
+        chunk_size: chunk_size as u32,
+        keyfile: keyfile.is_some(),
+        recovery_slots: vec![],
+        decoy: Some(Decoy::cover(cipher_suite)?),
+    };
+    header.chunk_size()?;
+    wrap_master_key(&mut header, password, keyfile.as_deref(), &master_key)?;
+    if let Some(key) = &recovery_code {
+        set_key_slot(&mut header, SlotKind::Code, random_bytes::<8>(), key, &master_key, vec![])?;
+    }
+
+    let region = seal_metadata(&mut header, &master_key, &Metadata::new_empty())?;
+    let data_start = data_start(region.len() as u64 - 4, &header);
+    Ok((region, data_start))
+}
+
+fn write_new_vault(mut f: Volume, region: &[u8], data_start: u64) -> Result<()> {
+    write_region(&mut f, region, data_start)?;
+    write_backup(&mut f, data_start, region)?;
+    f.sync_all()?;
+    Ok(())
+}
+
+/// `VaultError::WrongPassword` for a vault that didn't open with `credential`.
+fn unlock_error(credential: &'static str, detail: VaultError) -> VaultError {
+    VaultError::WrongPassword { credential, detail: Box::new(detail) }
+}
+
+/// Burns the time of a key derivation at the default costs, for a header that fails
+/// before its own derivation would run.
+fn derive_dummy_kek(password: &str) {
+    let _ = crate::crypto::derive_kek(
+        Kdf::DEFAULT,
+        password,
+        &[0u8; 16],
+        crate::crypto::DEFAULT_M_COST_KIB,
+        crate::crypto::DEFAULT_T_COST,
+        1,
+    );
+}
+
+fn check_header(header: &Header) -> Result<()> {
+    if &header.magic != MAGIC {
+        return Err(VaultError::corrupted("bad magic"));
+    }
+    if !(1..=VERSION).contains(&header.version) {
+        return Err(VaultError::Unsupported(format!("unsupported version {}", header.version)));
+    }
+    Ok(())
+}
+
+/// Unwraps the master key and decrypts metadata from one header copy.
+fn unlock(path: &str, header: &Header, password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<Session> {
+    let derived = check_header(header).and_then(|()| Ok((header.suite()?, header.derive_kek(password, keyfile)?)));
+    let (suite, kek) = derived.inspect_err(|_| derive_dummy_kek(password))?;
+    // Both KEKs are always derived, so the time it takes doesn't tell which of the
+    // two passwords was given, or whether the vault has a duress password at all.
+    let decoy = header_decoy(header);
+    let duress_kek = match &decoy {
+        Some(decoy) => Some(header.derive_kek_salted(password, &decoy.salt, keyfile)?),
+        None => None,
+    };
+    let mk_plain = match aead_decrypt(suite, &kek, &header.mk_wrap_nonce, &wrap_aad(header), &header.wrapped_master_key) {
+        Ok(mk_plain) => mk_plain,
+        Err(e) => {
+            if let (Some(decoy), Some(duress_kek)) = (&decoy, &duress_kek) {
+                if let Ok(decoy_key) = unwrap_decoy_key(decoy, duress_kek) {
+                    return open_decoy(path, header, decoy, decoy_key, keyfile);
+                }
+            }
+            // Decrypting the metadata as well, in vain, keeps a wrong password as slow
+            // as damaged metadata.
+            let _ = aead_decrypt(suite, &kek, &header.meta_nonce, &meta_aad(header), &header.meta_cipher);
+            return Err(e.wrapped("master key unwrap failed"));
+        }
+    };
+
+    if mk_plain.len() != KEY_LEN {
+        return Err(VaultError::corrupted("invalid master key length"));
+    }
+    let mut master_key = SecretKey::new([0u8; KEY_LEN]);
+    master_key.copy_from_slice(&mk_plain);
+    crate::crypto::zeroize_vec(mk_plain);
+    open_with_master_key(path, header, master_key, keyfile)
+}
+
+/// Decrypts the metadata with an unwrapped master key.
+fn open_with_master_key(
+    path: &str,
+    header: &Header,
+    master_key: SecretKey,
+    keyfile: Option<&[u8; KEY_LEN]>,
+) -> Result<Session> {
+    let suite = header.suite()?;
+    let chunk_size = header.chunk_size()?;
+    if header.version >= 3 && header.meta_len as usize != header.meta_cipher.len() {
+        return Err(VaultError::corrupted("corrupted header: metadata length mismatch"));
+    }
+    let meta_plain = aead_decrypt(suite, &master_key, &header.meta_nonce, &meta_aad(header), &header.meta_cipher)
+        .context("metadata auth failed (wrong password or corrupted vault)")?;
+    let meta = decode_metadata(&meta_plain)?;
+    Ok(Session {
+        path: path.to_string(),
+        suite,
+        padding: header.padding,
+        chunk_size,
+        master_key,
+        meta,
+        read_only: false,
+        keyfile: keyfile.copied().map(SecretKey::new),
+        slot: None,
+        decoy: false,
+        txn: None,
+        history: History::default(),
+        counter: None,
+        _lock: None,
+        backend: None,
+    })
+}
+
+/// Opens the decoy tree of the duress slot. To its user the session is one opened
+/// with the password, but it writes to the decoy tree only.
+fn open_decoy(
+    path: &str,
+    header: &Header,
+    decoy: &Decoy,
+    decoy_key: SecretKey,
+    keyfile: Option<&[u8; KEY_LEN]>,
+) -> Result<Session> {
+    let meta = unseal_decoy_tree(header.suite()?, decoy, &decoy_key)
+        .context("metadata auth failed (wrong password or corrupted vault)")?;
+    Ok(Session {
+        path: path.to_string(),
+        suite: header.suite()?,
+        padding: header.padding,
+        chunk_size: header.chunk_size()?,
+        master_key: decoy_key,
+        meta,
+        read_only: false,
+        keyfile: keyfile.copied().map(SecretKey::new),
+        slot: None,
+        decoy: true,
+        txn: None,
+        history: History::default(),
+        counter: None,
+        _lock: None,
+        backend: None,
+    })
+}
+
+fn lock_path(path: &str) -> String {
+    format!("{path}.lock")
+}
+
+/// Takes the advisory write lock on the vault (a sidecar file, since the vault itself
+/// is replaced by rename during compaction).
+fn lock_vault(path: &str) -> Result<File> {
+    let f = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(path))
+        .with_context(|| format!("create lock file for {path}"))?;
+    match f.try_lock() {
+        Ok(()) => Ok(f),
+        Err(std::fs::TryLockError::WouldBlock) => {
+            Err(VaultError::Locked)
+        }
+        Err(std::fs::TryLockError::Error(e)) => Err(e).context("lock vault"),
+    }
+}
+
+/// Opens the vault for writing. Fails if another process has it open. `keyfile` is
+/// the keyfile digest for a vault created with one.
+pub fn open_vault(path: &str, password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<Session> {
+    let lock = lock_vault(path)?;
+    recover(path).with_context(|| format!("recover {path}"))?;
+    let mut sess = open_session(Place::file(path), password, keyfile, true)?;
+    sess._lock = Some(lock);
+    Ok(sess)
+}
+
+/// Opens the vault in `backend` for writing. Its journal, lock and counter files are
+/// kept at `path` on the local disk (`<path>.journal`, ...): another process with the
+/// same `path` can't open it at the same time, but one elsewhere with another `path`
+/// isn't stopped.
+pub fn open_vault_in(backend: Arc<dyn Backend>, path: &str, password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<Session> {
+    let lock = lock_vault(path)?;
+    let place = Place { path, backend: Some(&backend) };
+    recover_at(place).with_context(|| format!("recover {path}"))?;
+    let mut sess = open_session(place, password, keyfile, true)?;
+    sess._lock = Some(lock);
+    sess.backend = Some(backend);
+    Ok(sess)
+}
+
+/// Opens the vault read-only, without taking the write lock: works on write-protected
+/// media and on a vault another process has open. An unfinished operation is left to
+/// its owner instead of being recovered.
+pub fn open_vault_readonly(path: &str, password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<Session> {
+    let mut sess = open_session(Place::file(path), password, keyfile, false)?;
+    sess.read_only = true;
+    Ok(sess)
+}
+
+/// Opens a vault read-only from `backend` instead of a file, e.g. one a web page holds
+/// in memory. The session's `path` is empty and no file next to the vault is read
+/// or written, so an unfinished operation is neither seen nor recovered.
+pub fn open_vault_backend(backend: Arc<dyn Backend>, password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<Session> {
+    let place = Place {
+        path: "",
+        backend: Some(&backend),
+    };
+    let mut sess = open_session(place, password, keyfile, false)?;
+    sess.read_only = true;
+    sess.backend = Some(backend);
+    Ok(sess)
+}
+
+/// Unlocks the primary header, falling back to the backup copy. With `repair` a
+/// damaged primary is rewritten from the backup.
+fn open_session(place: Place, password: &str, keyfile: Option<&[u8; KEY_LEN]>, repair: bool) -> Result<Session> {
+    let path = place.path;
+    let mut f = place.open(false).with_context(|| format!("open {path}"))?;
+    let primary = read_primary_header(&mut f);
+    let primary_bytes = primary.as_ref().ok().and_then(|(_, h)| serde_cbor::to_vec(h).ok());
+    let failed = |e| unlock_error(PASSWORD_CREDENTIAL, e);
+
+    let sess = match primary.and_then(|(_, header)| unlock(path, &header, password, keyfile)) {
+        Ok(v) => v,
+        Err(e) => {
+            // Primary header is damaged: try the backup copy at the end of the file.
+            let Some((data_start, header)) = read_backup_header(&mut f).ok().flatten() else {
+                if primary_bytes.is_none() {
+                    derive_dummy_kek(password);
+                }
+                return Err(failed(e));
+            };
+            let header_bytes = serde_cbor::to_vec(&header)?;
+            if primary_bytes.as_ref() == Some(&header_bytes) {
+                // Identical copy fails the same way (e.g. wrong password).
+                return Err(failed(e));
+            }
+            let Ok(v) = unlock(path, &header, password, keyfile) else {
+                return Err(failed(e));
+            };
+            drop(f);
+
+            // Best effort repair of the primary; opening must still work on read-only media.
+            if repair {
+                let mut region = (header_bytes.len() as u32).to_le_bytes().to_vec();
+                region.extend_from_slice(&header_bytes);
+                let _ = write_header_region(place, &region, data_start);
+            }
+            v
+        }
+    };
+    Ok(sess)
+}
+
+/// Writes the metadata of `sess` (deferred to `Session::commit` inside a transaction).
+pub fn save_metadata(sess: &mut Session, password: &str) -> Result<()> {
+    sess.ensure_writable()?;
+    if sess.txn.is_some() {
+        return Ok(());
+    }
+    let (data_start, data_len, header) = header_for_write(sess, password)?;
+    write_header(sess, header, data_start, data_len)
+}
+
+/// Reads the header for a metadata write: unwraps the master key again with
+/// `password` (MVP: keeps format simple) and moves an older header to the current
+/// format. Returns the data region offset and length with it.
+fn header_for_write(sess: &Session, password: &str) -> Result<(u64, u64, Header)> {
+    let mut f = sess.volume()?;
+    let (data_start, mut header) = read_header(&mut f)?;
+    check_password(sess, &header, password)?;
+    let data_len = data_len(&mut f, data_start)?;
+    drop(f);
+
+    if needs_upgrade(&header)? {
+        if sess.slot.is_some() || sess.decoy {
+            return Err(VaultError::Unsupported(UPGRADE_REFUSED.into()));
+        }
+        upgrade_header(sess, &mut header)?;
+        wrap_master_key(&mut header, password, sess.keyfile.as_deref(), &sess.master_key)?;
+    }
+    Ok((data_start, data_len, header))
+}
+
+/// Seals the metadata of `sess` into `header` and writes it through the journal.
+fn write_header(sess: &mut Session, mut header: Header, data_start: u64, data_len: u64) -> Result<()> {
+    sess.meta.generation += 1;
+    sess.meta.assign_uuids();
+    let region = match sess.decoy {
+        true => seal_decoy(&mut header, &sess.master_key, &sess.meta)?,
+        // the decoy tree is left as it is
+        false => {
+            header.padding = sess.padding;
+            seal_metadata(&mut header, &sess.master_key, &sess.meta)?
+        }
+    };
+    journal::commit(&sess.path, data_start, data_len, region.clone())?;
+    write_header_region(sess.place(), &region, data_start)?;
+    journal::finish(&sess.path)?;
+    record_generation(sess)
+}
+
+/// Wraps the master key under `key` in the key slot of `kind`, replacing an older
+/// slot of that kind (whose key then no longer opens the vault).
+pub fn add_key_slot(
+    sess: &mut Session,
+    password: &str,
+    kind: SlotKind,
+    id: [u8; 8],
+    key: &[u8; KEY_LEN],
+) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    set_key_slot(&mut header, kind, id, key, &sess.master_key, vec![])?;
+    write_header(sess, header, data_start, data_len)
+}
+
+fn set_key_slot(
+    header: &mut Header,
+    kind: SlotKind,
+    id: [u8; 8],
+    key: &[u8; KEY_LEN],
+    master_key: &[u8; KEY_LEN],
+    kem_ciphertext: Vec<u8>,
+) -> Result<()> {
+    let suite = header.suite()?;
+    let nonce = suite.random_nonce();
+    let slot_key = hkdf_derive(key, b"recovery:slot")?;
+    let wrapped = aead_encrypt(suite, &slot_key, &nonce, &slot_aad(header, kind.id(), &id), master_key)?;
+    header.recovery_slots.retain(|s| s.kind != kind.id());
+    header.recovery_slots.push(KeySlot {
+        kind: kind.id(),
+        id,
+        nonce,
+        wrapped_master_key: wrapped,
+        kem_ciphertext,
+        decoy: None,
+        tpm: None,
+    });
+    Ok(())
+}
+
+/// Removes the key slots of `kinds`; their keys no longer open the vault. Returns
+/// false if there were none.
+pub fn remove_key_slots(sess: &mut Session, password: &str, kinds: &[SlotKind]) -> Result<bool> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    let count = header.recovery_slots.len();
+    header.recovery_slots.retain(|s| kinds.iter().all(|k| k.id() != s.kind));
+    if header.recovery_slots.len() == count {
+        return Ok(false);
+    }
+    write_header(sess, header, data_start, data_len)?;
+    Ok(true)
+}
+
+/// Unwraps the master key from the slot of `kind`; `id`, if given, must be the slot's.
+fn unwrap_slot(header: &Header, kind: SlotKind, id: Option<&[u8; 8]>, key: &[u8; KEY_LEN]) -> Result<SecretKey> {
+    let slot = header
+        .recovery_slots
+        .iter()
+        .find(|s| s.kind == kind.id())
+        .ok_or_else(|| VaultError::invalid("the vault has no key slot of this kind"))?;
+    if id.is_some_and(|id| &slot.id != id) {
+        return Err(VaultError::invalid("this recovery key belongs to an older recovery set of the vault"));
+    }
+    let slot_key = hkdf_derive(key, b"recovery:slot")?;
+    let mk_plain = aead_decrypt(header.suite()?, &slot_key, &slot.nonce, &slot_aad(header, slot.kind, &slot.id), &slot.wrapped_master_key)
+        .context("wrong key for this key slot or corrupted header")?;
+    let master_key = mk_plain.as_slice().try_into().map(SecretKey::new).map_err(|_| VaultError::corrupted("invalid master key length"));
+    crate::crypto::zeroize_vec(mk_plain);
+    master_key
+}
+
+/// Opens the vault with the key of a key slot instead of the password; `id`, if
+/// given, must be the slot's. Metadata writes are authorized by the slot; the
+/// password can't be changed except by `reset_password` and `rekey` is refused.
+/// `read_only` is as in `open_vault_readonly`.
+pub fn open_vault_slot(
+    path: &str,
+    kind: SlotKind,
+    id: Option<&[u8; 8]>,
+    key: &[u8; KEY_LEN],
+    read_only: bool,
+) -> Result<Session> {
+    let lock = match read_only {
+        true => None,
+        false => {
+            let lock = lock_vault(path)?;
+            recover(path).with_context(|| format!("recover {path}"))?;
+            Some(lock)
+        }
+    };
+    let mut f = Volume::open(path, false).with_context(|| format!("open {path}"))?;
+    let opened = read_header(&mut f).and_then(|(_, header)| {
+        check_header(&header)?;
+        let master_key = unwrap_slot(&header, kind, id, key)?;
+        open_with_master_key(path, &header, master_key, None)
+    });
+    drop(f);
+    let mut sess = opened.map_err(|e| unlock_error(kind.credential(), e))?;
+    sess.slot = Some((kind, SecretKey::new(*key)));
+    sess.read_only = read_only;
+    sess._lock = lock;
+    Ok(sess)
+}
+
+/// Enrolls a hardware token (see `crypto::token`): a new token slot, with `pin`
+/// mixed in if given, replaces any earlier one. `remove_key_slots` with `TOKEN_SLOTS`
+/// revokes it.
+pub fn add_token_slot(sess: &mut Session, password: &str, command: Option<&str>, pin: Option<&str>) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    let id = random_bytes::<8>();
+    let mut response = token::respond(command, &token::challenge(&id))?;
+    let key = token::slot_key(&response, pin, &id, header.kdf_m_cost_kib, header.kdf_t_cost, header.kdf_p_cost());
+    response.zeroize();
+    let key = key?;
+    let kind = match pin {
+        Some(_) => SlotKind::TokenWithPin,
+        None => SlotKind::Token,
+    };
+    header.recovery_slots.retain(|s| TOKEN_SLOTS.iter().all(|k| k.id() != s.kind));
+    set_key_slot(&mut header, kind, id, &key, &sess.master_key, vec![])?;
+    write_header(sess, header, data_start, data_len)
+}
+
+/// Opens the vault with the enrolled hardware token (and its PIN, if it was enrolled
+/// with one) instead of the password.
+pub fn open_vault_token(path: &str, command: Option<&str>, pin: Option<&str>, read_only: bool) -> Result<Session> {
+    let mut f = Volume::open(path, false).with_context(|| format!("open {path}"))?;
+    let header = read_header(&mut f).and_then(|(_, header)| check_header(&header).map(|()| header));
+    drop(f);
+    let header = header.map_err(|e| unlock_error(SlotKind::Token.credential(), e))?;
+    let slot = header
+        .recovery_slots
+        .iter()
+        .find(|s| TOKEN_SLOTS.iter().any(|k| k.id() == s.kind))
+        .ok_or_else(|| VaultError::invalid("no token is enrolled for this vault"))?;
+    let kind = match slot.kind == SlotKind::TokenWithPin.id() {
+        true if pin.is_none() => return Err(VaultError::invalid("the token was enrolled with a PIN")),
+        true => SlotKind::TokenWithPin,
+        false => SlotKind::Token,
+    };
+    let mut response = token::respond(command, &token::challenge(&slot.id))?;
+    let pin = pin.filter(|_| kind == SlotKind::TokenWithPin);
+    let key = token::slot_key(&response, pin, &slot.id, header.kdf_m_cost_kib, header.kdf_t_cost, header.kdf_p_cost());
+    response.zeroize();
+    open_vault_slot(path, kind, Some(&slot.id), &*key?, read_only)
+}
+
+/// Adds the post-quantum key slot for the key pair of `seed` (see `crypto::pq`),
+/// replacing an earlier one, whose key file then no longer opens the vault.
+pub fn add_pq_slot(sess: &mut Session, password: &str, seed: &pq::Seed) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    let (ciphertext, key) = pq::encapsulate(seed)?;
+    let id = random_bytes::<8>();
+    set_key_slot(&mut header, SlotKind::PostQuantum, id, &key, &sess.master_key, ciphertext)?;
+    write_header(sess, header, data_start, data_len)
+}
+
+/// Opens the vault with the key file of its post-quantum key slot instead of the
+/// password.
+pub fn open_vault_pq(path: &str, seed: &pq::Seed, read_only: bool) -> Result<Session> {
+    let mut f = Volume::open(path, false).with_context(|| format!("open {path}"))?;
+    let header = read_header(&mut f).and_then(|(_, header)| check_header(&header).map(|()| header));
+    drop(f);
+    let header = header.map_err(|e| unlock_error(SlotKind::PostQuantum.credential(), e))?;
+    let slot = header
+        .recovery_slots
+        .iter()
+        .find(|s| s.kind == SlotKind::PostQuantum.id())
+        .ok_or_else(|| VaultError::invalid("the vault has no post-quantum key slot"))?;
+    let key = pq::decapsulate(seed, &slot.kem_ciphertext).map_err(|e| unlock_error(SlotKind::PostQuantum.credential(), e))?;
+    open_vault_slot(path, SlotKind::PostQuantum, Some(&slot.id), &key, read_only)
+}
+
+/// Seals a new slot key to this machine's TPM and the current values of `pcrs` (see
+/// `crypto::tpm`), replacing an earlier TPM slot.
+pub fn add_tpm_slot(sess: &mut Session, password: &str, pcrs: &str) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    let key = SecretKey::new(random_bytes());
+    let sealed = tpm::seal(pcrs, &key)?;
+    let id = random_bytes::<8>();
+    set_key_slot(&mut header, SlotKind::Tpm, id, &key, &sess.master_key, vec![])?;
+    if let Some(slot) = header.recovery_slots.iter_mut().find(|s| s.kind == SlotKind::Tpm.id()) {
+        slot.tpm = Some(sealed);
+    }
+    write_header(sess, header, data_start, data_len)
+}
+
+/// Opens the vault with the key its TPM slot has sealed, without a password. Fails on
+/// another machine or after a change of the boot state the key is sealed to.
+pub fn open_vault_tpm(path: &str, read_only: bool) -> Result<Session> {
+    let mut f = Volume::open(path, false).with_context(|| format!("open {path}"))?;
+    let header = read_header(&mut f).and_then(|(_, header)| check_header(&header).map(|()| header));
+    drop(f);
+    let header = header.map_err(|e| unlock_error(SlotKind::Tpm.credential(), e))?;
+    let (slot, sealed) = header
+        .recovery_slots
+        .iter()
+        .find_map(|s| s.tpm.as_ref().filter(|_| s.kind == SlotKind::Tpm.id()).map(|t| (s, t)))
+        .ok_or_else(|| VaultError::invalid("the vault has no TPM key slot"))?;
+    let key = tpm::unseal(sealed)?;
+    open_vault_slot(path, SlotKind::Tpm, Some(&slot.id), &key, read_only)
+}
+
+/// Sets up a duress password: opening the vault with it shows a decoy tree instead of
+/// the real one, empty until filled through a session opened with that password.
+/// Replaces an earlier duress password along with its decoy tree.
+pub fn set_duress_password(sess: &mut Session, password: &str, duress_password: &str) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    let suite = header.suite()?;
+    let kek = header.derive_kek(duress_password, sess.keyfile.as_deref())?;
+    if aead_decrypt(suite, &kek, &header.mk_wrap_nonce, &wrap_aad(&header), &header.wrapped_master_key).is_ok() {
+        return Err(VaultError::invalid("the duress password must differ from the password"));
+    }
+
+    let salt = random_bytes::<16>();
+    let duress_kek = header.derive_kek_salted(duress_password, &salt, sess.keyfile.as_deref())?;
+    let decoy_key = SecretKey::new(random_bytes());
+    let mut decoy = Decoy {
+        id: random_bytes(),
+        salt,
+        wrap_suite: suite.id(),
+        wrapped_key: vec![],
+        sealed_key: seal(suite, &*decoy_wrap_key(&sess.master_key)?, b"decoy key", &*decoy_key)?,
+        meta: vec![],
+    };
+    wrap_decoy_key(&mut decoy, salt, &duress_kek, &decoy_key)?;
+    header.decoy = Some(decoy);
+    store_decoy(&mut header, Some((&decoy_key, &Metadata::new_empty())))?;
+    write_header(sess, header, data_start, data_len)
+}
+
+/// Removes the duress password and its decoy tree. Returns whether there was one.
+pub fn remove_duress_password(sess: &mut Session, password: &str) -> Result<bool> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.ensure_real_tree(password)?;
+    let (data_start, data_len, mut header) = header_for_write(sess, password)?;
+    if open_decoy_tree(sess, &header)?.is_none() {
+        return Ok(false);
+    }
+    header.decoy = Some(Decoy::cover(sess.suite)?);
+    write_header(sess, header, data_start, data_len)?;
+    Ok(true)
+}
+
+/// Wraps the master key of `sess` under a new password (and keyfile, if given) with a
+/// new salt. The old password is not checked: the session already holds the key.
+pub fn reset_password(sess: &mut Session, new_password: &str, keyfile: Option<&[u8; KEY_LEN]>) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    let mut f = sess.volume()?;
+    let (data_start, mut header) = read_header(&mut f)?;
+    let data_len = data_len(&mut f, data_start)?;
+    drop(f);
+
+    if sess.decoy {
+        // The duress password is changed instead, in the duress slot.
+        if header.keyfile != keyfile.is_some() || needs_upgrade(&header)? {
+            return Err(VaultError::Unsupported(UPGRADE_REFUSED.into()));
+        }
+        let mut decoy = header_decoy(&header).ok_or_else(|| VaultError::corrupted("corrupted header"))?;
+        let salt = random_bytes::<16>();
+        let kek = header.derive_kek_salted(new_password, &salt, keyfile)?;
+        wrap_decoy_key(&mut decoy, salt, &kek, &sess.master_key)?;
+        header.decoy = Some(decoy);
+        sess.keyfile = keyfile.copied().map(SecretKey::new);
+        return write_header(sess, header, data_start, data_len);
+    }
+    upgrade_header(sess, &mut header)?;
+    header.salt = random_bytes::<16>();
+    header.keyfile = keyfile.is_some();
+    wrap_master_key(&mut header, new_password, keyfile, &sess.master_key)?;
+    sess.keyfile = keyfile.copied().map(SecretKey::new);
+    sess.slot = None;
+    write_header(sess, header, data_start, data_len)
+}
+
+/// Wraps the master key under `new_password` after checking `old_password` (with the
+/// session's keyfile, which stays required). The other key slots keep opening the
+/// vault; only the password changes.
+pub fn change_password(sess: &mut Session, old_password: &str, new_password: &str) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    let mut f = sess.volume()?;
+    let (_, header) = read_header(&mut f)?;
+    drop(f);
+    // A session opened with another slot holds no password to compare with.
+    let mk_plain = unwrap_with_password(sess, &header, old_password).map_err(|e| unlock_error(PASSWORD_CREDENTIAL, e))?;
+    let matches = mk_plain.as_slice() == sess.master_key.as_slice();
+    crate::crypto::zeroize_vec(mk_plain);
+    if !matches {
+        return Err(VaultError::corrupted("master key mismatch"));
+    }
+    let keyfile = sess.keyfile.as_deref().copied().map(SecretKey::new);
+    reset_password(sess, new_password, keyfile.as_deref())
+}
+
+/// From now on records the generation of `sess` in the counter file `path` after
+/// every metadata write (see `counter`). Returns the generation recorded there if it
+/// is newer than the vault's: the vault may have been replaced by an older copy.
+pub fn track_generation(sess: &mut Session, path: &str) -> Result<Option<u64>> {
+    // The counter file is sealed under the master key and tracks the real tree.
+    if sess.decoy {
+        return Ok(None);
+    }
+    let known = counter::load(path, &sess.path, sess.suite, &sess.master_key)?.unwrap_or(0);
+    sess.counter = Some((path.to_string(), known));
+    if known > sess.meta.generation {
+        return Ok(Some(known));
+    }
+    record_generation(sess)?;
+    Ok(None)
+}
+
+/// Updates the counter file, never lowering the generation recorded there.
+fn record_generation(sess: &mut Session) -> Result<()> {
+    if let Some((path, known)) = &mut sess.counter {
+        *known = (*known).max(sess.meta.generation);
+        counter::store(path, &sess.path, sess.suite, &sess.master_key, *known)?;
+    }
+    Ok(())
+}
+
+/// Chunk indexes are `u32`, so a file holds at most `u32::MAX` chunks.
+const MAX_FILE_CHUNKS: u64 = u32::MAX as u64;
+
+fn ensure_file_fits(size: u64, chunk_size: u64) -> Result<()> {
+    let max = MAX_FILE_CHUNKS * chunk_size;
+    if size > max {
+        return Err(VaultError::invalid(format!("file too large: at most {max} bytes with {chunk_size}-byte chunks")));
+    }
+    Ok(())
+}
+
+/// Reads until `buf` is full or EOF, so every chunk but the last has the full size.
+fn read_full(src: &mut (impl Read + ?Sized), buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match src.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Opens the vault for appending chunks after the data region and records the
+/// rollback point in the journal (already done by `Session::begin` in a transaction).
+/// Returns the file positioned at the end of the data region and the data region offset.
+fn begin_append(sess: &Session) -> Result<(Volume, u64)> {
+    let mut vf = sess.place().open(true)?;
+    let (data_start, _) = read_header(&mut vf)?;
+    let data_end = data_end(&mut vf, data_start)?;
+    if sess.txn.is_none() {
+        journal::begin(&sess.path, data_start, data_end - data_start)?;
+    }
+    // Chunks go where the backup header was; it is rewritten by save_metadata.
+    vf.set_len(data_end)?;
+    vf.seek(SeekFrom::End(0))?;
+    Ok((vf, data_start))
+}
+
+/// Cleans up after a failed append: the appended chunks are rolled back unless the
+/// metadata write was already committed. Returns whether the operation went through.
+/// Inside a transaction nothing is rolled back here: the chunks stay unreferenced
+/// until the transaction ends.
+fn abort_append(sess: &Session) -> Result<bool> {
+    if sess.txn.is_some() {
+        return Ok(false);
+    }
+    let committed = journal::load(&sess.path)?.is_some_and(|j| j.header.is_some());
+    recover_at(sess.place())?;
+    Ok(committed)
+}
+
+/// Seals a file's data key for `Node::data_keys`.
+fn seal_data_key(suite: CipherSuite, master_key: &[u8; KEY_LEN], owner: u64, key: &[u8; KEY_LEN]) -> Result<Vec<u8>> {
+    let wrap_key = hkdf_derive(master_key, b"file keys")?;
+    seal(suite, &wrap_key, format!("file key:{owner}").as_bytes(), key)
+}
+
+/// A fresh random data key for file `owner`, sealed.
+fn new_data_key(sess: &Session, owner: u64) -> Result<Vec<u8>> {
+    seal_data_key(sess.suite, &sess.master_key, owner, &SecretKey::new(random_bytes()))
+}
+
+/// Data key of file `owner` from its sealed form; empty stands for the key derived
+/// from the master key, which files written before per-file keys use.
+fn open_data_key(sess: &Session, owner: u64, sealed: &[u8]) -> Result<SecretKey> {
+    unseal_data_key(sess.suite, &sess.master_key, owner, sealed)
+}
+
+/// `open_data_key` for a tree sealed under `master_key`.
+fn unseal_data_key(suite: CipherSuite, master_key: &[u8; KEY_LEN], owner: u64, sealed: &[u8]) -> Result<SecretKey> {
+    if sealed.is_empty() {
+        return hkdf_derive(master_key, format!("file:{owner}").as_bytes());
+    }
+    let wrap_key = hkdf_derive(master_key, b"file keys")?;
+    let plain = unseal(suite, &wrap_key, format!("file key:{owner}").as_bytes(), sealed)
+        .with_context(|| format!("data key of file {owner} is corrupted"))?;
+    let key = plain.as_slice().try_into().map(SecretKey::new).map_err(|_| VaultError::corrupted("invalid data key length"));
+    crate::crypto::zeroize_vec(plain);
+    key
+}
+
+/// Data key of file `owner` for chunks held by `node`.
+fn data_key(sess: &Session, node: &Node, owner: u64) -> Result<SecretKey> {
+    open_data_key(sess, owner, node.data_keys.get(&owner).map_or(&[], Vec::as_slice))
+}
+
+/// Encrypts chunks of one file and appends them after the data region.
+/// Key of chunk `index` of a file. The random salt of a STREAM chunk is mixed in, so
+/// every write of a chunk gets its own key and the shorter random nonce prefix left
+/// by the 12-byte suites never repeats under one key.
+fn chunk_key(file_key: &[u8; KEY_LEN], index: u32, salt: &[u8]) -> Result<SecretKey> {
+    let mut info = format!("chunk:{index}").into_bytes();
+    if !salt.is_empty() {
+        info.push(b':');
+        info.extend_from_slice(salt);
+    }
+    hkdf_derive(file_key, &info)
+}
+
+/// Checks that the chunks of a file written as a STREAM come in order with nothing
+/// missing at the end: chunk `i` carries counter `i` and only the last one has the
+/// last-chunk flag. Since the nonce is part of the decryption, a chunk that decrypts
+/// is where it was written. Files written before STREAM chunks have none.
+fn check_stream(chunks: &[ChunkRef]) -> Result<()> {
+    if !chunks.iter().any(ChunkRef::is_stream) {
+        return Ok(());
+    }
+    for (i, ch) in chunks.iter().enumerate() {
+        let suffix = stream_suffix(i as u32 + 1, i + 1 == chunks.len());
+        if !ch.is_stream() || ch.index as usize != i + 1 || !ch.nonce.ends_with(&suffix) {
+            return Err(VaultError::corrupted(format!("chunk {} is out of place in the file's stream", ch.index)));
+        }
+    }
+    Ok(())
+}
+
+struct ChunkSink {
+    vf: Volume,
+    data_start: u64,
+    suite: CipherSuite,
+    padding: Padding,
+    file_id: u64,
+    file_key: SecretKey,
+}
+
+impl ChunkSink {
+    fn new(sess: &Session, vf: Volume, data_start: u64, file_id: u64, file_key: SecretKey) -> Self {
+        Self {
+            vf,
+            data_start,
+            suite: sess.suite,
+            padding: sess.padding,
+            file_id,
+            file_key,
+        }
+    }
+
+    /// Appends chunk `index`. With `compress` the plaintext is zstd-compressed first
+    /// (kept as is when that doesn't make it smaller). `last` seals it as a STREAM
+    /// segment, flagged as the file's last chunk or not; `None` writes a chunk with a
+    /// random nonce, for partly rewriting files from before STREAM chunks.
+    fn append(&mut self, index: u32, data: &[u8], compress: bool, last: Option<bool>) -> Result<ChunkRef> {
+        let (salt, nonce) = match last {
+            Some(last) => (random_bytes::<16>().to_vec(), self.suite.stream_nonce(index, last)),
+            None => (vec![], self.suite.random_nonce()),
+        };
+        let chunk_key = chunk_key(&self.file_key, index, &salt)?;
+        let aad = format!("{}:{index}", self.file_id).into_bytes();
+
+        let packed = match compress {
+            true => Some(zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)?).filter(|z| z.len() < data.len()),
+            false => None,
+        };
+        let (plain, compression) = match &packed {
+            Some(z) => (z.as_slice(), Compression::Zstd),
+            None => (data, Compression::None),
+        };
+        let pad_len = self.padding.padded_len(plain.len()) - plain.len();
+        let cipher = if pad_len == 0 {
+            aead_encrypt(self.suite, &chunk_key, &nonce, &aad, plain)?
+        } else {
+            let mut padded = Vec::with_capacity(plain.len() + pad_len);
+            padded.extend_from_slice(plain);
+            padded.resize(plain.len() + pad_len, 0);
+            let cipher = aead_encrypt(self.suite, &chunk_key, &nonce, &aad, &padded);
+            crate::crypto::zeroize_vec(padded);
+            cipher?
+        };
+
+        let offset = self.vf.stream_position()?;
+        self.vf.write_all(&cipher)?;
+        Ok(ChunkRef {
+            index,
+            offset: offset - self.data_start,
+            len: u32::try_from(cipher.len()).map_err(|_| VaultError::invalid("chunk too large"))?,
+            nonce,
+            compression,
+            plain_len: u32::try_from(data.len()).map_err(|_| VaultError::invalid("chunk too large"))?,
+            pad_len: pad_len as u32,
+            hash: Some(merkle::leaf_hash(&cipher)),
+            key_id: 0,
+            salt,
+        })
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.vf.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Plaintext imported between two progress checkpoints (see `staging`).
+const CHECKPOINT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Told how `import_file`, `import_dir` and `export_file` go, for a progress bar:
+/// which file is copied, then after every chunk how many more bytes of it are done.
+pub trait Progress {
+    /// Copying of `path` (the source of an import, the target of an export) of
+    /// `len` bytes starts.
+    fn file(&mut self, path: &Path, len: u64);
+
+    /// Returning false cancels the operation: it fails with `VaultError::Cancelled` and is
+    /// rolled back as after any other error.
+    fn advance(&mut self, bytes: u64) -> bool;
+}
+
+/// No progress shown, never cancelled.
+impl Progress for () {
+    fn file(&mut self, _: &Path, _: u64) {}
+
+    fn advance(&mut self, _: u64) -> bool {
+        true
+    }
+}
+
+/// Imports a file from the OS. With `compress`, each chunk is zstd-compressed before
+/// encryption (kept uncompressed when that doesn't make it smaller). When versioning
+/// is on, importing over an existing file replaces its contents and keeps the old
+/// ones as a version.
+///
+/// Outside a transaction, progress of a large import is checkpointed, and importing
+/// the same unchanged source to the same place again resumes after the last
+/// checkpoint (see `staging`); that goes for a cancelled import too.
+pub fn import_file(
+    sess: &mut Session,
+    password: &str,
+    os_path: &Path,
+    parent_id: u64,
+    name_in_vault: Option<String>,
+    compress: bool,
+    progress: &mut dyn Progress,
+) -> Result<u64> {
+    sess.ensure_writable()?;
+    let name = name_in_vault
+        .or_else(|| os_path.file_name().map(|s| s.to_string_lossy().to_string()))
+        .ok_or_else(|| VaultError::invalid("cannot determine filename"))?;
+    let name = sess.meta.normalize_name(&name)?;
+
+    let existing = sess.meta.child_named(parent_id, &name).map(|n| (n.id, n.node_type));
+    let replace = match existing {
+        Some((id, NodeType::File)) if sess.meta.versioning => Some(id),
+        _ => {
+            sess.meta.ensure_can_create(parent_id, &name)?;
+            None
+        }
+    };
+
+    let mut src = File::open(os_path)?;
+    let src_meta = src.metadata()?;
+    ensure_file_fits(src_meta.len(), sess.chunk_size as u64)?;
+
+    // Append after the data region (MVP: no freelist reuse)
+    let (mut vf, data_start) = begin_append(sess)?;
+    let data_len = vf.stream_position()? - data_start;
+
+    let backup = sess.meta.clone();
+    let file_id = replace.unwrap_or_else(|| sess.meta.alloc_id());
+    let mut stage = StagedImport {
+        file_id,
+        parent_id,
+        name,
+        source: std::fs::canonicalize(os_path)?.to_string_lossy().to_string(),
+        source_len: src_meta.len(),
+        source_mtime_ns: src_meta
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64),
+        chunk_size: sess.chunk_size,
+        size: 0,
+        chunks: vec![],
+        data_key: match replace {
+            Some(id) => sess.meta.get_node(id).and_then(|n| n.data_keys.get(&id).cloned()).unwrap_or_default(),
+            None => new_data_key(sess, file_id)?,
+        },
+    };
+    // Chunks of a staged import past the data region were dropped by `compact`/`rekey`.
+    let resumed = match sess.txn {
+        Some(_) => None,
+        None => staging::load(&sess.path, sess.suite, &sess.master_key)?
+            .filter(|s| s.same_import(&stage))
+            .filter(|s| s.chunks.iter().all(|ch| ch.offset + ch.len as u64 <= data_len)),
+    };
+    let mut staged = resumed.is_some();
+    if let Some(s) = resumed {
+        stage.file_id = s.file_id;
+        stage.size = s.size;
+        stage.chunks = s.chunks;
+        stage.data_key = s.data_key;
+    }
+    let mut head = vec![0u8; crate::mime::HEAD_LEN];
+    let head_len = read_full(&mut src, &mut head)?;
+    let mime = crate::mime::detect(&head[..head_len], &stage.name);
+    head.zeroize();
+    src.seek(SeekFrom::Start(0))?;
+    // The digest covers the source as read; a resumed import reads the part already
+    // stored once more for it.
+    let mut sha256 = Sha256::new();
+    std::io::copy(&mut (&mut src).take(stage.size), &mut sha256)?;
+    if src.stream_position()? != stage.size {
+        return Err(VaultError::invalid(format!("{} shrank since the import was interrupted", os_path.display())));
+    }
+    progress.file(os_path, src_meta.len());
+
+    let res = (|| -> Result<()> {
+        if !progress.advance(stage.size) {
+            return Err(VaultError::Cancelled);
+        }
+        let file_key = open_data_key(sess, stage.file_id, &stage.data_key)?;
+        let mut sink = ChunkSink::new(sess, vf, data_start, stage.file_id, file_key);
+
+        // An import resumed from before STREAM chunks goes on the old way.
+        let stream = stage.chunks.iter().all(ChunkRef::is_stream);
+        let mut buf = vec![0u8; sess.chunk_size];
+        let mut next = vec![0u8; sess.chunk_size];
+        let mut idx = stage.chunks.len() as u32;
+        let mut checkpoint = stage.size;
+        let mut n = read_full(&mut src, &mut buf)?;
+        while n > 0 {
+            // Reading one chunk ahead tells which one is the last; the source may have
+            // grown since the size check.
+            let ahead = read_full(&mut src, &mut next)?;
+            idx = idx.checked_add(1).ok_or_else(|| VaultError::invalid("file too large"))?;
+            stage.size += n as u64;
+            sha256.update(&buf[..n]);
+            stage.chunks.push(sink.append(idx, &buf[..n], compress, stream.then_some(ahead == 0))?);
+            std::mem::swap(&mut buf, &mut next);
+            if !progress.advance(n as u64) {
+                return Err(VaultError::Cancelled);
+            }
+            n = ahead;
+
+            if sess.txn.is_none() && stage.size - checkpoint >= CHECKPOINT_BYTES {
+                // Keep the chunks so far on rollback before recording them.
+                sink.sync()?;
+                journal::begin(&sess.path, data_start, sink.vf.stream_position()? - data_start)?;
+                staging::store(&sess.path, sess.suite, &sess.master_key, &stage)?;
+                checkpoint = stage.size;
+                staged = true;
+            }
+        }
+        let mut thumbnail = match mime.as_deref() {
+            Some(mime) if stage.size <= crate::thumb::MAX_SOURCE_BYTES => {
+                src.seek(SeekFrom::Start(0))?;
+                crate::thumb::render(std::io::BufReader::new(&mut src), mime)
+                    .map(|jpeg| sink.append(0, &jpeg, false, Some(true)))
+                    .transpose()?
+            }
+            _ => None,
+        };
+        sink.sync()?;
+        drop(sink);
+
+        let mut chunks = std::mem::take(&mut stage.chunks);
+        if stage.file_id != file_id {
+            // the staged id was given to another node since the import was interrupted
+            for ch in chunks.iter_mut().chain(&mut thumbnail) {
+                ch.key_id = stage.file_id;
+            }
+        }
+
+        // record in metadata
+        let sha256 = Some(sha256.finalize().into());
+        match replace {
+            Some(id) => {
+                sess.meta.set_contents(id, stage.size, chunks, sha256)?;
+            }
+            None => {
+                sess.meta.add_file(file_id, parent_id, stage.name.clone(), stage.size, chunks, sha256)?;
+            }
+        }
+        let source_mtime = src_meta.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+        sess.meta.set_times(file_id, crate::fsmeta::now(), source_mtime.map(|d| d.as_secs()))?;
+        sess.meta.set_mime(file_id, mime)?;
+        if !stage.data_key.is_empty() {
+            sess.meta.set_data_key(file_id, stage.file_id, stage.data_key.clone())?;
+        }
+        sess.meta.set_thumbnail(file_id, thumbnail)?;
+        index_for_search(sess, file_id)?;
+        save_metadata(sess, password)
+    })();
+
+    if let Err(e) = res {
+        if !abort_append(sess)? {
+            sess.meta = backup;
+            return Err(e);
+        }
+    }
+    if staged {
+        staging::clear(&sess.path)?;
+    }
+    Ok(file_id)
+}
+
+/// Imports directory `os_dir` with everything below it as a new directory in
+/// `parent_id`, file by file with `import_file`; symbolic links are skipped. Run it in
+/// a transaction for all or nothing. Returns the id of the new directory.
+pub fn import_dir(
+    sess: &mut Session,
+    password: &str,
+    os_dir: &Path,
+    parent_id: u64,
+    compress: bool,
+    progress: &mut dyn Progress,
+) -> Result<u64> {
+    let name = os_dir
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .ok_or_else(|| VaultError::invalid("cannot determine directory name"))?;
+    let dir_id = sess.meta.mkdir(parent_id, name)?;
+    let mut entries = std::fs::read_dir(os_dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for e in entries {
+        let path = e.path();
+        let file_type = e.file_type()?;
+        if file_type.is_dir() {
+            import_dir(sess, password, &path, dir_id, compress, progress)?;
+        } else if file_type.is_file() {
+            import_file(sess, password, &path, dir_id, None, compress, progress).with_context(|| path.display().to_string())?;
+        }
+    }
+    save_metadata(sess, password)?;
+    Ok(dir_id)
+}
+
+/// Writes `data` at `offset` of an existing file, growing it if needed (a gap past
+/// the old end is zero-filled). Only the affected chunks are re-encrypted and
+/// appended; the old ones go to the freelist, or stay in a version with versioning on.
+pub fn write_file(sess: &mut Session, password: &str, file_id: u64, offset: u64, data: &[u8]) -> Result<()> {
+    sess.ensure_writable()?;
+    let n = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?;
+    if n.node_type != NodeType::File {
+        return Err(VaultError::NotAFile);
+    }
+    if data.is_empty() {
+        return Ok(());
+    }
+    let old_size = n.size;
+    let old_chunks = n.chunks.clone();
+    let compress = old_chunks.iter().any(|c| c.compression == Compression::Zstd);
+
+    let end = offset + data.len() as u64;
+    let new_size = old_size.max(end);
+    // Files keep the chunk size they were written with.
+    let cs = match old_chunks.len() {
+        0 | 1 => sess.chunk_size as u64,
+        _ => old_chunks[0].plaintext_len(),
+    };
+    ensure_file_fits(new_size, cs)?;
+    // Files from before STREAM chunks keep random nonces until fully rewritten. In a
+    // STREAM file the old last chunk loses its last-chunk flag when the file grows.
+    let stream = old_chunks.iter().all(ChunkRef::is_stream);
+    let mut first = offset.min(old_size) / cs;
+    if stream && new_size > old_size && !old_chunks.is_empty() {
+        first = first.min(old_chunks.len() as u64 - 1);
+    }
+    let last = (end - 1) / cs;
+    let count = new_size.div_ceil(cs);
+
+    let file_key = data_key(sess, n, file_id)?;
+    let mut reader = VaultFileReader::open(sess, file_id)?;
+    let (vf, data_start) = begin_append(sess)?;
+    let backup = sess.meta.clone();
+
+    let res = (|| -> Result<()> {
+        let mut sink = ChunkSink::new(sess, vf, data_start, file_id, file_key);
+        let mut chunks = old_chunks.clone();
+
+        for ci in first..=last {
+            let chunk_start = ci * cs;
+            let chunk_len = (new_size - chunk_start).min(cs) as usize;
+
+            let mut plain = vec![0u8; chunk_len];
+            if let Some(old) = old_chunks.get(ci as usize) {
+                reader.seek(SeekFrom::Start(chunk_start))?;
+                let have = (old.plaintext_len() as usize).min(chunk_len);
+                reader.read_exact(&mut plain[..have])?;
+            }
+
+            // Overlay the part of `data` that falls into this chunk.
+            let from = offset.max(chunk_start);
+            let to = end.min(chunk_start + chunk_len as u64);
+            if from < to {
+                plain[(from - chunk_start) as usize..(to - chunk_start) as usize]
+                    .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+            }
+
+            let ch = sink.append((ci + 1) as u32, &plain, compress, stream.then_some(ci + 1 == count))?;
+            plain.zeroize();
+            match chunks.get_mut(ci as usize) {
+                Some(slot) => *slot = ch,
+                None => chunks.push(ch),
+            }
+        }
+        sink.sync()?;
+        drop(sink);
+
+        // Hashing the new contents would mean reading all of them.
+        sess.meta.set_contents(file_id, new_size, chunks, None)?;
+        index_for_search(sess, file_id)?;
+        save_metadata(sess, password)
+    })();
+
+    if let Err(e) = res {
+        if !abort_append(sess)? {
+            sess.meta = backup;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the contents of an existing file with `data`, re-encrypting all of it;
+/// unlike `write_file` the file may also shrink. The old chunks go to the freelist, or
+/// stay in a version with versioning on. The MIME type is kept.
+pub fn replace_file(sess: &mut Session, password: &str, file_id: u64, data: &[u8]) -> Result<()> {
+    ensure_file_fits(data.len() as u64, sess.chunk_size as u64)?;
+    replace_file_from(sess, password, file_id, &mut &data[..]).map(drop)
+}
+
+/// `replace_file` with the new contents read from `src` up to its end, a chunk at a
+/// time, so they needn't fit in memory (an upload of unknown length, a pipe). Returns
+/// the new size.
+pub fn replace_file_from(sess: &mut Session, password: &str, file_id: u64, src: &mut dyn Read) -> Result<u64> {
+    sess.ensure_writable()?;
+    let n = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?;
+    if n.node_type != NodeType::File {
+        return Err(VaultError::NotAFile);
+    }
+    let compress = n.chunks.iter().any(|c| c.compression == Compression::Zstd);
+    let cs = sess.chunk_size;
+
+    let file_key = data_key(sess, n, file_id)?;
+    let (vf, data_start) = begin_append(sess)?;
+    let backup = sess.meta.clone();
+
+    let res = (|| -> Result<u64> {
+        let mut sink = ChunkSink::new(sess, vf, data_start, file_id, file_key);
+        let mut sha256 = Sha256::new();
+        let mut chunks = vec![];
+        let mut size = 0u64;
+        let mut buf = vec![0u8; cs];
+        let mut next = vec![0u8; cs];
+        let mut len = read_full(src, &mut buf)?;
+        while len > 0 {
+            // Reading one chunk ahead tells which one is the last.
+            let ahead = read_full(src, &mut next)?;
+            size += len as u64;
+            ensure_file_fits(size, cs as u64)?;
+            sha256.update(&buf[..len]);
+            chunks.push(sink.append(chunks.len() as u32 + 1, &buf[..len], compress, Some(ahead == 0))?);
+            std::mem::swap(&mut buf, &mut next);
+            len = ahead;
+        }
+        buf.zeroize();
+        next.zeroize();
+        sink.sync()?;
+        drop(sink);
+
+        sess.meta.set_contents(file_id, size, chunks, Some(sha256.finalize().into()))?;
+        index_for_search(sess, file_id)?;
+        save_metadata(sess, password)?;
+        Ok(size)
+    })();
+
+    match res {
+        Ok(size) => Ok(size),
+        Err(e) => match abort_append(sess)? {
+            true => Ok(sess.meta.get_node(file_id).map_or(0, |n| n.size)),
+            false => {
+                sess.meta = backup;
+                Err(e)
+            }
+        },
+    }
+}
+
+/// Creates an empty file of type `mime` in `parent_id`, e.g. a note to be written
+/// afterwards with `replace_file`.
+pub fn create_file(sess: &mut Session, password: &str, parent_id: u64, name: &str, mime: Option<String>) -> Result<u64> {
+    sess.ensure_writable()?;
+    let backup = sess.meta.clone();
+    let res = (|| -> Result<u64> {
+        let id = sess.meta.alloc_id();
+        sess.meta.add_file(id, parent_id, name.to_string(), 0, vec![], Some(Sha256::digest(b"").into()))?;
+        sess.meta.set_mime(id, mime)?;
+        let sealed_key = new_data_key(sess, id)?;
+        sess.meta.set_data_key(id, id, sealed_key)?;
+        save_metadata(sess, password)?;
+        Ok(id)
+    })();
+    if res.is_err() {
+        sess.meta = backup;
+    }
+    res
+}
+
+/// Appends `data` to the end of an existing file.
+pub fn append_file(sess: &mut Session, password: &str, file_id: u64, data: &[u8]) -> Result<()> {
+    let size = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?.size;
+    write_file(sess, password, file_id, size, data)
+}
+
+/// Reads, decrypts and decompresses one chunk encrypted under the keys of `file_id`
+/// (see `ChunkRef::key_owner`).
+fn read_chunk(
+    suite: CipherSuite,
+    vf: &mut Volume,
+    data_start: u64,
+    file_id: u64,
+    file_key: &[u8; KEY_LEN],
+    ch: &ChunkRef,
+) -> Result<Vec<u8>> {
+    let chunk_key = chunk_key(file_key, ch.index, &ch.salt)?;
+    let aad = format!("{file_id}:{}", ch.index).into_bytes();
+
+    vf.seek(SeekFrom::Start(data_start + ch.offset))?;
+    let mut cipher = vec![0u8; ch.len as usize];
+    vf.read_exact(&mut cipher)?;
+    let mut plain = aead_decrypt(suite, &chunk_key, &ch.nonce, &aad, &cipher)?;
+    let stored_len = plain.len().checked_sub(ch.pad_len as usize).ok_or_else(|| VaultError::corrupted("bad chunk padding"))?;
+    plain.truncate(stored_len);
+
+    match ch.compression {
+        Compression::None => Ok(plain),
+        Compression::Zstd => {
+            let out = zstd::decode_all(plain.as_slice())?;
+            crate::crypto::zeroize_vec(plain);
+            Ok(out)
+        }
+    }
+}
+
+/// Streaming `Read + Seek` access to a file in the vault. Chunks are decrypted on
+/// demand and only the current one is kept in memory.
+pub struct VaultFileReader {
+    vf: Volume,
+    suite: CipherSuite,
+    data_start: u64,
+    file_id: u64,
+    // keys of every file whose chunks this file uses (itself, clone sources)
+    file_keys: HashMap<u64, SecretKey>,
+    chunks: Vec<ChunkRef>,
+    // plaintext offset of each chunk, plus the total length at the end
+    starts: Vec<u64>,
+    pos: u64,
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl VaultFileReader {
+    pub fn open(sess: &Session, file_id: u64) -> Result<Self> {
+        let n = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?;
+        if n.node_type != NodeType::File {
+            return Err(VaultError::NotAFile);
+        }
+
+        check_stream(&n.chunks)?;
+        let mut vf = sess.volume()?;
+        let (data_start, _) = read_header(&mut vf)?;
+
+        let mut starts = Vec::with_capacity(n.chunks.len() + 1);
+        let mut total = 0u64;
+        for ch in &n.chunks {
+            starts.push(total);
+            total += ch.plaintext_len();
+        }
+        starts.push(total);
+
+        let mut file_keys = HashMap::new();
+        for owner in n.chunks.iter().map(|ch| ch.key_owner(file_id)) {
+            if let std::collections::hash_map::Entry::Vacant(e) = file_keys.entry(owner) {
+                e.insert(data_key(sess, n, owner)?);
+            }
+        }
+
+        Ok(Self {
+            vf,
+            suite: sess.suite,
+            data_start,
+            file_id,
+            file_keys,
+            chunks: n.chunks.clone(),
+            starts,
+            pos: 0,
+            cached: None,
+        })
+    }
+
+    /// Plaintext length of the file.
+    pub fn len(&self) -> u64 {
+        *self.starts.last().unwrap_or(&0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Read for VaultFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len() {
+            return Ok(0);
+        }
+        let ci = self.starts.partition_point(|&s| s <= self.pos) - 1;
+
+        if self.cached.as_ref().map(|(i, _)| *i) != Some(ci) {
+            if let Some((_, mut old)) = self.cached.take() {
+                old.zeroize();
+            }
+            let ch = &self.chunks[ci];
+            let owner = ch.key_owner(self.file_id);
+            let plain = read_chunk(
+                self.suite,
+                &mut self.vf,
+                self.data_start,
+                owner,
+                &self.file_keys[&owner],
+                ch,
+            )
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            self.cached = Some((ci, plain));
+        }
+
+        let chunk = &self.cached.as_ref().expect("chunk just cached").1;
+        let in_chunk = (self.pos - self.starts[ci]) as usize;
+        let avail = chunk.len().saturating_sub(in_chunk);
+        if avail == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "chunk shorter than recorded"));
+        }
+        let n = avail.min(buf.len());
+        buf[..n].copy_from_slice(&chunk[in_chunk..in_chunk + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for VaultFileReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len().checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        let Some(target) = target else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start"));
+        };
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+impl Drop for VaultFileReader {
+    fn drop(&mut self) {
+        if let Some((_, buf)) = self.cached.as_mut() {
+            buf.zeroize();
+        }
+    }
+}
+
+pub fn read_file_bytes(sess: &Session, file_id: u64) -> Result<Vec<u8>> {
+    let mut reader = VaultFileReader::open(sess, file_id)?;
+    let mut out_bytes = Vec::with_capacity(reader.len() as usize);
+    reader.read_to_end(&mut out_bytes)?;
+    Ok(out_bytes)
+}
+
+/// Up to `len` bytes of file `file_id` from `offset`, fewer at the end of the file;
+/// only the chunks they fall in are decrypted.
+pub fn read_file_range(sess: &Session, file_id: u64, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut reader = VaultFileReader::open(sess, file_id)?;
+    let len = reader.len().saturating_sub(offset).min(len as u64) as usize;
+    let mut out = vec![0u8; len];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut out)?;
+    Ok(out)
+}
+
+/// Writes file `file_id` to `out_path`, with the modification time of the imported
+/// file (or of the last write in the vault). If the digest recorded at import doesn't
+/// match what was written, or the export fails or is cancelled midway, the output is
+/// deleted again.
+pub fn export_file(sess: &Session, file_id: u64, out_path: &Path, progress: &mut dyn Progress) -> Result<()> {
+    let node = sess.meta.get_node(file_id);
+    let expected = node.and_then(|n| n.sha256);
+    let mtime = node.and_then(|n| n.source_mtime).or(node.map(|n| n.modified_at)).filter(|&t| t != 0);
+    let mut reader = VaultFileReader::open(sess, file_id)?;
+    let mut out = OpenOptions::new().create(true).truncate(true).write(true).open(out_path)?;
+    progress.file(out_path, reader.len());
+    let mut sha256 = Sha256::new();
+    let mut buf = vec![0u8; sess.chunk_size];
+    let res = (|| -> Result<()> {
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            out.write_all(&buf[..n])?;
+            sha256.update(&buf[..n]);
+            if !progress.advance(n as u64) {
+                return Err(VaultError::Cancelled);
+            }
+        }
+        out.flush()?;
+        if let Some(secs) = mtime {
+            out.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))?;
+        }
+        Ok(())
+    })();
+    crate::crypto::zeroize_vec(buf);
+    drop(out);
+    let res = res.and_then(|()| match expected.is_some_and(|h| h != <Hash>::from(sha256.finalize())) {
+        true => Err(VaultError::corrupted("the exported data doesn't match the SHA-256 recorded at import")),
+        false => Ok(()),
+    });
+    if res.is_err() {
+        let _ = std::fs::remove_file(out_path);
+    }
+    res
+}
+
+/// SHA-256 of the current contents of file `file_id`, decrypted for it.
+pub fn file_sha256(sess: &Session, file_id: u64) -> Result<Hash> {
+    let mut reader = VaultFileReader::open(sess, file_id)?;
+    let mut sha256 = Sha256::new();
+    std::io::copy(&mut reader, &mut sha256)?;
+    Ok(sha256.finalize().into())
+}
+
+/// SHA-256 of every file outside the trash that has no recorded one (see
+/// `Metadata::duplicates`), decrypting them.
+pub fn missing_sha256(sess: &Session) -> Result<HashMap<u64, Hash>> {
+    let ids: Vec<u64> = sess
+        .meta
+        .nodes
+        .iter()
+        .filter(|n| n.node_type == NodeType::File && n.sha256.is_none() && n.size > 0)
+        .filter(|n| !sess.meta.is_in_trash(n.id))
+        .map(|n| n.id)
+        .collect();
+    ids.into_iter()
+        .map(|id| Ok((id, file_sha256(sess, id).with_context(|| sess.meta.path_of(id))?)))
+        .collect()
+}
+
+/// The JPEG thumbnail of `file_id`, if it has one.
+pub fn read_thumbnail(sess: &Session, file_id: u64) -> Result<Option<Vec<u8>>> {
+    let n = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?;
+    let Some(ch) = &n.thumbnail else {
+        return Ok(None);
+    };
+    let owner = ch.key_owner(file_id);
+    let mut vf = sess.volume()?;
+    let (data_start, _) = read_header(&mut vf)?;
+    let key = data_key(sess, n, owner)?;
+    Ok(Some(read_chunk(sess.suite, &mut vf, data_start, owner, &key, ch)?))
+}
+
+/// Makes thumbnails for the images that have none (imported before thumbnails were
+/// made, or rewritten in place), decrypting them. Returns the number made.
+pub fn make_thumbnails(sess: &mut Session, password: &str) -> Result<usize> {
+    sess.ensure_writable()?;
+    let mut made: Vec<(u64, Vec<u8>)> = vec![];
+    for n in &sess.meta.nodes {
+        let Some(mime) = n.mime.as_deref().filter(|m| crate::mime::is_viewable_image(m)) else {
+            continue;
+        };
+        if n.node_type != NodeType::File || n.thumbnail.is_some() || n.size > crate::thumb::MAX_SOURCE_BYTES {
+            continue;
+        }
+        let reader = VaultFileReader::open(sess, n.id).with_context(|| sess.meta.path_of(n.id))?;
+        if let Some(jpeg) = crate::thumb::render(std::io::BufReader::new(reader), mime) {
+            made.push((n.id, jpeg));
+        }
+    }
+    if made.is_empty() {
+        return Ok(0);
+    }
+
+    let (vf, data_start) = begin_append(sess)?;
+    let backup = sess.meta.clone();
+    let res = (|| -> Result<()> {
+        let mut vf = vf;
+        let mut thumbnails = vec![];
+        for (id, jpeg) in &made {
+            // Each under the key of its own file, like the file's chunks.
+            let key = data_key(sess, sess.meta.get_node(*id).expect("listed above"), *id)?;
+            let mut sink = ChunkSink::new(sess, vf, data_start, *id, key);
+            thumbnails.push((*id, sink.append(0, jpeg, false, Some(true))?));
+            vf = sink.vf;
+        }
+        vf.sync_all()?;
+        drop(vf);
+        for (id, ch) in thumbnails {
+            sess.meta.set_thumbnail(id, Some(ch))?;
+        }
+        save_metadata(sess, password)
+    })();
+
+    if let Err(e) = res {
+        if !abort_append(sess)? {
+            sess.meta = backup;
+            return Err(e);
+        }
+    }
+    Ok(made.len())
+}
+
+/// Text of file `file_id` for the search index (its first `fts::MAX_INDEXED_BYTES`),
+/// or None if it isn't a text file. Files imported before types were recorded are
+/// told by their first bytes.
+fn search_text(sess: &Session, file_id: u64) -> Result<Option<String>> {
+    let n = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?;
+    if n.size == 0 || n.mime.as_deref().is_some_and(|m| !crate::mime::is_text(m)) {
+        return Ok(None);
+    }
+    let mut reader = VaultFileReader::open(sess, file_id)?;
+    let mut buf = vec![];
+    (&mut reader).take(crate::fts::MAX_INDEXED_BYTES as u64).read_to_end(&mut buf)?;
+    let head = &buf[..buf.len().min(crate::mime::HEAD_LEN)];
+    let text = n
+        .mime
+        .clone()
+        .or_else(|| crate::mime::detect(head, &n.name))
+        .filter(|m| crate::mime::is_text(m))
+        .map(|_| String::from_utf8_lossy(&buf).into_owned());
+    buf.zeroize();
+    Ok(text)
+}
+
+/// Brings the search index, if enabled, up to date with the current contents of
+/// `file_id`. The caller saves the metadata.
+pub fn index_for_search(sess: &mut Session, file_id: u64) -> Result<()> {
+    if sess.meta.fts.is_none() {
+        return Ok(());
+    }
+    let text = search_text(sess, file_id)?;
+    if let Some(fts) = &mut sess.meta.fts {
+        match text {
+            Some(mut text) => {
+                fts.add(file_id, &text);
+                text.zeroize();
+            }
+            None => fts.remove(file_id),
+        }
+    }
+    Ok(())
+}
+
+/// Turns the full-text index on, indexing every text file (again, if it was on
+/// already), or off, dropping it. Returns the number of files indexed.
+pub fn set_search_index(sess: &mut Session, password: &str, enabled: bool) -> Result<usize> {
+    sess.ensure_writable()?;
+    let fts = if enabled {
+        let mut fts = crate::fts::Index::default();
+        let ids: Vec<u64> =
+            sess.meta.nodes.iter().filter(|n| n.node_type == NodeType::File).map(|n| n.id).collect();
+        for id in ids {
+            if let Some(mut text) = search_text(sess, id).with_context(|| sess.meta.path_of(id))? {
+                fts.add(id, &text);
+                text.zeroize();
+            }
+        }
+        Some(fts)
+    } else {
+        None
+    };
+    let count = fts.as_ref().map_or(0, |f| f.len());
+    sess.meta.fts = fts;
+    save_metadata(sess, password)?;
+    Ok(count)
+}
+
+/// Makes version `version` of `file_id` current again (see
+/// `Metadata::restore_version`) and saves.
+pub fn restore_version(sess: &mut Session, password: &str, file_id: u64, version: usize) -> Result<()> {
+    sess.ensure_writable()?;
+    sess.meta.restore_version(file_id, version)?;
+    index_for_search(sess, file_id)?;
+    save_metadata(sess, password)
+}
+
+/// Decrypts every chunk of `file_id`, checking the AEAD tags and the STREAM order,
+/// and compares the total plaintext length with the node size and its SHA-256 with
+/// the one recorded at import. Nothing is written anywhere.
+pub fn verify_file(sess: &Session, file_id: u64) -> Result<()> {
+    let n = sess.meta.get_node(file_id).ok_or(VaultError::NotFound)?;
+    if n.node_type != NodeType::File {
+        return Err(VaultError::NotAFile);
+    }
+    check_stream(&n.chunks)?;
+    let mut vf = sess.volume()?;
+    let (data_start, _) = read_header(&mut vf)?;
+
+    let mut file_keys: HashMap<u64, SecretKey> = HashMap::new();
+    let mut sha256 = Sha256::new();
+    let total = (|| -> Result<u64> {
+        let mut total = 0u64;
+        for ch in &n.chunks {
+            let owner = ch.key_owner(file_id);
+            if let std::collections::hash_map::Entry::Vacant(e) = file_keys.entry(owner) {
+                e.insert(data_key(sess, n, owner)?);
+            }
+            let plain = read_chunk(sess.suite, &mut vf, data_start, owner, &file_keys[&owner], ch)
+                .with_context(|| format!("chunk {}", ch.index))?;
+            total += plain.len() as u64;
+            sha256.update(&plain);
+            crate::crypto::zeroize_vec(plain);
+        }
+        Ok(total)
+    })()?;
+    if total != n.size {
+        return Err(VaultError::corrupted(format!("{total} bytes, expected {}", n.size)));
+    }
+    if n.sha256.is_some_and(|h| h != <Hash>::from(sha256.finalize())) {
+        return Err(VaultError::corrupted("SHA-256 differs from the one recorded at import"));
+    }
+    Ok(())
+}
+
+/// Copies file `src_id` of another vault, or the contents of directory `src_id`
+/// (without versions and the trash), into directory `parent_id` of `dst`. The data is
+/// decrypted chunk by chunk and re-encrypted under the keys of `dst` in its chunk size
+/// and padding. A name that is already taken gets a ` (2)`, ` (3)`, ... suffix; with
+/// `merge`, directories of the same name are merged instead. Runs as one transaction
+/// of `dst`. Returns the number of files copied.
+pub fn copy_from(
+    src: &Session,
+    dst: &mut Session,
+    password: &str,
+    src_id: u64,
+    parent_id: u64,
+    merge: bool,
+    progress: &mut dyn Progress,
+) -> Result<usize> {
+    let node_type = src.meta.get_node(src_id).ok_or(VaultError::NotFound)?.node_type;
+    copy_in_transaction(dst, password, |dst, copied| match node_type {
+        NodeType::File => copy_file_from(src, dst, src_id, parent_id, progress).map(|_| *copied = 1),
+        NodeType::Dir => copy_children_from(src, dst, src_id, parent_id, merge, copied, progress),
+    })
+}
+
+/// Copies nodes `src_ids` of another vault into directory `parent_id` of `dst` as
+/// `copy_from` does, but directories as themselves, with their contents. Taken names
+/// get a suffix; directories are not merged. Returns the number of files copied.
+pub fn copy_nodes_from(
+    src: &Session,
+    dst: &mut Session,
+    password: &str,
+    src_ids: &[u64],
+    parent_id: u64,
+    progress: &mut dyn Progress,
+) -> Result<usize> {
+    copy_in_transaction(dst, password, |dst, copied| {
+        for &id in src_ids {
+            let n = src.meta.get_node(id).ok_or(VaultError::NotFound)?;
+            match n.node_type {
+                NodeType::File => {
+                    copy_file_from(src, dst, id, parent_id, progress)?;
+                    *copied += 1;
+                }
+                NodeType::Dir => {
+                    let name = dst.meta.normalize_name(&n.name).with_context(|| n.name.clone())?;
+                    let dir = dst.meta.mkdir(parent_id, dst.meta.unique_name(parent_id, &name))?;
+                    copy_labels(src, dst, id, dir);
+                    copy_children_from(src, dst, id, dir, false, copied, progress).with_context(|| n.name.clone())?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Runs `op` in a transaction of `dst`, counting the files it copies.
+fn copy_in_transaction(
+    dst: &mut Session,
+    password: &str,
+    op: impl FnOnce(&mut Session, &mut usize) -> Result<()>,
+) -> Result<usize> {
+    dst.begin()?;
+    let mut copied = 0;
+    match op(dst, &mut copied) {
+        Ok(()) => dst.commit(password)?,
+        Err(e) => {
+            dst.rollback()?;
+            return Err(e);
+        }
+    }
+    Ok(copied)
+}
+
+fn copy_children_from(
+    src: &Session,
+    dst: &mut Session,
+    src_dir: u64,
+    dst_dir: u64,
+    merge: bool,
+    copied: &mut usize,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    let children: Vec<(u64, NodeType, String)> = src
+        .meta
+        .children_of(src_dir)
+        .into_iter()
+        .filter(|n| n.id != src.meta.trash_id)
+        .map(|n| (n.id, n.node_type, n.name.clone()))
+        .collect();
+    for (id, node_type, name) in children {
+        match node_type {
+            NodeType::File => {
+                copy_file_from(src, dst, id, dst_dir, progress)?;
+                *copied += 1;
+            }
+            NodeType::Dir => {
+                let dst_name = dst.meta.normalize_name(&name).with_context(|| name.clone())?;
+                let existing = dst
+                    .meta
+                    .name_conflict(dst_dir, &dst_name, None)
+                    .filter(|n| merge && n.node_type == NodeType::Dir)
+                    .map(|n| n.id);
+                let target = match existing {
+                    Some(dir) => dir,
+                    None => {
+                        let dir = dst.meta.mkdir(dst_dir, dst.meta.unique_name(dst_dir, &dst_name))?;
+                        copy_labels(src, dst, id, dir);
+                        dir
+                    }
+                };
+                copy_children_from(src, dst, id, target, merge, copied, progress)
+                    .with_context(|| name.clone())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn copy_file_from(
+    src: &Session,
+    dst: &mut Session,
+    src_id: u64,
+    parent_id: u64,
+    progress: &mut dyn Progress,
+) -> Result<u64> {
+    let n = src.meta.get_node(src_id).ok_or(VaultError::NotFound)?;
+    let name = dst.meta.normalize_name(&n.name).with_context(|| n.name.clone())?;
+    let name = dst.meta.unique_name(parent_id, &name);
+    dst.meta.ensure_can_create(parent_id, &name)?;
+    ensure_file_fits(n.size, dst.chunk_size as u64).with_context(|| n.name.clone())?;
+    let compress = n.chunks.iter().any(|ch| ch.compression == Compression::Zstd);
+    let mut reader = VaultFileReader::open(src, src_id).with_context(|| n.name.clone())?;
+    progress.file(Path::new(&n.name), n.size);
+
+    let (vf, data_start) = begin_append(dst)?;
+    let file_id = dst.meta.alloc_id();
+    let sealed_key = new_data_key(dst, file_id)?;
+    let mut sink = ChunkSink::new(dst, vf, data_start, file_id, open_data_key(dst, file_id, &sealed_key)?);
+    let mut buf = vec![0u8; dst.chunk_size];
+    let mut chunks: Vec<ChunkRef> = vec![];
+    let mut size: u64 = 0;
+    let mut idx: u32 = 0;
+    let mut sha256 = Sha256::new();
+    loop {
+        let n = read_full(&mut reader, &mut buf).with_context(|| name.clone())?;
+        if n == 0 {
+            break;
+        }
+        idx += 1;
+        size += n as u64;
+        sha256.update(&buf[..n]);
+        chunks.push(sink.append(idx, &buf[..n], compress, Some(size == reader.len()))?);
+        if !progress.advance(n as u64) {
+            buf.zeroize();
+            return Err(VaultError::Cancelled);
+        }
+    }
+    buf.zeroize();
+    let thumbnail = match read_thumbnail(src, src_id)? {
+        Some(jpeg) => Some(sink.append(0, &jpeg, false, Some(true))?),
+        None => None,
+    };
+    sink.sync()?;
+    drop(sink);
+    let (modified_at, source_mtime) = (n.modified_at, n.source_mtime);
+    dst.meta.add_file(file_id, parent_id, name, size, chunks, Some(sha256.finalize().into()))?;
+    dst.meta.set_times(file_id, modified_at, source_mtime)?;
+    dst.meta.set_mime(file_id, n.mime.clone())?;
+    copy_labels(src, dst, src_id, file_id);
+    dst.meta.set_data_key(file_id, file_id, sealed_key)?;
+    dst.meta.set_thumbnail(file_id, thumbnail)?;
+    index_for_search(dst, file_id)?;
+    Ok(file_id)
+}
+
+/// Carries the tags and extended attributes of `src_id` over to its copy `dst_id`.
+pub(crate) fn copy_labels(src: &Session, dst: &mut Session, src_id: u64, dst_id: u64) {
+    if let (Some(from), Some(to)) = (src.meta.get_node(src_id), dst.meta.get_node_mut(dst_id)) {
+        to.tags = from.tags.clone();
+        to.xattrs = from.xattrs.clone();
+    }
+}
+
+/// Deletes a node (recursively for directories). With `shred`, the freed ciphertext
+/// is overwritten with random bytes so it can't be decrypted even with the master key.
+pub fn remove_node(sess: &mut Session, password: &str, id: u64, shred: bool) -> Result<()> {
+    sess.ensure_writable()?;
+    let freed = sess.meta.remove_subtree(id)?;
+    release_ranges(sess, password, &freed, shred)
+}
+
+/// Permanently deletes everything in the trash (see `remove_node`).
+pub fn empty_trash(sess: &mut Session, password: &str, shred: bool) -> Result<()> {
+    sess.ensure_writable()?;
+    let freed = sess.meta.empty_trash()?;
+    release_ranges(sess, password, &freed, shred)
+}
+
+fn release_ranges(sess: &mut Session, password: &str, freed: &[FreeRange], shred: bool) -> Result<()> {
+    // Metadata first: a crash before shredding leaves unreferenced ranges, never
+    // metadata pointing at overwritten chunks.
+    save_metadata(sess, password)?;
+    match (shred, sess.txn.as_mut()) {
+        (false, _) => Ok(()),
+        (true, Some(txn)) => {
+            txn.shred.extend_from_slice(freed);
+            Ok(())
+        }
+        (true, None) => shred_ranges(sess.place(), freed),
+    }
+}
+
+/// Overwrites `ranges` of the data region with random bytes.
+fn shred_ranges(place: Place, ranges: &[FreeRange]) -> Result<()> {
+    if !ranges.is_empty() {
+        let mut vf = place.open(true)?;
+        let (data_start, _) = read_header(&mut vf)?;
+        let mut noise = vec![0u8; 1024 * 1024];
+        for r in ranges {
+            vf.seek(SeekFrom::Start(data_start + r.offset))?;
+            let mut left = r.len;
+            while left > 0 {
+                let n = left.min(noise.len() as u64) as usize;
+                rand::thread_rng().fill_bytes(&mut noise[..n]);
+                vf.write_all(&noise[..n])?;
+                left -= n as u64;
+            }
+        }
+        vf.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Outcome of `verify_fast`.
+pub struct AuditReport {
+    pub chunks_checked: usize,
+    /// Chunks written before Merkle hashes were recorded.
+    pub chunks_unhashed: usize,
+    pub problems: Vec<String>,
+}
+
+/// Checks every chunk ciphertext against its Merkle leaf hash and the stored file and
+/// vault roots against those hashes. Nothing is decrypted, so corruption and
+/// truncation are found without touching plaintext.
+pub fn verify_fast(sess: &Session) -> Result<AuditReport> {
+    let mut vf = sess.volume()?;
+    let (data_start, _) = read_header(&mut vf)?;
+    let data_len = data_len(&mut vf, data_start)?;
+
+    let mut report = AuditReport {
+        chunks_checked: 0,
+        chunks_unhashed: 0,
+        problems: vec![],
+    };
+    let mut seen = std::collections::HashSet::new();
+    let mut buf = vec![];
+    for n in sess.meta.nodes.iter().filter(|n| n.node_type == NodeType::File) {
+        for ch in n.all_chunks().filter(|ch| seen.insert(ch.offset)) {
+            let Some(expected) = ch.hash else {
+                report.chunks_unhashed += 1;
+                continue;
+            };
+            report.chunks_checked += 1;
+            if ch.offset + ch.len as u64 > data_len {
+                report.problems.push(format!("id={} chunk {}: past the end of the data (truncated)", n.id, ch.index));
+                continue;
+            }
+            buf.resize(ch.len as usize, 0);
+            vf.seek(SeekFrom::Start(data_start + ch.offset))?;
+            vf.read_exact(&mut buf)?;
+            if merkle::leaf_hash(&buf) != expected {
+                report.problems.push(format!("id={} chunk {}: ciphertext hash mismatch", n.id, ch.index));
+            }
+        }
+        if n.merkle_root != n.compute_merkle_root() {
+            report.problems.push(format!("id={}: Merkle root mismatch", n.id));
+        }
+    }
+    if sess.meta.merkle_root != sess.meta.compute_merkle_root() {
+        report.problems.push("vault Merkle root mismatch".to_string());
+    }
+    Ok(report)
+}
+
+/// Space a vault takes on disk, and how much of it `empty_trash` and `compact` would
+/// give back.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    /// Bytes of the vault file, all its parts together.
+    pub vault: u64,
+    /// Plaintext bytes of the files, the trash included.
+    pub logical: u64,
+    /// Ciphertext bytes of the data region no node refers to: freed chunks (the
+    /// freelist) and leftovers of interrupted writes. `compact` reclaims them.
+    pub free: u64,
+    /// Ciphertext bytes only nodes in the trash refer to: reclaimed by emptying the
+    /// trash and then compacting.
+    pub trash: u64,
+}
+
+pub fn usage(sess: &Session) -> Result<Usage> {
+    let mut f = sess.volume()?;
+    let (data_start, header) = read_header(&mut f)?;
+    let vault = f.len()?;
+    let in_trash = |n: &&Node| sess.meta.is_in_trash(n.id);
+    let outside: HashSet<u64> = sess
+        .meta
+        .nodes
+        .iter()
+        .filter(|n| !in_trash(n))
+        .flat_map(|n| n.all_chunks())
+        .map(|ch| ch.offset)
+        .collect();
+    let mut trash = 0;
+    let mut trashed = HashSet::new();
+    for ch in sess.meta.nodes.iter().filter(in_trash).flat_map(|n| n.all_chunks()) {
+        if !outside.contains(&ch.offset) && trashed.insert(ch.offset) {
+            trash += ch.len as u64;
+        }
+    }
+    // The decoy tree's chunks are live too. A duress session can't see the real tree,
+    // and counting its chunks as free would give the real tree away, so it reports
+    // what `compact` reclaims in it.
+    let free = match sess.decoy {
+        true => {
+            let data_len = data_len(&mut f, data_start)?;
+            data_len - free_tail(&sess.meta.freelist, data_len).0
+        }
+        false => {
+            let decoy = open_decoy_tree(sess, &header)?;
+            let live: HashMap<u64, u32> = sess
+                .meta
+                .nodes
+                .iter()
+                .chain(decoy.iter().flat_map(|(_, m)| m.nodes.iter()))
+                .flat_map(|n| n.all_chunks())
+                .map(|ch| (ch.offset, ch.len))
+                .collect();
+            let live: u64 = live.values().map(|&len| len as u64).sum();
+            data_len(&mut f, data_start)?.saturating_sub(live)
+        }
+    };
+    Ok(Usage {
+        vault,
+        logical: sess.meta.stats().size,
+        free,
+        trash,
+    })
+}
+
+/// Rewrites the data region keeping only chunks referenced by metadata,
+/// packs them back to back, updates every `ChunkRef.offset` and truncates the file.
+/// Returns the number of bytes reclaimed.
+pub fn compact(sess: &mut Session, password: &str) -> Result<u64> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.place().ensure_file()?;
+    if sess.decoy {
+        return trim_decoy(sess, password);
+    }
+    let mut f = sess.volume()?;
+    let (old_data_start, mut header) = read_header(&mut f)?;
+    check_password(sess, &header, password)?;
+    let old_data_len = data_len(&mut f, old_data_start)?;
+    if needs_upgrade(&header)? {
+        set_current_version(&mut header)?;
+        wrap_master_key(&mut header, password, sess.keyfile.as_deref(), &sess.master_key)?;
+    }
+
+    // Distinct live chunks in on-disk order (versions may share chunks), the decoy
+    // tree's included: old offset -> len.
+    let mut decoy = open_decoy_tree(sess, &header)?;
+    let live: BTreeMap<u64, u32> = sess
+        .meta
+        .nodes
+        .iter()
+        .chain(decoy.iter().flat_map(|(_, m)| m.nodes.iter()))
+        .flat_map(|n| n.all_chunks())
+        .map(|ch| (ch.offset, ch.len))
+        .collect();
+
+    let mut moved: HashMap<u64, u64> = HashMap::with_capacity(live.len());
+    let mut new_off: u64 = 0;
+    for (&off, &len) in &live {
+        moved.insert(off, new_off);
+        new_off += len as u64;
+    }
+
+    let mut meta = sess.meta.clone();
+    meta.generation += 1;
+    for ch in meta.nodes.iter_mut().flat_map(|n| n.all_chunks_mut()) {
+        ch.offset = moved[&ch.offset];
+    }
+    meta.freelist.clear();
+    if let Some((_, decoy_meta)) = &mut decoy {
+        for ch in decoy_meta.nodes.iter_mut().flat_map(|n| n.all_chunks_mut()) {
+            ch.offset = moved[&ch.offset];
+        }
+        decoy_meta.freelist.clear();
+    }
+    store_decoy(&mut header, decoy.as_ref().map(|(key, meta)| (&**key, meta)))?;
+
+    let region = seal_metadata(&mut header, &sess.master_key, &meta)?;
+
+    let mut tmp = Volume::create(&tmp_path(&sess.path), f.part_size())?;
+    {
+        let new_data_start = data_start(region.len() as u64 - 4, &header);
+        write_region(&mut tmp, &region, new_data_start)?;
+
+        let mut buf = vec![];
+        for (&off, &len) in &live {
+            buf.resize(len as usize, 0);
+            f.seek(SeekFrom::Start(old_data_start + off))?;
+            f.read_exact(&mut buf)?;
+            tmp.write_all(&buf)?;
+        }
+        write_backup(&mut tmp, new_data_start + new_off, &region)?;
+        tmp.sync_all()?;
+    }
+    drop(f);
+    replace_vault(&sess.path, tmp)?;
+    // the chunks of an interrupted import were not carried over
+    staging::clear(&sess.path)?;
+
+    sess.meta = meta;
+    record_generation(sess)?;
+    Ok(old_data_len.saturating_sub(new_off))
+}
+
+/// Where the data region ends without the ranges of `freelist` at its end, and the
+/// ranges left.
+fn free_tail(freelist: &[FreeRange], data_len: u64) -> (u64, Vec<FreeRange>) {
+    let mut end = data_len;
+    let mut left = freelist.to_vec();
+    while let Some(i) = left.iter().position(|r| r.len > 0 && r.offset + r.len == end) {
+        end = left.swap_remove(i).offset;
+    }
+    (end, left)
+}
+
+/// `compact` in a duress session, which can't move the chunks of the real tree: the
+/// chunks the decoy tree freed at the end of the data region are cut off.
+fn trim_decoy(sess: &mut Session, password: &str) -> Result<u64> {
+    let (data_start, data_len, header) = header_for_write(sess, password)?;
+    let (end, left) = free_tail(&sess.meta.freelist, data_len);
+    if end == data_len {
+        return Ok(0);
+    }
+    let backup = sess.meta.clone();
+    sess.meta.freelist = left;
+    if let Err(e) = write_header(sess, header, data_start, data_len) {
+        sess.meta = backup;
+        return Err(e);
+    }
+    // Dropped like the chunks of an append that never committed.
+    journal::begin(&sess.path, data_start, end)?;
+    recover_at(sess.place())?;
+    Ok(data_len - end)
+}
+
+/// Replaces the master key with a fresh one: every live chunk is re-encrypted under
+/// a new random data key of its file, metadata is resealed and the master key is wrapped
+/// again under a new salt. Like `compact`, the result is written to a temporary file
+/// that then replaces the vault. With `suite` the vault also switches cipher suites.
+/// Returns the number of chunks re-encrypted.
+pub fn rekey(sess: &mut Session, password: &str, suite: Option<CipherSuite>) -> Result<usize> {
+    sess.ensure_writable()?;
+    sess.ensure_no_transaction()?;
+    sess.place().ensure_file()?;
+    if sess.slot.is_some() {
+        return Err(VaultError::invalid("rekey needs the vault opened with its password"));
+    }
+    if sess.decoy {
+        return rekey_decoy(sess, password, suite);
+    }
+    let mut f = sess.volume()?;
+    let (old_data_start, mut header) = read_header(&mut f)?;
+    check_password(sess, &header, password)?;
+    set_current_version(&mut header)?;
+
+    // The decoy tree goes along: its chunks sit among the real ones. It is sealed
+    // with the suite of the vault, so it is re-encrypted the same way.
+    let mut decoy = open_decoy_tree(sess, &header)?;
+    let trees: Vec<(bool, &Metadata)> =
+        [(false, &sess.meta)].into_iter().chain(decoy.iter().map(|(_, m)| (true, m))).collect();
+    let tree_key = |in_decoy: bool| match (in_decoy, &decoy) {
+        (true, Some((decoy_key, _))) => decoy_key,
+        _ => &sess.master_key,
+    };
+
+    // Distinct live chunks in on-disk order: old offset -> (tree, key owner, chunk).
+    // Ids are per tree, hence keys by (tree, owner).
+    let live: BTreeMap<u64, (bool, u64, ChunkRef)> = trees
+        .iter()
+        .flat_map(|&(t, m)| m.nodes.iter().map(move |n| (t, n)))
+        .flat_map(|(t, n)| n.all_chunks().map(move |ch| (ch.offset, (t, ch.key_owner(n.id), ch.clone()))))
+        .collect();
+
+    // Every file gets a new random data key, files from before per-file keys too.
+    let mut old_keys: HashMap<(bool, u64), SecretKey> = HashMap::new();
+    for &(t, m) in &trees {
+        for n in &m.nodes {
+            for owner in n.all_chunks().map(|ch| ch.key_owner(n.id)) {
+                if let std::collections::hash_map::Entry::Vacant(e) = old_keys.entry((t, owner)) {
+                    let sealed = n.data_keys.get(&owner).map_or(&[][..], Vec::as_slice);
+                    e.insert(unseal_data_key(sess.suite, tree_key(t), owner, sealed)?);
+                }
+            }
+        }
+    }
+    let new_keys: HashMap<(bool, u64), SecretKey> = trees
+        .iter()
+        .flat_map(|&(t, m)| m.nodes.iter().filter(|n| n.node_type == NodeType::File).map(move |n| (t, n.id)))
+        .chain(old_keys.keys().copied())
+        .map(|owner| (owner, SecretKey::new(random_bytes())))
+        .collect();
+
+    // Everything is re-encrypted anyway, so this is also where a vault leaves
+    // ChaCha20-Poly1305 with 12-byte random nonces if no suite is asked for.
+    let new_suite = match suite {
+        Some(suite) => suite,
+        None if sess.suite == CipherSuite::ChaCha20Poly1305 => CipherSuite::XChaCha20Poly1305,
+        None => sess.suite,
+    };
+    let new_key = SecretKey::new(random_bytes::<KEY_LEN>());
+    header.salt = random_bytes::<16>();
+    // The slots wrap the old master key and their keys aren't known here. The duress
+    // slot wraps the decoy key instead, which stays; only what the master key seals
+    // changes, and without a duress password the random bytes in its place.
+    header.recovery_slots.clear();
+    header.cipher_suite = new_suite.id();
+    let fresh = Decoy::cover(new_suite)?;
+    if let Some(d) = &mut header.decoy {
+        d.sealed_key = match &decoy {
+            Some((decoy_key, _)) => seal(new_suite, &*decoy_wrap_key(&new_key)?, b"decoy key", &**decoy_key)?,
+            None => fresh.sealed_key,
+        };
+    }
+    wrap_master_key(&mut header, password, sess.keyfile.as_deref(), &new_key)?;
+
+    // Ciphertext lengths don't change, so new offsets, nonces and salts can be fixed
+    // up front. STREAM chunks keep their counter and last-chunk flag.
+    let mut moved: HashMap<u64, (u64, Vec<u8>, Vec<u8>)> = HashMap::with_capacity(live.len());
+    let mut new_off: u64 = 0;
+    for (&off, (_, _, ch)) in &live {
+        let (nonce, salt) = match ch.is_stream() {
+            true => (new_suite.stream_nonce(ch.index, ch.nonce.last() == Some(&1)), random_bytes::<16>().to_vec()),
+            false => (new_suite.random_nonce(), vec![]),
+        };
+        moved.insert(off, (new_off, nonce, salt));
+        new_off += ch.len as u64;
+    }
+    let mut meta = sess.meta.clone();
+    meta.generation += 1;
+    let new_trees: Vec<(bool, &[u8; KEY_LEN], &mut Metadata)> = [(false, &*new_key, &mut meta)]
+        .into_iter()
+        .chain(decoy.iter_mut().map(|(k, m)| (true, &**k, m)))
+        .collect();
+    for (t, key, m) in new_trees {
+        for ch in m.nodes.iter_mut().flat_map(|n| n.all_chunks_mut()) {
+            (ch.offset, ch.nonce, ch.salt) = moved[&ch.offset].clone();
+        }
+        for n in m.nodes.iter_mut().filter(|n| n.node_type == NodeType::File) {
+            let owners: Vec<u64> = n.all_chunks().map(|ch| ch.key_owner(n.id)).chain([n.id]).collect();
+            n.data_keys.clear();
+            for owner in owners {
+                n.data_keys.insert(owner, seal_data_key(new_suite, key, owner, &new_keys[&(t, owner)])?);
+            }
+        }
+        m.freelist.clear();
+    }
+    store_decoy(&mut header, decoy.as_ref().map(|(key, meta)| (&**key, meta)))?;
+
+    // Chunk hashes are only known after encryption, so the chunks are written first
+    // after a header reservation sized from a provisional seal.
+    let estimate = seal_metadata(&mut header, &new_key, &meta)?;
+    header.header_reserved = header.header_reserved.max((2 * estimate.len() as u64).next_multiple_of(4096));
+    let new_data_start = 4 + header.header_reserved;
+
+    let res = (|| -> Result<Volume> {
+        let mut tmp = Volume::create(&tmp_path(&sess.path), f.part_size())?;
+        tmp.seek(SeekFrom::Start(new_data_start))?;
+
+        let mut hashes: HashMap<u64, merkle::Hash> = HashMap::with_capacity(live.len());
+        let mut buf = vec![];
+        for (&off, (t, file_id, ch)) in &live {
+            let aad = format!("{file_id}:{}", ch.index).into_bytes();
+
+            let old_chunk_key = chunk_key(&old_keys[&(*t, *file_id)], ch.index, &ch.salt)?;
+            buf.resize(ch.len as usize, 0);
+            f.seek(SeekFrom::Start(old_data_start + off))?;
+            f.read_exact(&mut buf)?;
+            let plain = aead_decrypt(sess.suite, &old_chunk_key, &ch.nonce, &aad, &buf);
+
+            let (new_off, nonce, salt) = &moved[&off];
+            let new_chunk_key = chunk_key(&new_keys[&(*t, *file_id)], ch.index, salt)?;
+            let cipher = plain.and_then(|p| {
+                let c = aead_encrypt(new_suite, &new_chunk_key, nonce, &aad, &p);
+                crate::crypto::zeroize_vec(p);
+                c
+            })?;
+            hashes.insert(*new_off, merkle::leaf_hash(&cipher));
+            tmp.write_all(&cipher)?;
+        }
+
+        for m in [&mut meta].into_iter().chain(decoy.iter_mut().map(|(_, m)| m)) {
+            for ch in m.nodes.iter_mut().flat_map(|n| n.all_chunks_mut()) {
+                ch.hash = Some(hashes[&ch.offset]);
+            }
+            for n in m.nodes.iter_mut() {
+                n.merkle_root = n.compute_merkle_root();
+            }
+            m.merkle_root = m.compute_merkle_root();
+        }
+        store_decoy(&mut header, decoy.as_ref().map(|(key, meta)| (&**key, meta)))?;
+
+        let region = seal_metadata(&mut header, &new_key, &meta)?;
+        if data_start(region.len() as u64 - 4, &header) != new_data_start {
+            return Err(VaultError::invalid("header outgrew its reservation"));
+        }
+        tmp.seek(SeekFrom::Start(0))?;
+        write_region(&mut tmp, &region, new_data_start)?;
+        write_backup(&mut tmp, new_data_start + new_off, &region)?;
+        tmp.sync_all()?;
+        Ok(tmp)
+    })();
+    let tmp = match res {
+        Ok(tmp) => tmp,
+        Err(e) => {
+            let _ = volume::remove(&tmp_path(&sess.path));
+            return Err(e);
+        }
+    };
+    drop(f);
+    replace_vault(&sess.path, tmp)?;
+    staging::clear(&sess.path)?;
+
+    sess.master_key = new_key;
+    sess.suite = new_suite;
+    sess.meta = meta;
+    // resealed under the new key
+    record_generation(sess)?;
+    Ok(live.len())
+}
+
+/// `rekey` in a duress session, which can neither rewrite the real tree nor wrap the
+/// master key again: the chunks of the decoy tree are re-encrypted under new data keys
+/// and appended, and the ones they replace are shredded and freed.
+fn rekey_decoy(sess: &mut Session, password: &str, suite: Option<CipherSuite>) -> Result<usize> {
+    if let Some(suite) = suite.filter(|&s| s != sess.suite) {
+        return Err(VaultError::Unsupported(format!("can't switch this vault to {suite}")));
+    }
+    header_for_write(sess, password)?;
+    let live: BTreeMap<u64, (u64, ChunkRef)> = sess
+        .meta
+        .nodes
+        .iter()
+        .flat_map(|n| n.all_chunks().map(move |ch| (ch.offset, (ch.key_owner(n.id), ch.clone()))))
+        .collect();
+    let mut old_keys: HashMap<u64, SecretKey> = HashMap::new();
+    for n in &sess.meta.nodes {
+        for owner in n.all_chunks().map(|ch| ch.key_owner(n.id)) {
+            if let std::collections::hash_map::Entry::Vacant(e) = old_keys.entry(owner) {
+                e.insert(data_key(sess, n, owner)?);
+            }
+        }
+    }
+    let new_keys: HashMap<u64, SecretKey> = sess
+        .meta
+        .nodes
+        .iter()
+        .filter(|n| n.node_type == NodeType::File)
+        .map(|n| n.id)
+        .chain(old_keys.keys().copied())
+        .map(|owner| (owner, SecretKey::new(random_bytes())))
+        .collect();
+
+    let (vf, data_start) = begin_append(sess)?;
+    let backup = sess.meta.clone();
+    let res = (|| -> Result<()> {
+        let mut vf = vf;
+        let mut moved: HashMap<u64, (u64, Vec<u8>, Vec<u8>, merkle::Hash)> = HashMap::with_capacity(live.len());
+        let mut buf = vec![];
+        for (&off, (file_id, ch)) in &live {
+            let aad = format!("{file_id}:{}", ch.index).into_bytes();
+            let old_chunk_key = chunk_key(&old_keys[file_id], ch.index, &ch.salt)?;
+            buf.resize(ch.len as usize, 0);
+            vf.seek(SeekFrom::Start(data_start + off))?;
+            vf.read_exact(&mut buf)?;
+            let plain = aead_decrypt(sess.suite, &old_chunk_key, &ch.nonce, &aad, &buf);
+
+            let (nonce, salt) = match ch.is_stream() {
+                true => (sess.suite.stream_nonce(ch.index, ch.nonce.last() == Some(&1)), random_bytes::<16>().to_vec()),
+                false => (sess.suite.random_nonce(), vec![]),
+            };
+            let new_chunk_key = chunk_key(&new_keys[file_id], ch.index, &salt)?;
+            let cipher = plain.and_then(|p| {
+                let c = aead_encrypt(sess.suite, &new_chunk_key, &nonce, &aad, &p);
+                crate::crypto::zeroize_vec(p);
+                c
+            })?;
+            let new_off = vf.seek(SeekFrom::End(0))? - data_start;
+            vf.write_all(&cipher)?;
+            moved.insert(off, (new_off, nonce, salt, merkle::leaf_hash(&cipher)));
+        }
+        vf.sync_all()?;
+        drop(vf);
+
+        for ch in sess.meta.nodes.iter_mut().flat_map(|n| n.all_chunks_mut()) {
+            let (offset, nonce, salt, hash) = moved[&ch.offset].clone();
+            (ch.offset, ch.nonce, ch.salt, ch.hash) = (offset, nonce, salt, Some(hash));
+        }
+        for n in sess.meta.nodes.iter_mut() {
+            if n.node_type == NodeType::File {
+                let owners: Vec<u64> = n.all_chunks().map(|ch| ch.key_owner(n.id)).chain([n.id]).collect();
+                n.data_keys.clear();
+                for owner in owners {
+                    n.data_keys.insert(owner, seal_data_key(sess.suite, &sess.master_key, owner, &new_keys[&owner])?);
+                }
+            }
+            n.merkle_root = n.compute_merkle_root();
+        }
+        sess.meta.merkle_root = sess.meta.compute_merkle_root();
+        sess.meta.freelist.extend(live.iter().map(|(&offset, (_, ch))| FreeRange { offset, len: ch.len as u64 }));
+        save_metadata(sess, password)
+    })();
+
+    if let Err(e) = res {
+        if !abort_append(sess)? {
+            sess.meta = backup;
+            return Err(e);
+        }
+    }
+    let freed: Vec<FreeRange> = live.iter().map(|(&offset, (_, ch))| FreeRange { offset, len: ch.len as u64 }).collect();
+    shred_ranges(sess.place(), &freed)?;
+    Ok(live.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_vault(dir: &Path) -> String {
+        let path = dir.join("vault.dat").to_string_lossy().to_string();
+        let params = NewVault { m_cost_kib: 8192, t_cost: 1, chunk_size: MIN_CHUNK_SIZE, ..NewVault::default() };
+        create_vault(&path, "password", &params).unwrap();
+        path
+    }
+
+    #[test]
+    fn file_size_limit_is_u32_max_chunks() {
+        for cs in [MIN_CHUNK_SIZE as u64, DEFAULT_CHUNK_SIZE as u64, MAX_CHUNK_SIZE as u64] {
+            let max = u32::MAX as u64 * cs;
+            ensure_file_fits(max, cs).unwrap();
+            assert!(matches!(ensure_file_fits(max + 1, cs), Err(VaultError::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn write_refuses_to_grow_a_file_past_the_chunk_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = new_vault(dir.path());
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        let id = put_file(&mut sess, "password", "a.bin", b"abc");
+        drop(sess);
+        let vault_len = std::fs::metadata(&path).unwrap().len();
+
+        // one byte past the last one a file of 4 KiB chunks can hold
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        let end = u32::MAX as u64 * MIN_CHUNK_SIZE as u64;
+        match write_file(&mut sess, "password", id, end, b"x") {
+            Err(VaultError::Invalid(msg)) => assert!(msg.starts_with("file too large"), "{msg}"),
+            other => panic!("expected a size error, got {other:?}"),
+        }
+        assert_eq!(sess.meta.get_node(id).unwrap().size, 3);
+        drop(sess);
+
+        // nothing was written
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), vault_len);
+        let sess = open_vault(&path, "password", None).unwrap();
+        assert_eq!(read_file_bytes(&sess, id).unwrap(), b"abc");
+    }
+
+    /// Writes more than 4 GiB to the temporary directory: `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn import_reads_back_a_sparse_file_past_4_gib() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vault.dat").to_string_lossy().to_string();
+        let cs = 1024 * 1024;
+        let params = NewVault { m_cost_kib: 8192, t_cost: 1, chunk_size: cs, ..NewVault::default() };
+        create_vault(&path, "password", &params).unwrap();
+
+        // sparse but for a few bytes past 4 GiB and at the very end
+        let big = dir.path().join("big.bin");
+        let len = (4u64 << 30) + cs as u64 + 123;
+        let past = (4u64 << 30) + 1000;
+        let mut f = File::create(&big).unwrap();
+        f.set_len(len).unwrap();
+        f.seek(SeekFrom::Start(past)).unwrap();
+        f.write_all(b"past 4 GiB").unwrap();
+        f.seek(SeekFrom::Start(len - 3)).unwrap();
+        f.write_all(b"end").unwrap();
+        drop(f);
+
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        let root = sess.meta.root_id;
+        let id = import_file(&mut sess, "password", &big, root, None, false, &mut ()).unwrap();
+        drop(sess);
+
+        let header = read_vault_header(&path);
+        assert_eq!(header.meta_len as usize, header.meta_cipher.len());
+        let sess = open_vault(&path, "password", None).unwrap();
+        let n = sess.meta.get_node(id).unwrap();
+        assert_eq!(n.size, len);
+        assert_eq!(n.chunks.len() as u64, len.div_ceil(cs as u64));
+        // chunk indexes count from 1, so `u32::MAX` of them fit
+        assert!(n.chunks.iter().enumerate().all(|(i, c)| c.index as usize == i + 1));
+        assert_eq!(n.chunks.iter().map(|c| c.plaintext_len()).sum::<u64>(), len);
+        // stored uncompressed, so the last chunks lie past 4 GiB in the vault too
+        assert!(n.chunks.last().unwrap().offset > u32::MAX as u64);
+
+        assert_eq!(read_file_range(&sess, id, past, 10).unwrap(), b"past 4 GiB");
+        assert_eq!(read_file_range(&sess, id, len - 3, 10).unwrap(), b"end");
+        assert_eq!(read_file_range(&sess, id, past - 4, 4).unwrap(), [0; 4]);
+        let mut reader = VaultFileReader::open(&sess, id).unwrap();
+        reader.seek(SeekFrom::Start(past)).unwrap();
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"past 4 GiB");
+    }
+
+    #[test]
+    fn newer_schema_is_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = new_vault(dir.path());
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        sess.meta.schema_version = crate::fsmeta::SCHEMA_VERSION + 1;
+        assert!(matches!(save_metadata(&mut sess, "password"), Err(VaultError::Unsupported(_))));
+    }
+
+    #[test]
+    fn compact_shrinks_the_vault_and_keeps_the_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = new_vault(dir.path());
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        let data = |seed: u8, len: usize| -> Vec<u8> { (0..len).map(|i| (i as u8).wrapping_mul(31) ^ seed).collect() };
+        let files = [("a.bin", data(1, 3 * MIN_CHUNK_SIZE + 7)), ("b.bin", data(2, 5 * MIN_CHUNK_SIZE)), ("c.bin", data(3, 11))];
+        let ids: Vec<u64> = files.iter().map(|(name, bytes)| put_file(&mut sess, "password", name, bytes)).collect();
+        remove_node(&mut sess, "password", ids[1], false).unwrap();
+        let before = std::fs::metadata(&path).unwrap().len();
+
+        let freed = compact(&mut sess, "password").unwrap();
+        assert!(freed >= 5 * MIN_CHUNK_SIZE as u64);
+        assert_eq!(usage(&sess).unwrap().free, 0);
+        drop(sess);
+        assert!(std::fs::metadata(&path).unwrap().len() <= before - freed);
+
+        let sess = open_vault(&path, "password", None).unwrap();
+        assert!(sess.meta.get_node(ids[1]).is_none());
+        for (&id, (_, bytes)) in ids.iter().zip(&files).filter(|(&id, _)| id != ids[1]) {
+            assert_eq!(&read_file_bytes(&sess, id).unwrap(), bytes);
+            verify_file(&sess, id).unwrap();
+        }
+    }
+
+    /// Vault with one STREAM file of three chunks; `tamper` then edits its chunk refs in
+    /// the saved tree.
+    fn tampered_stream(dir: &Path, tamper: impl FnOnce(&mut Vec<ChunkRef>)) -> (String, u64) {
+        let path = new_vault(dir);
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        let data: Vec<u8> = (0..3 * MIN_CHUNK_SIZE).map(|i| (i / MIN_CHUNK_SIZE) as u8 + 1).collect();
+        let id = put_file(&mut sess, "password", "a.bin", &data);
+        let chunks = &mut sess.meta.get_node_mut(id).unwrap().chunks;
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(ChunkRef::is_stream));
+        tamper(chunks);
+        save_metadata(&mut sess, "password").unwrap();
+        (path, id)
+    }
+
+    /// Gives the refs the counters and last flag of where they now stand, as an edit
+    /// of the tree that gets past `check_stream` would.
+    fn relabel(chunks: &mut [ChunkRef]) {
+        let count = chunks.len();
+        for (i, ch) in chunks.iter_mut().enumerate() {
+            ch.index = i as u32 + 1;
+            let at = ch.nonce.len() - crate::crypto::STREAM_SUFFIX_LEN;
+            ch.nonce[at..].copy_from_slice(&stream_suffix(ch.index, i + 1 == count));
+        }
+    }
+
+    fn assert_unreadable(path: &str, id: u64, what: &str) {
+        let sess = open_vault(path, "password", None).unwrap();
+        let size = sess.meta.get_node(id).unwrap().size;
+        // (a chunk that fails to decrypt inside a read comes back wrapped as an io error)
+        for res in [read_file_bytes(&sess, id), read_file_range(&sess, id, 0, size as usize)] {
+            let msg = res.expect_err("the file read back").to_string();
+            assert!(msg.contains(what), "{msg}");
+        }
+        assert!(verify_file(&sess, id).is_err());
+    }
+
+    #[test]
+    fn swapped_chunks_do_not_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, id) = tampered_stream(dir.path(), |chunks| chunks.swap(0, 1));
+        assert_unreadable(&path, id, "out of place");
+
+        let dir = tempfile::tempdir().unwrap();
+        let (path, id) = tampered_stream(dir.path(), |chunks| {
+            chunks.swap(0, 1);
+            relabel(chunks);
+        });
+        assert_unreadable(&path, id, "aead");
+    }
+
+    #[test]
+    fn dropping_the_last_chunk_does_not_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, id) = tampered_stream(dir.path(), |chunks| {
+            chunks.pop();
+        });
+        assert_unreadable(&path, id, "out of place");
+
+        // chunk 2 flagged last has the nonce of no chunk that was written
+        let dir = tempfile::tempdir().unwrap();
+        let (path, id) = tampered_stream(dir.path(), |chunks| {
+            chunks.pop();
+            relabel(chunks);
+        });
+        assert_unreadable(&path, id, "aead");
+    }
+
+    /// What a write of `new` over file `a.bin` (holding `old`) does to the vault: the
+    /// vault before and after it, and where it was cut off in `cut`.
+    struct CutWrite {
+        path: String,
+        id: u64,
+        before: Vec<u8>,
+        after: Vec<u8>,
+        data_start: u64,
+        /// Length of the data region before the write.
+        data_len: u64,
+        /// Header region the write committed.
+        region: Vec<u8>,
+        /// The vault as the write left it just before the new header went in: the old
+        /// header, the chunks appended to the data region and no backup header.
+        cut: Vec<u8>,
+    }
+
+    fn cut_off_write(dir: &Path, old: &[u8], new: &[u8]) -> CutWrite {
+        let path = new_vault(dir);
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        let id = put_file(&mut sess, "password", "a.bin", old);
+        drop(sess);
+        let before = std::fs::read(&path).unwrap();
+        let (data_start, data_len) = {
+            let mut f = Volume::open(&path, false).unwrap();
+            let (start, _) = read_header(&mut f).unwrap();
+            (start, data_len(&mut f, start).unwrap())
+        };
+
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        replace_file(&mut sess, "password", id, new).unwrap();
+        drop(sess);
+        let after = std::fs::read(&path).unwrap();
+        let region_len = 4 + u32::from_le_bytes(after[..4].try_into().unwrap()) as usize;
+        let region = after[..region_len].to_vec();
+        // the header kept its reservation, so the data region didn't move
+        assert_eq!(read_header(&mut Volume::open(&path, false).unwrap()).unwrap().0, data_start);
+
+        let data_end = after.len() - (region_len + BACKUP_MAGIC.len());
+        let mut cut = after[..data_end].to_vec();
+        cut[..data_start as usize].copy_from_slice(&before[..data_start as usize]);
+        CutWrite { path, id, before, after, data_start, data_len, region, cut }
+    }
+
+    #[test]
+    fn write_cut_off_before_its_commit_is_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = vec![1u8; MIN_CHUNK_SIZE + 10];
+        let w = cut_off_write(dir.path(), &old, &vec![2u8; 3 * MIN_CHUNK_SIZE]);
+        std::fs::write(&w.path, &w.cut).unwrap();
+        journal::begin(&w.path, w.data_start, w.data_len).unwrap();
+
+        let sess = open_vault(&w.path, "password", None).unwrap();
+        assert!(journal::load(&w.path).unwrap().is_none());
+        assert_eq!(read_file_bytes(&sess, w.id).unwrap(), old);
+        drop(sess);
+        // the appended chunks are gone and the backup header is back
+        assert_eq!(std::fs::read(&w.path).unwrap(), w.before);
+    }
+
+    #[test]
+    fn write_cut_off_after_its_commit_is_replayed() {
+        let new = vec![2u8; 3 * MIN_CHUNK_SIZE];
+        // cut off before the new header, and while it was half written
+        for torn in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut w = cut_off_write(dir.path(), &[1u8; MIN_CHUNK_SIZE + 10], &new);
+            if torn {
+                let half = w.region.len() / 2;
+                w.cut[..half].copy_from_slice(&w.region[..half]);
+            }
+            std::fs::write(&w.path, &w.cut).unwrap();
+            journal::begin(&w.path, w.data_start, w.data_len).unwrap();
+            journal::commit(&w.path, w.data_start, w.data_len, w.region.clone()).unwrap();
+
+            let sess = open_vault(&w.path, "password", None).unwrap();
+            assert!(journal::load(&w.path).unwrap().is_none());
+            assert_eq!(read_file_bytes(&sess, w.id).unwrap(), new);
+            drop(sess);
+            assert_eq!(std::fs::read(&w.path).unwrap(), w.after, "torn: {torn}");
+        }
+    }
+
+    fn with_duress(dir: &Path) -> String {
+        let path = new_vault(dir);
+        let mut sess = open_vault(&path, "password", None).unwrap();
+        set_duress_password(&mut sess, "password", "duress").unwrap();
+        path
+    }
+
+    fn put_file(sess: &mut Session, password: &str, name: &str, data: &[u8]) -> u64 {
+        let root = sess.meta.root_id;
+        let id = create_file(sess, password, root, name, None).unwrap();
+        replace_file(sess, password, id, data).unwrap();
+        id
+    }
+
+    fn read_vault_header(path: &str) -> Header {
+        read_header(&mut Volume::open(path, false).unwrap()).unwrap().1
+    }
+
+    #[test]
+    fn duress_slot_looks_like_its_absence() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        let plain = read_vault_header(&new_vault(&a));
+        let duress = read_vault_header(&with_duress(&b));
+        for h in [&plain, &duress] {
+            assert!(h.recovery_slots.is_empty());
+        }
+        let (a, b) = (plain.decoy.unwrap(), duress.decoy.unwrap());
+        assert_eq!(a.wrap_suite, b.wrap_suite);
+        assert_eq!(a.wrapped_key.len(), b.wrapped_key.len());
+        assert_eq!(a.sealed_key.len(), b.sealed_key.len());
+        assert_eq!(a.meta.len(), b.meta.len());
+        assert_eq!(plain.header_reserved, duress.header_reserved);
+    }
+
+    #[test]
+    fn real_tree_writes_keep_the_decoy_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        let duress = with_duress(&b);
+        let mut sess = open_vault(&duress, "duress", None).unwrap();
+        put_file(&mut sess, "duress", "decoy.txt", b"nothing to see");
+        drop(sess);
+
+        for path in [new_vault(&a), duress] {
+            let block = read_vault_header(&path).decoy.unwrap().meta;
+            let mut real = open_vault(&path, "password", None).unwrap();
+            let id = put_file(&mut real, "password", "real.txt", b"abc");
+            real.meta.rename(id, "renamed.txt".into()).unwrap();
+            save_metadata(&mut real, "password").unwrap();
+            change_password(&mut real, "password", "password").unwrap();
+            drop(real);
+            assert_eq!(read_vault_header(&path).decoy.unwrap().meta, block);
+        }
+        let sess = open_vault(&b.join("vault.dat").to_string_lossy(), "duress", None).unwrap();
+        let id = sess.meta.child_named(sess.meta.root_id, "decoy.txt").unwrap().id;
+        assert_eq!(read_file_bytes(&sess, id).unwrap(), b"nothing to see");
+    }
+
+    #[test]
+    fn decoy_tree_past_its_slot_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = with_duress(dir.path());
+        let block = read_vault_header(&path).decoy.unwrap().meta;
+        let mut sess = open_vault(&path, "duress", None).unwrap();
+        let root = sess.meta.root_id;
+        // folders with random names, a hundred at a time, until the tree doesn't fit
+        while pack_decoy_tree(&sess.meta).is_ok() {
+            for _ in 0..100 {
+                let name = format!("{:032x}", u128::from_le_bytes(random_bytes()));
+                sess.meta.mkdir(root, name).unwrap();
+            }
+        }
+        match save_metadata(&mut sess, "duress") {
+            Err(VaultError::Invalid(msg)) => assert!(msg.starts_with("metadata too large: "), "{msg}"),
+            other => panic!("expected a size error, got {other:?}"),
+        }
+        drop(sess);
+
+        // nothing was written
+        assert_eq!(read_vault_header(&path).decoy.unwrap().meta, block);
+        let sess = open_vault(&path, "duress", None).unwrap();
+        assert!(sess.meta.children_of(root).is_empty());
+    }
+
+    #[test]
+    fn duress_session_keeps_its_own_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = with_duress(dir.path());
+        let mut sess = open_vault(&path, "duress", None).unwrap();
+        assert!(sess.decoy);
+        put_file(&mut sess, "duress", "decoy.txt", b"nothing to see");
+        drop(sess);
+
+        let mut real = open_vault(&path, "password", None).unwrap();
+        assert!(!real.decoy);
+        assert!(real.meta.child_named(real.meta.root_id, "decoy.txt").is_none());
+        put_file(&mut real, "password", "real.txt", &[1; 3 * MIN_CHUNK_SIZE]);
+        compact(&mut real, "password").unwrap();
+        drop(real);
+
+        let sess = open_vault(&path, "duress", None).unwrap();
+        assert!(sess.meta.child_named(sess.meta.root_id, "real.txt").is_none());
+        let id = sess.meta.child_named(sess.meta.root_id, "decoy.txt").unwrap().id;
+        assert_eq!(read_file_bytes(&sess, id).unwrap(), b"nothing to see");
+    }
+
+    #[test]
+    fn rekey_carries_the_decoy_tree_to_another_suite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = with_duress(dir.path());
+        let mut sess = open_vault(&path, "duress", None).unwrap();
+        put_file(&mut sess, "duress", "decoy.bin", &[7; 2 * MIN_CHUNK_SIZE + 5]);
+        drop(sess);
+
+        let mut real = open_vault(&path, "password", None).unwrap();
+        put_file(&mut real, "password", "real.bin", &[9; MIN_CHUNK_SIZE]);
+        rekey(&mut real, "password", Some(CipherSuite::Aes256Gcm)).unwrap();
+        drop(real);
+
+        let sess = open_vault(&path, "duress", None).unwrap();
+        let id = sess.meta.child_named(sess.meta.root_id, "decoy.bin").unwrap().id;
+        assert_eq!(read_file_bytes(&sess, id).unwrap(), vec![7; 2 * MIN_CHUNK_SIZE + 5]);
+        verify_file(&sess, id).unwrap();
+        drop(sess);
+        let real = open_vault(&path, "password", None).unwrap();
+        let id = real.meta.child_named(real.meta.root_id, "real.bin").unwrap().id;
+        assert_eq!(read_file_bytes(&real, id).unwrap(), vec![9; MIN_CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn duress_session_rekeys_and_compacts_its_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = with_duress(dir.path());
+        let mut sess = open_vault(&path, "duress", None).unwrap();
+        let id = put_file(&mut sess, "duress", "decoy.bin", &[3; 2 * MIN_CHUNK_SIZE]);
+        assert_eq!(rekey(&mut sess, "duress", None).unwrap(), 2);
+        assert_eq!(read_file_bytes(&sess, id).unwrap(), vec![3; 2 * MIN_CHUNK_SIZE]);
+
+        let region_len = || {
+            let mut f = Volume::open(&path, false).unwrap();
+            let (data_start, _) = read_header(&mut f).unwrap();
+            data_len(&mut f, data_start).unwrap()
+        };
+        let len = region_len();
+        remove_node(&mut sess, "duress", id, false).unwrap();
+        let freed = usage(&sess).unwrap().free;
+        assert!(freed > 0);
+        assert_eq!(compact(&mut sess, "duress").unwrap(), freed);
+        assert_eq!(region_len(), len - freed);
+        drop(sess);
+        open_vault(&path, "duress", None).unwrap();
+        open_vault(&path, "password", None).unwrap();
+    }
+
+    #[test]
+    fn duress_session_fails_slot_changes_like_a_wrong_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = with_duress(dir.path());
+        let mut real = open_vault(&path, "password", None).unwrap();
+        let wrong = set_duress_password(&mut real, "wrong", "other").unwrap_err();
+        drop(real);
+        let mut sess = open_vault(&path, "duress", None).unwrap();
+        let refused = set_duress_password(&mut sess, "duress", "other").unwrap_err();
+        assert_eq!(format!("{refused:?}"), format!("{wrong:?}"));
+
+        // the password itself can be changed
+        change_password(&mut sess, "duress", "duress 2").unwrap();
+        drop(sess);
+        assert!(open_vault(&path, "duress 2", None).unwrap().decoy);
+        assert!(open_vault(&path, "duress", None).is_err());
+        assert!(!open_vault(&path, "password", None).unwrap().decoy);
+    }
+}
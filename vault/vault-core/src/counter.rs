@@ -0,0 +1,70 @@
+//! External generation counter (`--counter-file`) to detect rollback of a vault.
+//!
+//! Every metadata write increments `Metadata::generation`. That alone can't tell an
+//! old copy of the vault from the current one, so the last known generation of each
+//! vault is also recorded in a counter file kept somewhere else (e.g. on the local
+//! disk while the vault lives on a USB stick or a cloud drive). A vault whose
+//! generation is lower than the recorded one has been replaced by an older copy.
+//!
+//! The file maps the absolute vault path to its generation, sealed under a key derived
+//! from the vault's master key, so an entry can't be lowered without that key.
+
+use crate::crypto::{hkdf_derive, seal, unseal, CipherSuite, SecretKey, KEY_LEN};
+use crate::error::{Result, VaultError};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+type Entries = BTreeMap<String, Vec<u8>>;
+
+fn counter_key(master_key: &[u8; KEY_LEN]) -> Result<SecretKey> {
+    hkdf_derive(master_key, b"counter:generation")
+}
+
+fn entry_name(vault_path: &str) -> Result<String> {
+    Ok(std::path::absolute(vault_path)?.to_string_lossy().to_string())
+}
+
+fn load_entries(counter_path: &str) -> Result<Entries> {
+    match std::fs::read(counter_path) {
+        Ok(bytes) => Ok(serde_cbor::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Entries::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Last generation recorded for `vault_path`, if any.
+pub fn load(counter_path: &str, vault_path: &str, suite: CipherSuite, master_key: &[u8; KEY_LEN]) -> Result<Option<u64>> {
+    let entries = load_entries(counter_path)?;
+    let name = entry_name(vault_path)?;
+    let Some(sealed) = entries.get(&name) else {
+        return Ok(None);
+    };
+    let plain = unseal(suite, &*counter_key(master_key)?, name.as_bytes(), sealed).map_err(|_| {
+        VaultError::corrupted(format!("counter entry for {name} was sealed by another vault; remove it from {counter_path} to start over"))
+    })?;
+    Ok(Some(u64::from_le_bytes(plain.as_slice().try_into().map_err(VaultError::corrupted)?)))
+}
+
+pub fn store(
+    counter_path: &str,
+    vault_path: &str,
+    suite: CipherSuite,
+    master_key: &[u8; KEY_LEN],
+    generation: u64,
+) -> Result<()> {
+    let mut entries = load_entries(counter_path)?;
+    let name = entry_name(vault_path)?;
+    let sealed = seal(suite, &*counter_key(master_key)?, name.as_bytes(), &generation.to_le_bytes())?;
+    entries.insert(name, sealed);
+
+    // Same temp + rename as the journal, so a crash never leaves a torn file.
+    let tmp_path = format!("{counter_path}.tmp");
+    {
+        let mut tmp = OpenOptions::new().create(true).truncate(true).write(true).open(&tmp_path)?;
+        tmp.write_all(&serde_cbor::to_vec(&entries)?)?;
+        tmp.sync_all()?;
+    }
+    std::fs::rename(tmp_path, counter_path)?;
+    Ok(())
+}
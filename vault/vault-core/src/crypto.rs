@@ -0,0 +1,428 @@
+//! Ciphers and key derivation of the vault: the AEAD suites of the metadata and
+//! chunks, Argon2id for passwords, HKDF for subkeys, and sealing of keys under other
+//! keys. Key material lives in [`SecretKey`]s, locked in RAM and wiped on drop.
+
+use crate::error::{Context, Result, VaultError};
+use argon2::{password_hash::SaltString, Argon2, Params, PasswordHasher};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, XChaCha20Poly1305, XNonce};
+use aes_gcm::Aes256Gcm;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::time::{Duration, Instant};
+use zeroize::Zeroize;
+
+pub mod age;
+pub mod pq;
+pub mod secmem;
+pub mod token;
+pub mod tpm;
+
+pub use secmem::SecretKey;
+
+pub const KEY_LEN: usize = 32;
+/// Authentication tag appended by every supported AEAD.
+pub const TAG_LEN: usize = 16;
+
+/// AEAD used for the wrapped master key, metadata and chunks.
+/// Identified in the header by `id()` (format v2+; v1 is always ChaCha20-Poly1305).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherSuite {
+    ChaCha20Poly1305,
+    /// 24-byte nonces: random nonces can't realistically collide, however many chunks
+    /// are written under one key.
+    XChaCha20Poly1305,
+    /// Much faster on CPUs with AES instructions (AES-NI, ARMv8 Crypto). 12-byte nonces,
+    /// like ChaCha20-Poly1305.
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    pub const ALL: [CipherSuite; 3] = [CipherSuite::ChaCha20Poly1305, CipherSuite::XChaCha20Poly1305, CipherSuite::Aes256Gcm];
+
+    /// Suite picked for a new vault when none is given: AES-256-GCM if the CPU has AES
+    /// instructions, XChaCha20-Poly1305 otherwise (software AES is slow and not
+    /// constant-time).
+    pub fn preferred() -> CipherSuite {
+        if has_aes_instructions() {
+            CipherSuite::Aes256Gcm
+        } else {
+            CipherSuite::XChaCha20Poly1305
+        }
+    }
+
+    pub const fn id(self) -> u16 {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 1,
+            CipherSuite::XChaCha20Poly1305 => 2,
+            CipherSuite::Aes256Gcm => 3,
+        }
+    }
+
+    pub fn from_id(id: u16) -> Result<Self> {
+        match id {
+            1 => Ok(CipherSuite::ChaCha20Poly1305),
+            2 => Ok(CipherSuite::XChaCha20Poly1305),
+            3 => Ok(CipherSuite::Aes256Gcm),
+            _ => Err(VaultError::Unsupported(format!("unsupported cipher suite {id}"))),
+        }
+    }
+
+    pub const fn nonce_len(self) -> usize {
+        match self {
+            CipherSuite::ChaCha20Poly1305 | CipherSuite::Aes256Gcm => 12,
+            CipherSuite::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Fresh random nonce of `nonce_len` bytes.
+    pub fn random_nonce(self) -> Vec<u8> {
+        let mut n = vec![0u8; self.nonce_len()];
+        rand::thread_rng().fill_bytes(&mut n);
+        n
+    }
+
+    /// STREAM nonce of segment `counter`: a random prefix followed by
+    /// `stream_suffix(counter, last)`.
+    pub fn stream_nonce(self, counter: u32, last: bool) -> Vec<u8> {
+        let mut n = self.random_nonce();
+        let at = n.len() - STREAM_SUFFIX_LEN;
+        n[at..].copy_from_slice(&stream_suffix(counter, last));
+        n
+    }
+}
+
+pub const STREAM_SUFFIX_LEN: usize = 5;
+
+/// End of a STREAM nonce: the segment counter (big endian) and a byte that is 1 only
+/// in the last segment, so segments can't be reordered, dropped or cut off at the end
+/// without failing to decrypt.
+pub fn stream_suffix(counter: u32, last: bool) -> [u8; STREAM_SUFFIX_LEN] {
+    let mut s = [0u8; STREAM_SUFFIX_LEN];
+    s[..4].copy_from_slice(&counter.to_be_bytes());
+    s[4] = last as u8;
+    s
+}
+
+impl std::str::FromStr for CipherSuite {
+    type Err = VaultError;
+
+    /// `chacha20-poly1305`, `xchacha20-poly1305`, `aes-256-gcm` or `auto` (see `preferred`).
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(CipherSuite::preferred()),
+            "chacha20-poly1305" => Ok(CipherSuite::ChaCha20Poly1305),
+            "xchacha20-poly1305" => Ok(CipherSuite::XChaCha20Poly1305),
+            "aes-256-gcm" => Ok(CipherSuite::Aes256Gcm),
+            _ => Err(VaultError::invalid("cipher must be auto, chacha20-poly1305, xchacha20-poly1305 or aes-256-gcm")),
+        }
+    }
+}
+
+impl std::fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => write!(f, "chacha20-poly1305"),
+            CipherSuite::XChaCha20Poly1305 => write!(f, "xchacha20-poly1305"),
+            CipherSuite::Aes256Gcm => write!(f, "aes-256-gcm"),
+        }
+    }
+}
+
+fn has_aes_instructions() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq")
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        std::arch::is_aarch64_feature_detected!("aes") && std::arch::is_aarch64_feature_detected!("pmull")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        false
+    }
+}
+
+/// Password-to-KEK derivation. Identified in the header by `id()` (format v2+).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id through the PHC string API, then HKDF-SHA256 (format v1). Only kept to
+    /// open existing vaults; they move to `Argon2idRaw` on the next write.
+    Argon2idPhcHkdf,
+    /// Raw 32-byte Argon2id output used directly as the KEK.
+    Argon2idRaw,
+}
+
+impl Kdf {
+    /// Used for new vaults and for every vault whose key gets wrapped again.
+    pub const DEFAULT: Kdf = Kdf::Argon2idRaw;
+
+    pub const fn id(self) -> u16 {
+        match self {
+            Kdf::Argon2idPhcHkdf => 1,
+            Kdf::Argon2idRaw => 2,
+        }
+    }
+
+    pub fn from_id(id: u16) -> Result<Self> {
+        match id {
+            1 => Ok(Kdf::Argon2idPhcHkdf),
+            2 => Ok(Kdf::Argon2idRaw),
+            _ => Err(VaultError::Unsupported(format!("unsupported kdf {id}"))),
+        }
+    }
+}
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut b = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut b);
+    b
+}
+
+fn argon2id(m_cost_kib: u32, t_cost: u32, p_cost: u32) -> Result<Argon2<'static>> {
+    let params = Params::new(m_cost_kib, t_cost, p_cost, Some(KEY_LEN))
+        .map_err(|e| VaultError::invalid(format!("argon2 params: {e}")))?;
+    Ok(Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params))
+}
+
+pub fn derive_kek_argon2id(
+    password: &str,
+    salt: &[u8; 16],
+    m_cost_kib: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<SecretKey> {
+    let mut out = SecretKey::new([0u8; KEY_LEN]);
+    argon2id(m_cost_kib, t_cost, p_cost)?
+        .hash_password_into(password.as_bytes(), salt, &mut *out)
+        .map_err(|e| VaultError::invalid(format!("argon2 hash: {e}")))?;
+    Ok(out)
+}
+
+/// Argon2id memory and time costs of `init` without `--target-ms`.
+pub const DEFAULT_M_COST_KIB: u32 = 128 * 1024;
+pub const DEFAULT_T_COST: u32 = 3;
+
+// Bounds of the costs `calibrate_kdf` picks from.
+const CALIBRATE_MIN_M_KIB: u32 = 64 * 1024;
+const CALIBRATE_MAX_M_KIB: u32 = 1024 * 1024;
+const CALIBRATE_T_COST: u32 = 3;
+const CALIBRATE_MAX_T_COST: u32 = 10;
+
+/// Argon2id memory and time costs for which one KEK derivation takes about `target`
+/// on this machine, with the measured time of one derivation with them. Memory grows
+/// first, as it is what makes guessing on GPUs expensive; passes go above 3 only once
+/// memory is at 1 GiB, and below 3 only on machines too slow for 64 MiB. The lane
+/// count `p_cost` is given.
+pub fn calibrate_kdf(target: Duration, p_cost: u32) -> Result<(u32, u32, Duration)> {
+    let measure = |m_cost_kib, t_cost| time_kdf(m_cost_kib, t_cost, p_cost);
+    let clamp_m = |m: f64| ((m / 1024.0).round() as u32 * 1024).clamp(CALIBRATE_MIN_M_KIB, CALIBRATE_MAX_M_KIB);
+
+    // Argon2 time is close to linear in memory × passes.
+    let probe = measure(CALIBRATE_MIN_M_KIB, 1)?;
+    let budget = target.as_secs_f64() / probe.as_secs_f64().max(1e-6) * CALIBRATE_MIN_M_KIB as f64;
+    let t_cost = match budget / CALIBRATE_T_COST as f64 {
+        m if m < CALIBRATE_MIN_M_KIB as f64 => ((budget / CALIBRATE_MIN_M_KIB as f64) as u32).clamp(1, CALIBRATE_T_COST),
+        m if m > CALIBRATE_MAX_M_KIB as f64 => {
+            ((budget / CALIBRATE_MAX_M_KIB as f64).round() as u32).clamp(CALIBRATE_T_COST, CALIBRATE_MAX_T_COST)
+        }
+        _ => CALIBRATE_T_COST,
+    };
+    let mut m_cost_kib = clamp_m(budget / t_cost as f64);
+    let mut elapsed = measure(m_cost_kib, t_cost)?;
+
+    // One correction for what the linear model misses (caches, memory bandwidth).
+    let corrected = clamp_m(m_cost_kib as f64 * target.as_secs_f64() / elapsed.as_secs_f64().max(1e-6));
+    if corrected != m_cost_kib {
+        m_cost_kib = corrected;
+        elapsed = measure(m_cost_kib, t_cost)?;
+    }
+    Ok((m_cost_kib, t_cost, elapsed))
+}
+
+/// Time of one KEK derivation with these Argon2id costs.
+pub fn time_kdf(m_cost_kib: u32, t_cost: u32, p_cost: u32) -> Result<Duration> {
+    let start = Instant::now();
+    derive_kek_argon2id("benchmark", &[0u8; 16], m_cost_kib, t_cost, p_cost)?;
+    Ok(start.elapsed())
+}
+
+/// Time to encrypt and to decrypt `total` bytes with `suite` in pieces of `chunk`
+/// bytes, as vault chunks are.
+pub fn time_aead(suite: CipherSuite, total: usize, chunk: usize) -> Result<(Duration, Duration)> {
+    // A throwaway key, so one nonce for every piece does no harm.
+    let key = SecretKey::new(random_bytes());
+    let nonce = suite.random_nonce();
+    let plain = vec![0u8; chunk];
+    let pieces = total.div_ceil(chunk);
+
+    let start = Instant::now();
+    let mut cipher = vec![];
+    for _ in 0..pieces {
+        cipher = aead_encrypt(suite, &key, &nonce, b"", &plain)?;
+    }
+    let encrypt = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..pieces {
+        aead_decrypt(suite, &key, &nonce, b"", &cipher)?;
+    }
+    Ok((encrypt, start.elapsed()))
+}
+
+/// The original derivation: Argon2id through the PHC string API with the salt
+/// base64-encoded, then HKDF over the hash bytes.
+fn derive_kek_argon2id_phc(
+    password: &str,
+    salt: &[u8; 16],
+    m_cost_kib: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<SecretKey> {
+    let argon2 = argon2id(m_cost_kib, t_cost, p_cost)?;
+    let salt_string = SaltString::encode_b64(salt)
+        .map_err(|e| VaultError::invalid(format!("salt encode: {e}")))?;
+
+    let mut out = SecretKey::new([0u8; KEY_LEN]);
+    let hash = argon2
+        .hash_password_customized(password.as_bytes(), None, None, argon2.params().clone(), &salt_string)
+        .map_err(|e| VaultError::invalid(format!("argon2 hash: {e}")))?;
+
+    let hk = Hkdf::<Sha256>::new(
+        None,
+        hash.hash
+            .ok_or_else(|| VaultError::invalid("argon2 missing hash"))?
+            .as_bytes(),
+    );
+    hk.expand(b"vault-kek", &mut *out)
+        .map_err(|e| VaultError::invalid(format!("hkdf expand: {e}")))?;
+    Ok(out)
+}
+
+pub fn derive_kek(
+    kdf: Kdf,
+    password: &str,
+    salt: &[u8; 16],
+    m_cost_kib: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<SecretKey> {
+    match kdf {
+        Kdf::Argon2idPhcHkdf => derive_kek_argon2id_phc(password, salt, m_cost_kib, t_cost, p_cost),
+        Kdf::Argon2idRaw => derive_kek_argon2id(password, salt, m_cost_kib, t_cost, p_cost),
+    }
+}
+
+fn check_nonce(suite: CipherSuite, nonce: &[u8]) -> Result<()> {
+    if nonce.len() != suite.nonce_len() {
+        return Err(VaultError::corrupted(format!("nonce is {} bytes, expected {} for {suite:?}", nonce.len(), suite.nonce_len())));
+    }
+    Ok(())
+}
+
+pub fn aead_encrypt(
+    suite: CipherSuite,
+    key: &[u8; KEY_LEN],
+    nonce: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    check_nonce(suite, nonce)?;
+    let payload = chacha20poly1305::aead::Payload { msg: plaintext, aad };
+    let out = match suite {
+        CipherSuite::ChaCha20Poly1305 => {
+            ChaCha20Poly1305::new(Key::from_slice(key)).encrypt(Nonce::from_slice(nonce), payload)?
+        }
+        CipherSuite::XChaCha20Poly1305 => {
+            XChaCha20Poly1305::new(Key::from_slice(key)).encrypt(XNonce::from_slice(nonce), payload)?
+        }
+        CipherSuite::Aes256Gcm => {
+            Aes256Gcm::new(aes_gcm::Key::<Aes256Gcm>::from_slice(key)).encrypt(aes_gcm::Nonce::from_slice(nonce), payload)?
+        }
+    };
+    Ok(out)
+}
+
+pub fn aead_decrypt(
+    suite: CipherSuite,
+    key: &[u8; KEY_LEN],
+    nonce: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>> {
+    check_nonce(suite, nonce)?;
+    let payload = chacha20poly1305::aead::Payload { msg: ciphertext, aad };
+    let out = match suite {
+        CipherSuite::ChaCha20Poly1305 => {
+            ChaCha20Poly1305::new(Key::from_slice(key)).decrypt(Nonce::from_slice(nonce), payload)?
+        }
+        CipherSuite::XChaCha20Poly1305 => {
+            XChaCha20Poly1305::new(Key::from_slice(key)).decrypt(XNonce::from_slice(nonce), payload)?
+        }
+        CipherSuite::Aes256Gcm => {
+            Aes256Gcm::new(aes_gcm::Key::<Aes256Gcm>::from_slice(key)).decrypt(aes_gcm::Nonce::from_slice(nonce), payload)?
+        }
+    };
+    Ok(out)
+}
+
+/// Encrypts `plaintext` under a fresh random nonce; returns `nonce || ciphertext`.
+pub fn seal(suite: CipherSuite, key: &[u8; KEY_LEN], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut out = suite.random_nonce();
+    let cipher = aead_encrypt(suite, key, &out, aad, plaintext)?;
+    out.extend_from_slice(&cipher);
+    Ok(out)
+}
+
+/// Reverses `seal`.
+pub fn unseal(suite: CipherSuite, key: &[u8; KEY_LEN], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < suite.nonce_len() {
+        return Err(VaultError::corrupted("sealed data too short"));
+    }
+    let (nonce, cipher) = sealed.split_at(suite.nonce_len());
+    aead_decrypt(suite, key, nonce, aad, cipher)
+}
+
+pub fn hkdf_derive(master_key: &[u8; KEY_LEN], info: &[u8]) -> Result<SecretKey> {
+    let hk = Hkdf::<Sha256>::new(None, master_key);
+    let mut out = SecretKey::new([0u8; KEY_LEN]);
+    hk.expand(info, &mut *out)
+        .map_err(|e| VaultError::invalid(format!("hkdf expand: {e}")))?;
+    Ok(out)
+}
+
+/// SHA-256 of a keyfile's contents (any file works; it must not be empty).
+pub fn keyfile_digest(path: &str) -> Result<SecretKey> {
+    let mut f = std::fs::File::open(path).with_context(|| format!("open keyfile {path}"))?;
+    digest_keyfile(&mut f, path)
+}
+
+/// `keyfile_digest` of a keyfile already read, e.g. one picked on a web page.
+pub fn keyfile_digest_of(bytes: &[u8]) -> Result<SecretKey> {
+    digest_keyfile(&mut &bytes[..], "keyfile")
+}
+
+fn digest_keyfile(src: &mut dyn std::io::Read, name: &str) -> Result<SecretKey> {
+    use sha2::Digest;
+    let mut hasher = Sha256::new();
+    if std::io::copy(src, &mut hasher)? == 0 {
+        return Err(VaultError::invalid(format!("keyfile {name} is empty")));
+    }
+    Ok(SecretKey::new(hasher.finalize().into()))
+}
+
+/// KEK of a vault with a second factor: HKDF over the password-derived key, salted
+/// with the keyfile digest.
+pub fn mix_keyfile(kek: &[u8; KEY_LEN], keyfile: &[u8; KEY_LEN]) -> Result<SecretKey> {
+    let hk = Hkdf::<Sha256>::new(Some(keyfile), kek);
+    let mut out = SecretKey::new([0u8; KEY_LEN]);
+    hk.expand(b"vault-kek:keyfile", &mut *out)
+        .map_err(|e| VaultError::invalid(format!("hkdf expand: {e}")))?;
+    Ok(out)
+}
+
+pub fn zeroize_vec(mut v: Vec<u8>) {
+    v.zeroize();
+}
\ No newline at end of file
@@ -0,0 +1,347 @@
+//! The age file format (age-encryption.org/v1), for backups (`crate::backup`): a file
+//! encrypted to X25519 public keys (`age1...`, as made by `age-keygen`) or to a
+//! passphrase. `age -d` and `rage -d` decrypt what is written here, and this reads what
+//! they write, so a backup can be opened without this program.
+//!
+//! The format itself is the `age` crate's, the library of `rage`; this module holds
+//! the keys of the backup commands and turns the crate's errors into `VaultError`s.
+//! The payload is streamed both ways in 64 KiB chunks: a truncated or reordered file
+//! fails at the chunk where it breaks.
+
+use crate::error::{Result, VaultError};
+use age::secrecy::SecretString;
+use std::io::{self, BufRead, Read, Write};
+use zeroize::Zeroizing;
+
+/// scrypt work factor of a new passphrase stanza: 2^18, 256 MiB and about a second,
+/// which is what `age` uses.
+pub const SCRYPT_WORK_FACTOR: u8 = 18;
+/// Highest work factor accepted when reading, as in `age`: more would take minutes.
+const MAX_SCRYPT_WORK_FACTOR: u8 = 22;
+
+/// Whom a file is encrypted to.
+pub enum Recipient {
+    X25519(age::x25519::Recipient),
+    Passphrase(Zeroizing<String>),
+}
+
+impl Recipient {
+    /// An `age1...` public key.
+    pub fn parse(s: &str) -> Result<Recipient> {
+        let key = s.trim().parse().map_err(|_| VaultError::invalid(format!("{} isn't an age public key (age1...)", s.trim())))?;
+        Ok(Recipient::X25519(key))
+    }
+}
+
+/// What a file is decrypted with.
+pub enum Identity {
+    X25519(age::x25519::Identity),
+    Passphrase(Zeroizing<String>),
+}
+
+/// The keys of an identity file: `AGE-SECRET-KEY-1...` lines, with `#` comments and
+/// blank lines between them (the output of `age-keygen`).
+pub fn parse_identities(text: &str) -> Result<Vec<Identity>> {
+    let mut keys = Vec::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let key = line.parse().map_err(|_| VaultError::invalid("not an age identity file (AGE-SECRET-KEY-1... lines)"))?;
+        keys.push(Identity::X25519(key));
+    }
+    if keys.is_empty() {
+        return Err(VaultError::invalid("no keys in the identity file"));
+    }
+    Ok(keys)
+}
+
+fn secret(passphrase: &str) -> SecretString {
+    SecretString::from(passphrase.to_owned())
+}
+
+/// Encrypts a stream to an age file (`finish` writes the end of it).
+pub struct Writer<W: Write>(age::stream::StreamWriter<W>);
+
+impl<W: Write> Writer<W> {
+    /// Writes the header for `recipients` to `inner`. A passphrase must be the only
+    /// recipient, as age requires; scrypt stretches it with the work factor
+    /// 2^`work_factor` (`SCRYPT_WORK_FACTOR` for a backup).
+    pub fn new(inner: W, recipients: &[Recipient], work_factor: u8) -> Result<Self> {
+        if !(1..64).contains(&work_factor) {
+            return Err(VaultError::invalid(format!("bad scrypt work factor 2^{work_factor}")));
+        }
+        let recipients: Vec<Box<dyn age::Recipient>> = recipients
+            .iter()
+            .map(|r| -> Box<dyn age::Recipient> {
+                match r {
+                    Recipient::X25519(key) => Box::new(key.clone()),
+                    Recipient::Passphrase(passphrase) => {
+                        let mut r = age::scrypt::Recipient::new(secret(passphrase));
+                        r.set_work_factor(work_factor);
+                        Box::new(r)
+                    }
+                }
+            })
+            .collect();
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| &**r)).map_err(|e| match e {
+            age::EncryptError::MissingRecipients => VaultError::invalid("no recipients to encrypt to"),
+            age::EncryptError::MixedRecipientAndPassphrase => {
+                VaultError::invalid("a passphrase can't be combined with other recipients")
+            }
+            age::EncryptError::Io(e) => VaultError::Io(e),
+            e => VaultError::invalid(e),
+        })?;
+        Ok(Writer(encryptor.wrap_output(inner)?))
+    }
+
+    /// Writes the last chunk and returns the output. Without it the file reads as
+    /// truncated.
+    pub fn finish(self) -> io::Result<W> {
+        self.0.finish()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Decrypts an age file as a stream. The header is checked by `new`; the payload
+/// fails with `InvalidData` where it was damaged or cut off.
+pub struct Reader<R: BufRead>(age::stream::StreamReader<R>);
+
+impl<R: BufRead> Reader<R> {
+    /// Reads the header of `inner` and opens it with one of `identities`.
+    pub fn new(inner: R, identities: &[Identity]) -> Result<Self> {
+        let identities: Vec<Box<dyn age::Identity>> = identities
+            .iter()
+            .map(|id| -> Box<dyn age::Identity> {
+                match id {
+                    Identity::X25519(key) => Box::new(key.clone()),
+                    Identity::Passphrase(passphrase) => {
+                        let mut id = age::scrypt::Identity::new(secret(passphrase));
+                        id.set_max_work_factor(MAX_SCRYPT_WORK_FACTOR);
+                        Box::new(id)
+                    }
+                }
+            })
+            .collect();
+        let reader = age::Decryptor::new_buffered(inner)
+            .and_then(|d| d.decrypt(identities.iter().map(|id| &**id)))
+            .map_err(decrypt_error)?;
+        Ok(Reader(reader))
+    }
+}
+
+impl<R: BufRead> Read for Reader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.0.read(out).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                io::Error::new(io::ErrorKind::InvalidData, "the backup is damaged or truncated")
+            }
+            _ => e,
+        })
+    }
+}
+
+fn decrypt_error(e: age::DecryptError) -> VaultError {
+    match e {
+        // a wrong passphrase fails to decrypt its stanza
+        age::DecryptError::NoMatchingKeys | age::DecryptError::DecryptionFailed => {
+            VaultError::invalid("the passphrase or identity doesn't open this backup")
+        }
+        age::DecryptError::ExcessiveWork { required, .. } => {
+            VaultError::Unsupported(format!("the scrypt work factor of the backup (2^{required}) is too high"))
+        }
+        age::DecryptError::Io(e) if !matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => {
+            VaultError::Io(e)
+        }
+        _ => VaultError::corrupted("not an age file, or its header is damaged"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::path::Path;
+
+    /// Work factor of the passphrase files made here: unoptimized scrypt is slow.
+    const TEST_WORK_FACTOR: u8 = 10;
+    /// Payload chunk size of the format.
+    const CHUNK: usize = 64 * 1024;
+
+    #[test]
+    fn bech32_keys() {
+        let secret = "AGE-SECRET-KEY-1XMWWC06LY3EE5RYTXM9MFLAZ2U56JJJ36S0MYPDRWSVLUL66MV4QX3S7F6";
+        assert_eq!(parse_identities(secret).unwrap().len(), 1);
+        assert!(parse_identities(&secret.replace("1X", "1x")).is_err());
+        assert!(parse_identities(&secret.replace("F6", "F7")).is_err());
+        assert!(Recipient::parse("age1xmwwc06ly3ee5rytxm9mflaz2u56jjj36s0mypdrwsvlul66mv4qx3s7f6").is_err());
+    }
+
+    fn x25519() -> (Recipient, Identity) {
+        let key = age::x25519::Identity::generate();
+        (Recipient::X25519(key.to_public()), Identity::X25519(key))
+    }
+
+    fn passphrase() -> Zeroizing<String> {
+        Zeroizing::new("correct horse".to_string())
+    }
+
+    fn encrypt(to: &[Recipient], data: &[u8]) -> Vec<u8> {
+        let mut w = Writer::new(Vec::new(), to, TEST_WORK_FACTOR).unwrap();
+        w.write_all(data).unwrap();
+        w.finish().unwrap()
+    }
+
+    fn decrypt(file: &[u8], with: &[Identity]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Reader::new(file, with)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn round_trip() {
+        // empty, exactly one chunk, and a short last chunk
+        for len in [0, CHUNK, 2 * CHUNK + 5] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let (recipient, identity) = x25519();
+            for (to, with) in
+                [(recipient, identity), (Recipient::Passphrase(passphrase()), Identity::Passphrase(passphrase()))]
+            {
+                assert_eq!(decrypt(&encrypt(&[to], &data), &[with]).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn work_factor_is_the_one_given() {
+        let file = encrypt(&[Recipient::Passphrase(passphrase())], b"data");
+        let header = String::from_utf8_lossy(&file[..file.windows(3).position(|w| w == b"---").unwrap()]).to_string();
+        let stanza = header.lines().find(|l| l.starts_with("-> scrypt ")).unwrap();
+        assert_eq!(stanza.rsplit(' ').next(), Some(TEST_WORK_FACTOR.to_string().as_str()));
+        assert!(Writer::new(Vec::new(), &[Recipient::Passphrase(passphrase())], 0).is_err());
+    }
+
+    #[test]
+    fn wrong_keys_and_mixed_recipients() {
+        let (recipient, _) = x25519();
+        let (_, other) = x25519();
+        let file = encrypt(&[recipient], b"data");
+        assert!(matches!(decrypt(&file, &[other]), Err(VaultError::Invalid(_))));
+        let file = encrypt(&[Recipient::Passphrase(passphrase())], b"data");
+        let wrong = Identity::Passphrase(Zeroizing::new("wrong".into()));
+        assert!(matches!(decrypt(&file, &[wrong]), Err(VaultError::Invalid(_))));
+
+        let (recipient, _) = x25519();
+        let mixed = [recipient, Recipient::Passphrase(passphrase())];
+        assert!(Writer::new(Vec::new(), &mixed, TEST_WORK_FACTOR).is_err());
+        assert!(Writer::new(Vec::new(), &[], TEST_WORK_FACTOR).is_err());
+    }
+
+    #[test]
+    fn truncated_payload_fails() {
+        let (recipient, identity) = x25519();
+        let data = vec![7u8; 2 * CHUNK + 5];
+        let file = encrypt(&[recipient], &data);
+        let payload = file.len() - (2 * CHUNK + 5 + 3 * 16);
+        // cut inside the last chunk, between chunks, and the last chunk dropped whole
+        for len in [file.len() - 1, payload + CHUNK + 16, payload + 2 * (CHUNK + 16)] {
+            match decrypt(&file[..len], std::slice::from_ref(&identity)) {
+                Err(VaultError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+                other => panic!("expected a damaged payload at {len}, got {other:?}"),
+            }
+        }
+        // the header alone
+        assert!(decrypt(&file[..payload - 16], &[identity]).is_err());
+    }
+
+    #[test]
+    fn tampered_payload_and_header_fail() {
+        let (recipient, identity) = x25519();
+        let data = vec![7u8; 2 * CHUNK + 5];
+        let file = encrypt(&[recipient], &data);
+        let payload = file.len() - (2 * CHUNK + 5 + 3 * 16);
+        let identity = std::slice::from_ref(&identity);
+
+        // a flipped bit in each chunk, the payload nonce and the header
+        for at in [payload + 5, payload + CHUNK + 16 + 5, file.len() - 1] {
+            let mut bad = file.clone();
+            bad[at] ^= 1;
+            match decrypt(&bad, identity) {
+                Err(VaultError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+                other => panic!("expected a damaged payload at {at}, got {other:?}"),
+            }
+        }
+        for at in [payload - 1, 30] {
+            let mut bad = file.clone();
+            bad[at] ^= 1;
+            assert!(decrypt(&bad, identity).is_err(), "flipped byte {at}");
+        }
+
+        // the first two chunks swapped
+        let mut swapped = file[..payload].to_vec();
+        swapped.extend_from_slice(&file[payload + CHUNK + 16..payload + 2 * (CHUNK + 16)]);
+        swapped.extend_from_slice(&file[payload..payload + CHUNK + 16]);
+        swapped.extend_from_slice(&file[payload + 2 * (CHUNK + 16)..]);
+        assert!(matches!(decrypt(&swapped, identity), Err(VaultError::Io(_))));
+    }
+
+    /// Files made by the reference implementations of age, each with the key that
+    /// opens it and what decrypting it must give.
+    #[test]
+    fn testkit() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/age-testkit");
+        let mut failed = Vec::new();
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if name == "README" {
+                continue;
+            }
+            let file = std::fs::read(&path).unwrap();
+            let split = file.windows(2).position(|w| w == b"\n\n").unwrap();
+            let (mut expect, mut payload, mut identities) = (String::new(), String::new(), Vec::new());
+            for line in std::str::from_utf8(&file[..split]).unwrap().lines() {
+                let (key, value) = line.split_once(": ").unwrap();
+                match key {
+                    "expect" => expect = value.to_string(),
+                    "payload" => payload = value.to_string(),
+                    "identity" => identities.extend(parse_identities(value).unwrap()),
+                    "passphrase" => identities.push(Identity::Passphrase(Zeroizing::new(value.to_string()))),
+                    _ => {}
+                }
+            }
+            let body = &file[split + 2..];
+            // The crate reads stanzas of early age versions, whose body has no short
+            // final line; its own testkit run allows these two the same way.
+            let legacy = ["stanza_missing_body", "stanza_missing_final_line"].contains(&name.as_str());
+            let ok = match Reader::new(body, &identities) {
+                Err(_) => expect != "success" && expect != "payload failure",
+                Ok(_) if legacy => expect == "header failure",
+                Ok(mut reader) => {
+                    let mut out = Vec::new();
+                    match reader.read_to_end(&mut out) {
+                        Ok(_) => expect == "success" && hex(&Sha256::digest(&out)) == payload,
+                        Err(_) => expect == "payload failure",
+                    }
+                }
+            };
+            if !ok {
+                failed.push(format!("{name} (expected {expect})"));
+            }
+            count += 1;
+        }
+        assert!(count > 80);
+        assert!(failed.is_empty(), "{failed:#?}");
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
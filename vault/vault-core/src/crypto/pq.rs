@@ -0,0 +1,112 @@
+//! Hybrid post-quantum key encapsulation for a key slot: X25519 plus ML-KEM-768.
+//!
+//! The password, recovery and token slots are symmetric (Argon2id, HKDF, AEAD with
+//! 256-bit keys) and need nothing more against a quantum computer. The post-quantum
+//! slot instead wraps the master key to a key pair whose secret half is a key file
+//! kept apart from the vault, e.g. an archive key in a safe. Its slot key comes from
+//! both an X25519 and an ML-KEM-768 shared secret, so it stays secret as long as
+//! either scheme holds: a vault recorded today can't be opened later with a quantum
+//! computer (harvest now, decrypt later), and a flaw in the young ML-KEM alone
+//! doesn't open it either.
+//!
+//! The key file keeps only a 96-byte seed: the ML-KEM-768 `d` and `z` seeds (FIPS 203)
+//! and the X25519 secret, from which both key pairs are rebuilt.
+
+use crate::error::{Result, VaultError};
+use super::secmem::Secret;
+use super::{SecretKey, KEY_LEN};
+use hkdf::Hkdf;
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, EncodedSizeUser, KemCore, MlKem768, B32};
+use rand::RngCore;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const SEED_LEN: usize = 96;
+/// ML-KEM-768 ciphertext.
+const KEM_CIPHERTEXT_LEN: usize = 1088;
+/// Slot ciphertext: the ML-KEM-768 ciphertext, then the ephemeral X25519 public key.
+const CIPHERTEXT_LEN: usize = KEM_CIPHERTEXT_LEN + 32;
+
+pub type Seed = Secret<SEED_LEN>;
+
+/// Seed of a new key pair.
+pub fn new_seed() -> Seed {
+    let mut seed = Seed::new([0u8; SEED_LEN]);
+    rand::thread_rng().fill_bytes(&mut *seed);
+    seed
+}
+
+struct KeyPair {
+    kem: <MlKem768 as KemCore>::DecapsulationKey,
+    kem_public: <MlKem768 as KemCore>::EncapsulationKey,
+    dh: StaticSecret,
+}
+
+impl KeyPair {
+    fn from_seed(seed: &Seed) -> Self {
+        let d = B32::try_from(&seed[..32]).expect("seed length");
+        let z = B32::try_from(&seed[32..64]).expect("seed length");
+        let (kem, kem_public) = MlKem768::generate_deterministic(&d, &z);
+        let dh_bytes: [u8; 32] = seed[64..].try_into().expect("seed length");
+        KeyPair {
+            kem,
+            kem_public,
+            dh: StaticSecret::from(dh_bytes),
+        }
+    }
+}
+
+/// Encapsulates a fresh slot key to the key pair of `seed`. Returns the ciphertext to
+/// keep in the slot and the key.
+pub fn encapsulate(seed: &Seed) -> Result<(Vec<u8>, SecretKey)> {
+    let keys = KeyPair::from_seed(seed);
+    let mut rng = rand::thread_rng();
+    let (kem_ct, kem_ss) = keys
+        .kem_public
+        .encapsulate(&mut rng)
+        .map_err(|_| VaultError::invalid("ML-KEM encapsulation failed"))?;
+    let ephemeral = StaticSecret::random_from_rng(&mut rng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let dh_ss = ephemeral.diffie_hellman(&PublicKey::from(&keys.dh));
+
+    let mut ciphertext = kem_ct.to_vec();
+    ciphertext.extend_from_slice(ephemeral_public.as_bytes());
+    let key = combine(&keys, &kem_ss, dh_ss.as_bytes(), &ciphertext)?;
+    Ok((ciphertext, key))
+}
+
+/// The slot key `encapsulate` returned along with `ciphertext`. A wrong seed yields a
+/// wrong key (ML-KEM rejects implicitly), which the slot's AEAD tag then catches.
+pub fn decapsulate(seed: &Seed, ciphertext: &[u8]) -> Result<SecretKey> {
+    if ciphertext.len() != CIPHERTEXT_LEN {
+        return Err(VaultError::corrupted("malformed post-quantum key slot"));
+    }
+    let keys = KeyPair::from_seed(seed);
+    let (kem_ct, ephemeral_public) = ciphertext.split_at(KEM_CIPHERTEXT_LEN);
+    let kem_ct = Ciphertext::<MlKem768>::try_from(kem_ct).expect("ciphertext length");
+    let kem_ss = keys
+        .kem
+        .decapsulate(&kem_ct)
+        .map_err(|_| VaultError::corrupted("ML-KEM decapsulation failed"))?;
+    let ephemeral_public: [u8; 32] = ephemeral_public.try_into().expect("ciphertext length");
+    let dh_ss = keys.dh.diffie_hellman(&PublicKey::from(ephemeral_public));
+    combine(&keys, &kem_ss, dh_ss.as_bytes(), ciphertext)
+}
+
+/// HKDF over both shared secrets, bound to the ciphertext and both public keys.
+fn combine(keys: &KeyPair, kem_ss: &[u8], dh_ss: &[u8], ciphertext: &[u8]) -> Result<SecretKey> {
+    let mut ikm = Secret::<64>::new([0u8; 64]);
+    ikm[..32].copy_from_slice(kem_ss);
+    ikm[32..].copy_from_slice(dh_ss);
+    let mut info = b"vault pq slot:x25519+ml-kem-768".to_vec();
+    info.extend_from_slice(ciphertext);
+    info.extend_from_slice(&keys.kem_public.as_bytes());
+    info.extend_from_slice(PublicKey::from(&keys.dh).as_bytes());
+
+    let hk = Hkdf::<Sha256>::new(None, &*ikm);
+    let mut out = SecretKey::new([0u8; KEY_LEN]);
+    hk.expand(&info, &mut *out)
+        .map_err(|e| VaultError::invalid(format!("hkdf expand: {e}")))?;
+    Ok(out)
+}
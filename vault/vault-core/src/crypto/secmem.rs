@@ -0,0 +1,181 @@
+//! Buffers for key material that are kept out of swap and wiped when dropped.
+//!
+//! Each buffer gets its own page-aligned allocation, locked into RAM with `mlock`
+//! (`VirtualLock` on Windows), so unlocking one on drop never unlocks another that
+//! shared its page. Locking is best effort: when it fails (e.g. `RLIMIT_MEMLOCK` is
+//! used up) the buffer works all the same, only unlocked, and is still zeroized.
+
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use std::ops::{Deref, DerefMut};
+use zeroize::Zeroize;
+
+/// Page-aligned, zero-initialized bytes locked into RAM while alive.
+struct LockedPages {
+    ptr: *mut u8,
+    layout: Layout,
+    locked: bool,
+}
+
+// The allocation is owned exclusively, like a `Box<[u8]>`.
+unsafe impl Send for LockedPages {}
+unsafe impl Sync for LockedPages {}
+
+impl LockedPages {
+    fn new(len: usize) -> Self {
+        let page = page_size();
+        let layout = Layout::from_size_align(len.max(1).next_multiple_of(page), page).expect("secret buffer layout");
+        // SAFETY: `layout` has a non-zero size.
+        let ptr = unsafe { alloc_zeroed(layout) };
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        let locked = lock(ptr, layout.size());
+        LockedPages { ptr, layout, locked }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `layout.size()` initialized bytes owned by `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `bytes`, and `&mut self` makes the access exclusive.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for LockedPages {
+    fn drop(&mut self) {
+        self.bytes_mut().zeroize();
+        if self.locked {
+            unlock(self.ptr, self.layout.size());
+        }
+        // SAFETY: allocated in `new` with this layout.
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// `N` secret bytes (a key) in locked memory, zeroized on drop.
+pub struct Secret<const N: usize> {
+    pages: LockedPages,
+}
+
+pub type SecretKey = Secret<{ super::KEY_LEN }>;
+
+impl<const N: usize> Secret<N> {
+    /// Moves `value` into locked memory and wipes the original.
+    pub fn new(mut value: [u8; N]) -> Self {
+        let mut s = Secret {
+            pages: LockedPages::new(N),
+        };
+        s.copy_from_slice(&value);
+        value.zeroize();
+        s
+    }
+}
+
+impl<const N: usize> Deref for Secret<N> {
+    type Target = [u8; N];
+
+    fn deref(&self) -> &[u8; N] {
+        self.pages.bytes()[..N].try_into().expect("secret length")
+    }
+}
+
+impl<const N: usize> DerefMut for Secret<N> {
+    fn deref_mut(&mut self) -> &mut [u8; N] {
+        (&mut self.pages.bytes_mut()[..N]).try_into().expect("secret length")
+    }
+}
+
+impl<const N: usize> Clone for Secret<N> {
+    fn clone(&self) -> Self {
+        Secret::new(**self)
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for Secret<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// A password kept in locked memory, zeroized on drop.
+pub struct SecretString {
+    pages: LockedPages,
+    len: usize,
+}
+
+impl SecretString {
+    pub fn new(s: &str) -> Self {
+        let mut pages = LockedPages::new(s.len());
+        pages.bytes_mut()[..s.len()].copy_from_slice(s.as_bytes());
+        SecretString { pages, len: s.len() }
+    }
+}
+
+impl Default for SecretString {
+    fn default() -> Self {
+        SecretString::new("")
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        std::str::from_utf8(&self.pages.bytes()[..self.len]).expect("copied from a str")
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretString(..)")
+    }
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions.
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        n if n > 0 => n as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> usize {
+    4096
+}
+
+#[cfg(unix)]
+fn lock(ptr: *mut u8, len: usize) -> bool {
+    // SAFETY: the range is a live allocation owned by the caller.
+    unsafe { libc::mlock(ptr as *const libc::c_void, len) == 0 }
+}
+
+#[cfg(unix)]
+fn unlock(ptr: *mut u8, len: usize) {
+    // SAFETY: as in `lock`.
+    unsafe { libc::munlock(ptr as *const libc::c_void, len) };
+}
+
+#[cfg(windows)]
+fn lock(ptr: *mut u8, len: usize) -> bool {
+    // SAFETY: the range is a live allocation owned by the caller.
+    unsafe { windows_sys::Win32::System::Memory::VirtualLock(ptr as *const std::ffi::c_void, len) != 0 }
+}
+
+#[cfg(windows)]
+fn unlock(ptr: *mut u8, len: usize) {
+    // SAFETY: as in `lock`.
+    unsafe { windows_sys::Win32::System::Memory::VirtualUnlock(ptr as *const std::ffi::c_void, len) };
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock(_ptr: *mut u8, _len: usize) -> bool {
+    false
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unlock(_ptr: *mut u8, _len: usize) {}
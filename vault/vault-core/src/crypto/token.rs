@@ -0,0 +1,78 @@
+//! Hardware token unlock through HMAC challenge-response.
+//!
+//! The vault doesn't talk to USB devices itself: it runs a challenge-response program
+//! with the hex challenge as its last argument and reads the hex response from its
+//! stdout. By default that is `ykchalresp -2` (YubiKey, HMAC-SHA1 in OTP slot 2);
+//! `--token-cmd` plugs in anything else, e.g. a script around `fido2-assert` for the
+//! FIDO2 `hmac-secret` extension. Such a program asks for the token's PIN itself.
+//!
+//! The challenge is fixed per key slot (derived from its id), so the token always
+//! answers the same and the answer can serve as a key. An optional PIN is stretched
+//! with Argon2id and mixed in, so a stolen token alone doesn't open the vault.
+
+use crate::error::{Result, VaultError};
+use super::{derive_kek_argon2id, SecretKey, KEY_LEN};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use std::process::Command;
+use zeroize::Zeroize;
+
+pub const DEFAULT_COMMAND: &str = "ykchalresp -2";
+
+/// Challenge sent to the token for the key slot `slot_id`.
+pub fn challenge(slot_id: &[u8; 8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"vault token challenge");
+    hasher.update(slot_id);
+    hasher.finalize().into()
+}
+
+/// Runs the challenge-response program (`DEFAULT_COMMAND` if `command` is `None`).
+pub fn respond(command: Option<&str>, challenge: &[u8; 32]) -> Result<Vec<u8>> {
+    let command = command.unwrap_or(DEFAULT_COMMAND);
+    let mut parts = command.split_whitespace();
+    let program = parts.next().ok_or_else(|| VaultError::device("empty token command"))?;
+    let challenge_hex: String = challenge.iter().map(|b| format!("{b:02x}")).collect();
+    let output = Command::new(program)
+        .args(parts)
+        .arg(challenge_hex)
+        .output()
+        .map_err(|e| VaultError::device(format!("run token command {program}: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(VaultError::device(format!("token command failed ({}): {}", output.status, stderr.trim())));
+    }
+    let mut text = String::from_utf8(output.stdout).map_err(|_| VaultError::device("token response is not text"))?;
+    let hex = text.trim();
+    let response = match hex.len() >= 32 && hex.len().is_multiple_of(2) && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        true => (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16)).collect::<Result<Vec<_>, _>>().ok(),
+        false => None,
+    };
+    text.zeroize();
+    response.ok_or_else(|| VaultError::device("token response is not a hex string of at least 16 bytes"))
+}
+
+/// Key of a token slot from the token's response and, for a slot with a PIN, the PIN
+/// (stretched with the vault's Argon2id parameters).
+pub fn slot_key(
+    response: &[u8],
+    pin: Option<&str>,
+    slot_id: &[u8; 8],
+    m_cost_kib: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<SecretKey> {
+    let stretched = match pin {
+        Some(pin) => {
+            let mut salt = [0u8; 16];
+            salt.copy_from_slice(&Sha256::new().chain_update(b"vault token pin").chain_update(slot_id).finalize()[..16]);
+            Some(derive_kek_argon2id(pin, &salt, m_cost_kib, t_cost, p_cost)?)
+        }
+        None => None,
+    };
+    let hk = Hkdf::<Sha256>::new(stretched.as_ref().map(|s| s.as_slice()), response);
+    let mut out = SecretKey::new([0u8; KEY_LEN]);
+    hk.expand(b"vault token slot", &mut *out)
+        .map_err(|e| VaultError::invalid(format!("hkdf expand: {e}")))?;
+    Ok(out)
+}
@@ -0,0 +1,142 @@
+//! TPM 2.0 auto-unlock: a slot key sealed to the machine's TPM and its boot state.
+//!
+//! As with hardware tokens, the vault doesn't talk to the device itself but runs the
+//! `tpm2_*` programs of tpm2-tools (which find the TPM through `/dev/tpmrm0`, or
+//! `TPM2TOOLS_TCTI`). The slot key is sealed under the owner hierarchy's primary key,
+//! which only this TPM can recreate, with a policy on the SHA-256 banks of the chosen
+//! PCRs. The TPM releases it only while those PCRs hold the values they had at
+//! enrollment: on this machine, after the same trusted boot. The disk moved to another
+//! machine, or a changed firmware, boot loader or Secure Boot state, leaves the key
+//! sealed; the password still opens the vault then.
+//!
+//! The sealed blobs are encrypted by the TPM and are kept in the key slot.
+
+use crate::error::{Result, VaultError};
+use super::{SecretKey, KEY_LEN};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroize;
+
+/// Firmware, boot loader and Secure Boot policy.
+pub const DEFAULT_PCRS: &str = "0,2,4,7";
+
+/// The slot key sealed by the TPM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    /// PCR indexes of the policy, e.g. `0,2,4,7`.
+    pub pcrs: String,
+    /// `TPM2B_PUBLIC` of the sealed object.
+    pub public: Vec<u8>,
+    /// `TPM2B_PRIVATE` of the sealed object, encrypted to the primary key.
+    pub private: Vec<u8>,
+}
+
+/// Checks a comma-separated list of PCR indexes (0..=23) and normalizes it.
+pub fn parse_pcrs(pcrs: &str) -> Result<String> {
+    let mut list = pcrs
+        .split(',')
+        .map(|p| match p.trim().parse::<u8>() {
+            Ok(n) if n <= 23 => Ok(n),
+            _ => Err(VaultError::invalid(format!("bad PCR index {p:?} (expected 0..23)"))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    list.sort_unstable();
+    list.dedup();
+    Ok(list.iter().map(u8::to_string).collect::<Vec<_>>().join(","))
+}
+
+/// Seals `key` to the current values of `pcrs`.
+pub fn seal(pcrs: &str, key: &[u8; KEY_LEN]) -> Result<Sealed> {
+    let pcrs = parse_pcrs(pcrs)?;
+    let dir = WorkDir::new()?;
+    create_primary(&dir)?;
+    let selection = format!("sha256:{pcrs}");
+    let policy = dir.file("policy.digest");
+    run(&dir, "tpm2_createpolicy", &["--policy-pcr", "-l", &selection, "-L", &policy], None)?;
+    let (public, private) = (dir.file("seal.pub"), dir.file("seal.priv"));
+    let primary = dir.file("primary.ctx");
+    run(
+        &dir,
+        "tpm2_create",
+        &["-C", &primary, "-L", &policy, "-i", "-", "-u", &public, "-r", &private],
+        Some(&key[..]),
+    )?;
+    Ok(Sealed {
+        pcrs,
+        public: std::fs::read(&public)?,
+        private: std::fs::read(&private)?,
+    })
+}
+
+/// The key `seal` sealed, if the PCRs still hold the values they had then.
+pub fn unseal(sealed: &Sealed) -> Result<SecretKey> {
+    let pcrs = parse_pcrs(&sealed.pcrs)?;
+    let dir = WorkDir::new()?;
+    create_primary(&dir)?;
+    let (public, private) = (dir.file("seal.pub"), dir.file("seal.priv"));
+    std::fs::write(&public, &sealed.public)?;
+    std::fs::write(&private, &sealed.private)?;
+    let (primary, object) = (dir.file("primary.ctx"), dir.file("seal.ctx"));
+    run(&dir, "tpm2_load", &["-C", &primary, "-u", &public, "-r", &private, "-c", &object], None)?;
+    let mut out = run(&dir, "tpm2_unseal", &["-c", &object, "-p", &format!("pcr:sha256:{pcrs}")], None)
+        .map_err(|e| e.wrapped("the TPM refused to unseal the key (another machine, or the boot state changed)"))?;
+    let key = <[u8; KEY_LEN]>::try_from(out.as_slice()).map(SecretKey::new);
+    out.zeroize();
+    key.map_err(|_| VaultError::device("the TPM returned a key of the wrong length"))
+}
+
+/// The owner hierarchy's default ECC primary key, the same every time on one TPM.
+fn create_primary(dir: &WorkDir) -> Result<()> {
+    let primary = dir.file("primary.ctx");
+    run(dir, "tpm2_createprimary", &["-C", "o", "-g", "sha256", "-G", "ecc", "-c", &primary], None)?;
+    Ok(())
+}
+
+/// Runs a tpm2-tools program, feeding it `stdin`; returns its stdout.
+fn run(dir: &WorkDir, program: &str, args: &[&str], stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(&dir.0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| VaultError::device(format!("run {program} (is tpm2-tools installed?): {e}")))?;
+    if let Some(input) = stdin {
+        child.stdin.take().expect("piped stdin").write_all(input)?;
+    }
+    drop(child.stdin.take());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(VaultError::device(format!("{program} failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(output.stdout)
+}
+
+/// Private temporary directory for the tools' context files, removed on drop. None
+/// of them holds the key, which goes through pipes only.
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    fn new() -> Result<Self> {
+        let name: String = super::random_bytes::<8>().iter().map(|b| format!("{b:02x}")).collect();
+        let path = std::env::temp_dir().join(format!("vault-tpm-{name}"));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&path)?;
+        Ok(WorkDir(path))
+    }
+
+    fn file(&self, name: &str) -> String {
+        Path::new(&self.0).join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
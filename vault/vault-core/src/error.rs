@@ -0,0 +1,112 @@
+//! Errors of the library. Each says what kind of failure it is, so callers can tell a
+//! wrong password from a damaged vault or a name that is taken without reading the
+//! message; the messages themselves are English and meant for the user.
+
+use std::fmt::Display;
+
+pub type Result<T, E = VaultError> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum VaultError {
+    /// The vault didn't open with what was given. It reads the same whatever failed
+    /// (a wrong password, a damaged header or damaged metadata), so that a guess or an
+    /// edit of the file isn't told how far it got; `detail` has the cause, which is
+    /// deliberately not the `source` (`--verbose-errors`).
+    #[error("wrong {credential}, or the vault is damaged")]
+    WrongPassword { credential: &'static str, detail: Box<VaultError> },
+    /// Vault data failed authentication or doesn't parse: the file was damaged or
+    /// tampered with.
+    #[error("{0}")]
+    Corrupted(String),
+    /// Written by a newer version of the program, or a format this build can't read.
+    #[error("{0}")]
+    Unsupported(String),
+    /// No node with the given id.
+    #[error("not found")]
+    NotFound,
+    /// A node with this name is already in the directory.
+    #[error("{0}")]
+    NameExists(String),
+    #[error("not a directory")]
+    NotADirectory,
+    #[error("not a file")]
+    NotAFile,
+    /// The session was opened with `open_vault_readonly`.
+    #[error("vault is open read-only")]
+    ReadOnly,
+    /// Another process has the vault open for writing.
+    #[error("vault is already open for writing by another process")]
+    Locked,
+    /// Cancelled through the operation's `Progress`.
+    #[error("cancelled")]
+    Cancelled,
+    /// The operation can't be done with these arguments or on this vault: a bad name,
+    /// a parameter out of range, a key slot the vault doesn't have.
+    #[error("{0}")]
+    Invalid(String),
+    /// A hardware token or the TPM, or the program that talks to it, failed.
+    #[error("{0}")]
+    Device(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// `source` happened while working on `context` (a path, a file name).
+    #[error("{context}")]
+    Context { context: String, source: Box<VaultError> },
+}
+
+impl VaultError {
+    /// The error under any `Context`: what actually went wrong.
+    pub fn root(&self) -> &VaultError {
+        match self {
+            VaultError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// This error as the cause of `context`, like `anyhow::Error::context`.
+    pub(crate) fn wrapped(self, context: impl Display) -> Self {
+        VaultError::Context { context: context.to_string(), source: Box::new(self) }
+    }
+
+    pub(crate) fn corrupted(msg: impl Display) -> Self {
+        VaultError::Corrupted(msg.to_string())
+    }
+
+    pub(crate) fn invalid(msg: impl Display) -> Self {
+        VaultError::Invalid(msg.to_string())
+    }
+
+    pub(crate) fn device(msg: impl Display) -> Self {
+        VaultError::Device(msg.to_string())
+    }
+}
+
+impl From<serde_cbor::Error> for VaultError {
+    fn from(e: serde_cbor::Error) -> Self {
+        VaultError::corrupted(e)
+    }
+}
+
+impl From<chacha20poly1305::aead::Error> for VaultError {
+    fn from(e: chacha20poly1305::aead::Error) -> Self {
+        VaultError::corrupted(e)
+    }
+}
+
+/// `context` and `with_context` for results, like `anyhow::Context`: the error keeps
+/// its kind under the added context.
+pub(crate) trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T>;
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<VaultError>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.with_context(|| context)
+    }
+
+    fn with_context<C: Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|e| VaultError::Context { context: context().to_string(), source: Box::new(e.into()) })
+    }
+}
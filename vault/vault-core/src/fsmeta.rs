@@ -0,0 +1,1270 @@
+//! The file tree of a vault, kept in its encrypted metadata: directories and files
+//! ([`Node`]), the chunks and versions of file contents, tags, favorites, the trash,
+//! the free space between chunks and the search index. Changes made here are written
+//! to the vault by `container::save_metadata`.
+
+use crate::error::{Result, VaultError};
+use crate::merkle::{self, Hash};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+/// Version of the `Metadata` layout this program writes. Bump it with a migration in
+/// `MIGRATIONS` when a change can't be read from older metadata by serde defaults
+/// alone (a field whose default would be wrong, a moved or reinterpreted field).
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[v]` upgrades metadata of schema `v` to `v + 1`.
+const MIGRATIONS: [fn(&mut Metadata); SCHEMA_VERSION as usize] = [migrate_0_to_1];
+
+/// Schema 0 is all metadata from before `schema_version`: each field added since the
+/// first release has a serde default meaning "unknown" (no hash, no times, no
+/// tags, ...), so there is nothing to convert.
+fn migrate_0_to_1(_meta: &mut Metadata) {}
+
+/// Default `Metadata::max_name_len`: NAME_MAX of most file systems, so that any name
+/// can be exported.
+pub const DEFAULT_MAX_NAME_LEN: usize = 255;
+
+fn default_max_name_len() -> usize {
+    DEFAULT_MAX_NAME_LEN
+}
+
+/// Longest extended attribute name, in bytes (as for Linux xattrs).
+pub const MAX_XATTR_NAME: usize = 255;
+/// Largest extended attribute value. Attributes live in the metadata, which is
+/// rewritten whole on every save, so bulky data belongs in a file.
+pub const MAX_XATTR_VALUE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NodeType {
+    Dir,
+    File,
+}
+
+/// How the plaintext of a chunk was transformed before encryption.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkRef {
+    pub index: u32,
+    pub offset: u64,
+    pub len: u32,
+    /// `CipherSuite::nonce_len` bytes (a 12-byte array in older metadata, which
+    /// deserializes the same way).
+    pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub compression: Compression,
+    /// Plaintext length (0 in older metadata, see `plaintext_len`).
+    #[serde(default)]
+    pub plain_len: u32,
+    /// Padding bytes after the stored data inside the ciphertext.
+    #[serde(default)]
+    pub pad_len: u32,
+    /// Merkle leaf hash of the ciphertext (absent in older metadata).
+    #[serde(default)]
+    pub hash: Option<Hash>,
+    /// File whose keys encrypted the chunk, if not the node holding it (set by
+    /// `Metadata::clone_file`).
+    #[serde(default)]
+    pub key_id: u64,
+    /// Random salt of the chunk key of a chunk sealed as a STREAM segment (empty in
+    /// older metadata, whose chunks have plain random nonces; see `is_stream`).
+    #[serde(default)]
+    pub salt: Vec<u8>,
+}
+
+impl ChunkRef {
+    /// Id of the file whose keys encrypted this chunk, for a chunk held by `node_id`.
+    pub fn key_owner(&self, node_id: u64) -> u64 {
+        match self.key_id {
+            0 => node_id,
+            id => id,
+        }
+    }
+
+    /// Whether the chunk is a STREAM segment: its nonce ends in its index and a flag
+    /// set only on the last chunk of the file (see `crypto::stream_suffix`).
+    pub fn is_stream(&self) -> bool {
+        !self.salt.is_empty()
+    }
+
+    pub fn plaintext_len(&self) -> u64 {
+        match self.plain_len {
+            // Older chunks are never compressed: ciphertext minus the 16-byte AEAD tag.
+            0 => (self.len as u64).saturating_sub(16),
+            n => n as u64,
+        }
+    }
+}
+
+/// Checks that `chunks` are numbered 1, 2, ... and hold exactly `size` bytes, so a
+/// truncated or overflowed chunk list is never recorded.
+fn check_chunks(size: u64, chunks: &[ChunkRef]) -> Result<()> {
+    let mut total: u64 = 0;
+    for (i, ch) in chunks.iter().enumerate() {
+        if ch.index as u64 != i as u64 + 1 {
+            return Err(VaultError::corrupted(format!("chunk {} recorded at position {}", ch.index, i + 1)));
+        }
+        total = total
+            .checked_add(ch.plaintext_len())
+            .ok_or_else(|| VaultError::corrupted("file size overflow"))?;
+    }
+    if total != size {
+        return Err(VaultError::corrupted(format!("chunks hold {total} bytes, file size is {size}")));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    pub id: u64,
+    /// Random identifier for references from outside the vault (bookmarks, sync):
+    /// unlike `id`, it stays unique across vaults and copies of the node get new ones.
+    /// Kept through renames, moves and the trash. Nodes of older metadata get one at
+    /// the next metadata write (`Metadata::assign_uuids`).
+    #[serde(default)]
+    pub uuid: Option<Uuid>,
+    pub parent_id: u64,
+    pub node_type: NodeType,
+    pub name: String,
+
+    // file only
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+    /// Previous contents, oldest first (only kept when `Metadata::versioning` is on).
+    #[serde(default)]
+    pub versions: Vec<FileVersion>,
+    /// Original parent while the node is in the trash.
+    #[serde(default)]
+    pub trashed_from: Option<u64>,
+    /// Merkle root over the current chunks (None if some chunk has no hash).
+    #[serde(default)]
+    pub merkle_root: Option<Hash>,
+    /// Random data keys of the files whose chunks the node holds (its own under its
+    /// id, clone sources under theirs), sealed under a key derived from the master
+    /// key. A file without an entry uses a key derived from the master key itself, as
+    /// every file did before per-file keys.
+    #[serde(default)]
+    pub data_keys: BTreeMap<u64, Vec<u8>>,
+    /// SHA-256 of the plaintext, recorded when the contents are written in one piece
+    /// (import, copy). None after a write in place and in older metadata.
+    #[serde(default)]
+    pub sha256: Option<Hash>,
+    /// Unix time (seconds) the node was added to the vault; 0 in older metadata.
+    #[serde(default)]
+    pub created_at: u64,
+    /// Unix time of the last change of its contents or name; 0 in older metadata.
+    #[serde(default)]
+    pub modified_at: u64,
+    /// Modification time of the imported OS file (Unix seconds), restored on export.
+    /// None once the contents are written in place in the vault.
+    #[serde(default)]
+    pub source_mtime: Option<u64>,
+    /// MIME type detected at import (see `mime::detect`); a write in place keeps it.
+    /// None for directories, empty files and older metadata.
+    #[serde(default)]
+    pub mime: Option<String>,
+    /// Labels across folders (e.g. "taxes", "scans"), sorted; see `Metadata::add_tag`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Extended attributes: data other tools attach to the node (thumbnails, sync
+    /// state, ...) under names of their own, e.g. `user.thumbnail`; see
+    /// `Metadata::set_xattr`.
+    #[serde(default)]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+    /// Pinned for quick access; see `Metadata::favorites`.
+    #[serde(default)]
+    pub favorite: bool,
+    /// Small JPEG preview of an image (see `thumb`): one chunk in the data region,
+    /// encrypted like the file's own under index 0, which file chunks never use.
+    /// Dropped when the contents change.
+    #[serde(default)]
+    pub thumbnail: Option<ChunkRef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileVersion {
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+    #[serde(default)]
+    pub sha256: Option<Hash>,
+    /// `Node::modified_at` and `Node::source_mtime` of these contents.
+    #[serde(default)]
+    pub modified_at: u64,
+    #[serde(default)]
+    pub source_mtime: Option<u64>,
+    #[serde(default)]
+    pub mime: Option<String>,
+}
+
+/// Current Unix time in seconds.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// std has no clock in the browser: `SystemTime::now` panics there
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// `YYYY-MM-DD HH:MM:SS` (UTC) of a Unix time, `-` for 0 (unknown).
+pub fn format_time(secs: u64) -> String {
+    if secs == 0 {
+        return "-".to_string();
+    }
+    // Civil date from days since 1970-01-01 (H. Hinnant's algorithm).
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let t = secs % 86400;
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}", t / 3600, t / 60 % 60, t % 60)
+}
+
+impl Node {
+    pub fn new(id: u64, parent_id: u64, node_type: NodeType, name: String) -> Self {
+        Self {
+            id,
+            uuid: Some(Uuid::new_v4()),
+            parent_id,
+            node_type,
+            name,
+            size: 0,
+            chunks: vec![],
+            versions: vec![],
+            trashed_from: None,
+            merkle_root: None,
+            data_keys: BTreeMap::new(),
+            sha256: None,
+            created_at: now(),
+            modified_at: now(),
+            source_mtime: None,
+            mime: None,
+            tags: vec![],
+            xattrs: BTreeMap::new(),
+            favorite: false,
+            thumbnail: None,
+        }
+    }
+
+    /// Merkle root computed from the chunk hashes recorded in metadata.
+    pub fn compute_merkle_root(&self) -> Option<Hash> {
+        let leaves: Option<Vec<Hash>> = self.chunks.iter().map(|ch| ch.hash).collect();
+        leaves.map(|l| merkle::root(&l))
+    }
+
+    /// Whether the current contents are the very chunks of `other`'s, as after
+    /// `Metadata::clone_file`: a copy that takes no space of its own.
+    pub fn shares_chunks(&self, other: &Node) -> bool {
+        !self.chunks.is_empty()
+            && self.chunks.len() == other.chunks.len()
+            && self.chunks.iter().zip(&other.chunks).all(|(a, b)| a.offset == b.offset)
+    }
+
+    /// Chunks of the current contents, of all kept versions and of the thumbnail.
+    pub fn all_chunks(&self) -> impl Iterator<Item = &ChunkRef> {
+        self.chunks
+            .iter()
+            .chain(self.versions.iter().flat_map(|v| v.chunks.iter()))
+            .chain(self.thumbnail.iter())
+    }
+
+    pub fn all_chunks_mut(&mut self) -> impl Iterator<Item = &mut ChunkRef> {
+        self.chunks
+            .iter_mut()
+            .chain(self.versions.iter_mut().flat_map(|v| v.chunks.iter_mut()))
+            .chain(self.thumbnail.iter_mut())
+    }
+}
+
+/// Plaintext bytes the extra copies in a group of `Metadata::duplicates` take: all
+/// but the first, leaving out copies sharing the chunks of an earlier one.
+pub fn duplicate_bytes(group: &[&Node]) -> u64 {
+    let own = (1..group.len())
+        .filter(|&i| !group[..i].iter().any(|earlier| group[i].shares_chunks(earlier)))
+        .count();
+    group.first().map_or(0, |n| n.size * own as u64)
+}
+
+/// Totals over a directory tree or the whole vault (see `Metadata::dir_stats`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub files: u64,
+    pub dirs: u64,
+    /// Plaintext bytes of the current contents of the files.
+    pub size: u64,
+    /// Ciphertext bytes the files take in the vault, kept versions included; a chunk
+    /// shared by copies (`clone_file`) counts once.
+    pub stored: u64,
+    /// Levels of directories below the top one down to the deepest node (1 when it
+    /// holds only files, 0 when empty).
+    pub depth: u32,
+}
+
+/// Column a directory listing is ordered by; see `SortOrder`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+    #[default]
+    Name,
+    /// MIME type of files; directories have none.
+    Type,
+    Size,
+    Modified,
+}
+
+/// How the GUI orders the contents of a directory, kept per vault in `Metadata::sort`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    /// Orders `(shown name, node)` pairs: directories first, then by the key, ties by
+    /// name. Names compare ignoring case.
+    pub fn sort(&self, items: &mut [(String, &Node)]) {
+        items.sort_by(|(a_name, a), (b_name, b)| {
+            let by_name = || a_name.to_lowercase().cmp(&b_name.to_lowercase()).then_with(|| a_name.cmp(b_name));
+            let by_key = match self.key {
+                SortKey::Name => by_name(),
+                SortKey::Type => a.mime.cmp(&b.mime).then_with(by_name),
+                SortKey::Size => a.size.cmp(&b.size).then_with(by_name),
+                SortKey::Modified => a.modified_at.cmp(&b.modified_at).then_with(by_name),
+            };
+            let by_key = if self.descending { by_key.reverse() } else { by_key };
+            (a.node_type != NodeType::Dir).cmp(&(b.node_type != NodeType::Dir)).then(by_key)
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeRange {
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Layout version (see `SCHEMA_VERSION`); 0 in metadata older than the field.
+    #[serde(default)]
+    pub schema_version: u32,
+    pub next_id: u64,
+    pub root_id: u64,
+    pub nodes: Vec<Node>,
+    pub freelist: Vec<FreeRange>,
+    /// Keep previous contents of files when they are overwritten.
+    #[serde(default)]
+    pub versioning: bool,
+    /// Longest name of a new or renamed node, in bytes of UTF-8.
+    #[serde(default = "default_max_name_len")]
+    pub max_name_len: usize,
+    /// Names differing only in case collide in a directory, as on Windows and macOS,
+    /// where exporting both would overwrite one with the other.
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Hidden directory holding deleted nodes (0 until something is deleted).
+    #[serde(default)]
+    pub trash_id: u64,
+    /// Merkle root over `(id, merkle_root)` of all files, by id.
+    #[serde(default)]
+    pub merkle_root: Option<Hash>,
+    /// Incremented by every metadata write, so an older copy of the vault can be told
+    /// apart (see `counter`).
+    #[serde(default)]
+    pub generation: u64,
+    /// Full-text index of the text files, if enabled. Only `container` fills it (it
+    /// reads the contents); the node methods here drop the entries they invalidate.
+    #[serde(default)]
+    pub fts: Option<crate::fts::Index>,
+    /// Order of directory listings chosen in the GUI.
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// The last sync with each replica of this vault, by the replica's root UUID;
+    /// see `sync`.
+    #[serde(default)]
+    pub sync_peers: BTreeMap<Uuid, crate::sync::Base>,
+    /// Built on first use after loading; see `Index`.
+    #[serde(skip)]
+    index: OnceLock<Index>,
+}
+
+/// Lookups by id and by parent over `Metadata::nodes`, so they don't scan all nodes.
+/// Not serialized. The `Metadata` methods keep it current; nodes are only added,
+/// removed, renamed or moved through them, never through `nodes` or `get_node_mut`.
+#[derive(Debug, Clone, Default)]
+struct Index {
+    /// Position in `nodes` by id.
+    pos: HashMap<u64, usize>,
+    /// `(name, id)` of the children by parent id. The trash may hold several nodes of
+    /// one name.
+    children: HashMap<u64, BTreeSet<(String, u64)>>,
+    /// Ids by parent id and lowercased name, for `Metadata::case_insensitive`.
+    folded: HashMap<(u64, String), BTreeSet<u64>>,
+}
+
+impl Index {
+    fn build(nodes: &[Node]) -> Self {
+        let mut index = Index::default();
+        for (i, n) in nodes.iter().enumerate() {
+            index.insert(n, i);
+        }
+        index
+    }
+
+    fn insert(&mut self, n: &Node, pos: usize) {
+        self.pos.insert(n.id, pos);
+        self.children.entry(n.parent_id).or_default().insert((n.name.clone(), n.id));
+        self.folded.entry((n.parent_id, n.name.to_lowercase())).or_default().insert(n.id);
+    }
+
+    fn unlink(&mut self, parent_id: u64, name: &str, id: u64) {
+        if let Some(c) = self.children.get_mut(&parent_id) {
+            c.remove(&(name.to_string(), id));
+        }
+        let key = (parent_id, name.to_lowercase());
+        if let Some(ids) = self.folded.get_mut(&key) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.folded.remove(&key);
+            }
+        }
+    }
+}
+
+impl Metadata {
+    pub fn new_empty() -> Self {
+        let root = Node::new(1, 0, NodeType::Dir, "/".to_string());
+        Self {
+            schema_version: SCHEMA_VERSION,
+            next_id: 2,
+            root_id: 1,
+            nodes: vec![root],
+            freelist: vec![],
+            versioning: false,
+            max_name_len: DEFAULT_MAX_NAME_LEN,
+            case_insensitive: false,
+            trash_id: 0,
+            merkle_root: Some(merkle::root(&[])),
+            generation: 0,
+            fts: None,
+            sort: SortOrder::default(),
+            sync_peers: BTreeMap::new(),
+            index: OnceLock::new(),
+        }
+    }
+
+    /// Runs the migrations from `schema_version` up to `SCHEMA_VERSION`, in memory;
+    /// the next metadata write stores the result. Metadata from a newer program is
+    /// left as it is: it can be read, but writing it would drop what this program
+    /// doesn't know (see `is_newer_schema`).
+    pub fn migrate(&mut self) {
+        while let Some(step) = MIGRATIONS.get(self.schema_version as usize) {
+            step(self);
+            self.schema_version += 1;
+        }
+    }
+
+    pub fn is_newer_schema(&self) -> bool {
+        self.schema_version > SCHEMA_VERSION
+    }
+
+    fn index(&self) -> &Index {
+        self.index.get_or_init(|| Index::build(&self.nodes))
+    }
+
+    fn push_node(&mut self, node: Node) {
+        if let Some(index) = self.index.get_mut() {
+            index.insert(&node, self.nodes.len());
+        }
+        self.nodes.push(node);
+    }
+
+    /// Moves node `id` under `parent_id` and/or renames it.
+    fn relink(&mut self, id: u64, parent_id: u64, name: String) -> Result<()> {
+        let i = *self.index().pos.get(&id).ok_or(VaultError::NotFound)?;
+        let n = &mut self.nodes[i];
+        let old_parent = std::mem::replace(&mut n.parent_id, parent_id);
+        let old_name = std::mem::replace(&mut n.name, name);
+        if let Some(index) = self.index.get_mut() {
+            index.unlink(old_parent, &old_name, id);
+            index.insert(&self.nodes[i], i);
+        }
+        Ok(())
+    }
+
+    pub fn alloc_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn node_by_uuid(&self, uuid: &Uuid) -> Option<&Node> {
+        self.nodes.iter().find(|n| n.uuid.as_ref() == Some(uuid))
+    }
+
+    /// Gives nodes from older metadata their `Node::uuid`.
+    pub fn assign_uuids(&mut self) {
+        for n in self.nodes.iter_mut().filter(|n| n.uuid.is_none()) {
+            n.uuid = Some(Uuid::new_v4());
+        }
+    }
+
+    pub fn get_node(&self, id: u64) -> Option<&Node> {
+        self.index().pos.get(&id).map(|&i| &self.nodes[i])
+    }
+
+    /// Don't change `id`, `parent_id` or `name` through it (see `Index`).
+    pub fn get_node_mut(&mut self, id: u64) -> Option<&mut Node> {
+        let i = *self.index().pos.get(&id)?;
+        Some(&mut self.nodes[i])
+    }
+
+    /// Children of `parent_id`, sorted by name.
+    pub fn children_of(&self, parent_id: u64) -> Vec<&Node> {
+        let index = self.index();
+        match index.children.get(&parent_id) {
+            Some(c) => c.iter().map(|(_, id)| &self.nodes[index.pos[id]]).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The child of `parent_id` called `name` (the first one, in the trash).
+    pub fn child_named(&self, parent_id: u64, name: &str) -> Option<&Node> {
+        let index = self.index();
+        let from = (name.to_string(), 0);
+        let (child_name, id) = index.children.get(&parent_id)?.range(from..).next()?;
+        (child_name == name).then(|| &self.nodes[index.pos[id]])
+    }
+
+    /// `name` in NFC, the form names are stored and compared in (so `é` typed as one
+    /// character or as `e` plus an accent is the same name), if it is a valid name:
+    /// not empty, `.` or `..`, free of path separators and control characters, and at
+    /// most `max_name_len` bytes long. Names in older metadata aren't checked until
+    /// they are renamed.
+    pub fn normalize_name(&self, name: &str) -> Result<String> {
+        let name: String = name.nfc().collect();
+        if name.is_empty() {
+            return Err(VaultError::invalid("empty name"));
+        }
+        if name == "." || name == ".." {
+            return Err(VaultError::invalid(format!("{name:?} is not a valid name")));
+        }
+        if name.contains(['/', '\\']) {
+            return Err(VaultError::invalid(format!("name {name:?} contains a path separator")));
+        }
+        if name.chars().any(char::is_control) {
+            return Err(VaultError::invalid(format!("name {name:?} contains a control character")));
+        }
+        if name.len() > self.max_name_len {
+            return Err(VaultError::invalid(format!("name is {} bytes long, the limit is {}", name.len(), self.max_name_len)));
+        }
+        Ok(name)
+    }
+
+    /// A child of `parent_id` other than `except` that `name` collides with: one of
+    /// the same name, or with `case_insensitive` one differing only in case.
+    pub fn name_conflict(&self, parent_id: u64, name: &str, except: Option<u64>) -> Option<&Node> {
+        if !self.case_insensitive {
+            return self.child_named(parent_id, name).filter(|n| Some(n.id) != except);
+        }
+        let index = self.index();
+        let ids = index.folded.get(&(parent_id, name.to_lowercase()))?;
+        let id = ids.iter().find(|&&id| Some(id) != except)?;
+        Some(&self.nodes[index.pos[id]])
+    }
+
+    /// Checks that `name` can be created under `parent_id`.
+    pub fn ensure_can_create(&self, parent_id: u64, name: &str) -> Result<()> {
+        if self.get_node(parent_id).filter(|n| n.node_type == NodeType::Dir).is_none() {
+            return Err(VaultError::NotADirectory);
+        }
+        if self.is_in_trash(parent_id) {
+            return Err(VaultError::invalid("parent is in the trash"));
+        }
+        self.ensure_name_free(parent_id, name, None)
+    }
+
+    fn ensure_name_free(&self, parent_id: u64, name: &str, except: Option<u64>) -> Result<()> {
+        match self.name_conflict(parent_id, name, except) {
+            Some(n) if n.name != name => Err(VaultError::NameExists(format!("name already exists as {:?} (names ignore case)", n.name))),
+            Some(_) => Err(VaultError::NameExists("name already exists".into())),
+            None => Ok(()),
+        }
+    }
+
+    /// Names outside the trash that differ only in case from another in the same
+    /// directory, grouped by directory and name: what to rename before exporting to a
+    /// case-insensitive file system, or after turning `case_insensitive` on.
+    pub fn case_collisions(&self) -> Vec<(u64, Vec<String>)> {
+        let index = self.index();
+        let mut found: Vec<(u64, Vec<String>)> = index
+            .folded
+            .iter()
+            .filter(|((parent_id, _), ids)| ids.len() > 1 && !self.is_in_trash(*parent_id))
+            .map(|((parent_id, _), ids)| {
+                let mut names: Vec<String> = ids.iter().map(|id| self.nodes[index.pos[id]].name.clone()).collect();
+                names.sort();
+                (*parent_id, names)
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    /// `name`, or the first of `name (2)`, `name (3)`, ... (before the extension) not
+    /// yet taken in `parent_id`. The part before the suffix is shortened (at a character
+    /// boundary) as needed to keep the name within `max_name_len`.
+    pub fn unique_name(&self, parent_id: u64, name: &str) -> String {
+        let taken = |n: &str| self.name_conflict(parent_id, n, None).is_some();
+        if !taken(name) {
+            return name.to_string();
+        }
+        let (stem, ext) = match name.rfind('.') {
+            Some(i) if i > 0 => name.split_at(i),
+            _ => (name, ""),
+        };
+        (2..)
+            .map(|i| {
+                let suffix = format!(" ({i}){ext}");
+                let mut end = stem.len().min(self.max_name_len.saturating_sub(suffix.len()));
+                while !stem.is_char_boundary(end) {
+                    end -= 1;
+                }
+                format!("{}{suffix}", &stem[..end])
+            })
+            .find(|n| !taken(n))
+            .unwrap()
+    }
+
+    pub fn mkdir(&mut self, parent_id: u64, name: String) -> Result<u64> {
+        let name = self.normalize_name(&name)?;
+        self.ensure_can_create(parent_id, &name)?;
+        let id = self.alloc_id();
+        self.push_node(Node::new(id, parent_id, NodeType::Dir, name));
+        Ok(id)
+    }
+
+    /// Puts back an empty directory removed with `remove_subtree`, under its old id
+    /// (to redo a `mkdir`).
+    pub fn restore_dir(&mut self, dir: Node) -> Result<()> {
+        if dir.node_type != NodeType::Dir || self.get_node(dir.id).is_some() || dir.id >= self.next_id {
+            return Err(VaultError::invalid("cannot restore this directory"));
+        }
+        self.ensure_can_create(dir.parent_id, &dir.name)?;
+        self.push_node(dir);
+        Ok(())
+    }
+
+    /// Adds a file node under an id previously obtained from `alloc_id`
+    /// (chunks are bound to the id, so it must be known before writing).
+    pub fn add_file(
+        &mut self,
+        id: u64,
+        parent_id: u64,
+        name: String,
+        size: u64,
+        chunks: Vec<ChunkRef>,
+        sha256: Option<Hash>,
+    ) -> Result<u64> {
+        let name = self.normalize_name(&name)?;
+        self.ensure_can_create(parent_id, &name)?;
+        check_chunks(size, &chunks)?;
+        let mut node = Node::new(id, parent_id, NodeType::File, name);
+        node.size = size;
+        node.chunks = chunks;
+        node.sha256 = sha256;
+        self.push_node(node);
+        self.update_merkle_roots(id);
+        Ok(id)
+    }
+
+    /// Vault-level Merkle root computed from the roots stored in the file nodes.
+    pub fn compute_merkle_root(&self) -> Option<Hash> {
+        let mut files: Vec<&Node> = self.nodes.iter().filter(|n| n.node_type == NodeType::File).collect();
+        files.sort_by_key(|n| n.id);
+        let leaves: Option<Vec<Hash>> = files
+            .iter()
+            .map(|n| {
+                let root = n.merkle_root?;
+                let mut buf = n.id.to_le_bytes().to_vec();
+                buf.extend_from_slice(&root);
+                Some(merkle::leaf_hash(&buf))
+            })
+            .collect();
+        leaves.map(|l| merkle::root(&l))
+    }
+
+    /// Recomputes the Merkle root of file `id` (if it still exists) and of the vault.
+    pub fn update_merkle_roots(&mut self, id: u64) {
+        if let Some(n) = self.get_node_mut(id) {
+            n.merkle_root = n.compute_merkle_root();
+        }
+        self.merkle_root = self.compute_merkle_root();
+    }
+
+    /// Copy-on-write copy of file `id`: the new node shares the chunks of the current
+    /// contents instead of duplicating ciphertext. A shared chunk is freed only once no
+    /// node references it any more (see `unreferenced`), and rewriting either file
+    /// only replaces its own references.
+    pub fn clone_file(&mut self, id: u64, parent_id: u64, name: String) -> Result<u64> {
+        let src = self.get_node(id).ok_or(VaultError::NotFound)?;
+        if src.node_type != NodeType::File {
+            return Err(VaultError::NotAFile);
+        }
+        let (size, sha256, modified_at, source_mtime) = (src.size, src.sha256, src.modified_at, src.source_mtime);
+        let (mime, tags, xattrs) = (src.mime.clone(), src.tags.clone(), src.xattrs.clone());
+        let shared = |ch: &ChunkRef| ChunkRef {
+            key_id: ch.key_owner(id),
+            ..ch.clone()
+        };
+        let chunks: Vec<ChunkRef> = src.chunks.iter().map(shared).collect();
+        let thumbnail = src.thumbnail.as_ref().map(shared);
+        let data_keys: BTreeMap<u64, Vec<u8>> = src
+            .data_keys
+            .iter()
+            .filter(|(owner, _)| chunks.iter().chain(&thumbnail).any(|ch| ch.key_id == **owner))
+            .map(|(owner, key)| (*owner, key.clone()))
+            .collect();
+        let name = self.normalize_name(&name)?;
+        self.ensure_can_create(parent_id, &name)?;
+        let new_id = self.alloc_id();
+        self.add_file(new_id, parent_id, name, size, chunks, sha256)?;
+        self.set_times(new_id, modified_at, source_mtime)?;
+        let n = self.get_node_mut(new_id).expect("just added");
+        n.mime = mime;
+        n.tags = tags;
+        n.xattrs = xattrs;
+        n.thumbnail = thumbnail;
+        for (owner, key) in data_keys {
+            self.set_data_key(new_id, owner, key)?;
+        }
+        if let Some(fts) = &mut self.fts {
+            fts.copy(id, new_id);
+        }
+        Ok(new_id)
+    }
+
+    /// Sets the modification times of node `id` (see `Node::modified_at`), e.g. those
+    /// of an imported or copied file.
+    pub fn set_times(&mut self, id: u64, modified_at: u64, source_mtime: Option<u64>) -> Result<()> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.modified_at = modified_at;
+        n.source_mtime = source_mtime;
+        Ok(())
+    }
+
+    pub fn set_mime(&mut self, id: u64, mime: Option<String>) -> Result<()> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.mime = mime;
+        Ok(())
+    }
+
+    /// Sets the thumbnail of file `id` (a chunk already written), freeing the one it
+    /// replaces.
+    pub fn set_thumbnail(&mut self, id: u64, thumbnail: Option<ChunkRef>) -> Result<Vec<FreeRange>> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        if n.node_type != NodeType::File {
+            return Err(VaultError::NotAFile);
+        }
+        let old = std::mem::replace(&mut n.thumbnail, thumbnail);
+        let freed = self.unreferenced(old.iter());
+        self.freelist.extend(freed.iter().cloned());
+        Ok(freed)
+    }
+
+    pub fn set_favorite(&mut self, id: u64, favorite: bool) -> Result<()> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.favorite = favorite;
+        Ok(())
+    }
+
+    /// Favorite nodes outside the trash with their paths, by path. A favorite moved to
+    /// the trash stays one and is listed again once restored.
+    pub fn favorites(&self) -> Vec<(String, &Node)> {
+        let mut found: Vec<(String, &Node)> = self
+            .nodes
+            .iter()
+            .filter(|n| n.favorite && !self.is_in_trash(n.id))
+            .map(|n| (self.path_of(n.id), n))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    /// Tags node `id` with `tag` (surrounding whitespace dropped); false if it already
+    /// had it.
+    pub fn add_tag(&mut self, id: u64, tag: &str) -> Result<bool> {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Err(VaultError::invalid("empty tag"));
+        }
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        match n.tags.binary_search_by(|t| t.as_str().cmp(tag)) {
+            Ok(_) => Ok(false),
+            Err(i) => {
+                n.tags.insert(i, tag.to_string());
+                Ok(true)
+            }
+        }
+    }
+
+    /// Removes `tag` from node `id`; false if it didn't have it.
+    pub fn remove_tag(&mut self, id: u64, tag: &str) -> Result<bool> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        let before = n.tags.len();
+        n.tags.retain(|t| t != tag.trim());
+        Ok(n.tags.len() != before)
+    }
+
+    pub fn get_xattr(&self, id: u64, name: &str) -> Result<Option<&[u8]>> {
+        let n = self.get_node(id).ok_or(VaultError::NotFound)?;
+        Ok(n.xattrs.get(name).map(Vec::as_slice))
+    }
+
+    /// Sets extended attribute `name` of node `id`, replacing its value.
+    pub fn set_xattr(&mut self, id: u64, name: &str, value: Vec<u8>) -> Result<()> {
+        if name.is_empty() || name.len() > MAX_XATTR_NAME {
+            return Err(VaultError::invalid(format!("attribute name must be 1 to {MAX_XATTR_NAME} bytes")));
+        }
+        if value.len() > MAX_XATTR_VALUE {
+            return Err(VaultError::invalid(format!("attribute value is {} bytes, at most {MAX_XATTR_VALUE} allowed", value.len())));
+        }
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.xattrs.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Removes extended attribute `name` of node `id`; false if it had none.
+    pub fn remove_xattr(&mut self, id: u64, name: &str) -> Result<bool> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        Ok(n.xattrs.remove(name).is_some())
+    }
+
+    /// Every tag used outside the trash, with the number of nodes that have it.
+    pub fn all_tags(&self) -> BTreeMap<String, usize> {
+        let mut tags = BTreeMap::new();
+        for n in self.nodes.iter().filter(|n| !n.tags.is_empty() && !self.is_in_trash(n.id)) {
+            for t in &n.tags {
+                *tags.entry(t.clone()).or_insert(0) += 1;
+            }
+        }
+        tags
+    }
+
+    /// Nodes below directory `dir_id`, outside the trash, that have every tag of `tags`
+    /// and, with `name`, whose name contains it (ignoring case). Returns each with its
+    /// path from `dir_id`, in tree order.
+    pub fn find(&self, dir_id: u64, name: Option<&str>, tags: &[String]) -> Vec<(String, &Node)> {
+        let name = name.map(str::to_lowercase);
+        let mut found = vec![];
+        let mut stack: Vec<(String, &Node)> = self
+            .children_of(dir_id)
+            .into_iter()
+            .rev()
+            .map(|n| (format!("/{}", n.name), n))
+            .collect();
+        while let Some((path, n)) = stack.pop() {
+            if n.id == self.trash_id {
+                continue;
+            }
+            if n.node_type == NodeType::Dir {
+                stack.extend(self.children_of(n.id).into_iter().rev().map(|c| (format!("{path}/{}", c.name), c)));
+            }
+            let name_ok = name.as_ref().is_none_or(|q| n.name.to_lowercase().contains(q.as_str()));
+            if name_ok && tags.iter().all(|t| n.tags.iter().any(|x| x == t.trim())) {
+                found.push((path, n));
+            }
+        }
+        found
+    }
+
+    /// Records the sealed data key of file `owner` for the chunks node `id` holds
+    /// (see `Node::data_keys`).
+    pub fn set_data_key(&mut self, id: u64, owner: u64, sealed: Vec<u8>) -> Result<()> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.data_keys.insert(owner, sealed);
+        Ok(())
+    }
+
+    /// Path of node `id` from the root, e.g. `/Docs/a.pdf` (or `/.trash/a.pdf`).
+    pub fn path_of(&self, id: u64) -> String {
+        let mut names = vec![];
+        let mut cur = self.get_node(id);
+        while let Some(n) = cur.filter(|n| n.id != self.root_id) {
+            names.push(n.name.as_str());
+            cur = self.get_node(n.parent_id);
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
+
+    /// Groups of files outside the trash with the same contents, by SHA-256 and size:
+    /// the recorded `Node::sha256`, or for files without one (written in place) the
+    /// digest in `computed`, if any. Empty files aren't reported. Each group is
+    /// ordered by path; the groups by the bytes duplicates take, most first.
+    pub fn duplicates(&self, computed: &HashMap<u64, Hash>) -> Vec<Vec<&Node>> {
+        let mut groups: HashMap<(Hash, u64), Vec<&Node>> = HashMap::new();
+        for n in &self.nodes {
+            if n.node_type != NodeType::File || n.size == 0 || self.is_in_trash(n.id) {
+                continue;
+            }
+            if let Some(digest) = n.sha256.or_else(|| computed.get(&n.id).copied()) {
+                groups.entry((digest, n.size)).or_default().push(n);
+            }
+        }
+        let mut dupes: Vec<Vec<&Node>> = groups.into_values().filter(|g| g.len() > 1).collect();
+        for g in &mut dupes {
+            g.sort_by_cached_key(|n| self.path_of(n.id));
+        }
+        dupes.sort_by_key(|g| std::cmp::Reverse(duplicate_bytes(g)));
+        dupes
+    }
+
+    /// Stats of every node of the vault, the contents of the trash included.
+    pub fn stats(&self) -> Stats {
+        match self.trash_id {
+            0 => self.tree_stats(&[self.root_id]),
+            trash_id => self.tree_stats(&[self.root_id, trash_id]),
+        }
+    }
+
+    /// Stats of everything below directory `id` (the trash is no part of the root's
+    /// tree).
+    pub fn dir_stats(&self, id: u64) -> Result<Stats> {
+        match self.get_node(id) {
+            Some(n) if n.node_type == NodeType::Dir => Ok(self.tree_stats(&[id])),
+            Some(_) => Err(VaultError::NotADirectory),
+            None => Err(VaultError::NotFound),
+        }
+    }
+
+    fn tree_stats(&self, tops: &[u64]) -> Stats {
+        let mut stats = Stats::default();
+        let mut seen = HashSet::new();
+        let mut stack: Vec<(u64, u32)> = tops.iter().map(|&id| (id, 0)).collect();
+        while let Some((dir, depth)) = stack.pop() {
+            for n in self.children_of(dir) {
+                stats.depth = stats.depth.max(depth + 1);
+                match n.node_type {
+                    NodeType::Dir => {
+                        stats.dirs += 1;
+                        stack.push((n.id, depth + 1));
+                    }
+                    NodeType::File => {
+                        stats.files += 1;
+                        stats.size += n.size;
+                        for ch in n.all_chunks().filter(|ch| seen.insert(ch.offset)) {
+                            stats.stored += ch.len as u64;
+                        }
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// Offsets of every chunk still referenced by some node (current or versions).
+    pub fn referenced_offsets(&self) -> HashSet<u64> {
+        self.nodes.iter().flat_map(|n| n.all_chunks()).map(|ch| ch.offset).collect()
+    }
+
+    /// Chunks of `chunks` that nothing references any more, as free ranges.
+    fn unreferenced<'a>(&self, chunks: impl Iterator<Item = &'a ChunkRef>) -> Vec<FreeRange> {
+        let live = self.referenced_offsets();
+        let mut seen = HashSet::new();
+        chunks
+            .filter(|ch| !live.contains(&ch.offset) && seen.insert(ch.offset))
+            .map(|ch| FreeRange {
+                offset: ch.offset,
+                len: ch.len as u64,
+            })
+            .collect()
+    }
+
+    /// Replaces the contents of a file (`sha256` as in `Node::sha256`). With versioning
+    /// on, the old contents are kept as a version; otherwise their chunks are added to
+    /// the freelist and returned. The thumbnail is freed either way.
+    pub fn set_contents(
+        &mut self,
+        id: u64,
+        size: u64,
+        chunks: Vec<ChunkRef>,
+        sha256: Option<Hash>,
+    ) -> Result<Vec<FreeRange>> {
+        check_chunks(size, &chunks)?;
+        if let Some(fts) = &mut self.fts {
+            fts.remove(id);
+        }
+        let versioning = self.versioning;
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        if n.node_type != NodeType::File {
+            return Err(VaultError::NotAFile);
+        }
+        let old = FileVersion {
+            size: std::mem::replace(&mut n.size, size),
+            chunks: std::mem::replace(&mut n.chunks, chunks),
+            sha256: std::mem::replace(&mut n.sha256, sha256),
+            modified_at: std::mem::replace(&mut n.modified_at, now()),
+            source_mtime: n.source_mtime.take(),
+            mime: n.mime.clone(),
+        };
+        let thumbnail = n.thumbnail.take();
+        if versioning {
+            n.versions.push(old);
+            self.update_merkle_roots(id);
+            let freed = self.unreferenced(thumbnail.iter());
+            self.freelist.extend(freed.iter().cloned());
+            return Ok(freed);
+        }
+        self.update_merkle_roots(id);
+        let freed = self.unreferenced(old.chunks.iter().chain(&thumbnail));
+        self.freelist.extend(freed.iter().cloned());
+        Ok(freed)
+    }
+
+    /// Makes version `version` (1-based, oldest first) current again; the current
+    /// contents become the newest version, so restoring is always reversible.
+    pub fn restore_version(&mut self, id: u64, version: usize) -> Result<()> {
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        if version == 0 || version > n.versions.len() {
+            return Err(VaultError::invalid(format!("no version {version}")));
+        }
+        let v = n.versions.remove(version - 1);
+        let cur = FileVersion {
+            size: std::mem::replace(&mut n.size, v.size),
+            chunks: std::mem::replace(&mut n.chunks, v.chunks),
+            sha256: std::mem::replace(&mut n.sha256, v.sha256),
+            modified_at: std::mem::replace(&mut n.modified_at, v.modified_at),
+            source_mtime: std::mem::replace(&mut n.source_mtime, v.source_mtime),
+            mime: std::mem::replace(&mut n.mime, v.mime),
+        };
+        n.versions.push(cur);
+        let thumbnail = n.thumbnail.take();
+        if let Some(fts) = &mut self.fts {
+            fts.remove(id);
+        }
+        self.update_merkle_roots(id);
+        let freed = self.unreferenced(thumbnail.iter());
+        self.freelist.extend(freed);
+        Ok(())
+    }
+
+    pub fn rename(&mut self, id: u64, new_name: String) -> Result<()> {
+        let new_name = self.normalize_name(&new_name)?;
+        let parent_id = self.get_node(id).ok_or(VaultError::NotFound)?.parent_id;
+        // With `case_insensitive`, the node may change the case of its own name.
+        self.ensure_name_free(parent_id, &new_name, Some(id))?;
+        self.relink(id, parent_id, new_name)?;
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.modified_at = now();
+        Ok(())
+    }
+
+    /// Moves a node (with its subtree) into directory `parent_id`, keeping its name.
+    pub fn move_node(&mut self, id: u64, parent_id: u64) -> Result<()> {
+        if id == self.root_id || id == self.trash_id {
+            return Err(VaultError::invalid("cannot move this directory"));
+        }
+        let n = self.get_node(id).ok_or(VaultError::NotFound)?;
+        if n.parent_id == parent_id {
+            return Ok(());
+        }
+        let name = n.name.clone();
+        let mut p = parent_id;
+        while p != 0 {
+            if p == id {
+                return Err(VaultError::invalid("cannot move a directory into itself"));
+            }
+            p = self.get_node(p).map_or(0, |n| n.parent_id);
+        }
+        self.ensure_can_create(parent_id, &name)?;
+        self.relink(id, parent_id, name)?;
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.trashed_from = None;
+        n.modified_at = now();
+        Ok(())
+    }
+
+    /// True for the trash directory and everything below it.
+    pub fn is_in_trash(&self, mut id: u64) -> bool {
+        if self.trash_id == 0 {
+            return false;
+        }
+        loop {
+            if id == self.trash_id {
+                return true;
+            }
+            match self.get_node(id) {
+                Some(n) if n.parent_id != 0 => id = n.parent_id,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Top-level nodes in the trash, sorted by name.
+    pub fn trash_items(&self) -> Vec<&Node> {
+        if self.trash_id == 0 {
+            return vec![];
+        }
+        self.children_of(self.trash_id)
+    }
+
+    /// Moves a node (with its subtree) to the trash, remembering where it came from.
+    pub fn trash(&mut self, id: u64) -> Result<()> {
+        if id == self.root_id {
+            return Err(VaultError::invalid("cannot remove root"));
+        }
+        if self.get_node(id).is_none() {
+            return Err(VaultError::NotFound);
+        }
+        if self.is_in_trash(id) {
+            return Err(VaultError::invalid("already in the trash"));
+        }
+        if self.get_node(self.trash_id).is_none() {
+            self.trash_id = self.alloc_id();
+            self.push_node(Node::new(self.trash_id, 0, NodeType::Dir, ".trash".to_string()));
+        }
+        let trash_id = self.trash_id;
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.trashed_from = Some(n.parent_id);
+        let name = n.name.clone();
+        self.relink(id, trash_id, name)
+    }
+
+    /// Moves a top-level trash item back to its original parent, or to the root if
+    /// that parent is gone or itself in the trash. Returns the new parent id.
+    pub fn restore_from_trash(&mut self, id: u64) -> Result<u64> {
+        let n = self.get_node(id).ok_or(VaultError::NotFound)?;
+        if self.trash_id == 0 || n.parent_id != self.trash_id {
+            return Err(VaultError::invalid("not in the trash"));
+        }
+        let parent_id = n
+            .trashed_from
+            .filter(|&p| {
+                self.get_node(p).is_some_and(|p| p.node_type == NodeType::Dir) && !self.is_in_trash(p)
+            })
+            .unwrap_or(self.root_id);
+        let name = n.name.clone();
+        self.ensure_can_create(parent_id, &name)?;
+        self.relink(id, parent_id, name)?;
+        let n = self.get_node_mut(id).ok_or(VaultError::NotFound)?;
+        n.trashed_from = None;
+        Ok(parent_id)
+    }
+
+    /// Permanently removes everything in the trash; returns the freed ranges.
+    pub fn empty_trash(&mut self) -> Result<Vec<FreeRange>> {
+        let ids: Vec<u64> = self.trash_items().iter().map(|n| n.id).collect();
+        let mut freed = vec![];
+        for id in ids {
+            freed.extend(self.remove_subtree(id)?);
+        }
+        Ok(freed)
+    }
+
+    /// Removes a node with all descendants. Their chunk ranges are added to the
+    /// freelist and returned.
+    pub fn remove_subtree(&mut self, id: u64) -> Result<Vec<FreeRange>> {
+        if id == self.root_id || id == self.trash_id {
+            return Err(VaultError::invalid("cannot remove root"));
+        }
+        if self.get_node(id).is_none() {
+            return Err(VaultError::NotFound);
+        }
+
+        // Collect ids in subtree.
+        let mut stack = vec![id];
+        let mut to_remove: HashSet<u64> = HashSet::new();
+        while let Some(cur) = stack.pop() {
+            to_remove.insert(cur);
+            if let Some(children) = self.index().children.get(&cur) {
+                stack.extend(children.iter().map(|(_, id)| *id));
+            }
+        }
+
+        let (removed, kept): (Vec<Node>, Vec<Node>) =
+            std::mem::take(&mut self.nodes).into_iter().partition(|n| to_remove.contains(&n.id));
+        self.nodes = kept;
+        // Positions have shifted.
+        self.index = OnceLock::new();
+        self.merkle_root = self.compute_merkle_root();
+        if let Some(fts) = &mut self.fts {
+            for n in &removed {
+                fts.remove(n.id);
+            }
+        }
+
+        let freed = self.unreferenced(removed.iter().flat_map(|n| n.all_chunks()));
+        self.freelist.extend(freed.iter().cloned());
+        Ok(freed)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Metadata as written by the first release, before `schema_version` and all the
+    /// fields with serde defaults: a directory with one file of one chunk.
+    const METADATA_V0: &[u8] = include_bytes!("../testdata/metadata-v0.cbor");
+
+    #[test]
+    fn migrate_upgrades_v0_metadata() {
+        let mut meta: Metadata = serde_cbor::from_slice(METADATA_V0).unwrap();
+        assert_eq!(meta.schema_version, 0);
+        meta.migrate();
+        assert_eq!(meta.schema_version, SCHEMA_VERSION);
+        assert!(!meta.is_newer_schema());
+
+        assert_eq!(meta.next_id, 4);
+        assert_eq!(meta.max_name_len, DEFAULT_MAX_NAME_LEN);
+        assert_eq!(meta.freelist.len(), 1);
+        let docs = meta.child_named(meta.root_id, "docs").unwrap().id;
+        let file = meta.child_named(docs, "notes.txt").unwrap();
+        assert_eq!((file.id, file.size), (3, 11));
+        assert_eq!(file.chunks[0].nonce, [7; 12]);
+        assert_eq!(file.chunks[0].plain_len, 0);
+        assert_eq!(file.merkle_root, None);
+    }
+
+    #[test]
+    fn newer_schema_is_left_alone() {
+        let mut meta = Metadata::new_empty();
+        meta.schema_version = SCHEMA_VERSION + 1;
+        let mut meta: Metadata = serde_cbor::from_slice(&serde_cbor::to_vec(&meta).unwrap()).unwrap();
+        meta.migrate();
+        assert_eq!(meta.schema_version, SCHEMA_VERSION + 1);
+        assert!(meta.is_newer_schema());
+    }
+
+    #[test]
+    fn unique_name_stays_within_max_name_len() {
+        let mut meta = Metadata::new_empty();
+        meta.max_name_len = 15;
+        let root = meta.root_id;
+        // two-byte characters, so the cut falls inside one
+        let name = "ааааа.txt";
+        meta.mkdir(root, name.to_string()).unwrap();
+        let second = meta.unique_name(root, name);
+        assert_eq!(second, "ааа (2).txt");
+        assert!(second.len() <= meta.max_name_len);
+        meta.mkdir(root, second).unwrap();
+        assert_eq!(meta.unique_name(root, name), "ааа (3).txt");
+    }
+}
@@ -0,0 +1,115 @@
+//! Full-text search over the text files of a vault: an inverted index kept in the
+//! encrypted metadata (so it is as secret as the file names), ranked with BM25.
+//!
+//! The index is optional (`Metadata::fts`): it holds every word of every text file,
+//! and the metadata is written whole on every change, so a vault of many large text
+//! files pays for it in metadata size.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Of each file, only this many bytes from the start are indexed.
+pub const MAX_INDEXED_BYTES: usize = 16 * 1024 * 1024;
+/// Longer words (base64, hashes, ...) aren't indexed.
+const MAX_TERM_CHARS: usize = 64;
+
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Index {
+    /// Occurrences of each term by file id.
+    postings: BTreeMap<String, BTreeMap<u64, u32>>,
+    /// Number of terms of each indexed file.
+    docs: BTreeMap<u64, u32>,
+}
+
+/// Lowercased words of `text`: runs of letters and digits.
+pub fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty() && w.chars().count() <= MAX_TERM_CHARS)
+        .map(str::to_lowercase)
+}
+
+impl Index {
+    /// Indexes `text` as the contents of file `id`, replacing what was indexed for it.
+    pub fn add(&mut self, id: u64, text: &str) {
+        self.remove(id);
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for t in terms(text) {
+            *counts.entry(t).or_insert(0) += 1;
+        }
+        if counts.is_empty() {
+            return;
+        }
+        self.docs.insert(id, counts.values().sum());
+        for (t, n) in counts {
+            self.postings.entry(t).or_default().insert(id, n);
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        if self.docs.remove(&id).is_none() {
+            return;
+        }
+        self.postings.retain(|_, files| {
+            files.remove(&id);
+            !files.is_empty()
+        });
+    }
+
+    /// Indexes the contents of `from` for its copy `to`.
+    pub fn copy(&mut self, from: u64, to: u64) {
+        let Some(&len) = self.docs.get(&from) else {
+            return;
+        };
+        self.docs.insert(to, len);
+        for files in self.postings.values_mut() {
+            if let Some(&n) = files.get(&from) {
+                files.insert(to, n);
+            }
+        }
+    }
+
+    /// Number of indexed files.
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    /// Files containing every word of `query`, best match first, with their BM25
+    /// scores.
+    pub fn search(&self, query: &str) -> Vec<(u64, f64)> {
+        let mut words: Vec<String> = terms(query).collect();
+        words.sort();
+        words.dedup();
+        if words.is_empty() || self.docs.is_empty() {
+            return vec![];
+        }
+        let Some(lists) = words.iter().map(|w| self.postings.get(w)).collect::<Option<Vec<_>>>() else {
+            return vec![];
+        };
+        let n = self.docs.len() as f64;
+        let avg_len = self.docs.values().map(|&l| l as f64).sum::<f64>() / n;
+        // Candidates from the rarest word, kept if they have all the others.
+        let rarest = lists.iter().min_by_key(|l| l.len()).expect("words is not empty");
+        let mut hits: Vec<(u64, f64)> = rarest
+            .keys()
+            .filter(|id| lists.iter().all(|l| l.contains_key(id)))
+            .map(|&id| {
+                let len = self.docs.get(&id).copied().unwrap_or(0) as f64;
+                let score = lists
+                    .iter()
+                    .map(|l| {
+                        let df = l.len() as f64;
+                        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        let tf = l[&id] as f64;
+                        idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * len / avg_len))
+                    })
+                    .sum();
+                (id, score)
+            })
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits
+    }
+}
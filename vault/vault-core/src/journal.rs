@@ -0,0 +1,108 @@
+//! Sidecar journal (`<vault>.journal`) that makes writes crash-safe.
+//!
+//! Every mutating operation goes through the same steps:
+//! 1. `begin`: record the current length of the data region;
+//! 2. append ciphertext to the data region (if any);
+//! 3. `commit`: record the complete new header region — this is the commit point;
+//! 4. write the header region into the vault;
+//! 5. `finish`: remove the journal.
+//!
+//! On the next open a journal without a header is rolled back (data region truncated
+//! to its recorded length), a journal with a header is replayed. Header writes are done
+//! in place when they fit the reserved header region, so replay also repairs a torn
+//! header.
+//!
+//! Operations that rewrite the whole vault into `<vault>.tmp` record `begin_replace`
+//! before moving it into place, so a move interrupted half way through the parts of a
+//! split vault is finished on the next open.
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    /// Offset of the data region before the operation started.
+    pub data_start: u64,
+    /// Length of the data region before the operation started.
+    pub data_len: u64,
+    /// New header region (`[u32 len][cbor(header)]`), present once committed.
+    pub header: Option<Vec<u8>>,
+    /// Set while a rewritten copy (`<vault>.tmp`, this many parts) is being moved over
+    /// the vault; replay finishes the move.
+    #[serde(default)]
+    pub replace_parts: Option<usize>,
+}
+
+pub fn journal_path(vault_path: &str) -> String {
+    format!("{vault_path}.journal")
+}
+
+pub fn load(vault_path: &str) -> Result<Option<Journal>> {
+    match std::fs::read(journal_path(vault_path)) {
+        Ok(bytes) => Ok(Some(serde_cbor::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn store(vault_path: &str, j: &Journal) -> Result<()> {
+    // Written via temp + rename so a crash never leaves a torn record.
+    let path = journal_path(vault_path);
+    let tmp_path = format!("{path}.tmp");
+    {
+        let mut tmp = OpenOptions::new().create(true).truncate(true).write(true).open(&tmp_path)?;
+        tmp.write_all(&serde_cbor::to_vec(j)?)?;
+        tmp.sync_all()?;
+    }
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+pub fn begin(vault_path: &str, data_start: u64, data_len: u64) -> Result<()> {
+    store(
+        vault_path,
+        &Journal {
+            data_start,
+            data_len,
+            header: None,
+            replace_parts: None,
+        },
+    )
+}
+
+/// Records the new header region. Keeps `data_len` of an already started operation.
+pub fn commit(vault_path: &str, data_start: u64, data_len: u64, header: Vec<u8>) -> Result<()> {
+    let data_len = load(vault_path)?.map(|j| j.data_len).unwrap_or(data_len);
+    store(
+        vault_path,
+        &Journal {
+            data_start,
+            data_len,
+            header: Some(header),
+            replace_parts: None,
+        },
+    )
+}
+
+/// Records that the complete `<vault>.tmp` is about to replace the vault.
+pub fn begin_replace(vault_path: &str, parts: usize) -> Result<()> {
+    store(
+        vault_path,
+        &Journal {
+            data_start: 0,
+            data_len: 0,
+            header: None,
+            replace_parts: Some(parts),
+        },
+    )
+}
+
+pub fn finish(vault_path: &str) -> Result<()> {
+    match std::fs::remove_file(journal_path(vault_path)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,55 @@
+//! The encrypted container of vault, as a library: everything the `vault` CLI and GUI
+//! do to a vault file goes through here, so other programs can embed it too.
+//!
+//! - [`container`] creates, opens and writes vaults: a [`container::Session`] is an
+//!   open vault, and free functions take it to import, read, export, delete and
+//!   compact files, manage key slots and verify the vault.
+//! - [`fsmeta`] is the file tree of an open vault ([`fsmeta::Metadata`], in
+//!   `Session::meta`): directories, files with their chunks, versions, tags and the
+//!   trash. Changes to it are written by [`container::save_metadata`].
+//! - [`crypto`] holds the ciphers and key derivation, and key material in locked,
+//!   zeroized memory ([`crypto::SecretKey`]).
+//! - [`recovery`] makes the recovery codes that open a vault without its password.
+//! - [`mime`] tells the content type recorded for imported files.
+//! - [`sync`] syncs a vault with a replica both ways.
+//! - [`backup`] writes the whole tree to an age-encrypted tar archive and restores
+//!   one.
+//! - [`backend`] is where the bytes of a vault are kept: files on disk, memory, or
+//!   storage of the embedding program, e.g. in a browser (the crate builds for
+//!   `wasm32-unknown-unknown`) or in object storage.
+//!
+//! Errors are [`VaultError`]s: their kind tells a wrong password from a damaged vault,
+//! a missing node or a taken name, and their messages are English and meant for the
+//! user.
+//!
+//! ```no_run
+//! use vault_core::container;
+//!
+//! # fn main() -> vault_core::Result<()> {
+//! let sess = container::open_vault("vault.dat", "password", None)?;
+//! for node in sess.meta.children_of(sess.meta.root_id) {
+//!     println!("{} ({} bytes)", node.name, node.size);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod backend;
+pub mod backup;
+pub mod container;
+pub mod crypto;
+pub mod error;
+pub mod fsmeta;
+pub mod mime;
+pub mod recovery;
+pub mod sync;
+
+pub use error::{Result, VaultError};
+
+mod counter;
+mod fts;
+mod journal;
+mod merkle;
+mod staging;
+mod thumb;
+mod volume;
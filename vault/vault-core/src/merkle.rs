@@ -0,0 +1,43 @@
+//! SHA-256 Merkle tree over chunk ciphertexts. Lets the vault be audited for
+//! corruption or truncation without decrypting anything.
+//!
+//! Leaves and inner nodes use distinct prefixes (`0x00` / `0x01`) so a leaf can't be
+//! passed off as a subtree; an odd node at the end of a level is carried up unchanged.
+
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+pub fn leaf_hash(ciphertext: &[u8]) -> Hash {
+    let mut h = Sha256::new();
+    h.update([0u8]);
+    h.update(ciphertext);
+    h.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut h = Sha256::new();
+    h.update([1u8]);
+    h.update(left);
+    h.update(right);
+    h.finalize().into()
+}
+
+/// Root over `leaves` in order (hash of the empty string for no leaves).
+pub fn root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return Sha256::digest([]).into();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [l, r] => node_hash(l, r),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
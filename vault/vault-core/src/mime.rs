@@ -0,0 +1,90 @@
+//! Content type of imported files, from their first bytes (magic numbers), so the
+//! GUI picks icons and viewers by what a file is rather than by trial decoding.
+
+/// Bytes from the start of a file that `detect` looks at.
+pub const HEAD_LEN: usize = 8192;
+
+/// MIME type of a file starting with `head` (the first `HEAD_LEN` bytes or fewer).
+/// Binary formats are told by their magic numbers; text has none, so UTF-8 text is
+/// `text/plain`, or a more specific type known from the extension of `name`. None for
+/// an empty file.
+pub fn detect(head: &[u8], name: &str) -> Option<String> {
+    if head.is_empty() {
+        return None;
+    }
+    if let Some(kind) = infer::get(head) {
+        return Some(kind.mime_type().to_string());
+    }
+    if !is_utf8_prefix(head) {
+        return Some("application/octet-stream".to_string());
+    }
+    let ext = name.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase()).unwrap_or_default();
+    let text = match ext.as_str() {
+        "md" | "markdown" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        _ => "text/plain",
+    };
+    Some(text.to_string())
+}
+
+/// Valid UTF-8, except perhaps for a character cut off at the end of the head.
+fn is_utf8_prefix(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() == HEAD_LEN,
+    }
+}
+
+/// Whether the built-in text viewer shows it (text formats, including JSON and XML).
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/") || matches!(mime, "application/json" | "application/xml" | "image/svg+xml")
+}
+
+/// Whether the built-in image viewer decodes it.
+pub fn is_viewable_image(mime: &str) -> bool {
+    matches!(mime, "image/png" | "image/jpeg")
+}
+
+/// Whether the built-in player plays it (it tells the format by the contents).
+pub fn is_audio(mime: &str) -> bool {
+    mime.starts_with("audio/")
+}
+
+/// Whether the built-in player can be asked to play it (GStreamer, when the GUI is
+/// built with it).
+pub fn is_video(mime: &str) -> bool {
+    mime.starts_with("video/")
+}
+
+/// Icon for a file of type `mime` in the file list.
+pub fn icon(mime: Option<&str>) -> &'static str {
+    let Some(mime) = mime else {
+        return "📄";
+    };
+    match mime.split_once('/').map_or(mime, |(top, _)| top) {
+        _ if is_text(mime) => "📝",
+        "image" => "🖼",
+        "audio" => "🎵",
+        "video" => "🎞",
+        _ if mime == "application/pdf" => "📕",
+        _ if matches!(
+            mime,
+            "application/zip"
+                | "application/gzip"
+                | "application/x-tar"
+                | "application/x-7z-compressed"
+                | "application/vnd.rar"
+                | "application/x-xz"
+                | "application/zstd"
+                | "application/x-bzip2"
+        ) =>
+        {
+            "📦"
+        }
+        _ => "📄",
+    }
+}
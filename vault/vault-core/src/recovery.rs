@@ -0,0 +1,387 @@
+//! Recovery of a vault whose password is lost.
+//!
+//! A random recovery key wraps the master key in a slot of the header
+//! (`container::add_key_slot`). The key itself is never stored: it is split into
+//! Shamir shares over GF(256), any `threshold` of which give it back. Shares are
+//! short text lines, so they can be printed as well as kept as files.
+//!
+//! Share format: `vlt-share-1:<set id>:<threshold>:<x>:<y>:<check>`, all hex; the set
+//! id is the id of the header slot and `check` is the first 4 bytes of SHA-256 over
+//! everything before it, to catch typos in a share typed in from paper.
+//!
+//! A recovery code is the other kind: 160 random bits written as Crockford base32 in
+//! groups of four (`7KQ2-...`), whose SHA-256 is the recovery key. It is made once,
+//! e.g. at `init`, and meant to be written down.
+//!
+//! A post-quantum key file holds the seed of the key pair of the hybrid slot (see
+//! `crypto::pq`) as `vlt-pq-key-1:<seed>:<check>`, checked like a share.
+
+use crate::crypto::pq::{self, Seed};
+use crate::crypto::{SecretKey, KEY_LEN};
+use crate::error::{Result, VaultError};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+const SHARE_PREFIX: &str = "vlt-share-1";
+
+/// One Shamir share of a recovery key.
+#[derive(Debug, Clone)]
+pub struct Share {
+    /// Id of the recovery slot the key belongs to.
+    pub set_id: [u8; 8],
+    pub threshold: u8,
+    /// Evaluation point, 1..=255.
+    pub x: u8,
+    pub y: Vec<u8>,
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.y.zeroize();
+    }
+}
+
+impl Share {
+    pub fn encode(&self) -> String {
+        let body = format!(
+            "{SHARE_PREFIX}:{}:{:02x}:{:02x}:{}",
+            hex(&self.set_id),
+            self.threshold,
+            self.x,
+            hex(&self.y)
+        );
+        let check = hex(&Sha256::digest(body.as_bytes())[..4]);
+        format!("{body}:{check}")
+    }
+
+    /// Parses a share from `encode`'s format; lines starting with `#` and blank lines
+    /// are skipped, so a whole share file can be passed.
+    pub fn decode(text: &str) -> Result<Share> {
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty() && !l.starts_with('#'))
+            .ok_or_else(|| VaultError::invalid("no share found"))?;
+        let (body, check) = line.rsplit_once(':').ok_or_else(|| VaultError::invalid("malformed share"))?;
+        if hex(&Sha256::digest(body.as_bytes())[..4]) != check.to_ascii_lowercase() {
+            return Err(VaultError::invalid("share checksum mismatch (typo?)"));
+        }
+        let parts: Vec<&str> = body.split(':').collect();
+        let [prefix, set_id, threshold, x, y] = parts[..] else {
+            return Err(VaultError::invalid("malformed share"));
+        };
+        if prefix != SHARE_PREFIX {
+            return Err(VaultError::invalid(format!("unsupported share format {prefix}")));
+        }
+        let share = Share {
+            set_id: unhex(set_id)?.try_into().map_err(|_| VaultError::invalid("malformed share set id"))?,
+            threshold: u8::from_str_radix(threshold, 16).map_err(VaultError::invalid)?,
+            x: u8::from_str_radix(x, 16).map_err(VaultError::invalid)?,
+            y: unhex(y)?,
+        };
+        if share.x == 0 || share.threshold == 0 {
+            return Err(VaultError::invalid("malformed share"));
+        }
+        Ok(share)
+    }
+}
+
+/// Splits `key` into `shares` shares, any `threshold` of which recover it.
+pub fn split_key(set_id: [u8; 8], key: &[u8; KEY_LEN], shares: u8, threshold: u8) -> Result<Vec<Share>> {
+    if threshold == 0 || threshold > shares {
+        return Err(VaultError::invalid("threshold must be between 1 and the number of shares"));
+    }
+    let mut out: Vec<Share> = (1..=shares)
+        .map(|x| Share {
+            set_id,
+            threshold,
+            x,
+            y: Vec::with_capacity(KEY_LEN),
+        })
+        .collect();
+    // One random polynomial of degree threshold-1 per key byte, the byte being its
+    // constant term.
+    let mut coeffs = vec![0u8; threshold as usize];
+    for &secret in key {
+        coeffs[0] = secret;
+        rand::thread_rng().fill_bytes(&mut coeffs[1..]);
+        for share in &mut out {
+            // Horner's rule.
+            let y = coeffs.iter().rev().fold(0u8, |acc, &c| gf_mul(acc, share.x) ^ c);
+            share.y.push(y);
+        }
+    }
+    coeffs.zeroize();
+    Ok(out)
+}
+
+/// Recovers the key from at least `threshold` shares of one set; returns the set id
+/// with it.
+pub fn combine_key(shares: &[Share]) -> Result<([u8; 8], SecretKey)> {
+    let first = shares.first().ok_or_else(|| VaultError::invalid("no shares given"))?;
+    if shares.iter().any(|s| s.set_id != first.set_id) {
+        return Err(VaultError::invalid("shares belong to different recovery sets"));
+    }
+    if shares.iter().any(|s| s.threshold != first.threshold || s.y.len() != KEY_LEN) {
+        return Err(VaultError::invalid("malformed share"));
+    }
+    let mut xs: Vec<u8> = shares.iter().map(|s| s.x).collect();
+    xs.sort_unstable();
+    xs.dedup();
+    if xs.len() != shares.len() {
+        return Err(VaultError::invalid("the same share was given twice"));
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(VaultError::invalid(format!("{} shares needed, {} given", first.threshold, shares.len())));
+    }
+
+    // Lagrange interpolation at x = 0 (subtraction is xor in GF(256)).
+    let used = &shares[..first.threshold as usize];
+    let mut key = SecretKey::new([0u8; KEY_LEN]);
+    for (j, sj) in used.iter().enumerate() {
+        let mut basis = 1u8;
+        for (m, sm) in used.iter().enumerate() {
+            if m != j {
+                basis = gf_mul(basis, gf_mul(sm.x, gf_inv(sm.x ^ sj.x)));
+            }
+        }
+        for (k, &y) in key.iter_mut().zip(&sj.y) {
+            *k ^= gf_mul(y, basis);
+        }
+    }
+    Ok((first.set_id, key))
+}
+
+const CODE_BYTES: usize = 20;
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A fresh recovery code and its recovery key.
+pub fn new_code() -> (String, SecretKey) {
+    let mut bytes = [0u8; CODE_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let mut code = String::new();
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &b in &bytes {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            if !code.is_empty() && code.len() % 5 == 4 {
+                code.push('-');
+            }
+            code.push(CROCKFORD[(acc >> bits) as usize & 31] as char);
+        }
+    }
+    let key = code_key(&bytes);
+    bytes.zeroize();
+    (code, key)
+}
+
+/// Recovery key of a code typed in by the user: case, dashes and spaces don't
+/// matter, and O/I/L are read as 0/1/1.
+pub fn parse_code(code: &str) -> Result<SecretKey> {
+    let mut bytes = Vec::with_capacity(CODE_BYTES);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut digits = 0;
+    for c in code.chars().filter(|c| *c != '-' && !c.is_whitespace()) {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        let v = CROCKFORD
+            .iter()
+            .position(|&d| d as char == c)
+            .ok_or_else(|| VaultError::invalid(format!("invalid character {c:?} in recovery code")))?;
+        acc = (acc << 5) | v as u32;
+        bits += 5;
+        digits += 1;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if digits != CODE_BYTES * 8 / 5 {
+        return Err(VaultError::invalid(format!("a recovery code has {} characters, got {digits}", CODE_BYTES * 8 / 5)));
+    }
+    let key = code_key(&bytes);
+    bytes.zeroize();
+    Ok(key)
+}
+
+const PQ_KEY_PREFIX: &str = "vlt-pq-key-1";
+
+/// Key file line for the key pair of `seed`.
+pub fn encode_pq_key(seed: &Seed) -> String {
+    let body = format!("{PQ_KEY_PREFIX}:{}", hex(&seed[..]));
+    let check = hex(&Sha256::digest(body.as_bytes())[..4]);
+    format!("{body}:{check}")
+}
+
+/// Seed from a post-quantum key file; comment lines are skipped as in `Share::decode`.
+pub fn decode_pq_key(text: &str) -> Result<Seed> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .ok_or_else(|| VaultError::invalid("no post-quantum key found"))?;
+    let (body, check) = line.rsplit_once(':').ok_or_else(|| VaultError::invalid("malformed post-quantum key"))?;
+    if hex(&Sha256::digest(body.as_bytes())[..4]) != check.to_ascii_lowercase() {
+        return Err(VaultError::invalid("post-quantum key checksum mismatch"));
+    }
+    let (prefix, seed) = body.split_once(':').ok_or_else(|| VaultError::invalid("malformed post-quantum key"))?;
+    if prefix != PQ_KEY_PREFIX {
+        return Err(VaultError::invalid(format!("unsupported post-quantum key format {prefix}")));
+    }
+    let mut bytes = unhex(seed)?;
+    let seed = <[u8; pq::SEED_LEN]>::try_from(bytes.as_slice()).map(Seed::new);
+    bytes.zeroize();
+    seed.map_err(|_| VaultError::invalid("malformed post-quantum key"))
+}
+
+fn code_key(bytes: &[u8]) -> SecretKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"vault recovery code");
+    hasher.update(bytes);
+    SecretKey::new(hasher.finalize().into())
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1 (the AES field), without
+/// data-dependent branches on the operands' bits.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0u8;
+    for _ in 0..8 {
+        p ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    p
+}
+
+/// Inverse of a non-zero element: a^254.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut e = 254u8;
+    while e > 0 {
+        if e & 1 == 1 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        e >>= 1;
+    }
+    result
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(VaultError::invalid("malformed hex"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| VaultError::invalid("malformed hex")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_key() -> [u8; KEY_LEN] {
+        let mut key = [0u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        key
+    }
+
+    #[test]
+    fn every_threshold_subset_recovers_the_key() {
+        for (n, k) in [(1, 1), (2, 1), (2, 2), (3, 2), (5, 3), (6, 6), (7, 4)] {
+            let key = random_key();
+            let set_id = [n; 8];
+            // through the text form, as shares are kept
+            let shares: Vec<Share> =
+                split_key(set_id, &key, n, k).unwrap().iter().map(|s| Share::decode(&s.encode()).unwrap()).collect();
+            for mask in 1u32..1 << n {
+                let subset: Vec<Share> =
+                    shares.iter().enumerate().filter(|(i, _)| mask & 1 << i != 0).map(|(_, s)| s.clone()).collect();
+                let res = combine_key(&subset);
+                if subset.len() >= k as usize {
+                    let (id, got) = res.unwrap();
+                    assert_eq!((id, *got), (set_id, key), "n={n} k={k} mask={mask:b}");
+                } else {
+                    assert!(matches!(res, Err(VaultError::Invalid(_))), "n={n} k={k} mask={mask:b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_parameters_and_share_sets_are_refused() {
+        let key = random_key();
+        assert!(split_key([0; 8], &key, 3, 0).is_err());
+        assert!(split_key([0; 8], &key, 3, 4).is_err());
+        assert!(combine_key(&[]).is_err());
+
+        let shares = split_key([1; 8], &key, 3, 2).unwrap();
+        let twice = combine_key(&[shares[0].clone(), shares[0].clone()]);
+        assert!(matches!(twice, Err(VaultError::Invalid(msg)) if msg.contains("twice")));
+
+        // another split of the same key, with its own id
+        let other = split_key([2; 8], &key, 3, 2).unwrap();
+        let mixed = combine_key(&[shares[0].clone(), other[1].clone()]);
+        assert!(matches!(mixed, Err(VaultError::Invalid(msg)) if msg.contains("different recovery sets")));
+    }
+
+    #[test]
+    fn checksum_catches_a_typo() {
+        let share = &split_key([3; 8], &random_key(), 3, 2).unwrap()[0];
+        let text = share.encode();
+        let body_len = text.rfind(':').unwrap();
+        for (i, c) in text[..body_len].char_indices().filter(|(_, c)| *c != ':') {
+            let typo = if c == '0' { '1' } else { '0' };
+            let bad = format!("{}{typo}{}", &text[..i], &text[i + 1..]);
+            match Share::decode(&bad) {
+                Err(VaultError::Invalid(msg)) => assert!(msg.contains("checksum"), "{bad}: {msg}"),
+                other => panic!("{bad} decoded: {other:?}"),
+            }
+        }
+        // a share file: comments and blank lines around the share are skipped
+        let file = format!("# share 1 of 3\n\n  {text}\n");
+        assert_eq!(Share::decode(&file).unwrap().y, share.y);
+    }
+
+    #[test]
+    fn code_round_trip() {
+        let (code, key) = new_code();
+        assert_eq!(code.len(), 32 + 7);
+        assert!(code.split('-').all(|group| group.len() == 4));
+        assert_eq!(*parse_code(&code).unwrap(), *key);
+
+        // lower case, without dashes, with spaces, and the look-alike letters
+        let plain = code.replace('-', "");
+        for typed in [
+            code.to_lowercase(),
+            plain.clone(),
+            plain.chars().collect::<Vec<_>>().chunks(4).map(|c| c.iter().collect::<String>()).collect::<Vec<_>>().join(" "),
+            code.replace('0', "O").replace('1', "I"),
+            code.replace('0', "o").replace('1', "l"),
+            code.replace('1', "L"),
+        ] {
+            assert_eq!(*parse_code(&typed).unwrap(), *key, "{typed}");
+        }
+
+        assert!(parse_code(&plain[1..]).is_err());
+        assert!(parse_code(&format!("{plain}0")).is_err());
+        // U is not a Crockford digit
+        assert!(parse_code(&format!("U{}", &plain[1..])).is_err());
+        assert_ne!(*parse_code(&format!("{}{}", if plain.starts_with('0') { "1" } else { "0" }, &plain[1..])).unwrap(), *key);
+    }
+}
@@ -0,0 +1,93 @@
+//! Progress record of a large import (`<vault>.import`), so an import interrupted by a
+//! crash or an error can be resumed instead of redone.
+//!
+//! While importing, `import_file` periodically syncs the chunks written so far, moves
+//! the journal's rollback point past them and stores this record. Recovery then keeps
+//! those chunks (unreferenced by metadata) and a re-run of the same import picks up
+//! after the last recorded chunk. There is one record per vault; a newer large import
+//! replaces it. The record names the source and the target, so it is sealed under a key
+//! derived from the master key.
+
+use crate::crypto::{hkdf_derive, seal, unseal, CipherSuite, SecretKey, KEY_LEN};
+use crate::error::Result;
+use crate::fsmeta::ChunkRef;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedImport {
+    /// Id whose keys encrypt the chunks (the node id, unless it was taken meanwhile).
+    pub file_id: u64,
+    pub parent_id: u64,
+    pub name: String,
+    /// Canonical path, length and modification time of the source file.
+    pub source: String,
+    pub source_len: u64,
+    pub source_mtime_ns: u64,
+    pub chunk_size: usize,
+    /// Plaintext bytes covered by `chunks`.
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+    /// Sealed data key of `file_id` (see `Node::data_keys`; empty in older records,
+    /// whose chunks use the key derived from the master key).
+    #[serde(default)]
+    pub data_key: Vec<u8>,
+}
+
+impl StagedImport {
+    /// True if `other` imports the same, unchanged source to the same place.
+    pub fn same_import(&self, other: &StagedImport) -> bool {
+        self.parent_id == other.parent_id
+            && self.name == other.name
+            && self.source == other.source
+            && self.source_len == other.source_len
+            && self.source_mtime_ns == other.source_mtime_ns
+            && self.chunk_size == other.chunk_size
+    }
+}
+
+pub fn staging_path(vault_path: &str) -> String {
+    format!("{vault_path}.import")
+}
+
+fn staging_key(master_key: &[u8; KEY_LEN]) -> Result<SecretKey> {
+    hkdf_derive(master_key, b"staging:import")
+}
+
+/// Loads the record; one sealed under a different master key (e.g. before `rekey`) is
+/// ignored.
+pub fn load(vault_path: &str, suite: CipherSuite, master_key: &[u8; KEY_LEN]) -> Result<Option<StagedImport>> {
+    let bytes = match std::fs::read(staging_path(vault_path)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Ok(plain) = unseal(suite, &*staging_key(master_key)?, b"import", &bytes) else {
+        return Ok(None);
+    };
+    Ok(Some(serde_cbor::from_slice(&plain)?))
+}
+
+pub fn store(vault_path: &str, suite: CipherSuite, master_key: &[u8; KEY_LEN], s: &StagedImport) -> Result<()> {
+    let bytes = seal(suite, &*staging_key(master_key)?, b"import", &serde_cbor::to_vec(s)?)?;
+
+    // Same temp + rename as the journal, so a crash never leaves a torn record.
+    let path = staging_path(vault_path);
+    let tmp_path = format!("{path}.tmp");
+    {
+        let mut tmp = OpenOptions::new().create(true).truncate(true).write(true).open(&tmp_path)?;
+        tmp.write_all(&bytes)?;
+        tmp.sync_all()?;
+    }
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+pub fn clear(vault_path: &str) -> Result<()> {
+    match std::fs::remove_file(staging_path(vault_path)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,563 @@
+//! Two-way sync of a vault with a replica of it, e.g. one on the local disk and one on
+//! a USB stick or a cloud drive. Nodes are matched by `Node::uuid`, so a replica starts
+//! as a copy of the vault file or as an empty vault filled by the first sync.
+//!
+//! What changed on each side is told by comparing every node with its [`Stamp`] at the
+//! last sync, kept by both vaults as a [`Base`] (`Metadata::sync_peers`); while
+//! `Metadata::generation` of both is still what the base recorded, nothing did. Then:
+//!
+//! - a node added or changed on one side only is copied, moved or rewritten on the
+//!   other; one deleted (or put in the trash) on one side only goes to the trash of
+//!   the other, unless it was changed there, in which case it is copied back;
+//! - a file whose contents changed on both sides to different ones (by their SHA-256)
+//!   is a conflict: the remote version is renamed to `name (conflict YYYY-MM-DD)` and
+//!   becomes a new node, so both vaults end up with both versions;
+//! - a node moved, renamed or relabeled differently on both sides takes the local
+//!   place.
+//!
+//! [`plan`] finds what to do without writing anything, and [`apply`] does it in one
+//! transaction of each vault.
+
+use crate::container::{self, Progress, Session, VaultFileReader};
+use crate::error::{Result, VaultError};
+use crate::fsmeta::{format_time, now, Metadata, Node, NodeType};
+use crate::merkle::Hash;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use uuid::Uuid;
+
+/// The two vaults as they were after their last sync, as recorded by one of them,
+/// under the root UUID of the other.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Base {
+    /// Syncs of the pair so far. The bases of the two vaults differ only if a sync
+    /// failed between committing the remote vault and the local one; then the older
+    /// base is the right one for both.
+    pub round: u64,
+    /// `Metadata::generation` of this vault and of the other one after the sync.
+    pub generation: u64,
+    pub peer_generation: u64,
+    /// Nodes in both vaults after the sync, as stamped in this vault and in the other.
+    pub nodes: BTreeMap<Uuid, (Stamp, Stamp)>,
+}
+
+impl Base {
+    /// The same base as recorded by the other vault.
+    fn swapped(&self) -> Base {
+        Base {
+            round: self.round,
+            generation: self.peer_generation,
+            peer_generation: self.generation,
+            nodes: self.nodes.iter().map(|(u, &(mine, theirs))| (*u, (theirs, mine))).collect(),
+        }
+    }
+}
+
+/// Fingerprint of a node, changed by every change that sync carries over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    /// Of the parent, the name, the tags, the extended attributes and the favorite mark.
+    place: Hash,
+    /// The SHA-256 of the contents if it is recorded, else a hash of the chunk nonces.
+    /// Zero for a directory.
+    contents: Hash,
+}
+
+/// Which vault of the two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+impl Side {
+    fn other(self) -> Side {
+        match self {
+            Side::Local => Side::Remote,
+            Side::Remote => Side::Local,
+        }
+    }
+}
+
+/// What a sync does to one of the vaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: usize,
+    /// Files whose contents are replaced.
+    pub updated: usize,
+    /// Nodes moved, renamed or relabeled.
+    pub moved: usize,
+    /// Nodes put in the trash.
+    pub deleted: usize,
+}
+
+/// A node to copy, move, rewrite or trash in vault `to`, from the other one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Op {
+    to: Side,
+    uuid: Uuid,
+}
+
+/// A remote file in conflict, to be renamed and given a new UUID.
+#[derive(Debug, Clone)]
+struct Split {
+    id: u64,
+    name: String,
+    uuid: Uuid,
+}
+
+/// What [`apply`] is to do, from [`plan`].
+#[derive(Debug, Clone)]
+pub struct Plan {
+    local_id: Uuid,
+    remote_id: Uuid,
+    round: u64,
+    /// Nothing changed since the last sync: there is nothing to write, not even a base.
+    up_to_date: bool,
+    splits: Vec<Split>,
+    // parents before children
+    adds: Vec<Op>,
+    moves: Vec<Op>,
+    updates: Vec<Op>,
+    trashes: Vec<Op>,
+    conflicts: Vec<String>,
+    bytes: u64,
+}
+
+impl Plan {
+    /// Nothing to copy, move or delete either way.
+    pub fn is_empty(&self) -> bool {
+        self.splits.is_empty()
+            && self.adds.is_empty()
+            && self.moves.is_empty()
+            && self.updates.is_empty()
+            && self.trashes.is_empty()
+    }
+
+    pub fn changes(&self, to: Side) -> Changes {
+        let count = |ops: &[Op]| ops.iter().filter(|op| op.to == to).count();
+        Changes {
+            added: count(&self.adds),
+            updated: count(&self.updates),
+            moved: count(&self.moves),
+            deleted: count(&self.trashes),
+        }
+    }
+
+    /// Local paths of the files in conflict; the remote version of each is added next
+    /// to it under a conflict name.
+    pub fn conflicts(&self) -> &[String] {
+        &self.conflicts
+    }
+
+    /// Bytes of the files to copy or rewrite, for progress.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+/// A node outside the trash, as sync sees it.
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    id: u64,
+    /// UUID of the parent, nil for the root.
+    parent: Uuid,
+    stamp: Stamp,
+}
+
+/// The synced nodes of a vault (all but the root and the trash), by UUID.
+struct Tree {
+    items: HashMap<Uuid, Item>,
+}
+
+impl Tree {
+    fn of(meta: &Metadata) -> Result<Tree> {
+        let mut items = HashMap::new();
+        for n in &meta.nodes {
+            let Some(uuid) = n.uuid else { continue };
+            if n.id == meta.root_id || n.parent_id == 0 || meta.is_in_trash(n.id) {
+                continue;
+            }
+            let parent = parent_uuid(meta, n);
+            items.insert(uuid, Item { id: n.id, parent, stamp: stamp(parent, n)? });
+        }
+        Ok(Tree { items })
+    }
+
+    /// Directories above `uuid` (0 for a node at the root).
+    fn depth(&self, uuid: &Uuid) -> usize {
+        let mut depth = 0;
+        let mut at = self.items.get(uuid).map_or(Uuid::nil(), |i| i.parent);
+        // bounded, in case of a loop in damaged metadata
+        while !at.is_nil() && depth <= self.items.len() {
+            depth += 1;
+            at = self.items.get(&at).map_or(Uuid::nil(), |i| i.parent);
+        }
+        depth
+    }
+}
+
+fn parent_uuid(meta: &Metadata, n: &Node) -> Uuid {
+    match n.parent_id == meta.root_id {
+        true => Uuid::nil(),
+        false => meta.get_node(n.parent_id).and_then(|p| p.uuid).unwrap_or_default(),
+    }
+}
+
+fn stamp(parent: Uuid, n: &Node) -> Result<Stamp> {
+    let place = serde_cbor::to_vec(&(parent, &n.name, &n.tags, &n.xattrs, n.favorite))?;
+    let contents = match (n.node_type, n.sha256) {
+        (NodeType::Dir, _) => [0; 32],
+        (NodeType::File, Some(sha256)) => sha256,
+        // new nonces whenever the contents are written
+        (NodeType::File, None) => {
+            let nonces: Vec<&[u8]> = n.chunks.iter().map(|c| c.nonce.as_slice()).collect();
+            Sha256::digest(serde_cbor::to_vec(&(n.size, nonces))?).into()
+        }
+    };
+    Ok(Stamp { place: Sha256::digest(place).into(), contents })
+}
+
+/// SHA-256 of the contents of file `id`, decrypting them if none is recorded.
+fn digest(sess: &Session, id: u64) -> Result<Hash> {
+    match sess.meta.get_node(id).and_then(|n| n.sha256) {
+        Some(sha256) => Ok(sha256),
+        None => container::file_sha256(sess, id),
+    }
+}
+
+fn root_uuid(meta: &Metadata) -> Result<Uuid> {
+    meta.get_node(meta.root_id).and_then(|n| n.uuid).ok_or_else(|| VaultError::corrupted("the root has no UUID"))
+}
+
+/// `report (conflict 2026-10-14).pdf` for `report.pdf`, free in the directory of `id`.
+fn conflict_name(meta: &Metadata, id: u64) -> Result<String> {
+    let n = meta.get_node(id).ok_or(VaultError::NotFound)?;
+    let (stem, ext) = match n.name.rfind('.') {
+        Some(i) if i > 0 => n.name.split_at(i),
+        _ => (n.name.as_str(), ""),
+    };
+    let day = &format_time(now())[..10];
+    Ok(meta.unique_name(n.parent_id, &format!("{stem} (conflict {day}){ext}")))
+}
+
+/// Works out what syncing `local` with `remote` does, reading file contents only to
+/// compare files that changed on both sides. Nodes of older metadata get their UUIDs
+/// here and a copy of `local` its own root UUID, in memory: `apply` saves them.
+pub fn plan(local: &mut Session, remote: &mut Session) -> Result<Plan> {
+    local.meta.assign_uuids();
+    remote.meta.assign_uuids();
+    let local_id = root_uuid(&local.meta)?;
+    let mut remote_id = root_uuid(&remote.meta)?;
+    if remote_id == local_id {
+        // a copy of the vault file: the two are told apart from now on
+        remote_id = Uuid::new_v4();
+        let root_id = remote.meta.root_id;
+        remote.meta.get_node_mut(root_id).ok_or(VaultError::NotFound)?.uuid = Some(remote_id);
+    }
+    // Only a base both vaults know is safe: deletions are told from it.
+    let base = match (local.meta.sync_peers.get(&remote_id), remote.meta.sync_peers.get(&local_id)) {
+        (Some(l), Some(r)) if r.round < l.round => Some(r.swapped()),
+        (Some(l), Some(_)) => Some(l.clone()),
+        _ => None,
+    };
+    let mut plan = Plan {
+        local_id,
+        remote_id,
+        round: base.as_ref().map_or(0, |b| b.round) + 1,
+        up_to_date: false,
+        splits: vec![],
+        adds: vec![],
+        moves: vec![],
+        updates: vec![],
+        trashes: vec![],
+        conflicts: vec![],
+        bytes: 0,
+    };
+    let base = base.unwrap_or_default();
+    if base.round > 0 && base.generation == local.meta.generation && base.peer_generation == remote.meta.generation {
+        plan.up_to_date = true;
+        return Ok(plan);
+    }
+    let changed = |side: Side, uuid: &Uuid, part: fn(&Stamp) -> Hash, now: &Stamp| match base.nodes.get(uuid) {
+        Some((mine, theirs)) => part(if side == Side::Local { mine } else { theirs }) != part(now),
+        None => true,
+    };
+    let place = |s: &Stamp| s.place;
+    let contents = |s: &Stamp| s.contents;
+
+    let l = Tree::of(&local.meta)?;
+    let mut r = Tree::of(&remote.meta)?;
+
+    // Files changed on both sides to different contents: the remote one becomes a
+    // new node next to it, which the rest of the plan copies to the local vault.
+    let mut both: Vec<(Uuid, Item, Item)> = l
+        .items
+        .iter()
+        .filter_map(|(u, li)| r.items.get(u).map(|ri| (*u, *li, *ri)))
+        .filter(|(u, li, ri)| {
+            li.stamp.contents != ri.stamp.contents
+                && changed(Side::Local, u, contents, &li.stamp)
+                && changed(Side::Remote, u, contents, &ri.stamp)
+        })
+        .collect();
+    both.sort_by_key(|(_, li, _)| li.id);
+    for (u, li, ri) in both {
+        if digest(local, li.id)? == digest(remote, ri.id)? {
+            continue;
+        }
+        let split = Split { id: ri.id, name: conflict_name(&remote.meta, ri.id)?, uuid: Uuid::new_v4() };
+        r.items.remove(&u);
+        r.items.insert(split.uuid, ri);
+        plan.conflicts.push(local.meta.path_of(li.id));
+        plan.splits.push(split);
+    }
+
+    let mut adds: HashSet<Op> = HashSet::new();
+    let mut trashes: HashSet<Op> = HashSet::new();
+    let uuids: HashSet<Uuid> = l.items.keys().chain(r.items.keys()).copied().collect();
+    for u in uuids {
+        match (l.items.get(&u), r.items.get(&u)) {
+            (Some(li), Some(ri)) => {
+                if li.stamp.place != ri.stamp.place {
+                    if changed(Side::Local, &u, place, &li.stamp) {
+                        plan.moves.push(Op { to: Side::Remote, uuid: u });
+                    } else if changed(Side::Remote, &u, place, &ri.stamp) {
+                        plan.moves.push(Op { to: Side::Local, uuid: u });
+                    }
+                }
+                if li.stamp.contents != ri.stamp.contents {
+                    let local_changed = changed(Side::Local, &u, contents, &li.stamp);
+                    match (local_changed, changed(Side::Remote, &u, contents, &ri.stamp)) {
+                        (true, false) => plan.updates.push(Op { to: Side::Remote, uuid: u }),
+                        (false, true) => plan.updates.push(Op { to: Side::Local, uuid: u }),
+                        // the same contents, or split off above
+                        _ => {}
+                    }
+                }
+            }
+            (Some(li), None) => match base.nodes.get(&u) {
+                Some((mine, _)) if *mine == li.stamp => {
+                    trashes.insert(Op { to: Side::Local, uuid: u });
+                }
+                _ => {
+                    adds.insert(Op { to: Side::Remote, uuid: u });
+                }
+            },
+            (None, Some(ri)) => match base.nodes.get(&u) {
+                Some((_, theirs)) if *theirs == ri.stamp => {
+                    trashes.insert(Op { to: Side::Remote, uuid: u });
+                }
+                _ => {
+                    adds.insert(Op { to: Side::Local, uuid: u });
+                }
+            },
+            (None, None) => unreachable!(),
+        }
+    }
+
+    // A node copied or moved into a directory the target doesn't have (deleted there,
+    // or trashed with it) brings the directory back instead.
+    let tree = |side: Side| if side == Side::Local { &l } else { &r };
+    let mut queue: Vec<Op> = adds.iter().chain(&plan.moves).copied().collect();
+    while let Some(op) = queue.pop() {
+        let parent = tree(op.to.other()).items[&op.uuid].parent;
+        let dir = Op { to: op.to, uuid: parent };
+        if parent.is_nil() || tree(op.to).items.contains_key(&parent) || adds.contains(&dir) {
+            continue;
+        }
+        trashes.remove(&Op { to: op.to.other(), uuid: parent });
+        adds.insert(dir);
+        queue.push(dir);
+    }
+
+    plan.adds = adds.into_iter().collect();
+    plan.trashes = trashes.into_iter().collect();
+    plan.adds.sort_by_key(|op| (tree(op.to.other()).depth(&op.uuid), tree(op.to.other()).items[&op.uuid].id));
+    plan.moves.sort_by_key(|op| (tree(op.to.other()).depth(&op.uuid), tree(op.to.other()).items[&op.uuid].id));
+    plan.updates.sort_by_key(|op| tree(op.to.other()).items[&op.uuid].id);
+    plan.trashes.sort_by_key(|op| (tree(op.to).depth(&op.uuid), tree(op.to).items[&op.uuid].id));
+    for op in plan.adds.iter().chain(&plan.updates) {
+        let src = if op.to == Side::Local { &*remote } else { &*local };
+        let id = tree(op.to.other()).items[&op.uuid].id;
+        plan.bytes += src.meta.get_node(id).map_or(0, |n| n.size);
+    }
+    Ok(plan)
+}
+
+/// Does what `plan` found, in one transaction of each vault: the remote one is
+/// committed first, so that a failure in between leaves the local base older (see
+/// `Base::round`). Both sessions must be the ones `plan` looked at, unchanged since.
+pub fn apply(
+    local: &mut Session,
+    local_password: &str,
+    remote: &mut Session,
+    remote_password: &str,
+    plan: &Plan,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    if plan.up_to_date {
+        return Ok(());
+    }
+    remote.begin()?;
+    if let Err(e) = local.begin() {
+        remote.rollback()?;
+        return Err(e);
+    }
+    if let Err(e) = run(local, local_password, remote, remote_password, plan, progress) {
+        local.rollback()?;
+        remote.rollback()?;
+        return Err(e);
+    }
+    if let Err(e) = remote.commit(remote_password) {
+        local.rollback()?;
+        return Err(e);
+    }
+    local.commit(local_password)
+}
+
+fn run(
+    local: &mut Session,
+    local_password: &str,
+    remote: &mut Session,
+    remote_password: &str,
+    plan: &Plan,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    for split in &plan.splits {
+        remote.meta.rename(split.id, split.name.clone())?;
+        remote.meta.get_node_mut(split.id).ok_or(VaultError::NotFound)?.uuid = Some(split.uuid);
+    }
+    // UUID -> id of every node, the trash included
+    let uuids = |meta: &Metadata| -> HashMap<Uuid, u64> {
+        meta.nodes.iter().filter_map(|n| Some((n.uuid?, n.id))).collect()
+    };
+    let mut ids = [uuids(&local.meta), uuids(&remote.meta)];
+    let slot = |side: Side| side as usize;
+
+    for op in &plan.adds {
+        let (src, dst) = pair(op.to, local, remote);
+        let src_id = ids[slot(op.to.other())][&op.uuid];
+        let parent = dst_parent(src, dst, &ids[slot(op.to)], src_id)?;
+        // a node of this UUID in the trash there stays as a separate one
+        if let Some(&old) = ids[slot(op.to)].get(&op.uuid) {
+            dst.meta.get_node_mut(old).ok_or(VaultError::NotFound)?.uuid = Some(Uuid::new_v4());
+        }
+        let n = src.meta.get_node(src_id).ok_or(VaultError::NotFound)?;
+        let id = match n.node_type {
+            NodeType::Dir => {
+                let name = dst.meta.normalize_name(&n.name)?;
+                dst.meta.mkdir(parent, dst.meta.unique_name(parent, &name))?
+            }
+            NodeType::File => container::copy_file_from(src, dst, src_id, parent, progress)?,
+        };
+        copy_labels(src, dst, src_id, id)?;
+        dst.meta.get_node_mut(id).ok_or(VaultError::NotFound)?.uuid = Some(op.uuid);
+        ids[slot(op.to)].insert(op.uuid, id);
+    }
+
+    for op in &plan.moves {
+        let (src, dst) = pair(op.to, local, remote);
+        let (src_id, id) = (ids[slot(op.to.other())][&op.uuid], ids[slot(op.to)][&op.uuid]);
+        let parent = dst_parent(src, dst, &ids[slot(op.to)], src_id)?;
+        let name = dst.meta.normalize_name(&src.meta.get_node(src_id).ok_or(VaultError::NotFound)?.name)?;
+        move_to(&mut dst.meta, id, parent, &name, &op.uuid)?;
+        copy_labels(src, dst, src_id, id)?;
+    }
+
+    for op in &plan.updates {
+        let (src, dst) = pair(op.to, local, remote);
+        let (src_id, id) = (ids[slot(op.to.other())][&op.uuid], ids[slot(op.to)][&op.uuid]);
+        let n = src.meta.get_node(src_id).ok_or(VaultError::NotFound)?;
+        progress.file(Path::new(&n.name), n.size);
+        let password = if op.to == Side::Local { local_password } else { remote_password };
+        container::replace_file_from(dst, password, id, &mut VaultFileReader::open(src, src_id)?)?;
+        dst.meta.set_times(id, n.modified_at, n.source_mtime)?;
+        dst.meta.set_mime(id, n.mime.clone())?;
+        if !progress.advance(n.size) {
+            return Err(VaultError::Cancelled);
+        }
+    }
+
+    for op in &plan.trashes {
+        let meta = if op.to == Side::Local { &mut local.meta } else { &mut remote.meta };
+        let id = ids[slot(op.to)][&op.uuid];
+        // already there with a directory trashed before it
+        if !meta.is_in_trash(id) {
+            meta.trash(id)?;
+        }
+    }
+
+    let (l, r) = (Tree::of(&local.meta)?, Tree::of(&remote.meta)?);
+    let nodes: BTreeMap<Uuid, (Stamp, Stamp)> = l
+        .items
+        .iter()
+        .filter_map(|(u, li)| r.items.get(u).map(|ri| (*u, (li.stamp, ri.stamp))))
+        .collect();
+    // each commit writes the metadata once
+    let base = Base {
+        round: plan.round,
+        generation: local.meta.generation + 1,
+        peer_generation: remote.meta.generation + 1,
+        nodes,
+    };
+    remote.meta.sync_peers.insert(plan.local_id, base.swapped());
+    local.meta.sync_peers.insert(plan.remote_id, base);
+    Ok(())
+}
+
+/// The vault to copy from and the one to write, for a change to `to`.
+fn pair<'a>(to: Side, local: &'a mut Session, remote: &'a mut Session) -> (&'a Session, &'a mut Session) {
+    match to {
+        Side::Local => (remote, local),
+        Side::Remote => (local, remote),
+    }
+}
+
+/// `container::copy_labels` and the favorite mark, which are part of the `Stamp`.
+fn copy_labels(src: &Session, dst: &mut Session, src_id: u64, dst_id: u64) -> Result<()> {
+    container::copy_labels(src, dst, src_id, dst_id);
+    let favorite = src.meta.get_node(src_id).ok_or(VaultError::NotFound)?.favorite;
+    dst.meta.get_node_mut(dst_id).ok_or(VaultError::NotFound)?.favorite = favorite;
+    Ok(())
+}
+
+/// Id in `dst` of the parent that node `src_id` of `src` has.
+fn dst_parent(src: &Session, dst: &Session, dst_ids: &HashMap<Uuid, u64>, src_id: u64) -> Result<u64> {
+    let n = src.meta.get_node(src_id).ok_or(VaultError::NotFound)?;
+    match parent_uuid(&src.meta, n) {
+        p if p.is_nil() => Ok(dst.meta.root_id),
+        p => dst_ids.get(&p).copied().ok_or(VaultError::NotFound),
+    }
+}
+
+/// Moves node `id` into `parent` as `name`, or the first free name after it. A move
+/// into its own subtree (it was moved the other way round on the other side) is left
+/// out.
+fn move_to(meta: &mut Metadata, id: u64, parent: u64, name: &str, uuid: &Uuid) -> Result<()> {
+    let n = meta.get_node(id).ok_or(VaultError::NotFound)?;
+    if n.parent_id == parent {
+        if n.name != name {
+            let free = match meta.name_conflict(parent, name, Some(id)) {
+                Some(_) => meta.unique_name(parent, name),
+                None => name.to_string(),
+            };
+            meta.rename(id, free)?;
+        }
+        return Ok(());
+    }
+    let mut p = parent;
+    while p != 0 {
+        if p == id {
+            return Ok(());
+        }
+        p = meta.get_node(p).map_or(0, |n| n.parent_id);
+    }
+    // out of the way of the names on both ends first
+    meta.rename(id, format!(".sync-{}", uuid.simple()))?;
+    meta.move_node(id, parent)?;
+    let free = meta.unique_name(parent, name);
+    meta.rename(id, free)
+}
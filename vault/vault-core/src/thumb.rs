@@ -0,0 +1,25 @@
+//! Thumbnails of images, made at import so a grid of them can be shown without
+//! decrypting and decoding every original.
+
+use std::io::{BufRead, Seek};
+
+/// Longer side of a thumbnail, in pixels.
+pub const SIZE: u32 = 256;
+/// Larger images aren't decoded for a thumbnail (their pixels would need much more
+/// memory than that).
+pub const MAX_SOURCE_BYTES: u64 = 64 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 80;
+
+/// JPEG thumbnail of an image of type `mime` read from `src`, or None if it isn't an
+/// image the built-in viewer decodes or doesn't decode.
+pub fn render(src: impl BufRead + Seek, mime: &str) -> Option<Vec<u8>> {
+    if !crate::mime::is_viewable_image(mime) {
+        return None;
+    }
+    let format = image::ImageFormat::from_mime_type(mime)?;
+    let img = image::ImageReader::with_format(src, format).decode().ok()?;
+    let rgb = img.thumbnail(SIZE, SIZE).to_rgb8();
+    let mut out = vec![];
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&rgb).ok()?;
+    Some(out)
+}
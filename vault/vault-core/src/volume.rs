@@ -0,0 +1,249 @@
+//! Vault storage as one file or split into numbered parts (`vault.dat.001`,
+//! `vault.dat.002`, ...), e.g. for file systems with a file size limit.
+//!
+//! `Volume` presents the parts as one contiguous file, so the container code keeps a
+//! single offset space: part `i` holds bytes `[i * part_size, (i + 1) * part_size)`.
+//! Every part but the last is exactly `part_size` long, so the part size of an
+//! existing split vault is the length of its first part; while there is only one part
+//! it comes from the header (`set_part_size`).
+//!
+//! Parts are [`Backend`]s: files here, or the one backend of a vault stored elsewhere
+//! (`from_backend`), which is never split.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::backend::{Backend, FileBackend};
+
+pub struct Volume {
+    base: String,
+    split: bool,
+    parts: Vec<Arc<dyn Backend>>,
+    // u64::MAX for a single file (or a split vault whose part size isn't known yet)
+    part_size: u64,
+    pos: u64,
+}
+
+/// File name of part `index` (0-based) of a split vault.
+pub fn part_path(base: &str, index: usize) -> String {
+    format!("{base}.{:03}", index + 1)
+}
+
+/// True if `base` refers to a split vault (`base` itself absent, `base.001` present).
+pub fn is_split(base: &str) -> bool {
+    !Path::new(base).exists() && Path::new(&part_path(base, 0)).exists()
+}
+
+pub fn exists(base: &str) -> bool {
+    Path::new(base).exists() || Path::new(&part_path(base, 0)).exists()
+}
+
+/// Number of consecutive parts starting from `.001`.
+fn count_parts(base: &str) -> usize {
+    (0..).take_while(|&i| Path::new(&part_path(base, i)).exists()).count()
+}
+
+fn remove_if_exists(path: &str) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Removes a volume (single file or all parts).
+pub fn remove(base: &str) -> std::io::Result<()> {
+    remove_if_exists(base)?;
+    // Highest first, so an interrupted removal never leaves a gap.
+    for i in (0..count_parts(base)).rev() {
+        remove_if_exists(&part_path(base, i))?;
+    }
+    Ok(())
+}
+
+/// Moves volume `from` over volume `to`, which becomes `parts` parts long (1 for a
+/// single file). Parts already moved are skipped, so an interrupted call can be
+/// repeated with the same arguments.
+pub fn replace(from: &str, to: &str, parts: usize, split: bool) -> std::io::Result<()> {
+    if !split {
+        if Path::new(from).exists() {
+            std::fs::rename(from, to)?;
+        }
+        return Ok(());
+    }
+    for i in 0..parts {
+        let src = part_path(from, i);
+        if Path::new(&src).exists() {
+            std::fs::rename(src, part_path(to, i))?;
+        }
+    }
+    for i in (parts..count_parts(to).max(parts)).rev() {
+        remove_if_exists(&part_path(to, i))?;
+    }
+    Ok(())
+}
+
+impl Volume {
+    pub fn open(base: &str, write: bool) -> std::io::Result<Self> {
+        let split = is_split(base);
+        let paths: Vec<String> = match split {
+            true => (0..count_parts(base)).map(|i| part_path(base, i)).collect(),
+            false => vec![base.to_string()],
+        };
+        let parts = paths
+            .iter()
+            .map(|p| Ok(file_part(OpenOptions::new().read(true).write(write).open(p)?)))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let part_size = match split && parts.len() > 1 {
+            true => parts[0].len()?,
+            false => u64::MAX,
+        };
+        Ok(Self {
+            base: base.to_string(),
+            split,
+            parts,
+            part_size,
+            pos: 0,
+        })
+    }
+
+    pub fn from_backend(backend: Arc<dyn Backend>) -> Self {
+        Self {
+            base: String::new(),
+            split: false,
+            parts: vec![backend],
+            part_size: u64::MAX,
+            pos: 0,
+        }
+    }
+
+    /// Creates an empty volume, split into parts of `part_size` bytes unless it is 0.
+    pub fn create(base: &str, part_size: u64) -> std::io::Result<Self> {
+        remove(base)?;
+        let split = part_size > 0;
+        let first = if split { part_path(base, 0) } else { base.to_string() };
+        let f = OpenOptions::new().create(true).truncate(true).read(true).write(true).open(first)?;
+        Ok(Self {
+            base: base.to_string(),
+            split,
+            parts: vec![file_part(f)],
+            part_size: if split { part_size } else { u64::MAX },
+            pos: 0,
+        })
+    }
+
+    /// Part size recorded in the header; only needed while a split vault has one part.
+    pub fn set_part_size(&mut self, part_size: u64) {
+        if self.split && self.parts.len() == 1 && part_size > 0 {
+            self.part_size = part_size;
+        }
+    }
+
+    /// Part size for a new volume of the same layout (0 for a single file).
+    pub fn part_size(&self) -> u64 {
+        match self.split {
+            true => self.part_size,
+            false => 0,
+        }
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn is_split(&self) -> bool {
+        self.split
+    }
+
+    pub fn len(&self) -> std::io::Result<u64> {
+        let last = self.parts.len() as u64 - 1;
+        Ok(last * self.part_size + self.parts[last as usize].len()?)
+    }
+
+    pub fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+        let needed = (len.div_ceil(self.part_size) as usize).max(1);
+        while self.parts.len() > needed {
+            self.parts.pop();
+            remove_if_exists(&part_path(&self.base, self.parts.len()))?;
+        }
+        while self.parts.len() < needed {
+            self.add_part()?;
+        }
+        let last = self.parts.len() - 1;
+        self.parts[last].set_len(len - last as u64 * self.part_size)
+    }
+
+    pub fn sync_all(&self) -> std::io::Result<()> {
+        for p in &self.parts {
+            p.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Fills up the last part and starts the next one.
+    fn add_part(&mut self) -> std::io::Result<()> {
+        if !self.split || self.part_size == u64::MAX {
+            return Err(std::io::Error::other("volume part size unknown"));
+        }
+        let last = self.parts.len() - 1;
+        self.parts[last].set_len(self.part_size)?;
+        let f = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(part_path(&self.base, self.parts.len()))?;
+        self.parts.push(file_part(f));
+        Ok(())
+    }
+}
+
+fn file_part(f: std::fs::File) -> Arc<dyn Backend> {
+    Arc::new(FileBackend::new(f))
+}
+
+impl Read for Volume {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let index = (self.pos / self.part_size) as usize;
+        let Some(part) = self.parts.get(index) else {
+            return Ok(0);
+        };
+        let off = self.pos % self.part_size;
+        let n = (buf.len() as u64).min(self.part_size - off) as usize;
+        let read = part.read_at(off, &mut buf[..n])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for Volume {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let index = (self.pos / self.part_size) as usize;
+        while self.parts.len() <= index {
+            self.add_part()?;
+        }
+        let off = self.pos % self.part_size;
+        let n = (buf.len() as u64).min(self.part_size - off) as usize;
+        self.parts[index].write_at(off, &buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    // nothing buffered here; `sync_all` makes writes durable
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Volume {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(d) => self.len()?.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = new.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,169 @@
+/* C API of vault-core, from vault-ffi/src/lib.rs: regenerate with cbindgen (see cbindgen.toml), do not edit. */
+
+#ifndef VAULT_H
+#define VAULT_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define VAULT_OK 0
+
+#define VAULT_ERR_WRONG_PASSWORD 1
+
+#define VAULT_ERR_CORRUPTED 2
+
+#define VAULT_ERR_UNSUPPORTED 3
+
+#define VAULT_ERR_NOT_FOUND 4
+
+#define VAULT_ERR_NAME_EXISTS 5
+
+#define VAULT_ERR_NOT_A_DIRECTORY 6
+
+#define VAULT_ERR_NOT_A_FILE 7
+
+#define VAULT_ERR_READ_ONLY 8
+
+#define VAULT_ERR_LOCKED 9
+
+// A bad argument: a null pointer, a string that isn't UTF-8, an invalid name.
+#define VAULT_ERR_INVALID 10
+
+#define VAULT_ERR_DEVICE 11
+
+#define VAULT_ERR_IO 12
+
+// A bug in the library; the session may be left in any state.
+#define VAULT_ERR_PANIC 13
+
+#define VAULT_ERR_OTHER 14
+
+// An open vault.
+typedef struct VaultSession VaultSession;
+
+// A node of a directory listing (`vault_list`).
+typedef struct VaultEntry {
+  uint64_t id;
+  bool is_dir;
+  // Bytes of a file, 0 for a directory.
+  uint64_t size;
+  // Unix time of the last change.
+  uint64_t modified;
+  // UTF-8, NUL-terminated; freed by `vault_free_entries`.
+  char *name;
+} VaultEntry;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failed call on this thread, or null. Valid until the next call
+// on this thread.
+const char *vault_last_error(void);
+
+// Opens the vault at `path` with `password` and, if it was created with one, the
+// keyfile at `keyfile` (else null). A read-only session takes no write lock and
+// fails every change with `VAULT_ERR_READ_ONLY`. The session goes to `*out`, to be
+// closed with `vault_close`.
+//
+// # Safety
+// `path`, `password` and a non-null `keyfile` are NUL-terminated strings; `out` is
+// valid for a write.
+int32_t vault_open(const char *path,
+                   const char *password,
+                   const char *keyfile,
+                   bool read_only,
+                   VaultSession **out);
+
+// Closes a session from `vault_open` (null is ignored): the keys are wiped and the
+// write lock is released.
+//
+// # Safety
+// `session` is null or from `vault_open` and not closed yet.
+void vault_close(VaultSession *session);
+
+// Id of the root directory.
+//
+// # Safety
+// `session` is from `vault_open`.
+uint64_t vault_root_id(const VaultSession *session);
+
+// Lists directory `dir_id`, sorted by name, into `*entries` (`*count` of them), to be
+// freed with `vault_free_entries`.
+//
+// # Safety
+// `session` is from `vault_open`; `entries` and `count` are valid for writes.
+int32_t vault_list(VaultSession *session, uint64_t dir_id, VaultEntry **entries, size_t *count);
+
+// Frees a listing from `vault_list` (null is ignored).
+//
+// # Safety
+// `entries` and `count` are what `vault_list` returned, not freed yet.
+void vault_free_entries(VaultEntry *entries, size_t count);
+
+// Id of the node named `name` in directory `parent_id`, or `VAULT_ERR_NOT_FOUND`.
+//
+// # Safety
+// `session` is from `vault_open`; `name` is a NUL-terminated string; `id` is valid
+// for a write.
+int32_t vault_lookup(VaultSession *session, uint64_t parent_id, const char *name, uint64_t *id);
+
+// Reads up to `len` bytes of file `file_id` from `offset` into `buf`; `*read` is how
+// many there were (0 at or past the end).
+//
+// # Safety
+// `session` is from `vault_open`; `buf` is valid for writes of `len` bytes; `read` is
+// valid for a write.
+int32_t vault_read(VaultSession *session,
+                   uint64_t file_id,
+                   uint64_t offset,
+                   uint8_t *buf,
+                   size_t len,
+                   size_t *read);
+
+// Writes `len` bytes at `offset` of file `file_id`, growing it if they go past the
+// end.
+//
+// # Safety
+// `session` is from `vault_open`; `data` is valid for reads of `len` bytes.
+int32_t vault_write(VaultSession *session,
+                    uint64_t file_id,
+                    uint64_t offset,
+                    const uint8_t *data,
+                    size_t len);
+
+// Replaces the whole contents of file `file_id` with `len` bytes of `data` (it may
+// shrink).
+//
+// # Safety
+// `session` is from `vault_open`; `data` is valid for reads of `len` bytes.
+int32_t vault_replace(VaultSession *session, uint64_t file_id, const uint8_t *data, size_t len);
+
+// Creates an empty file `name` in directory `parent_id`; its id goes to `*id`.
+//
+// # Safety
+// `session` is from `vault_open`; `name` is a NUL-terminated string; `id` is valid
+// for a write.
+int32_t vault_create_file(VaultSession *session, uint64_t parent_id, const char *name, uint64_t *id);
+
+// Creates directory `name` in `parent_id`; its id goes to `*id`.
+//
+// # Safety
+// `session` is from `vault_open`; `name` is a NUL-terminated string; `id` is valid
+// for a write.
+int32_t vault_mkdir(VaultSession *session, uint64_t parent_id, const char *name, uint64_t *id);
+
+// Moves node `id` (a directory with everything in it) to the trash.
+//
+// # Safety
+// `session` is from `vault_open`.
+int32_t vault_remove(VaultSession *session, uint64_t id);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VAULT_H */
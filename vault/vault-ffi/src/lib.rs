@@ -0,0 +1,379 @@
+//! C API of vault-core (`include/vault.h`), so the container format can be used from
+//! C and C++, and through them from Swift and Kotlin apps.
+//!
+//! Every function returns a status: `VAULT_OK` or one of the `VAULT_ERR_*` codes,
+//! which follow the kinds of [`VaultError`]; `vault_last_error` has the message of the
+//! last failure on the calling thread. Results go to out-parameters. A session must
+//! not be used from two threads at once.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use vault_core::container::{self, Session};
+use vault_core::fsmeta::{Metadata, NodeType};
+use vault_core::VaultError;
+use zeroize::Zeroizing;
+
+pub const VAULT_OK: i32 = 0;
+pub const VAULT_ERR_WRONG_PASSWORD: i32 = 1;
+pub const VAULT_ERR_CORRUPTED: i32 = 2;
+pub const VAULT_ERR_UNSUPPORTED: i32 = 3;
+pub const VAULT_ERR_NOT_FOUND: i32 = 4;
+pub const VAULT_ERR_NAME_EXISTS: i32 = 5;
+pub const VAULT_ERR_NOT_A_DIRECTORY: i32 = 6;
+pub const VAULT_ERR_NOT_A_FILE: i32 = 7;
+pub const VAULT_ERR_READ_ONLY: i32 = 8;
+pub const VAULT_ERR_LOCKED: i32 = 9;
+/// A bad argument: a null pointer, a string that isn't UTF-8, an invalid name.
+pub const VAULT_ERR_INVALID: i32 = 10;
+pub const VAULT_ERR_DEVICE: i32 = 11;
+pub const VAULT_ERR_IO: i32 = 12;
+/// A bug in the library; the session may be left in any state.
+pub const VAULT_ERR_PANIC: i32 = 13;
+pub const VAULT_ERR_OTHER: i32 = 14;
+
+/// An open vault.
+pub struct VaultSession {
+    sess: Session,
+    // needed again for every write
+    password: Zeroizing<String>,
+}
+
+/// A node of a directory listing (`vault_list`).
+#[repr(C)]
+pub struct VaultEntry {
+    pub id: u64,
+    pub is_dir: bool,
+    /// Bytes of a file, 0 for a directory.
+    pub size: u64,
+    /// Unix time of the last change.
+    pub modified: u64,
+    /// UTF-8, NUL-terminated; freed by `vault_free_entries`.
+    pub name: *mut c_char,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn status_of(e: &VaultError) -> i32 {
+    match e.root() {
+        VaultError::WrongPassword { .. } => VAULT_ERR_WRONG_PASSWORD,
+        VaultError::Corrupted(_) => VAULT_ERR_CORRUPTED,
+        VaultError::Unsupported(_) => VAULT_ERR_UNSUPPORTED,
+        VaultError::NotFound => VAULT_ERR_NOT_FOUND,
+        VaultError::NameExists(_) => VAULT_ERR_NAME_EXISTS,
+        VaultError::NotADirectory => VAULT_ERR_NOT_A_DIRECTORY,
+        VaultError::NotAFile => VAULT_ERR_NOT_A_FILE,
+        VaultError::ReadOnly => VAULT_ERR_READ_ONLY,
+        VaultError::Locked => VAULT_ERR_LOCKED,
+        VaultError::Invalid(_) => VAULT_ERR_INVALID,
+        VaultError::Device(_) => VAULT_ERR_DEVICE,
+        VaultError::Io(_) => VAULT_ERR_IO,
+        _ => VAULT_ERR_OTHER,
+    }
+}
+
+fn set_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Runs `f` as the body of an API function: its error becomes the status and the last
+/// error message, a panic doesn't unwind into C.
+fn call(f: impl FnOnce() -> vault_core::Result<()>) -> i32 {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => VAULT_OK,
+        Ok(Err(e)) => {
+            // the message with its causes, like the CLI prints it
+            let mut msg = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(cause) = source {
+                msg.push_str(": ");
+                msg.push_str(&cause.to_string());
+                source = cause.source();
+            }
+            set_error(msg);
+            status_of(&e)
+        }
+        Err(_) => {
+            set_error("internal error (panic)".into());
+            VAULT_ERR_PANIC
+        }
+    }
+}
+
+fn null_argument() -> VaultError {
+    VaultError::Invalid("null argument".into())
+}
+
+/// # Safety
+/// `p` is null or a NUL-terminated string that lives for `'a`.
+unsafe fn str_arg<'a>(p: *const c_char) -> vault_core::Result<&'a str> {
+    if p.is_null() {
+        return Err(null_argument());
+    }
+    CStr::from_ptr(p).to_str().map_err(|_| VaultError::Invalid("string is not UTF-8".into()))
+}
+
+/// # Safety
+/// `p` is null or points to a `VaultSession` from `vault_open` not used elsewhere now.
+unsafe fn session_arg<'a>(p: *mut VaultSession) -> vault_core::Result<&'a mut VaultSession> {
+    p.as_mut().ok_or_else(null_argument)
+}
+
+/// # Safety
+/// `p` is null or valid for a write of `T`.
+unsafe fn put<T>(p: *mut T, value: T) -> vault_core::Result<()> {
+    if p.is_null() {
+        return Err(null_argument());
+    }
+    p.write(value);
+    Ok(())
+}
+
+/// Applies `change` to the metadata and saves it; the metadata in memory is put back
+/// if either fails.
+fn change<T>(s: &mut VaultSession, change: impl FnOnce(&mut Metadata) -> vault_core::Result<T>) -> vault_core::Result<T> {
+    let backup = s.sess.meta.clone();
+    let res = change(&mut s.sess.meta).and_then(|v| container::save_metadata(&mut s.sess, &s.password).map(|()| v));
+    if res.is_err() {
+        s.sess.meta = backup;
+    }
+    res
+}
+
+/// Message of the last failed call on this thread, or null. Valid until the next call
+/// on this thread.
+#[no_mangle]
+pub extern "C" fn vault_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |m| m.as_ptr()))
+}
+
+/// Opens the vault at `path` with `password` and, if it was created with one, the
+/// keyfile at `keyfile` (else null). A read-only session takes no write lock and
+/// fails every change with `VAULT_ERR_READ_ONLY`. The session goes to `*out`, to be
+/// closed with `vault_close`.
+///
+/// # Safety
+/// `path`, `password` and a non-null `keyfile` are NUL-terminated strings; `out` is
+/// valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn vault_open(
+    path: *const c_char,
+    password: *const c_char,
+    keyfile: *const c_char,
+    read_only: bool,
+    out: *mut *mut VaultSession,
+) -> i32 {
+    call(|| {
+        if out.is_null() {
+            return Err(null_argument());
+        }
+        let (path, password) = (str_arg(path)?, str_arg(password)?);
+        let keyfile = match keyfile.is_null() {
+            true => None,
+            false => Some(vault_core::crypto::keyfile_digest(str_arg(keyfile)?)?),
+        };
+        let sess = match read_only {
+            true => container::open_vault_readonly(path, password, keyfile.as_deref())?,
+            false => container::open_vault(path, password, keyfile.as_deref())?,
+        };
+        let s = VaultSession {
+            sess,
+            password: Zeroizing::new(password.to_string()),
+        };
+        put(out, Box::into_raw(Box::new(s)))
+    })
+}
+
+/// Closes a session from `vault_open` (null is ignored): the keys are wiped and the
+/// write lock is released.
+///
+/// # Safety
+/// `session` is null or from `vault_open` and not closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vault_close(session: *mut VaultSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Id of the root directory.
+///
+/// # Safety
+/// `session` is from `vault_open`.
+#[no_mangle]
+pub unsafe extern "C" fn vault_root_id(session: *const VaultSession) -> u64 {
+    session.as_ref().map_or(0, |s| s.sess.meta.root_id)
+}
+
+/// Lists directory `dir_id`, sorted by name, into `*entries` (`*count` of them), to be
+/// freed with `vault_free_entries`.
+///
+/// # Safety
+/// `session` is from `vault_open`; `entries` and `count` are valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn vault_list(
+    session: *mut VaultSession,
+    dir_id: u64,
+    entries: *mut *mut VaultEntry,
+    count: *mut usize,
+) -> i32 {
+    call(|| {
+        let meta = &session_arg(session)?.sess.meta;
+        match meta.get_node(dir_id) {
+            Some(n) if n.node_type == NodeType::Dir => {}
+            Some(_) => return Err(VaultError::NotADirectory),
+            None => return Err(VaultError::NotFound),
+        }
+        if entries.is_null() || count.is_null() {
+            return Err(null_argument());
+        }
+        let list: Box<[VaultEntry]> = meta
+            .children_of(dir_id)
+            .into_iter()
+            .map(|n| VaultEntry {
+                id: n.id,
+                is_dir: n.node_type == NodeType::Dir,
+                size: n.size,
+                modified: n.modified_at,
+                // names have no control characters, so no NUL either
+                name: CString::new(n.name.as_str()).unwrap_or_default().into_raw(),
+            })
+            .collect();
+        put(count, list.len())?;
+        put(entries, Box::into_raw(list).cast::<VaultEntry>())
+    })
+}
+
+/// Frees a listing from `vault_list` (null is ignored).
+///
+/// # Safety
+/// `entries` and `count` are what `vault_list` returned, not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vault_free_entries(entries: *mut VaultEntry, count: usize) {
+    if entries.is_null() {
+        return;
+    }
+    let list = Box::from_raw(std::ptr::slice_from_raw_parts_mut(entries, count));
+    for e in list.iter() {
+        drop(CString::from_raw(e.name));
+    }
+}
+
+/// Id of the node named `name` in directory `parent_id`, or `VAULT_ERR_NOT_FOUND`.
+///
+/// # Safety
+/// `session` is from `vault_open`; `name` is a NUL-terminated string; `id` is valid
+/// for a write.
+#[no_mangle]
+pub unsafe extern "C" fn vault_lookup(session: *mut VaultSession, parent_id: u64, name: *const c_char, id: *mut u64) -> i32 {
+    call(|| {
+        let meta = &session_arg(session)?.sess.meta;
+        let name = meta.normalize_name(str_arg(name)?)?;
+        let node = meta.name_conflict(parent_id, &name, None).ok_or(VaultError::NotFound)?;
+        put(id, node.id)
+    })
+}
+
+/// Reads up to `len` bytes of file `file_id` from `offset` into `buf`; `*read` is how
+/// many there were (0 at or past the end).
+///
+/// # Safety
+/// `session` is from `vault_open`; `buf` is valid for writes of `len` bytes; `read` is
+/// valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn vault_read(
+    session: *mut VaultSession,
+    file_id: u64,
+    offset: u64,
+    buf: *mut u8,
+    len: usize,
+    read: *mut usize,
+) -> i32 {
+    call(|| {
+        let s = session_arg(session)?;
+        if buf.is_null() && len > 0 {
+            return Err(null_argument());
+        }
+        let data = Zeroizing::new(container::read_file_range(&s.sess, file_id, offset, len)?);
+        if !data.is_empty() {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+        }
+        put(read, data.len())
+    })
+}
+
+/// Writes `len` bytes at `offset` of file `file_id`, growing it if they go past the
+/// end.
+///
+/// # Safety
+/// `session` is from `vault_open`; `data` is valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vault_write(session: *mut VaultSession, file_id: u64, offset: u64, data: *const u8, len: usize) -> i32 {
+    call(|| {
+        let s = session_arg(session)?;
+        container::write_file(&mut s.sess, &s.password, file_id, offset, bytes(data, len)?)
+    })
+}
+
+/// Replaces the whole contents of file `file_id` with `len` bytes of `data` (it may
+/// shrink).
+///
+/// # Safety
+/// `session` is from `vault_open`; `data` is valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn vault_replace(session: *mut VaultSession, file_id: u64, data: *const u8, len: usize) -> i32 {
+    call(|| {
+        let s = session_arg(session)?;
+        container::replace_file(&mut s.sess, &s.password, file_id, bytes(data, len)?)
+    })
+}
+
+/// # Safety
+/// `data` is null with `len` 0, or valid for reads of `len` bytes.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> vault_core::Result<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(null_argument()),
+        (false, _) => Ok(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Creates an empty file `name` in directory `parent_id`; its id goes to `*id`.
+///
+/// # Safety
+/// `session` is from `vault_open`; `name` is a NUL-terminated string; `id` is valid
+/// for a write.
+#[no_mangle]
+pub unsafe extern "C" fn vault_create_file(session: *mut VaultSession, parent_id: u64, name: *const c_char, id: *mut u64) -> i32 {
+    call(|| {
+        let s = session_arg(session)?;
+        let new_id = container::create_file(&mut s.sess, &s.password, parent_id, str_arg(name)?, None)?;
+        put(id, new_id)
+    })
+}
+
+/// Creates directory `name` in `parent_id`; its id goes to `*id`.
+///
+/// # Safety
+/// `session` is from `vault_open`; `name` is a NUL-terminated string; `id` is valid
+/// for a write.
+#[no_mangle]
+pub unsafe extern "C" fn vault_mkdir(session: *mut VaultSession, parent_id: u64, name: *const c_char, id: *mut u64) -> i32 {
+    call(|| {
+        let (s, name) = (session_arg(session)?, str_arg(name)?);
+        let new_id = change(s, |meta| meta.mkdir(parent_id, name.to_string()))?;
+        put(id, new_id)
+    })
+}
+
+/// Moves node `id` (a directory with everything in it) to the trash.
+///
+/// # Safety
+/// `session` is from `vault_open`.
+#[no_mangle]
+pub unsafe extern "C" fn vault_remove(session: *mut VaultSession, id: u64) -> i32 {
+    call(|| change(session_arg(session)?, |meta| meta.trash(id)))
+}
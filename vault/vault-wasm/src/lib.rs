@@ -0,0 +1,96 @@
+//! vault-core compiled to WebAssembly, for `www/index.html`: a page that opens a vault
+//! and exports its files entirely in the browser, e.g. for emergency access from a
+//! machine without the app. Nothing leaves the page: the vault is read into memory
+//! with the File API and decrypted here.
+//!
+//! Sessions are read-only (`container::open_vault_backend`). Node ids are JavaScript
+//! numbers, exact as long as a vault has fewer than 2^53 nodes.
+
+use std::sync::Arc;
+use vault_core::backend::MemoryBackend;
+use vault_core::container::{self, Session};
+use vault_core::fsmeta::NodeType;
+use vault_core::VaultError;
+use wasm_bindgen::prelude::*;
+
+/// An open vault.
+#[wasm_bindgen]
+pub struct Vault {
+    sess: Session,
+}
+
+// the message with its causes, like the CLI prints it
+fn js_error(e: VaultError) -> JsError {
+    let mut msg = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        msg.push_str(": ");
+        msg.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    JsError::new(&msg)
+}
+
+fn set(obj: &js_sys::Object, key: &str, value: JsValue) {
+    // can't fail on a plain object
+    let _ = js_sys::Reflect::set(obj, &key.into(), &value);
+}
+
+#[wasm_bindgen]
+impl Vault {
+    /// Opens the vault in `bytes` with `password` and, if it was created with one, the
+    /// contents of its keyfile. The Argon2 derivation blocks the page for a while.
+    pub fn open(bytes: Vec<u8>, password: &str, keyfile: Option<Vec<u8>>) -> Result<Vault, JsError> {
+        let keyfile = keyfile.map(|k| vault_core::crypto::keyfile_digest_of(&k)).transpose().map_err(js_error)?;
+        let backend = Arc::new(MemoryBackend::new(bytes));
+        let sess = container::open_vault_backend(backend, password, keyfile.as_deref()).map_err(js_error)?;
+        Ok(Vault { sess })
+    }
+
+    #[wasm_bindgen(getter, js_name = rootId)]
+    pub fn root_id(&self) -> f64 {
+        self.sess.meta.root_id as f64
+    }
+
+    /// Directory `dir_id`, sorted by name: `{ id, name, isDir, size, modified, mime }`
+    /// for each node, `modified` in Unix seconds and `mime` null if not known.
+    pub fn list(&self, dir_id: f64) -> Result<js_sys::Array, JsError> {
+        let meta = &self.sess.meta;
+        match meta.get_node(dir_id as u64) {
+            Some(n) if n.node_type == NodeType::Dir => {}
+            Some(_) => return Err(js_error(VaultError::NotADirectory)),
+            None => return Err(js_error(VaultError::NotFound)),
+        }
+        let list = js_sys::Array::new();
+        for n in meta.children_of(dir_id as u64) {
+            let obj = js_sys::Object::new();
+            set(&obj, "id", (n.id as f64).into());
+            set(&obj, "name", n.name.as_str().into());
+            set(&obj, "isDir", (n.node_type == NodeType::Dir).into());
+            set(&obj, "size", (n.size as f64).into());
+            set(&obj, "modified", (n.modified_at as f64).into());
+            set(&obj, "mime", n.mime.as_deref().map_or(JsValue::NULL, JsValue::from));
+            list.push(&obj);
+        }
+        Ok(list)
+    }
+
+    /// Path of node `id` from the root, `/a/b.txt`.
+    #[wasm_bindgen(js_name = pathOf)]
+    pub fn path_of(&self, id: f64) -> String {
+        self.sess.meta.path_of(id as u64)
+    }
+
+    /// Parent directory of node `id` (the root is its own parent).
+    #[wasm_bindgen(js_name = parentOf)]
+    pub fn parent_of(&self, id: f64) -> Result<f64, JsError> {
+        let meta = &self.sess.meta;
+        let n = meta.get_node(id as u64).ok_or_else(|| js_error(VaultError::NotFound))?;
+        Ok(if n.id == meta.root_id { n.id } else { n.parent_id } as f64)
+    }
+
+    /// Whole contents of file `file_id`, decrypted and checked.
+    pub fn read(&self, file_id: f64) -> Result<Vec<u8>, JsError> {
+        container::read_file_bytes(&self.sess, file_id as u64).map_err(js_error)
+    }
+}